@@ -69,26 +69,24 @@ fn main() -> ! {
     // 2: Set up DMA, for non-blocking transfers:
     let mut dma = Dma::new(&mut dp.DMA1, &dp.RCC);
 
-    let mut dma_buf = [0];
+    // DMA buffers must be `'static`, so they stay valid while the transfer is in progress.
+    let dma_buf = cortex_m::singleton!(: [u16; 1] = [0]).unwrap();
 
     // Begin a DMA transfer. Note that the `DmaChannel` we pass here is only used on
     // MCUs that use `DMAMUX`, eg L5, G0, and G4. For those, you need to run `mux`, to
     // set the channel: `dma::mux(DmaPeriph::Dma1, DmaChannel::C1, MuxInput::Adc1);
-    unsafe {
-        adc.read_dma(
-            &mut dma_buf,
-            &[chan_num],
-            DmaChannel::C1,
-            Default::default(),
-            &mut dma,
-        )
-    };
+    let transfer = adc.read_dma(
+        dma_buf,
+        &[chan_num],
+        DmaChannel::C1,
+        Default::default(),
+        &mut dma,
+    );
 
     // Wait for the transfer to complete. Ie by handling the channel's transfer-complete
     // interrupt in an ISR, which is enabled by the `read_dma` command.
-    // For this example, we block until the flag is set.
-    while !dma.transfer_is_complete(DmaChannel::C1) {}
-    dma.stop(DmaChannel::C1);
+    // For this example, we block until the flag is set. This returns the buffer.
+    let dma_buf = transfer.wait(&mut dma);
 
     defmt::println!("Reading: {:?}", &dma_buf[0]);
 
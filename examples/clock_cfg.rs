@@ -12,8 +12,14 @@
 use cortex_m_rt::entry;
 
 use stm32_hal2::{
-    clocks::{self, ApbPrescaler, Clocks, InputSrc, MsiRng, PllCfg, PllSrc, Pllm, Pllr},
+    clocks::{
+        self, ApbPrescaler, Clocks, InputSrc, KernelClock, KernelClocks, Mco, McoPrescaler, McoSrc,
+        MsiRng, PllCfg, PllSrc, Pllm, Pllr,
+    },
+    crs::{Crs, CrsConfig, CrsInterrupt, CrsSyncSrc},
+    gpio::{Pin, PinMode, Port},
     low_power, pac,
+    timer::Timer,
 };
 
 #[entry]
@@ -89,6 +95,24 @@ fn main() -> ! {
         ..Default::default()
     };
 
+    // On H7, PLL3 can run in fractional mode, eg to produce an exact audio clock for SAI. With a
+    // 2Mhz reference, this sets PLL3P to 2Mhz * (98 + 2490 / 8192) / 16 ~= 12.288Mhz (256 x 48kHz).
+    // Run `clock_cfg.set_fracn(3, fracn)` later to switch sample rate families without stopping
+    // the PLL. PLL2 and PLL3 outputs can also be selected for SPI, ADC, FDCAN and QSPI kernel clocks.
+    let clock_cfg = Clocks {
+        pll3: PllCfg {
+            enabled: true,
+            pllp_en: true,
+            divn: 98,
+            divp: 16,
+            fracn: 2490,
+            ..PllCfg::disabled()
+        },
+        sai1_src: SaiSrc::Pll3P,
+        adc_src: AdcSrc::Pll2P,
+        ..Default::default()
+    };
+
     // Or on L4 or WB, using the PLLSAI:
     let clock_cfg = Clocks {
         pllsai1: PllCfg {
@@ -115,6 +139,10 @@ fn main() -> ! {
     // Enable the Clock Security System (CSS)
     clock_cfg.security_system = true;
 
+    // Enable the LSE clock security system. (Not available on F3 or F4) This only takes effect
+    // if the LSE is already running as the RTC clock.
+    clock_cfg.lse_security_system = true;
+
     // Bypass HSE output
     clock_cfg.hse_bypass = true;
 
@@ -129,6 +157,11 @@ fn main() -> ! {
     // `clk48_src: Clk48Src::MSI` in the clock cfg, which is the default for L4 and L5.
     clocks_cfg.enable_msi_48();
 
+    // (L4, L5, WB and WL) If an LSE crystal is connected, MSI can run in PLL-mode, where hardware
+    // continually trims it against the LSE. This is accurate enough for USB without an HSE. The LSE
+    // must already be running, eg from `Rtc::new()`, before running `setup()`.
+    clock_cfg.msi_pll_mode = true;
+
     // Change  PLL prescalers:
     clock_cfg.pllm = Pllm::Div4;
     clock_cfg.plln = 22;
@@ -137,25 +170,114 @@ fn main() -> ! {
     // Change some of the peripheral prescalers
     clock_cfg.apb1prescaler = ApbPrescaler::Div2;
 
+    // Or, let the HAL find PLL settings for a target system clock. Here, 80Mhz from a 16Mhz HSE,
+    // with PLLQ also configured to produce the 48Mhz USB clock. This returns an error describing
+    // which constraint failed, if no valid config exists.
+    let clock_cfg = Clocks::from_target(PllSrc::Hse(16_000_000), 80_000_000, true).unwrap();
+
+    // Peripherals that can run from a clock other than their APB clock (eg USART, I2C and LPTIM)
+    // are configured using the `kernel_clocks` field. Peripheral `new()` functions use this to
+    // calculate baud rates and timings. Fields left as `None` aren't changed by `setup()`.
+    // For example, to run USART1 from HSI, so its baud rate doesn't depend on SYSCLK:
+    let clock_cfg = Clocks {
+        kernel_clocks: KernelClocks {
+            usart1: Some(KernelClock::Hsi),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
     // Enable the Clock Recovery System (CRS), to automatically trim the HSI48 on variants
     // that include it. (eg STM32l4x2 and L4x3, L5, G4)
     clocks::enable_crs(CrsSyncSrc::Usb);
 
+    // Or, for more control, use the `crs` module. Here, we lock the HSI48 to the LSE, and check
+    // how close it is. RELOAD and FELIM are calculated from the sync frequency.
+    let crs_cfg = CrsConfig {
+        sync_src: CrsSyncSrc::Lse,
+        sync_freq: 32_768,
+        ..Default::default()
+    };
+    let mut crs = Crs::new(dp.CRS, crs_cfg);
+    crs.enable_interrupt(CrsInterrupt::Error);
+    if crs.status().sync_ok {
+        defmt::println!("HSI48 locked. Trim: {}", crs.trim());
+    }
+
     // If you need to modify functionality not supported by this library,
     // you can make register writes directly  using the PAC. If you find missing functionality
     // you find useful, consider making an issue or PR on Github.
-    // For example, to set I2C1 to use HSI as its source, on L4:
+    // For example, to set UART4 to use HSI as its source, on L4x6:
     dp.RCC
         .ccipr
-        .modify(|_, w| unsafe { w.i2c1sel().bits(0b10) });
+        .modify(|_, w| unsafe { w.uart4sel().bits(0b10) });
 
     // Configure clock registers. The previous creation and modification of `clock_cfg`
     // only set up a configuration struct; `Clocks::setup` performs the MCU operations.
     clock_cfg.setup().unwrap();
 
+    // Output the PLL's R output, divided by 4, on the MCO pin, eg to check it with an oscilloscope.
+    // (On F4 and H7, select an output using `McoSrc::Mco1` or `McoSrc::Mco2`.)
+    let mco = Mco {
+        src: McoSrc::Pllr,
+        prescaler: McoPrescaler::Div4,
+    };
+    let mut mco_pin = Pin::new(Port::A, 8, PinMode::Alt(0));
+    mco.enable(&mut mco_pin);
+    defmt::println!("MCO speed: {:?}", clock_cfg.mco_speed(&mco));
+
     // Show speeds.
     defmt::println!("Speeds: {:?}", clock_cfg.calc_speeds());
 
+    // Or, get every derived speed as a struct, which implements `Display`. (And `uDisplay`, with
+    // the `ufmt` feature) Use `Clocks::read_active` to read the configuration from RCC registers,
+    // eg to check what the MCU is actually running at. Its argument is the HSE frequency, in Hz,
+    // which can't be read back. The RTC clock is set up by `Rtc::new()`, so read it separately.
+    let mut report = clock_cfg.report();
+    report.rtc = clock_cfg.rtc_clock();
+    let active = Clocks::read_active(16_000_000).report();
+    defmt::println!(
+        "SYSCLK: {}, active SYSCLK: {}",
+        report.sysclk,
+        active.sysclk
+    );
+
+    // To change clock speeds after setup, eg to save power when idle, use `switch_to`. This sets
+    // flash wait states in a safe order. Peripherals whose timing depends on clock speeds need to
+    // be updated afterwards. (`uart`, `i2c`, `spi`, and `timer` are set up as in their examples.)
+    let prev_clocks = clock_cfg.clone();
+    let mut clock_cfg = clock_cfg;
+    clock_cfg
+        .switch_to(Clocks {
+            input_src: InputSrc::Hsi,
+            ..Default::default()
+        })
+        .unwrap();
+
+    uart.retime(&clock_cfg);
+    i2c.retime(&clock_cfg);
+    spi.retime(&prev_clocks, &clock_cfg);
+    timer.retime(&clock_cfg).unwrap();
+
+    // If the HSE fails while the CSS is enabled, hardware switches SYSCLK to an internal
+    // oscillator, and triggers an NMI. Clear the failure flag in its handler, then move the clock
+    // tree off the HSE, using the returned frequency to update peripherals as required:
+    // #[exception]
+    // fn NonMaskableInt() {
+    //     if let Some(failure) = clocks::osc_failure() {
+    //         clocks::clear_osc_failure(failure);
+    //     }
+    // }
+    let sysclk = clock_cfg.fail_over().unwrap();
+
+    // Measure the timer clock against the LSE, (L4, L5, WB and WL) and trim HSI16 if it's fast or
+    // slow. Compare to the speed we expect from the clock config:
+    let mut timer = Timer::new_tim16(dp.TIM16, 1., Default::default(), &clock_cfg);
+    let measured = timer.measure_lse(32);
+    if measured > clock_cfg.apb2_timer() {
+        clocks::set_hsi_trim(clocks::hsi_trim().saturating_sub(1));
+    }
+
     loop {
         low_power::sleep_now();
     }
@@ -19,9 +19,14 @@ use cortex_m_rt::entry;
 use stm32_hal2::{
     adc::{Adc, AdcChannel, Align, CkMode, InputType, OperationMode},
     clocks::Clocks,
-    gpio::{self, Edge, OutputSpeed, Pin, PinMode, PinState, Port},
+    gpio::{
+        self,
+        typestate::{Alternate, Analog, Output, PushPull, PA10, PA9, PB3},
+        Edge, OutputSpeed, Pin, PinMode, PinState, Port, Signal,
+    },
     low_power, pac,
     prelude::*,
+    usart::Usart,
 };
 
 use embedded_hal::digital::OutputPin;
@@ -63,6 +68,10 @@ pub fn setup_pins() {
     let _uart_tx = Pin::new(Port::A, 9, PinMode::Alt(7));
     let _uart_rx = Pin::new(Port::A, 10, PinMode::Alt(7));
 
+    // Or, look up the AF number from the signal. This returns an error if the pin can't carry it.
+    let mut lpuart_tx = Pin::new(Port::A, 2, PinMode::Input);
+    lpuart_tx.set_alt_for(Signal::Lpuart1Tx).unwrap();
+
     // Set up USB pins
     let _usb_dm = Pin::new(Port::A, 11, PinMode::Alt(14));
     let _usb_dp = Pin::new(Port::A, 12, PinMode::Alt(14));
@@ -130,6 +139,16 @@ fn main() -> ! {
     delay.delay_ms(500);
     example_output.set_low();
 
+    // Optionally, use type-state pins, whose port, number, and mode are part of their type.
+    // Peripherals' `new_with_pins` functions only accept pins set to the right alternate function.
+    let tx: PA9<Alternate<7>> = PA9::claim().unwrap();
+    let rx = PA10::<Analog>::claim().unwrap().into_mode::<Alternate<7>>();
+    let uart = Usart::new_with_pins(dp.USART1, (tx, rx), 9_600, Default::default(), &clock_cfg);
+
+    // Convert to a dynamic `Pin` when you need to choose pins at runtime.
+    let mut led = PB3::<Output<PushPull>>::claim().unwrap().erase();
+    led.set_high();
+
     // Unmask interrupt lines associated with the input pins we've configured interrupts
     // for in `setup_pins`.
     unsafe {
@@ -133,14 +133,16 @@ mod imu {
     /// are processed in the Transfer Complete ISR.
     pub fn read_imu_dma(starting_addr: u8, spi: &mut Spi<SPI1>, cs: &mut Pin, dma: &mut Dma<DMA1>) {
         // First byte is the first data reg, per this IMU's. Remaining bytes are empty, while
-        // the MISO line transmits readings.
-        let write_buf = [starting_addr, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        // the MISO line transmits readings. DMA buffers must be `'static`.
+        static mut WRITE_BUF: [u8; 13] = [0; 13];
 
         cs.set_low();
 
         unsafe {
+            WRITE_BUF[0] = starting_addr;
+
             spi.transfer_dma(
-                &write_buf,
+                &WRITE_BUF,
                 &mut crate::IMU_READINGS,
                 DmaChannel::C1,
                 DmaChannel::C2,
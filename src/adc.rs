@@ -5,7 +5,7 @@ use cortex_m::{asm, delay::Delay, interrupt::free};
 use core::{ops::Deref, ptr};
 
 use crate::{
-    clocks::Clocks,
+    clocks::{Clocks, KernelClockPeriph},
     pac::{self, RCC},
     util::rcc_en_reset,
 };
@@ -16,15 +16,16 @@ use paste::paste;
 cfg_if! {
     if #[cfg(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1"))))] {
         use crate::pac::dma as dma_p;
-    } else if #[cfg(feature = "f4")] {} else {
+    } else if #[cfg(feature = "f4")] {
+        use crate::pac::dma2 as dma_p;
+    } else {
         use crate::pac::dma1 as dma_p;
     }
 }
 
-#[cfg(not(any(feature = "f4", feature = "l552")))]
-use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
+use crate::dma::{self, ChannelCfg, Dma, DmaChannel, Transfer, WriteBuffer};
 
-#[cfg(any(feature = "f3", feature = "l4"))]
+#[cfg(any(feature = "f3", feature = "f4", feature = "l4"))]
 use crate::dma::DmaInput;
 
 // Address of the ADCinterval voltage reference. This address is found in the User manual. It appears
@@ -192,6 +193,25 @@ pub enum Prescaler {
     D256 = 0b1011,
 }
 
+impl Prescaler {
+    pub fn value(&self) -> u16 {
+        match self {
+            Self::D1 => 1,
+            Self::D2 => 2,
+            Self::D4 => 4,
+            Self::D6 => 6,
+            Self::D8 => 8,
+            Self::D10 => 10,
+            Self::D12 => 12,
+            Self::D16 => 16,
+            Self::D32 => 32,
+            Self::D64 => 64,
+            Self::D128 => 128,
+            Self::D256 => 256,
+        }
+    }
+}
+
 #[cfg(not(feature = "h7"))]
 /// ADC data register alignment
 #[derive(Clone, Copy)]
@@ -341,13 +361,8 @@ macro_rules! hal {
                     // Reference Manual: "ADEN bit cannot be set during ADCAL=1
                     // and 4 ADC clock cycle after the ADCAL
                     // bit is cleared by hardware."
-                    let adc_per_cpu_cycles = match result.cfg.clock_mode {
-                        ClockMode::Async => unimplemented!(),
-                        ClockMode::SyncDiv1 => 1,
-                        ClockMode::SyncDiv2 => 2,
-                        ClockMode::SyncDiv4 => 4,
-                    };
-                    asm::delay(adc_per_cpu_cycles * 4 * 2); // additional x2 is a pad;
+                    let cpu_per_adc_cycles = clock_cfg.sysclk() / result.speed(clock_cfg).max(1);
+                    asm::delay(cpu_per_adc_cycles.max(1) * 4 * 2); // additional x2 is a pad;
 
                     result.set_sequence_len(1);  // as a default
 
@@ -362,6 +377,21 @@ macro_rules! hal {
                 }
             }
 
+            /// Get the ADC clock frequency, in hz. In async mode, this is the ADC kernel clock
+            /// (see `Clocks::kernel_clocks`, or `Clocks::adc_src` on H7) divided by the prescaler.
+            /// Otherwise, it's HCLK divided by the clock mode's divider.
+            pub fn speed(&self, clock_cfg: &Clocks) -> u32 {
+                match self.cfg.clock_mode {
+                    ClockMode::Async => {
+                        clock_cfg.kernel_speed(KernelClockPeriph::Adc)
+                            / self.cfg.prescaler.value() as u32
+                    }
+                    ClockMode::SyncDiv1 => clock_cfg.hclk(),
+                    ClockMode::SyncDiv2 => clock_cfg.hclk() / 2,
+                    ClockMode::SyncDiv4 => clock_cfg.hclk() / 4,
+                }
+            }
+
             /// Set the ADC conversion sequence length, between 1 and 16.
             pub fn set_sequence_len(&mut self, len: u8) {
                 if len - 1 >= 16 {
@@ -838,29 +868,39 @@ macro_rules! hal {
 
             // todo: fn read_voltage, using vrefint and L4xx-hal style calibration?
 
-            #[cfg(not(any(feature = "f4", feature = "l552")))]
             /// Take a reading, using DMA. Sets conversion sequence; no need to set it directly.
-            /// Note that the `channel` argument is only used on F3 and L4.
-            pub unsafe fn read_dma<D>(
-                &mut self, buf: &mut [u16],
+            /// On F3, the `channel` argument has no effect with DMA1, and on L4, it has no effect.
+            /// On F4, it's the DMA2 stream to use. Returns a `Transfer` that holds `buf` until the readings are complete.
+            pub fn read_dma<B, D>(
+                &mut self, mut buf: B,
                 adc_channels: &[u8],
                 dma_channel: DmaChannel,
                 channel_cfg: ChannelCfg,
                 dma: &mut Dma<D>
-            ) where
+            ) -> Transfer<B>
+            where
+                B: WriteBuffer<Word = u16>,
                 D: Deref<Target = dma_p::RegisterBlock>,
             {
-                let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+                let (ptr, len) = unsafe { buf.write_buffer() };
                 // The software is allowed to write (dmaen and dmacfg) only when ADSTART=0 and JADSTART=0 (which
                 // ensures that no conversion is ongoing)
                 self.stop_conversions();
 
-                #[cfg(not(feature = "h7"))]
+                #[cfg(not(any(feature = "f4", feature = "h7")))]
                 self.regs.cfgr.modify(|_, w| {
                     w.dmacfg().bit(channel_cfg.circular == dma::Circular::Enabled);
                     w.dmaen().set_bit()
                 });
 
+                // F4 sets DMA mode in CR2. DDS keeps issuing requests after the last transfer, for
+                // circular DMA.
+                #[cfg(feature = "f4")]
+                self.regs.cr2.modify(|_, w| {
+                    w.dds().bit(channel_cfg.circular == dma::Circular::Enabled);
+                    w.dma().set_bit()
+                });
+
                 #[cfg(feature = "h7")]
                 self.regs.cfgr.modify(|_, w| {
                     // Note: To use non-DMA after this has been set, need to configure manually.
@@ -869,21 +909,35 @@ macro_rules! hal {
                 });
 
                 // L44 RM, Table 41. "DMA1 requests for each channel
-                // todo: DMA2 support.
-                #[cfg(any(feature = "f3", feature = "l4"))]
-                let dma_channel = match self.device {
-                    AdcDevice::One => DmaInput::Adc1.dma1_channel(),
-                    AdcDevice::Two => DmaInput::Adc2.dma1_channel(),
-                    _ => panic!("DMA on ADC beyond 2 is not supported. If it is for your MCU, please submit an issue \
-                or PR on Github.")
-                };
+                #[cfg(feature = "f3")]
+                let dma_channel = dma.select_channel(
+                    match self.device {
+                        AdcDevice::One => DmaInput::Adc1.dma1_channel(),
+                        AdcDevice::Two => DmaInput::Adc2.dma1_channel(),
+                        _ => panic!("DMA on ADC beyond 2 is not supported. If it is for your MCU, please submit an issue \
+                    or PR on Github.")
+                    },
+                    dma_channel,
+                );
 
                 #[cfg(feature = "l4")]
-                match self.device {
+                let dma_channel = match self.device {
                     AdcDevice::One => dma.channel_select(DmaInput::Adc1),
                     AdcDevice::Two => dma.channel_select(DmaInput::Adc2),
                     _ => unimplemented!(),
-                }
+                };
+
+                // F4 RM0090, Table 43. "DMA2 request mapping"
+                #[cfg(feature = "f4")]
+                dma.channel_select(
+                    dma_channel,
+                    match self.device {
+                        AdcDevice::One => DmaInput::Adc1,
+                        AdcDevice::Two => DmaInput::Adc2,
+                        AdcDevice::Three => DmaInput::Adc3,
+                        _ => panic!("F4 has no ADC beyond 3."),
+                    },
+                );
 
                 let mut seq_len = 0;
                 for (i, ch) in adc_channels.iter().enumerate() {
@@ -892,7 +946,10 @@ macro_rules! hal {
                 }
                 self.set_sequence_len(seq_len);
 
+                #[cfg(not(feature = "f4"))]
                 self.regs.cr.modify(|_, w| w.adstart().set_bit());  // Start
+                #[cfg(feature = "f4")]
+                self.regs.cr2.modify(|_, w| w.swstart().set_bit());
 
                 // Since converted channel values are stored into a unique data register, it is useful to use
                 // DMA for conversion of more than one channel. This avoids the loss of the data already
@@ -945,6 +1002,8 @@ macro_rules! hal {
                     dma::DataSize::S16,
                     channel_cfg,
                 );
+
+                Transfer::new(buf, dma_channel, dma.periph)
             }
 
             /// Enable a specific type of ADC interrupt.
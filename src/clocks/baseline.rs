@@ -5,8 +5,14 @@
 // Similar in from to the H7 clocks module, but includes notable differendes.
 
 use crate::{
-    clocks::SpeedError,
+    clocks::{
+        rtc_clock, switch_steps, ClockReport, KernelClock, KernelClockPeriph, KernelClocks,
+        OscFailure, PllDivider, PllSolveError, PllSpeeds, SpeedError, SwitchStep,
+        KERNEL_CLOCK_PERIPHS,
+    },
+    gpio::{OutputSpeed, Pin, PinMode},
     pac::{self, FLASH, RCC},
+    rtc::RtcClockSource,
     util::rcc_en_reset,
 };
 
@@ -186,6 +192,63 @@ enum WaitState {
     W5 = 5,
 }
 
+// SYSCLK and PLL limits, in Hz, used by `Clocks::from_target`. VCO input is the PLL source
+// after PLLM; VCO output is VCO input x PLLN. See the RCC_PLLCFGR register description in your RM.
+cfg_if! {
+    if #[cfg(feature = "l4")] {
+        const MAX_SYSCLK: u32 = 80_000_000;
+        const VCO_IN_MIN: u32 = 4_000_000;
+        const VCO_OUT_MIN: u32 = 64_000_000;
+        const PLLN_MIN: u8 = 8;
+        const PLLN_MAX: u8 = 86;
+    } else if #[cfg(feature = "l5")] {
+        const MAX_SYSCLK: u32 = 110_000_000;
+        const VCO_IN_MIN: u32 = 4_000_000;
+        const VCO_OUT_MIN: u32 = 64_000_000;
+        const PLLN_MIN: u8 = 8;
+        const PLLN_MAX: u8 = 127;
+    } else if #[cfg(feature = "g0")] {
+        const MAX_SYSCLK: u32 = 64_000_000;
+        const VCO_IN_MIN: u32 = 2_660_000;
+        const VCO_OUT_MIN: u32 = 64_000_000;
+        const PLLN_MIN: u8 = 8;
+        const PLLN_MAX: u8 = 86;
+    } else if #[cfg(feature = "g4")] {
+        const MAX_SYSCLK: u32 = 170_000_000;
+        const VCO_IN_MIN: u32 = 2_660_000;
+        const VCO_OUT_MIN: u32 = 96_000_000;
+        const PLLN_MIN: u8 = 8;
+        const PLLN_MAX: u8 = 127;
+    } else if #[cfg(feature = "wb")] {
+        const MAX_SYSCLK: u32 = 64_000_000;
+        const VCO_IN_MIN: u32 = 2_660_000;
+        const VCO_OUT_MIN: u32 = 96_000_000;
+        const PLLN_MIN: u8 = 6;
+        const PLLN_MAX: u8 = 127;
+    } else { // WL
+        const MAX_SYSCLK: u32 = 48_000_000;
+        const VCO_IN_MIN: u32 = 2_660_000;
+        const VCO_OUT_MIN: u32 = 96_000_000;
+        const PLLN_MIN: u8 = 6;
+        const PLLN_MAX: u8 = 127;
+    }
+}
+
+const VCO_IN_MAX: u32 = 16_000_000;
+const VCO_OUT_MAX: u32 = 344_000_000;
+
+#[cfg(feature = "l4")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Dynamic voltage scaling range. Range 2 reduces power consumption, but limits SYSCLK to
+/// 26Mhz, and the PLL VCO output to 128Mhz. Sets PWR_CR1 register, VOS field. (L4 RM, 5.1.8)
+pub enum VoltageRange {
+    /// High performance range. Up to 80Mhz.
+    Range1 = 0b01,
+    /// Low-power range. Up to 26Mhz.
+    Range2 = 0b10,
+}
+
 #[cfg(not(any(feature = "g0", feature = "g4")))]
 #[derive(Clone, Copy, PartialEq)]
 #[repr(u8)]
@@ -224,10 +287,29 @@ impl MsiRange {
             Self::R48M => 48_000_000,
         }
     }
+
+    /// Convert from the MSIRANGE register field. Reserved values are treated as 4Mhz.
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0b0000 => Self::R100k,
+            0b0001 => Self::R200k,
+            0b0010 => Self::R400k,
+            0b0011 => Self::R800k,
+            0b0100 => Self::R1M,
+            0b0101 => Self::R2M,
+            0b0111 => Self::R8M,
+            0b1000 => Self::R16M,
+            0b1001 => Self::R24M,
+            0b1010 => Self::R32M,
+            0b1011 => Self::R48M,
+            _ => Self::R4M,
+        }
+    }
 }
 
 /// Configures the speeds, and enable status of an individual PLL (PLL1, or SAIPLL). Note that the `enable`
 /// field has no effect for PLL1.
+#[derive(Clone, Copy)]
 pub struct PllCfg {
     /// Only relevant for PLLSAI1.
     pub enabled: bool,
@@ -368,6 +450,40 @@ impl Pllm {
     }
 }
 
+#[cfg(not(any(feature = "l5", feature = "g4")))]
+/// PLLM values, in the order `Clocks::from_target` tries them.
+const PLLM_DIVS: [Pllm; 8] = [
+    Pllm::Div1,
+    Pllm::Div2,
+    Pllm::Div3,
+    Pllm::Div4,
+    Pllm::Div5,
+    Pllm::Div6,
+    Pllm::Div7,
+    Pllm::Div8,
+];
+
+#[cfg(any(feature = "l5", feature = "g4"))]
+/// PLLM values, in the order `Clocks::from_target` tries them.
+const PLLM_DIVS: [Pllm; 16] = [
+    Pllm::Div1,
+    Pllm::Div2,
+    Pllm::Div3,
+    Pllm::Div4,
+    Pllm::Div5,
+    Pllm::Div6,
+    Pllm::Div7,
+    Pllm::Div8,
+    Pllm::Div9,
+    Pllm::Div10,
+    Pllm::Div11,
+    Pllm::Div12,
+    Pllm::Div13,
+    Pllm::Div14,
+    Pllm::Div15,
+    Pllm::Div16,
+];
+
 #[cfg(any(feature = "g0", feature = "wb"))]
 #[derive(Clone, Copy)]
 #[repr(u8)]
@@ -398,6 +514,18 @@ impl Pllr {
     }
 }
 
+#[cfg(any(feature = "g0", feature = "wb"))]
+/// PLLR (and PLLQ) values, in the order `Clocks::from_target` tries them.
+const PLLR_DIVS: [Pllr; 7] = [
+    Pllr::Div2,
+    Pllr::Div3,
+    Pllr::Div4,
+    Pllr::Div5,
+    Pllr::Div6,
+    Pllr::Div7,
+    Pllr::Div8,
+];
+
 #[cfg(not(any(feature = "g0", feature = "wb")))]
 #[derive(Clone, Copy)]
 #[repr(u8)]
@@ -421,6 +549,10 @@ impl Pllr {
     }
 }
 
+#[cfg(not(any(feature = "g0", feature = "wb")))]
+/// PLLR (and PLLQ) values, in the order `Clocks::from_target` tries them.
+const PLLR_DIVS: [Pllr; 4] = [Pllr::Div2, Pllr::Div4, Pllr::Div6, Pllr::Div8];
+
 #[derive(Clone, Copy)]
 #[repr(u8)]
 /// Divisor for PLLP. Sets `PLLCFGR` reg, `PLLP` field.
@@ -488,6 +620,31 @@ impl HclkPrescaler {
             Self::Div512 => 512,
         }
     }
+
+    /// Convert from the HPRE register field.
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            #[cfg(feature = "wb")]
+            0b0001 => Self::Div3,
+            #[cfg(feature = "wb")]
+            0b0010 => Self::Div5,
+            #[cfg(feature = "wb")]
+            0b0101 => Self::Div6,
+            #[cfg(feature = "wb")]
+            0b0110 => Self::Div10,
+            #[cfg(feature = "wb")]
+            0b0111 => Self::Div32,
+            0b1000 => Self::Div2,
+            0b1001 => Self::Div4,
+            0b1010 => Self::Div8,
+            0b1011 => Self::Div16,
+            0b1100 => Self::Div64,
+            0b1101 => Self::Div128,
+            0b1110 => Self::Div256,
+            0b1111 => Self::Div512,
+            _ => Self::Div1,
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -511,6 +668,17 @@ impl ApbPrescaler {
             Self::Div16 => 16,
         }
     }
+
+    /// Convert from a PPRE register field.
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0b100 => Self::Div2,
+            0b101 => Self::Div4,
+            0b110 => Self::Div8,
+            0b111 => Self::Div16,
+            _ => Self::Div1,
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -527,9 +695,94 @@ pub enum SaiSrc {
     ExtClk = 0b11,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Clock output on the MCO pin. Sets RCC_CFGR register, MCOSEL field.
+pub enum McoSrc {
+    Sysclk = 0b0001,
+    #[cfg(not(any(feature = "g0", feature = "g4")))]
+    Msi = 0b0010,
+    Hsi = 0b0011,
+    Hse = 0b0100,
+    /// The main PLL's R output. (PLLRCLK)
+    Pllr = 0b0101,
+    Lsi = 0b0110,
+    #[cfg(not(any(feature = "wb", feature = "wl")))]
+    Lse = 0b0111,
+    #[cfg(any(feature = "wb", feature = "wl"))]
+    Lse = 0b1000,
+    #[cfg(any(feature = "g0b1", feature = "g0c1"))]
+    Hsi48 = 0b0010,
+    #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+    Hsi48 = 0b1000, // Not valid for some L4 variants.
+    #[cfg(feature = "wb")]
+    Hsi48 = 0b1001,
+    #[cfg(feature = "wl")]
+    /// The main PLL's P output. (PLLPCLK)
+    Pllp = 0b1101,
+    #[cfg(feature = "wl")]
+    /// The main PLL's Q output. (PLLQCLK)
+    Pllq = 0b1110,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Divides the MCO source clock. Sets RCC_CFGR register, MCOPRE field.
+pub enum McoPrescaler {
+    Div1 = 0b000,
+    Div2 = 0b001,
+    Div4 = 0b010,
+    Div8 = 0b011,
+    Div16 = 0b100,
+    #[cfg(feature = "g0")]
+    Div32 = 0b101,
+    #[cfg(feature = "g0")]
+    Div64 = 0b110,
+    #[cfg(feature = "g0")]
+    Div128 = 0b111,
+}
+
+impl McoPrescaler {
+    pub fn value(&self) -> u32 {
+        1 << (*self as u8)
+    }
+}
+
+/// Microcontroller Clock Output (MCO) configuration. See the `clocks` module documentation.
+#[derive(Clone, Copy)]
+pub struct Mco {
+    pub src: McoSrc,
+    pub prescaler: McoPrescaler,
+}
+
+impl Mco {
+    /// Configure `pin` for MCO, and start outputting the clock on it. MCO is alternate function 0
+    /// on PA8; some variants can use other pins. (eg PF2 on G0) See your MCU's datasheet.
+    pub fn enable(&self, pin: &mut Pin) {
+        pin.mode(PinMode::Alt(0));
+        pin.output_speed(OutputSpeed::High);
+
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        // Set the prescaler before selecting the source, so the output doesn't glitch at a
+        // higher speed.
+        rcc.cfgr
+            .modify(|_, w| unsafe { w.mcopre().bits(self.prescaler as u8) });
+        rcc.cfgr
+            .modify(|_, w| unsafe { w.mcosel().bits(self.src as u8) });
+    }
+}
+
+/// Stop outputting a clock on the MCO pin.
+pub fn disable_mco() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.cfgr.modify(|_, w| unsafe { w.mcosel().bits(0) });
+}
+
 /// Settings used to configure clocks. Create this struct by using its `Default::default()`
 /// implementation, then modify as required, referencing your RM's clock tree,
 /// or Stm32Cube IDE's interactive clock manager. Apply settings by running `.setup()`.
+#[derive(Clone)]
 pub struct Clocks {
     /// The input source for the system and peripheral clocks. Eg HSE, HSI, PLL etc
     pub input_src: InputSrc,
@@ -564,7 +817,19 @@ pub struct Clocks {
     /// Bypass the HSE output, for use with oscillators that don't need it. Saves power, and
     /// frees up the pin for use as GPIO.
     pub hse_bypass: bool,
+    /// Enable the HSE clock security system (CSS). If the HSE fails, hardware switches SYSCLK to
+    /// an internal oscillator, and triggers an NMI. See `osc_failure()` and `Clocks::fail_over()`.
+    /// Defaults to false.
     pub security_system: bool,
+    /// Enable the LSE clock security system. This only takes effect if the LSE is running and
+    /// selected as the RTC clock (eg by `Rtc::new()`) when `setup()` is run. Once enabled, only a
+    /// backup domain reset disables it. Defaults to false.
+    pub lse_security_system: bool,
+    #[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
+    /// Run the MSI in PLL-mode, where hardware continuously trims it against the LSE. This makes
+    /// MSI accurate enough for USB, and for UART at high baud rates. This only takes effect if the
+    /// LSE is running when `setup()` is run. Defaults to false.
+    pub msi_pll_mode: bool,
     #[cfg(not(any(feature = "g0", feature = "wl")))]
     /// Enable the HSI48. For L4, this is only applicable for some devices.
     pub hsi48_on: bool,
@@ -577,10 +842,15 @@ pub struct Clocks {
     #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
     /// SAI1 kernel clock source selection
     pub sai1_src: SaiSrc,
+    /// Kernel clock selection for USART, LPUART, I2C, LPTIM and ADC peripherals.
+    pub kernel_clocks: KernelClocks,
     #[cfg(feature = "g4")]
     /// Range 1 boost mode: Used to increase regulator voltage to 1.28v, for when system
     /// clock frequency is up to 170Mhz. Defaults to true.
     pub boost_mode: bool,
+    #[cfg(feature = "l4")]
+    /// The voltage scaling range. Range 2 limits SYSCLK to 26Mhz. Defaults to Range 1.
+    pub voltage_range: VoltageRange,
 }
 
 // todo: On L4/5, add a way to enable the MSI for use as CLK48.
@@ -595,6 +865,7 @@ impl Clocks {
             return Err(e);
         }
 
+        #[cfg(not(any(feature = "wb", feature = "wl")))]
         let rcc = unsafe { &(*RCC::ptr()) };
         let flash = unsafe { &(*FLASH::ptr()) };
 
@@ -604,139 +875,37 @@ impl Clocks {
                                                          // todo doesn't have this?
         rcc_en_reset!(apb2, syscfg, rcc);
 
-        // Adjust flash wait states according to the HCLK frequency.
-        // We need to do this before enabling PLL, or it won't enable.
-        let sysclk = self.sysclk();
+        #[cfg(feature = "g4")]
+        self.enable_boost_mode();
 
-        cfg_if! {
-            if #[cfg(feature = "wb")] {
-                let hclk = sysclk / self.hclk4_prescaler.value() as u32;
-            } else if #[cfg(feature = "wl")] {
-                let hclk = sysclk / self.hclk3_prescaler.value() as u32;
-            } else {
-                let hclk = sysclk / self.hclk_prescaler.value() as u32;
-            }
+        // Raise the voltage range before speeding up.
+        #[cfg(feature = "l4")]
+        if self.voltage_range == VoltageRange::Range1 {
+            self.set_voltage_range();
         }
 
-        cfg_if! {
-            if #[cfg(feature = "g4")] {
-                if self.boost_mode {
-                    // The sequence to switch from Range1 normal mode to Range1 boost mode is:
-                    // 1. The system clock must be divided by 2 using the AHB prescaler before switching to a
-                    // higher system frequency.
-                    rcc.cfgr.modify(|_, w| unsafe { w.hpre().bits(HclkPrescaler::Div2 as u8) });
-                    // 2. Clear the R1MODE bit is in the PWR_CR5 register.
-                    let pwr = unsafe { &(*pac::PWR::ptr()) };
-                    pwr.cr5.modify(|_, w| w.r1mode().clear_bit());
-                }
+        // Adjust flash wait states according to the HCLK frequency.
+        // We need to do this before enabling PLL, or it won't enable.
+        flash
+            .acr
+            .modify(|_, w| unsafe { w.latency().bits(self.wait_states() as u8) });
 
-                // (Remaining steps accomplished below)
-                // 3. Adjust the number of wait states according to the new frequency target in range1 boost
-                // mode
-                // 4. Configure and switch to new system frequency.
-                // 5. Wait for at least 1us and then reconfigure the AHB prescaler to get the needed HCLK
-                // clock frequency.
-            }
-        }
+        self.configure();
 
-        cfg_if! {
-            if #[cfg(feature = "l4")] {  // RM section 3.3.3
-                flash.acr.modify(|_, w| unsafe {
-                    if hclk <= 16_000_000 {
-                        w.latency().bits(WaitState::W0 as u8)
-                    } else if hclk <= 32_000_000 {
-                        w.latency().bits(WaitState::W1 as u8)
-                    } else if hclk <= 48_000_000 {
-                        w.latency().bits(WaitState::W2 as u8)
-                    } else if hclk <= 64_000_000 {
-                        w.latency().bits(WaitState::W3 as u8)
-                    } else {
-                        w.latency().bits(WaitState::W4 as u8)
-                    }
-                });
-            } else if #[cfg(feature = "l5")] {  // RM section 6.3.3
-                flash.acr.modify(|_, w| unsafe {
-                    if hclk <= 20_000_000 {
-                        w.latency().bits(WaitState::W0 as u8)
-                    } else if hclk <= 40_000_000 {
-                        w.latency().bits(WaitState::W1 as u8)
-                    } else if hclk <= 60_000_000 {
-                        w.latency().bits(WaitState::W2 as u8)
-                    } else if hclk <= 80_000_000 {
-                        w.latency().bits(WaitState::W3 as u8)
-                    } else if hclk <= 100_000_000 {
-                        w.latency().bits(WaitState::W4 as u8)
-                    } else {
-                        w.latency().bits(WaitState::W5 as u8)
-                    }
-                });
-            } else if #[cfg(feature = "g0")] {  // G0. RM section 3.3.4
-                flash.acr.modify(|_, w| unsafe {
-                    if hclk <= 24_000_000 {
-                        w.latency().bits(WaitState::W0 as u8)
-                    } else if hclk <= 48_000_000 {
-                        w.latency().bits(WaitState::W1 as u8)
-                    } else {
-                        w.latency().bits(WaitState::W2 as u8)
-                    }
-                });
-            } else if #[cfg(feature = "wb")] {  // WB. RM section 3.3.4, Table 4.
-            // Note: This applies to HCLK4 HCLK. (See HCLK4 used above for hclk var.)
-                flash.acr.modify(|_, w| unsafe {
-                    if hclk <= 18_000_000 {
-                        w.latency().bits(WaitState::W0 as u8)
-                    } else if hclk <= 36_000_000 {
-                        w.latency().bits(WaitState::W1 as u8)
-                    } else if hclk <= 54_000_000 {
-                        w.latency().bits(WaitState::W2 as u8)
-                    } else {
-                        w.latency().bits(WaitState::W3 as u8)
-                    }
-                });
-            } else if #[cfg(any(feature = "wb", feature = "wl"))] {  // WL. RM section 3.3.4, Table 5.
-            // Note: This applies to HCLK3 HCLK. (See HCLK3 used above for hclk var.)
-                flash.acr.modify(|_, w| unsafe {
-                    if hclk <= 18_000_000 {
-                        w.latency().bits(WaitState::W0 as u8)
-                    } else if hclk <= 36_000_000 {
-                        w.latency().bits(WaitState::W1 as u8)
-                    } else {
-                        w.latency().bits(WaitState::W2 as u8)
-                    }
-                });
-            } else {  // G4. RM section 3.3.3
-                flash.acr.modify(|_, w| unsafe {
-                    if self.boost_mode {
-                        // Vcore Range 1 boost mode
-                        if hclk <= 34_000_000 {
-                            w.latency().bits(WaitState::W0 as u8)
-                        } else if hclk <= 68_000_000 {
-                            w.latency().bits(WaitState::W1 as u8)
-                        } else if hclk <= 102_000_000 {
-                            w.latency().bits(WaitState::W2 as u8)
-                        } else if hclk <= 136_000_000 {
-                            w.latency().bits(WaitState::W3 as u8)
-                        } else {
-                            w.latency().bits(WaitState::W4 as u8)
-                        }
-                    } else {
-                        // Vcore Range 1 normal mode.
-                        if hclk <= 30_000_000 {
-                            w.latency().bits(WaitState::W0 as u8)
-                        } else if hclk <= 60_000_000 {
-                            w.latency().bits(WaitState::W1 as u8)
-                        } else if hclk <= 90_000_000 {
-                            w.latency().bits(WaitState::W2 as u8)
-                        } else if hclk <= 120_000_000 {
-                            w.latency().bits(WaitState::W3 as u8)
-                        } else {
-                            w.latency().bits(WaitState::W4 as u8)
-                        }
-                    }
-                });
-            }
+        // Lower the voltage range only once the clocks are within its limits.
+        #[cfg(feature = "l4")]
+        if self.voltage_range == VoltageRange::Range2 {
+            self.set_voltage_range();
         }
 
+        Ok(())
+    }
+
+    /// Enable oscillators and the PLL, and set clock sources and prescalers. Flash wait states
+    /// must be high enough for both the current and new HCLK before running this.
+    fn configure(&self) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
         // Reference Manual, 6.2.5:
         // The device embeds 3 PLLs: PLL, PLLSAI1, PLLSAI2. Each PLL provides up to three
         // independent outputs. The internal PLLs can be used to multiply the HSI16, HSE or MSI
@@ -772,7 +941,6 @@ impl Clocks {
                 });
                 // Wait for the MSI to be ready.
                 while rcc.cr.read().msirdy().bit_is_clear() {}
-                // todo: If LSE is enabled, calibrate MSI.
             }
             InputSrc::Hse(_) => {
                 rcc.cr.modify(|_, w| w.hseon().set_bit());
@@ -819,6 +987,13 @@ impl Clocks {
             }
         }
 
+        // RM: "MSIPLLEN must be enabled after LSE is enabled (LSERDY set by hardware). There is a
+        // hardware protection to avoid enabling MSIPLLEN if LSE is not ready."
+        #[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
+        if self.msi_pll_mode && rcc.bdcr.read().lserdy().bit_is_set() {
+            rcc.cr.modify(|_, w| w.msipllen().set_bit());
+        }
+
         rcc.cr.modify(|_, w| {
             // Enable bypass mode on HSE, since we're using a ceramic oscillator.
             #[cfg(feature = "wl")]
@@ -827,19 +1002,6 @@ impl Clocks {
             w.hsebyp().bit(self.hse_bypass)
         });
 
-        rcc.cfgr.modify(|_, w| unsafe {
-            w.sw().bits(self.input_src.bits());
-            w.hpre().bits(self.hclk_prescaler as u8);
-            #[cfg(not(feature = "g0"))]
-            w.ppre2().bits(self.apb2_prescaler as u8); // HCLK division for APB2.
-            #[cfg(any(feature = "l4", feature = "l5"))]
-            w.stopwuck().bit(self.stop_wuck as u8 != 0);
-            #[cfg(not(feature = "g0"))]
-            return w.ppre1().bits(self.apb1_prescaler as u8); // HCLK division for APB1
-            #[cfg(feature = "g0")]
-            return w.ppre().bits(self.apb1_prescaler as u8);
-        });
-
         // todo: Adapt this logic for H7? Mix H7 into this module?
         #[cfg(feature = "wb")]
         rcc.extcfgr.modify(|_, w| unsafe {
@@ -853,6 +1015,29 @@ impl Clocks {
 
         rcc.cr.modify(|_, w| w.csson().bit(self.security_system));
 
+        // LSECSSON must be set after the LSE and LSI are ready, and the LSE is selected as the RTC
+        // clock.
+        if self.lse_security_system
+            && rcc.bdcr.read().lserdy().bit_is_set()
+            && rcc.bdcr.read().rtcsel().bits() == 0b01
+        {
+            cfg_if! {
+                if #[cfg(feature = "wb")] {
+                    rcc.csr.modify(|_, w| w.lsi1on().set_bit());
+                    while rcc.csr.read().lsi1rdy().bit_is_clear() {}
+                } else {
+                    rcc.csr.modify(|_, w| w.lsion().set_bit());
+                    while rcc.csr.read().lsirdy().bit_is_clear() {}
+                }
+            }
+
+            // BDCR is write-protected unless backup domain access is enabled.
+            let pwr = unsafe { &(*pac::PWR::ptr()) };
+            pwr.cr1.modify(|_, w| w.dbp().set_bit());
+
+            rcc.bdcr.modify(|_, w| w.lsecsson().set_bit());
+        }
+
         #[cfg(any(feature = "l4", feature = "g4"))]
         rcc.ccipr
             .modify(|_, w| unsafe { w.clk48sel().bits(self.clk48_src as u8) });
@@ -861,6 +1046,67 @@ impl Clocks {
         rcc.ccipr1
             .modify(|_, w| unsafe { w.clk48msel().bits(self.clk48_src as u8) });
 
+        // `validate_speeds()` has already rejected sources a peripheral can't use.
+        // Fields that aren't configured keep their current value.
+        let kernel_bits = |periph, current| {
+            self.kernel_clocks
+                .get(periph)
+                .and_then(|src| kernel_clock_bits(periph, src))
+                .unwrap_or(current)
+        };
+
+        cfg_if! {
+            if #[cfg(feature = "l5")] {
+                rcc.ccipr1.modify(|r, w| unsafe {
+                    w.usart1sel().bits(kernel_bits(KernelClockPeriph::Usart1, r.usart1sel().bits()));
+                    w.usart2sel().bits(kernel_bits(KernelClockPeriph::Usart2, r.usart2sel().bits()));
+                    w.usart3sel().bits(kernel_bits(KernelClockPeriph::Usart3, r.usart3sel().bits()));
+                    w.lpuart1sel().bits(kernel_bits(KernelClockPeriph::Lpuart1, r.lpuart1sel().bits()));
+                    w.i2c1sel().bits(kernel_bits(KernelClockPeriph::I2c1, r.i2c1sel().bits()));
+                    w.i2c2sel().bits(kernel_bits(KernelClockPeriph::I2c2, r.i2c2sel().bits()));
+                    w.i2c3sel().bits(kernel_bits(KernelClockPeriph::I2c3, r.i2c3sel().bits()));
+                    w.lptim1sel().bits(kernel_bits(KernelClockPeriph::Lptim1, r.lptim1sel().bits()));
+                    w.lptim2sel().bits(kernel_bits(KernelClockPeriph::Lptim2, r.lptim2sel().bits()));
+                    w.adcsel().bits(kernel_bits(KernelClockPeriph::Adc, r.adcsel().bits()))
+                });
+            } else if #[cfg(feature = "g0")] {
+                rcc.ccipr.modify(|r, w| unsafe {
+                    #[cfg(any(feature = "g071", feature = "g081", feature = "g0b1", feature = "g0c1"))]
+                    w.usart2sel().bits(kernel_bits(KernelClockPeriph::Usart2, r.usart2sel().bits()));
+                    // Value line G0 variants don't have LPUART or LPTIM.
+                    #[cfg(not(any(feature = "g030", feature = "g050", feature = "g070", feature = "g0b0")))]
+                    w.lpuart1sel().bits(kernel_bits(KernelClockPeriph::Lpuart1, r.lpuart1sel().bits()));
+                    #[cfg(not(any(feature = "g030", feature = "g050", feature = "g070", feature = "g0b0")))]
+                    w.lptim1sel().bits(kernel_bits(KernelClockPeriph::Lptim1, r.lptim1sel().bits()));
+                    #[cfg(not(any(feature = "g030", feature = "g050", feature = "g070", feature = "g0b0")))]
+                    w.lptim2sel().bits(kernel_bits(KernelClockPeriph::Lptim2, r.lptim2sel().bits()));
+                    w.usart1sel().bits(kernel_bits(KernelClockPeriph::Usart1, r.usart1sel().bits()));
+                    w.i2c1sel().bits(kernel_bits(KernelClockPeriph::I2c1, r.i2c1sel().bits()));
+                    w.adcsel().bits(kernel_bits(KernelClockPeriph::Adc, r.adcsel().bits()))
+                });
+            } else {
+                rcc.ccipr.modify(|r, w| unsafe {
+                    #[cfg(not(feature = "wb"))]
+                    w.usart2sel().bits(kernel_bits(KernelClockPeriph::Usart2, r.usart2sel().bits()));
+                    #[cfg(not(any(feature = "wb", feature = "wl")))]
+                    w.usart3sel().bits(kernel_bits(KernelClockPeriph::Usart3, r.usart3sel().bits()));
+                    #[cfg(not(feature = "wb"))]
+                    w.i2c2sel().bits(kernel_bits(KernelClockPeriph::I2c2, r.i2c2sel().bits()));
+                    #[cfg(not(feature = "g4"))]
+                    w.lptim2sel().bits(kernel_bits(KernelClockPeriph::Lptim2, r.lptim2sel().bits()));
+                    #[cfg(feature = "g4")]
+                    w.adc12sel().bits(kernel_bits(KernelClockPeriph::Adc, r.adc12sel().bits()));
+                    #[cfg(not(feature = "g4"))]
+                    w.adcsel().bits(kernel_bits(KernelClockPeriph::Adc, r.adcsel().bits()));
+                    w.usart1sel().bits(kernel_bits(KernelClockPeriph::Usart1, r.usart1sel().bits()));
+                    w.lpuart1sel().bits(kernel_bits(KernelClockPeriph::Lpuart1, r.lpuart1sel().bits()));
+                    w.i2c1sel().bits(kernel_bits(KernelClockPeriph::I2c1, r.i2c1sel().bits()));
+                    w.i2c3sel().bits(kernel_bits(KernelClockPeriph::I2c3, r.i2c3sel().bits()));
+                    w.lptim1sel().bits(kernel_bits(KernelClockPeriph::Lptim1, r.lptim1sel().bits()))
+                });
+            }
+        }
+
         // Note that with this code setup, PLLSAI won't work properly unless using
         // the input source is PLL.
         if let InputSrc::Pll(pll_src) = self.input_src {
@@ -980,6 +1226,24 @@ impl Clocks {
             }
         }
 
+        // Select the system clock only once the PLL is locked with its new settings. If SW
+        // selected the PLL while it was still running with its old settings, PLLON couldn't be
+        // cleared above.
+        rcc.cfgr.modify(|_, w| unsafe {
+            w.sw().bits(self.input_src.bits());
+            w.hpre().bits(self.hclk_prescaler as u8);
+            #[cfg(not(feature = "g0"))]
+            w.ppre2().bits(self.apb2_prescaler as u8); // HCLK division for APB2.
+            #[cfg(any(feature = "l4", feature = "l5"))]
+            w.stopwuck().bit(self.stop_wuck as u8 != 0);
+            #[cfg(not(feature = "g0"))]
+            return w.ppre1().bits(self.apb1_prescaler as u8); // HCLK division for APB1
+            #[cfg(feature = "g0")]
+            return w.ppre().bits(self.apb1_prescaler as u8);
+        });
+        // Unused oscillators are turned off below, so wait for the switch to complete.
+        while rcc.cfgr.read().sws().bits() != self.input_src.bits() {}
+
         // Enable the HSI48 as required, which is used for USB, RNG, etc.
         // Only valid for some devices (On at least L4, and G4.)
         #[cfg(not(any(feature = "g0", feature = "wl")))]
@@ -1037,87 +1301,377 @@ impl Clocks {
         #[cfg(feature = "wb")]
         rcc.csr
             .modify(|_, w| unsafe { w.rfwkpsel().bits(self.rf_wakeup_src as u8) });
-
-        Ok(())
     }
 
-    /// Re-select input source; used after Stop and Standby modes, where the system reverts
-    /// to MSI or HSI after wake.
-    pub fn reselect_input(&self) {
+    /// Switch to a new clock configuration at runtime, eg to scale frequency up or down. Flash
+    /// wait states are raised before the clocks speed up, and lowered after they slow down. If
+    /// the PLL is the current system clock, HSI16 is used as the system clock while the PLL is
+    /// reconfigured. Returns an error without making changes if `new_cfg` is invalid.
+    /// Peripherals that derive timing from clock speeds must be updated after this; see
+    /// `Usart::retime`, `I2c::retime`, `Spi::retime`, and `Timer::retime`.
+    pub fn switch_to(&mut self, new_cfg: Self) -> Result<(), SpeedError> {
+        new_cfg.validate_speeds()?;
+
         let rcc = unsafe { &(*RCC::ptr()) };
+        let flash = unsafe { &(*FLASH::ptr()) };
 
-        // Re-select the input source; useful for changing input source, or reverting
-        // from stop or standby mode. This assumes we're on a clean init,
-        // or waking up from stop mode etc.
+        // Low-power run limits SYSCLK to 2Mhz.
+        #[cfg(any(feature = "l4", feature = "l5"))]
+        if new_cfg.sysclk() > 2_000_000 {
+            let pwr = unsafe { &(*pac::PWR::ptr()) };
+            if pwr.cr1.read().lpr().bit_is_set() {
+                crate::low_power::return_from_low_power_run();
+            }
+        }
 
-        match self.input_src {
-            InputSrc::Hse(_) => {
-                rcc.cr.modify(|_, w| w.hseon().set_bit());
-                while rcc.cr.read().hserdy().bit_is_clear() {}
+        #[cfg(feature = "g4")]
+        new_cfg.enable_boost_mode();
 
-                rcc.cfgr
-                    .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
-            }
-            InputSrc::Pll(pll_src) => {
-                // todo: DRY with above.
-                match pll_src {
-                    PllSrc::Hse(_) => {
-                        rcc.cr.modify(|_, w| w.hseon().set_bit());
-                        while rcc.cr.read().hserdy().bit_is_clear() {}
-                    }
-                    PllSrc::Hsi => {
-                        #[cfg(any(feature = "l4", feature = "l5"))]
-                        // Generally reverts to MSI (see note below)
-                        if let StopWuck::Msi = self.stop_wuck {
-                            rcc.cr.modify(|_, w| w.hsion().set_bit());
-                            while rcc.cr.read().hsirdy().bit_is_clear() {}
-                        }
-                        // If on G, we'll already be on HSI, so need to take action.
-                    }
-                    #[cfg(not(any(feature = "g0", feature = "g4")))]
-                    PllSrc::Msi(range) => {
-                        // Range initializes to 4Mhz, so set that as well.
-                        #[cfg(not(feature = "wb"))]
-                        rcc.cr.modify(|_, w| unsafe {
-                            w.msirange().bits(range as u8);
-                            w.msirgsel().set_bit()
-                        });
-                        #[cfg(feature = "wb")]
-                        rcc.cr
-                            .modify(|_, w| unsafe { w.msirange().bits(range as u8) });
+        #[cfg(feature = "l4")]
+        if new_cfg.voltage_range == VoltageRange::Range1 {
+            new_cfg.set_voltage_range();
+        }
 
-                        if let StopWuck::Hsi = self.stop_wuck {
-                            rcc.cr.modify(|_, w| w.msion().set_bit());
+        let wait_states_prev = flash.acr.read().latency().bits();
+        let wait_states = new_cfg.wait_states() as u8;
+        let from_pll = matches!(self.input_src, InputSrc::Pll(_));
+
+        for step in switch_steps(from_pll, wait_states_prev, wait_states) {
+            match step {
+                SwitchStep::RaiseWaitStates => {
+                    flash
+                        .acr
+                        .modify(|_, w| unsafe { w.latency().bits(wait_states) });
+                    // Make sure the new setting is in effect before increasing speed.
+                    while flash.acr.read().latency().bits() != wait_states {}
+                }
+                SwitchStep::SysclkToInternal => {
+                    rcc.cr.modify(|_, w| w.hsion().set_bit());
+                    while rcc.cr.read().hsirdy().bit_is_clear() {}
 
-                            while rcc.cr.read().msirdy().bit_is_clear() {}
-                        }
-                    }
-                    PllSrc::None => (),
+                    rcc.cfgr
+                        .modify(|_, w| unsafe { w.sw().bits(InputSrc::Hsi.bits()) });
+                    while rcc.cfgr.read().sws().bits() != InputSrc::Hsi.bits() {}
+                }
+                SwitchStep::StopPll => {
+                    rcc.cr.modify(|_, w| w.pllon().clear_bit());
+                    while rcc.cr.read().pllrdy().bit_is_set() {}
                 }
+                SwitchStep::Configure => {
+                    new_cfg.configure();
+                    // Don't lower wait states until the switch to the new system clock is complete.
+                    while rcc.cfgr.read().sws().bits() != new_cfg.input_src.bits() {}
+                }
+                SwitchStep::LowerWaitStates => {
+                    flash
+                        .acr
+                        .modify(|_, w| unsafe { w.latency().bits(wait_states) });
+                }
+            }
+        }
 
-                rcc.cr.modify(|_, w| w.pllon().clear_bit());
-                while rcc.cr.read().pllrdy().bit_is_set() {}
+        #[cfg(feature = "l4")]
+        if new_cfg.voltage_range == VoltageRange::Range2 {
+            new_cfg.set_voltage_range();
+        }
 
-                rcc.cfgr
-                    .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+        *self = new_cfg;
 
-                rcc.cr.modify(|_, w| w.pllon().set_bit());
-                while rcc.cr.read().pllrdy().bit_is_clear() {}
-            }
-            InputSrc::Hsi => {
-                {
-                    // (This note applies to L4 and L5 only)
-                    // From L4 Reference Manual, RCC_CFGR register section:
-                    // "Configured by HW to force MSI oscillator selection when exiting Standby or Shutdown mode.
-                    // Configured by HW to force MSI or HSI16 oscillator selection when exiting Stop mode or in
-                    // case of failure of the HSE oscillator, depending on STOPWUCK value."
+        Ok(())
+    }
 
-                    // So, if stopwuck is at its default value of MSI, we need to re-enable HSI,
-                    // and re-select it. Otherwise, take no action. Reverse for MSI-reselection.
-                    // For G, we already are using HSI, so need to take action either.
-                    #[cfg(not(any(feature = "g0", feature = "g4")))]
-                    if let StopWuck::Msi = self.stop_wuck {
-                        rcc.cr.modify(|_, w| w.hsion().set_bit());
+    /// Reconfigure clocks after an HSE failure, so they no longer use the HSE. The PLL is kept
+    /// at the same SYSCLK if it can be, using the HSI16 as its source. (Or on L4, L5, WB, and WL,
+    /// the oscillator selected by `stop_wuck`.) Otherwise, that oscillator is used directly as
+    /// SYSCLK. Takes no action if the HSE isn't used. Returns the new SYSCLK frequency in Hz;
+    /// peripherals should be re-timed, as after `switch_to()`.
+    pub fn fail_over(&mut self) -> Result<u32, SpeedError> {
+        match self.input_src {
+            InputSrc::Hse(_) | InputSrc::Pll(PllSrc::Hse(_)) => (),
+            _ => return Ok(self.sysclk()),
+        }
+
+        cfg_if! {
+            if #[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))] {
+                let (input_src, pll_src) = match self.stop_wuck {
+                    StopWuck::Msi => (InputSrc::Msi(MsiRange::R4M), PllSrc::Msi(MsiRange::R4M)),
+                    StopWuck::Hsi => (InputSrc::Hsi, PllSrc::Hsi),
+                };
+            } else {
+                let (input_src, pll_src) = (InputSrc::Hsi, PllSrc::Hsi);
+            }
+        }
+
+        if let InputSrc::Pll(_) = self.input_src {
+            if let Ok(solved) = Self::from_target(pll_src, self.sysclk(), self.pll.pllq_en) {
+                let with_pll = Self {
+                    input_src: solved.input_src,
+                    pll: solved.pll,
+                    ..self.clone()
+                };
+                // `switch_to` makes no changes if the config is invalid, eg due to prescalers.
+                if self.switch_to(with_pll).is_ok() {
+                    return Ok(self.sysclk());
+                }
+            }
+        }
+
+        self.switch_to(Self {
+            input_src,
+            ..self.clone()
+        })?;
+
+        Ok(self.sysclk())
+    }
+
+    /// The flash wait states required for this config's HCLK.
+    fn wait_states(&self) -> WaitState {
+        let sysclk = self.sysclk();
+
+        cfg_if! {
+            if #[cfg(feature = "wb")] {
+                let hclk = sysclk / self.hclk4_prescaler.value() as u32;
+            } else if #[cfg(feature = "wl")] {
+                let hclk = sysclk / self.hclk3_prescaler.value() as u32;
+            } else {
+                let hclk = sysclk / self.hclk_prescaler.value() as u32;
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "l4")] {  // RM section 3.3.3
+                let wait_states = if self.voltage_range == VoltageRange::Range2 {
+                    if hclk <= 6_000_000 {
+                        WaitState::W0
+                    } else if hclk <= 12_000_000 {
+                        WaitState::W1
+                    } else if hclk <= 18_000_000 {
+                        WaitState::W2
+                    } else {
+                        WaitState::W3
+                    }
+                } else if hclk <= 16_000_000 {
+                    WaitState::W0
+                } else if hclk <= 32_000_000 {
+                    WaitState::W1
+                } else if hclk <= 48_000_000 {
+                    WaitState::W2
+                } else if hclk <= 64_000_000 {
+                    WaitState::W3
+                } else {
+                    WaitState::W4
+                };
+            } else if #[cfg(feature = "l5")] {  // RM section 6.3.3
+                let wait_states = if hclk <= 20_000_000 {
+                    WaitState::W0
+                } else if hclk <= 40_000_000 {
+                    WaitState::W1
+                } else if hclk <= 60_000_000 {
+                    WaitState::W2
+                } else if hclk <= 80_000_000 {
+                    WaitState::W3
+                } else if hclk <= 100_000_000 {
+                    WaitState::W4
+                } else {
+                    WaitState::W5
+                };
+            } else if #[cfg(feature = "g0")] {  // G0. RM section 3.3.4
+                let wait_states = if hclk <= 24_000_000 {
+                    WaitState::W0
+                } else if hclk <= 48_000_000 {
+                    WaitState::W1
+                } else {
+                    WaitState::W2
+                };
+            } else if #[cfg(feature = "wb")] {  // WB. RM section 3.3.4, Table 4.
+            // Note: This applies to HCLK4 HCLK. (See HCLK4 used above for hclk var.)
+                let wait_states = if hclk <= 18_000_000 {
+                    WaitState::W0
+                } else if hclk <= 36_000_000 {
+                    WaitState::W1
+                } else if hclk <= 54_000_000 {
+                    WaitState::W2
+                } else {
+                    WaitState::W3
+                };
+            } else if #[cfg(any(feature = "wb", feature = "wl"))] {  // WL. RM section 3.3.4, Table 5.
+            // Note: This applies to HCLK3 HCLK. (See HCLK3 used above for hclk var.)
+                let wait_states = if hclk <= 18_000_000 {
+                    WaitState::W0
+                } else if hclk <= 36_000_000 {
+                    WaitState::W1
+                } else {
+                    WaitState::W2
+                };
+            } else {  // G4. RM section 3.3.3
+                let wait_states = if self.boost_mode {
+                    // Vcore Range 1 boost mode
+                    if hclk <= 34_000_000 {
+                        WaitState::W0
+                    } else if hclk <= 68_000_000 {
+                        WaitState::W1
+                    } else if hclk <= 102_000_000 {
+                        WaitState::W2
+                    } else if hclk <= 136_000_000 {
+                        WaitState::W3
+                    } else {
+                        WaitState::W4
+                    }
+                } else {
+                    // Vcore Range 1 normal mode.
+                    if hclk <= 30_000_000 {
+                        WaitState::W0
+                    } else if hclk <= 60_000_000 {
+                        WaitState::W1
+                    } else if hclk <= 90_000_000 {
+                        WaitState::W2
+                    } else if hclk <= 120_000_000 {
+                        WaitState::W3
+                    } else {
+                        WaitState::W4
+                    }
+                };
+            }
+        }
+
+        wait_states
+    }
+
+    #[cfg(feature = "g4")]
+    /// Start the switch to Range 1 boost mode, if `boost_mode` is set.
+    fn enable_boost_mode(&self) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        if self.boost_mode {
+            // The sequence to switch from Range1 normal mode to Range1 boost mode is:
+            // 1. The system clock must be divided by 2 using the AHB prescaler before switching to a
+            // higher system frequency.
+            rcc.cfgr
+                .modify(|_, w| unsafe { w.hpre().bits(HclkPrescaler::Div2 as u8) });
+            // 2. Clear the R1MODE bit is in the PWR_CR5 register.
+            let pwr = unsafe { &(*pac::PWR::ptr()) };
+            pwr.cr5.modify(|_, w| w.r1mode().clear_bit());
+        }
+
+        // (Remaining steps accomplished below)
+        // 3. Adjust the number of wait states according to the new frequency target in range1 boost
+        // mode
+        // 4. Configure and switch to new system frequency.
+        // 5. Wait for at least 1us and then reconfigure the AHB prescaler to get the needed HCLK
+        // clock frequency.
+    }
+
+    #[cfg(feature = "l4")]
+    /// Set the voltage scaling range, and wait for the regulator to reach it.
+    fn set_voltage_range(&self) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        let pwr = unsafe { &(*pac::PWR::ptr()) };
+
+        rcc.apb1enr1.modify(|_, w| w.pwren().set_bit());
+        pwr.cr1
+            .modify(|_, w| unsafe { w.vos().bits(self.voltage_range as u8) });
+        while pwr.sr2.read().vosf().bit_is_set() {}
+    }
+
+    /// The maximum SYSCLK, HCLK, APB, and PLL output frequency for this config. On L4, this
+    /// depends on the voltage range.
+    fn max_sysclk(&self) -> u32 {
+        #[cfg(feature = "l4")]
+        if self.voltage_range == VoltageRange::Range2 {
+            return 26_000_000;
+        }
+
+        MAX_SYSCLK
+    }
+
+    /// The maximum PLL VCO output frequency for this config. On L4, this depends on the voltage
+    /// range.
+    fn vco_out_max(&self) -> u32 {
+        #[cfg(feature = "l4")]
+        if self.voltage_range == VoltageRange::Range2 {
+            return 128_000_000;
+        }
+
+        VCO_OUT_MAX
+    }
+
+    /// Re-select input source; used after Stop and Standby modes, where the system reverts
+    /// to MSI or HSI after wake.
+    pub fn reselect_input(&self) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        // Re-select the input source; useful for changing input source, or reverting
+        // from stop or standby mode. This assumes we're on a clean init,
+        // or waking up from stop mode etc.
+
+        match self.input_src {
+            InputSrc::Hse(_) => {
+                rcc.cr.modify(|_, w| w.hseon().set_bit());
+                while rcc.cr.read().hserdy().bit_is_clear() {}
+
+                rcc.cfgr
+                    .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+            }
+            InputSrc::Pll(pll_src) => {
+                // todo: DRY with above.
+                match pll_src {
+                    PllSrc::Hse(_) => {
+                        rcc.cr.modify(|_, w| w.hseon().set_bit());
+                        while rcc.cr.read().hserdy().bit_is_clear() {}
+                    }
+                    PllSrc::Hsi => {
+                        #[cfg(any(feature = "l4", feature = "l5"))]
+                        // Generally reverts to MSI (see note below)
+                        if let StopWuck::Msi = self.stop_wuck {
+                            rcc.cr.modify(|_, w| w.hsion().set_bit());
+                            while rcc.cr.read().hsirdy().bit_is_clear() {}
+                        }
+                        // If on G, we'll already be on HSI, so need to take action.
+                    }
+                    #[cfg(not(any(feature = "g0", feature = "g4")))]
+                    PllSrc::Msi(range) => {
+                        // Range initializes to 4Mhz, so set that as well.
+                        #[cfg(not(feature = "wb"))]
+                        rcc.cr.modify(|_, w| unsafe {
+                            w.msirange().bits(range as u8);
+                            w.msirgsel().set_bit()
+                        });
+                        #[cfg(feature = "wb")]
+                        rcc.cr
+                            .modify(|_, w| unsafe { w.msirange().bits(range as u8) });
+
+                        if let StopWuck::Hsi = self.stop_wuck {
+                            rcc.cr.modify(|_, w| w.msion().set_bit());
+
+                            while rcc.cr.read().msirdy().bit_is_clear() {}
+                        }
+                    }
+                    PllSrc::None => (),
+                }
+
+                rcc.cr.modify(|_, w| w.pllon().clear_bit());
+                while rcc.cr.read().pllrdy().bit_is_set() {}
+
+                rcc.cfgr
+                    .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+
+                rcc.cr.modify(|_, w| w.pllon().set_bit());
+                while rcc.cr.read().pllrdy().bit_is_clear() {}
+            }
+            InputSrc::Hsi => {
+                {
+                    // (This note applies to L4 and L5 only)
+                    // From L4 Reference Manual, RCC_CFGR register section:
+                    // "Configured by HW to force MSI oscillator selection when exiting Standby or Shutdown mode.
+                    // Configured by HW to force MSI or HSI16 oscillator selection when exiting Stop mode or in
+                    // case of failure of the HSE oscillator, depending on STOPWUCK value."
+
+                    // So, if stopwuck is at its default value of MSI, we need to re-enable HSI,
+                    // and re-select it. Otherwise, take no action. Reverse for MSI-reselection.
+                    // For G, we already are using HSI, so need to take action either.
+                    #[cfg(not(any(feature = "g0", feature = "g4")))]
+                    if let StopWuck::Msi = self.stop_wuck {
+                        rcc.cr.modify(|_, w| w.hsion().set_bit());
                         while rcc.cr.read().hsirdy().bit_is_clear() {}
 
                         rcc.cfgr
@@ -1165,9 +1719,8 @@ impl Clocks {
     #[cfg(any(feature = "l4", feature = "l5"))]
     /// Use this to change the MSI speed. Run this only if your clock source is MSI.
     /// Ends in a state with MSI on at the new speed, and HSI off.
+    /// If `msi_pll_mode` is set, the MSI stays locked to the LSE at the new speed.
     pub fn change_msi_speed(&mut self, range: MsiRange) {
-        // todo: Calibrate MSI with LSE / HSE(?) if avail?
-
         let rcc = unsafe { &(*RCC::ptr()) };
 
         match self.input_src {
@@ -1288,17 +1841,21 @@ impl Clocks {
             pub fn usb(&self) -> u32 {
                 unimplemented!("No USB on G0 or WL");
             }
-        } else if #[cfg(feature = "g4")] {
-            pub fn usb(&self) -> u32 {
-                48_000_000 // Uses hsi48.
-            }
-        } else { // L4 and L5
+        } else {
+            /// Get the USB (CLK48) frequency, in hz
             pub fn usb(&self) -> u32 {
+                let pll_in = self.pll_src_freq().unwrap_or(0) / self.pll.divm.value() as u32;
+
                 match self.clk48_src {
                     Clk48Src::Hsi48 => 48_000_000,
-                    Clk48Src::PllSai1 => unimplemented!(),
-                    Clk48Src::Pllq => unimplemented!(),
-                    Clk48Src::Msi => unimplemented!(),
+                    #[cfg(not(feature = "g4"))]
+                    Clk48Src::PllSai1 => {
+                        pll_in * self.pllsai1.divn as u32 / self.pllsai1.divq.value() as u32
+                    }
+                    Clk48Src::Pllq => pll_in * self.pll.divn as u32 / self.pll.divq.value() as u32,
+                    // Set to 48Mhz by `enable_msi_48()`.
+                    #[cfg(not(feature = "g4"))]
+                    Clk48Src::Msi => 48_000_000,
                 }
             }
         }
@@ -1383,90 +1940,692 @@ impl Clocks {
         }
     }
 
+    /// Get the frequency output on the MCO pin by `mco`, in hz. `None` if this config doesn't
+    /// determine its source's speed; see the `clocks` module documentation.
+    pub fn mco_speed(&self, mco: &Mco) -> Option<u32> {
+        let src_freq = match mco.src {
+            McoSrc::Sysclk => Some(self.sysclk()),
+            #[cfg(not(any(feature = "g0", feature = "g4")))]
+            McoSrc::Msi => match self.input_src {
+                InputSrc::Msi(range) | InputSrc::Pll(PllSrc::Msi(range)) => Some(range.value()),
+                _ => None,
+            },
+            McoSrc::Hsi => Some(16_000_000),
+            McoSrc::Hse => match self.input_src {
+                InputSrc::Hse(freq) | InputSrc::Pll(PllSrc::Hse(freq)) => Some(freq),
+                _ => None,
+            },
+            McoSrc::Pllr => self.pll_speeds(&self.pll).and_then(|s| s.r),
+            McoSrc::Lsi => Some(32_000),
+            McoSrc::Lse => Some(32_768),
+            #[cfg(any(
+                feature = "l4",
+                feature = "l5",
+                feature = "g4",
+                feature = "wb",
+                feature = "g0b1",
+                feature = "g0c1"
+            ))]
+            McoSrc::Hsi48 => Some(48_000_000),
+            #[cfg(feature = "wl")]
+            McoSrc::Pllp => self.pll_speeds(&self.pll).and_then(|s| s.p),
+            #[cfg(feature = "wl")]
+            McoSrc::Pllq => self.pll_speeds(&self.pll).and_then(|s| s.q),
+        };
+
+        src_freq.map(|freq| freq / mco.prescaler.value())
+    }
+
+    /// Get the kernel clock frequency of a peripheral, in hz, as selected in `kernel_clocks`.
+    pub fn kernel_speed(&self, periph: KernelClockPeriph) -> u32 {
+        match self.kernel_clocks.source(periph) {
+            KernelClock::Apb => match periph {
+                KernelClockPeriph::Usart1 => self.apb2(),
+                _ => self.apb1(),
+            },
+            KernelClock::Sysclk => self.sysclk(),
+            KernelClock::Hsi => 16_000_000,
+            KernelClock::Lse => 32_768,
+            KernelClock::Lsi => 32_000,
+        }
+    }
+
+    /// The PLL source frequency, in hz, prior to PLLM. `None` if SYSCLK isn't driven by the PLL.
+    fn pll_src_freq(&self) -> Option<u32> {
+        match self.input_src {
+            InputSrc::Pll(pll_src) => match pll_src {
+                #[cfg(not(any(feature = "g0", feature = "g4")))]
+                PllSrc::Msi(range) => Some(range.value()),
+                PllSrc::Hsi => Some(16_000_000),
+                PllSrc::Hse(freq) => Some(freq),
+                PllSrc::None => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Check a PLL's dividers, VCO input and output speeds, and enabled outputs. `pll_num` is 1
+    /// for the main PLL, 2 for PLLSAI1, and 3 for PLLSAI2. All PLLs use the main PLL's PLLM.
+    fn validate_pll(&self, pll_num: u8, cfg: &PllCfg, input_freq: u32) -> Result<(), SpeedError> {
+        if cfg.divn < PLLN_MIN || cfg.divn > PLLN_MAX {
+            return Err(SpeedError::PllDividerOutOfRange {
+                pll: pll_num,
+                divider: PllDivider::N,
+                value: cfg.divn as u16,
+                min: PLLN_MIN as u16,
+                max: PLLN_MAX as u16,
+            });
+        }
+
+        // A `pdiv` of 0 selects the `divp` setting instead.
+        if cfg.pdiv == 1 || cfg.pdiv > 31 {
+            return Err(SpeedError::PllDividerOutOfRange {
+                pll: pll_num,
+                divider: PllDivider::P,
+                value: cfg.pdiv as u16,
+                min: 2,
+                max: 31,
+            });
+        }
+
+        let vco_in = input_freq / self.pll.divm.value() as u32;
+        if vco_in < VCO_IN_MIN || vco_in > VCO_IN_MAX {
+            return Err(SpeedError::VcoInputOutOfRange {
+                pll: pll_num,
+                freq: vco_in,
+                min: VCO_IN_MIN,
+                max: VCO_IN_MAX,
+            });
+        }
+
+        let vco_out = vco_in * cfg.divn as u32;
+        let vco_out_max = self.vco_out_max();
+        if vco_out < VCO_OUT_MIN || vco_out > vco_out_max {
+            return Err(SpeedError::VcoOutputOutOfRange {
+                pll: pll_num,
+                freq: vco_out,
+                min: VCO_OUT_MIN,
+                max: vco_out_max,
+            });
+        }
+
+        let outputs = [
+            (cfg.pllp_en, PllDivider::P, cfg.pvalue()),
+            (cfg.pllq_en, PllDivider::Q, cfg.divq.value()),
+            (cfg.pllr_en, PllDivider::R, cfg.divr.value()),
+        ];
+
+        let max = self.max_sysclk();
+        for (enabled, output, div) in outputs.iter() {
+            let freq = vco_out / *div as u32;
+            if *enabled && freq > max {
+                return Err(SpeedError::PllOutputOverMax {
+                    pll: pll_num,
+                    output: *output,
+                    freq,
+                    max,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that every PLL, bus and kernel clock this struct configures is within limits.
     pub fn validate_speeds(&self) -> Result<(), SpeedError> {
-        #[cfg(feature = "l4")]
-        let max_clock = 80_000_000;
+        // todo: L4+ (ie R, S, P, Q) can go up to 120_000.
 
-        #[cfg(feature = "l5")]
-        let max_clock = 110_000_000;
+        // Note that PLLSAI won't run unless the input source is PLL; see `setup()`.
+        if let Some(input_freq) = self.pll_src_freq() {
+            self.validate_pll(1, &self.pll, input_freq)?;
 
-        #[cfg(feature = "g0")]
-        let max_clock = 64_000_000;
+            #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
+            if self.pllsai1.enabled {
+                self.validate_pll(2, &self.pllsai1, input_freq)?;
+            }
 
-        #[cfg(feature = "g4")]
-        let max_clock = 170_000_000;
+            #[cfg(any(feature = "l4x5", feature = "l4x6"))]
+            if self.pllsai2.enabled {
+                self.validate_pll(3, &self.pllsai2, input_freq)?;
+            }
+        }
 
+        let max_sysclk = self.max_sysclk();
+        let sysclk = self.sysclk();
+        if sysclk > max_sysclk {
+            return Err(SpeedError::SysclkOverMax {
+                freq: sysclk,
+                max: max_sysclk,
+            });
+        }
+
+        let hclk = self.hclk();
+        if hclk > max_sysclk {
+            return Err(SpeedError::HclkOverMax {
+                bus: 1,
+                freq: hclk,
+                max: max_sysclk,
+            });
+        }
+
+        // CPU2 runs from HCLK2, and flash and shared memory from HCLK4.
         #[cfg(feature = "wb")]
-        let max_clock = 64_000_000;
+        {
+            let hclk2 = sysclk / self.hclk2_prescaler.value() as u32;
+            if hclk2 > 32_000_000 {
+                return Err(SpeedError::HclkOverMax {
+                    bus: 2,
+                    freq: hclk2,
+                    max: 32_000_000,
+                });
+            }
 
+            let hclk4 = sysclk / self.hclk4_prescaler.value() as u32;
+            if hclk4 > max_sysclk {
+                return Err(SpeedError::HclkOverMax {
+                    bus: 4,
+                    freq: hclk4,
+                    max: max_sysclk,
+                });
+            }
+        }
+
+        // Flash and shared memory run from HCLK3.
         #[cfg(feature = "wl")]
-        let max_clock = 48_000_000;
+        {
+            let hclk3 = sysclk / self.hclk3_prescaler.value() as u32;
+            if hclk3 > max_sysclk {
+                return Err(SpeedError::HclkOverMax {
+                    bus: 3,
+                    freq: hclk3,
+                    max: max_sysclk,
+                });
+            }
+        }
 
-        // todo: Check valid PLL output range as well. You can use Cube, mousing over the PLL
-        // todo speed to find these.
+        // G4 RM, Table 9: Range 1 normal mode supports up to 150Mhz, with 4 wait states.
+        #[cfg(feature = "g4")]
+        if !self.boost_mode && hclk > 150_000_000 {
+            return Err(SpeedError::WaitStatesInsufficient {
+                hclk,
+                max: 150_000_000,
+            });
+        }
 
-        // todo: L4+ (ie R, S, P, Q) can go up to 120_000.
+        let apb1 = self.apb1();
+        if apb1 > max_sysclk {
+            return Err(SpeedError::ApbOverMax {
+                bus: 1,
+                freq: apb1,
+                max: max_sysclk,
+            });
+        }
 
-        #[cfg(any(feature = "l4", feature = "l5", feature = "wb"))]
-        if self.pll.divn < 7
-            || self.pll.divn > 86
-            || self.pllsai1.divn < 7
-            || self.pllsai1.divn > 86
+        #[cfg(not(feature = "g0"))]
         {
-            return Err(SpeedError::new("A PLL divider is out of limits"));
+            let apb2 = self.apb2();
+            if apb2 > max_sysclk {
+                return Err(SpeedError::ApbOverMax {
+                    bus: 2,
+                    freq: apb2,
+                    max: max_sysclk,
+                });
+            }
         }
 
-        if self.pll.pdiv == 1 {
-            return Err(SpeedError::new("A Pllp divider is invalid"));
+        // Only check USB if it's driven by an enabled PLL output; HSI48 and MSI are fixed at 48Mhz.
+        #[cfg(not(any(feature = "g0", feature = "wl")))]
+        {
+            let usb_from_pll = match self.clk48_src {
+                Clk48Src::Pllq => self.pll.pllq_en,
+                #[cfg(not(feature = "g4"))]
+                Clk48Src::PllSai1 => self.pllsai1.enabled && self.pllsai1.pllq_en,
+                _ => false,
+            };
+
+            if usb_from_pll && self.pll_src_freq().is_some() {
+                let usb = self.usb();
+                if usb != 48_000_000 {
+                    return Err(SpeedError::UsbNot48Mhz { freq: usb });
+                }
+            }
         }
 
-        #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
-        if self.pllsai1.pdiv == 1 {
-            return Err(SpeedError::new("A Pllp divider is invalid"));
+        for periph in KERNEL_CLOCK_PERIPHS.iter() {
+            if let Some(src) = self.kernel_clocks.get(*periph) {
+                if kernel_clock_bits(*periph, src).is_none() {
+                    return Err(SpeedError::InvalidKernelClock {
+                        periph: *periph,
+                        src,
+                    });
+                }
+            }
         }
 
-        #[cfg(any(feature = "l4x5", feature = "l4x6"))]
-        if self.pllsai2.pdiv == 1 {
-            return Err(SpeedError::new("A Pllp divider is invalid"));
+        Ok(())
+    }
+
+    /// Create a config that runs SYSCLK from the PLL at `sysclk` Hz, by searching the PLLM, PLLN
+    /// and PLLR values available on this MCU, within its VCO input and output ranges. If
+    /// `usb_48mhz_required` is true, PLLQ is also set to 48Mhz, and selected as the CLK48 source.
+    /// Lower PLLM values are tried first, since a higher VCO input frequency reduces jitter.
+    /// Other fields are left at their `Default` values; modify as required, then run `setup()`.
+    pub fn from_target(
+        pll_src: PllSrc,
+        sysclk: u32,
+        usb_48mhz_required: bool,
+    ) -> Result<Self, PllSolveError> {
+        let input_freq = match pll_src {
+            #[cfg(not(any(feature = "g0", feature = "g4")))]
+            PllSrc::Msi(range) => range.value(),
+            PllSrc::Hsi => 16_000_000,
+            PllSrc::Hse(freq) => freq,
+            PllSrc::None => return Err(PllSolveError::NoPllSource),
+        };
+
+        if sysclk > MAX_SYSCLK {
+            return Err(PllSolveError::AboveMax {
+                requested: sysclk,
+                max: MAX_SYSCLK,
+            });
         }
 
-        #[cfg(feature = "g0")]
-        if self.pll.divn < 9 || self.pll.divn > 86 {
-            return Err(SpeedError::new("A PLL divider is out of limits"));
+        // No USB on G0 or WL.
+        #[cfg(any(feature = "g0", feature = "wl"))]
+        if usb_48mhz_required {
+            return Err(PllSolveError::Usb48Unreachable);
         }
 
-        #[cfg(feature = "g4")]
-        if self.pll.divn < 8 || self.pll.divn > 127 {
-            return Err(SpeedError::new("A PLL divider is out of limits"));
+        let mut vco_in_reachable = false;
+        let mut usb_unreachable = false;
+        let mut closest = 0;
+
+        for divm in PLLM_DIVS.iter() {
+            let vco_in = input_freq / divm.value() as u32;
+            if vco_in < VCO_IN_MIN || vco_in > VCO_IN_MAX {
+                continue;
+            }
+            vco_in_reachable = true;
+
+            for divn in PLLN_MIN..=PLLN_MAX {
+                let vco_out = vco_in * divn as u32;
+                if vco_out < VCO_OUT_MIN || vco_out > VCO_OUT_MAX {
+                    continue;
+                }
+
+                for divr in PLLR_DIVS.iter() {
+                    // Matches the calculation in `sysclk()`.
+                    let freq = vco_out / divr.value() as u32;
+                    if freq != sysclk {
+                        if freq < sysclk && freq > closest {
+                            closest = freq;
+                        }
+                        continue;
+                    }
+
+                    let divq = if usb_48mhz_required {
+                        PLLR_DIVS
+                            .iter()
+                            .find(|divq| vco_out / divq.value() as u32 == 48_000_000)
+                    } else {
+                        None
+                    };
+
+                    if usb_48mhz_required && divq.is_none() {
+                        usb_unreachable = true;
+                        continue;
+                    }
+
+                    return Ok(Self {
+                        input_src: InputSrc::Pll(pll_src),
+                        pll: PllCfg {
+                            pllq_en: divq.is_some(),
+                            divm: *divm,
+                            divn,
+                            divr: *divr,
+                            divq: divq.copied().unwrap_or(Pllr::Div4),
+                            ..Default::default()
+                        },
+                        #[cfg(not(any(feature = "g0", feature = "wl")))]
+                        clk48_src: if divq.is_some() {
+                            Clk48Src::Pllq
+                        } else {
+                            Clk48Src::Hsi48
+                        },
+                        ..Default::default()
+                    });
+                }
+            }
         }
 
-        // todo: on WB, input src / PlLM * plln Must be between 96 and 344 Mhz.
-        // todo; Cube will validate this. Others probably have a similar restriction.
-        // todo: Put this check here.
+        if !vco_in_reachable {
+            Err(PllSolveError::VcoInputUnreachable {
+                src_freq: input_freq,
+            })
+        } else if usb_unreachable {
+            Err(PllSolveError::Usb48Unreachable)
+        } else {
+            Err(PllSolveError::NoSolution { closest })
+        }
+    }
 
-        // todo: QC these limits
-        // todo: Note that this involves repeatedly calculating sysclk.
-        // todo. We could work around thsi by calcing it once here.
-        if self.sysclk() > max_clock {
-            return Err(SpeedError::new("Sysclk out of limits"));
+    /// Calculate every clock speed this config produces, eg for logging. Use with
+    /// `Clocks::read_active()` to report the speeds the MCU is actually running at. This doesn't
+    /// read registers; `rtc` is `None`. See `rtc_clock()`.
+    pub fn report(&self) -> ClockReport {
+        cfg_if! {
+            if #[cfg(any(feature = "g0", feature = "wl"))] {
+                let usb = None;
+            } else {
+                let usb = Some(self.usb());
+            }
         }
 
-        // todo: What are the actual hclk limits? Not always sysclk?
+        cfg_if! {
+            if #[cfg(any(feature = "g0", feature = "g4", feature = "wl"))] {
+                let sai1 = None;
+            } else {
+                let sai1 = match self.sai1_src {
+                    SaiSrc::ExtClk => None,
+                    _ => Some(self.sai1_speed()),
+                };
+            }
+        }
 
-        if self.hclk() > max_clock {
-            return Err(SpeedError::new("Hclk out of limits"));
+        ClockReport {
+            sysclk: self.sysclk(),
+            hclk: self.hclk(),
+            systick: self.systick(),
+            apb1: self.apb1(),
+            apb1_timer: self.apb1_timer(),
+            apb2: self.apb2(),
+            apb2_timer: self.apb2_timer(),
+            apb3: None,
+            apb4: None,
+            usb,
+            sai1,
+            adc: Some(self.kernel_speed(KernelClockPeriph::Adc)),
+            plls: [
+                self.pll_speeds(&self.pll),
+                #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
+                self.pll_speeds(&self.pllsai1),
+                #[cfg(any(feature = "g0", feature = "g4", feature = "wl"))]
+                None,
+                #[cfg(any(feature = "l4x5", feature = "l4x6"))]
+                self.pll_speeds(&self.pllsai2),
+                #[cfg(not(any(feature = "l4x5", feature = "l4x6")))]
+                None,
+            ],
+            rtc: None,
         }
+    }
+
+    /// Read the RTC clock source from the RCC BDCR register, and calculate its speed, using this
+    /// config's HSE frequency. The RTC clock is set up by `Rtc::new()`, so `report()` doesn't
+    /// include it; eg set `ClockReport::rtc` with this.
+    pub fn rtc_clock(&self) -> Option<(RtcClockSource, u32)> {
+        let hse_freq = match self.input_src {
+            InputSrc::Hse(freq) | InputSrc::Pll(PllSrc::Hse(freq)) => freq,
+            _ => 0,
+        };
 
-        if self.apb1() > max_clock {
-            return Err(SpeedError::new("Apb1 out of limits"));
+        rtc_clock(hse_freq)
+    }
+
+    /// Output speeds of an enabled PLL. `None` if it isn't running. All PLLs use the main PLL's
+    /// source and PLLM.
+    fn pll_speeds(&self, cfg: &PllCfg) -> Option<PllSpeeds> {
+        let input_freq = self.pll_src_freq()?;
+        if !cfg.enabled {
+            return None;
         }
 
-        #[cfg(not(feature = "g0"))]
-        if self.apb2() > max_clock {
-            return Err(SpeedError::new("Apb2 out of limits"));
+        let vco_out = input_freq / self.pll.divm.value() as u32 * cfg.divn as u32;
+        let output = |enabled, div: u8| {
+            if enabled {
+                Some(vco_out / div as u32)
+            } else {
+                None
+            }
+        };
+
+        Some(PllSpeeds {
+            p: output(cfg.pllp_en, cfg.pvalue()),
+            q: output(cfg.pllq_en, cfg.divq.value()),
+            r: output(cfg.pllr_en, cfg.divr.value()),
+        })
+    }
+
+    /// Read the active clock configuration from RCC registers, eg to find what a bootloader set
+    /// up, or to check speeds with `Clocks::read_active(hse_freq).report()`. `hse_freq` is in Hz;
+    /// it can't be read from registers, and is ignored if the HSE isn't used. The input source,
+    /// main PLL, bus prescalers, and oscillator settings are read; other fields keep their
+    /// `Default` values.
+    pub fn read_active(hse_freq: u32) -> Self {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        let cr = rcc.cr.read();
+        let cfgr = rcc.cfgr.read();
+
+        // After waking from Standby, the MSI range is set by MSISRANGE until MSIRGSEL is set.
+        cfg_if! {
+            if #[cfg(any(feature = "l4", feature = "l5"))] {
+                let msi_range = if cr.msirgsel().bit_is_set() {
+                    MsiRange::from_bits(cr.msirange().bits())
+                } else {
+                    MsiRange::from_bits(rcc.csr.read().msisrange().bits())
+                };
+            } else if #[cfg(any(feature = "wb", feature = "wl"))] {
+                let msi_range = MsiRange::from_bits(cr.msirange().bits());
+            }
         }
 
-        Ok(())
+        cfg_if! {
+            if #[cfg(feature = "g0")] {
+                let pllcfgr = rcc.pllsyscfgr.read();
+            } else {
+                let pllcfgr = rcc.pllcfgr.read();
+            }
+        }
+
+        let pll_src = match pllcfgr.pllsrc().bits() {
+            #[cfg(not(any(feature = "g0", feature = "g4")))]
+            0b01 => PllSrc::Msi(msi_range),
+            0b10 => PllSrc::Hsi,
+            0b11 => PllSrc::Hse(hse_freq),
+            _ => PllSrc::None,
+        };
+
+        cfg_if! {
+            if #[cfg(feature = "g0")] {
+                let input_src = match cfgr.sws().bits() {
+                    0b001 => InputSrc::Hse(hse_freq),
+                    0b010 => InputSrc::Pll(pll_src),
+                    0b011 => InputSrc::Lsi,
+                    0b100 => InputSrc::Lse,
+                    _ => InputSrc::Hsi,
+                };
+            } else if #[cfg(feature = "g4")] {
+                let input_src = match cfgr.sws().bits() {
+                    0b10 => InputSrc::Hse(hse_freq),
+                    0b11 => InputSrc::Pll(pll_src),
+                    _ => InputSrc::Hsi,
+                };
+            } else {
+                let input_src = match cfgr.sws().bits() {
+                    0b00 => InputSrc::Msi(msi_range),
+                    0b01 => InputSrc::Hsi,
+                    0b10 => InputSrc::Hse(hse_freq),
+                    _ => InputSrc::Pll(pll_src),
+                };
+            }
+        }
+
+        let default = Self::default();
+
+        let pllm_bits = pllcfgr.pllm().bits();
+        let pllr_bits = pllcfgr.pllr().bits();
+        let pllq_bits = pllcfgr.pllq().bits();
+
+        cfg_if! {
+            if #[cfg(any(feature = "g0", feature = "wb", feature = "wl"))] {
+                let divp = if pllcfgr.pllp().bits() == Pllp::Div17 as u8 {
+                    Pllp::Div17
+                } else {
+                    Pllp::Div7
+                };
+            } else {
+                let divp = if pllcfgr.pllp().bit_is_set() {
+                    Pllp::Div17
+                } else {
+                    Pllp::Div7
+                };
+            }
+        }
+
+        let pll = PllCfg {
+            enabled: true,
+            pllr_en: pllcfgr.pllren().bit_is_set(),
+            pllq_en: pllcfgr.pllqen().bit_is_set(),
+            pllp_en: pllcfgr.pllpen().bit_is_set(),
+            divm: PLLM_DIVS
+                .iter()
+                .find(|d| **d as u8 == pllm_bits)
+                .copied()
+                .unwrap_or(default.pll.divm),
+            divn: pllcfgr.plln().bits(),
+            divr: PLLR_DIVS
+                .iter()
+                .find(|d| **d as u8 == pllr_bits)
+                .copied()
+                .unwrap_or(default.pll.divr),
+            divq: PLLR_DIVS
+                .iter()
+                .find(|d| **d as u8 == pllq_bits)
+                .copied()
+                .unwrap_or(default.pll.divq),
+            divp,
+            #[cfg(not(any(
+                feature = "g0",
+                feature = "wb",
+                feature = "wl",
+                feature = "l4x5",
+                feature = "l4x3"
+            )))]
+            pdiv: pllcfgr.pllpdiv().bits(),
+            #[cfg(any(
+                feature = "g0",
+                feature = "wb",
+                feature = "wl",
+                feature = "l4x5",
+                feature = "l4x3"
+            ))]
+            pdiv: 0,
+        };
+
+        cfg_if! {
+            if #[cfg(feature = "g0")] {
+                let apb1_prescaler = ApbPrescaler::from_bits(cfgr.ppre().bits());
+            } else {
+                let apb1_prescaler = ApbPrescaler::from_bits(cfgr.ppre1().bits());
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "wl")] {
+                let hse_bypass = cr.hsebyppwr().bit_is_set();
+            } else {
+                let hse_bypass = cr.hsebyp().bit_is_set();
+            }
+        }
+
+        Self {
+            input_src,
+            pll,
+            hclk_prescaler: HclkPrescaler::from_bits(cfgr.hpre().bits()),
+            #[cfg(feature = "wb")]
+            hclk2_prescaler: HclkPrescaler::from_bits(rcc.extcfgr.read().c2hpre().bits()),
+            #[cfg(feature = "wl")]
+            hclk3_prescaler: HclkPrescaler::from_bits(rcc.extcfgr.read().shdhpre().bits()),
+            #[cfg(feature = "wb")]
+            hclk4_prescaler: HclkPrescaler::from_bits(rcc.extcfgr.read().shdhpre().bits()),
+            apb1_prescaler,
+            #[cfg(not(feature = "g0"))]
+            apb2_prescaler: ApbPrescaler::from_bits(cfgr.ppre2().bits()),
+            hse_bypass,
+            security_system: cr.csson().bit_is_set(),
+            #[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
+            msi_pll_mode: cr.msipllen().bit_is_set(),
+            #[cfg(any(feature = "l4", feature = "l5"))]
+            stop_wuck: if cfgr.stopwuck().bit_is_set() {
+                StopWuck::Hsi
+            } else {
+                StopWuck::Msi
+            },
+            #[cfg(feature = "l4")]
+            voltage_range: if unsafe { (*pac::PWR::ptr()).cr1.read().vos().bits() } == 0b10 {
+                VoltageRange::Range2
+            } else {
+                VoltageRange::Range1
+            },
+            ..default
+        }
+    }
+}
+
+/// The CCIPR field value that selects `src` as `periph`'s kernel clock, or `None` if
+/// `periph` can't use it.
+fn kernel_clock_bits(periph: KernelClockPeriph, src: KernelClock) -> Option<u8> {
+    match periph {
+        KernelClockPeriph::I2c1 | KernelClockPeriph::I2c2 | KernelClockPeriph::I2c3 => match src {
+            KernelClock::Apb => Some(0b00),
+            KernelClock::Sysclk => Some(0b01),
+            KernelClock::Hsi => Some(0b10),
+            _ => None,
+        },
+        KernelClockPeriph::Lptim1 | KernelClockPeriph::Lptim2 => match src {
+            KernelClock::Apb => Some(0b00),
+            KernelClock::Lsi => Some(0b01),
+            KernelClock::Hsi => Some(0b10),
+            KernelClock::Lse => Some(0b11),
+            _ => None,
+        },
+        // We don't currently support the PLL outputs as ADC clock sources.
+        KernelClockPeriph::Adc => {
+            cfg_if! {
+                if #[cfg(feature = "g0")] {
+                    let bits = match src {
+                        KernelClock::Sysclk => Some(0b00),
+                        KernelClock::Hsi => Some(0b10),
+                        _ => None,
+                    };
+                } else if #[cfg(feature = "g4")] {
+                    let bits = match src {
+                        KernelClock::Sysclk => Some(0b10),
+                        _ => None,
+                    };
+                } else if #[cfg(feature = "wl")] {
+                    let bits = match src {
+                        KernelClock::Hsi => Some(0b01),
+                        KernelClock::Sysclk => Some(0b11),
+                        _ => None,
+                    };
+                } else {
+                    let bits = match src {
+                        KernelClock::Sysclk => Some(0b11),
+                        _ => None,
+                    };
+                }
+            }
+            bits
+        }
+        // USARTs and LPUART1
+        _ => match src {
+            KernelClock::Apb => Some(0b00),
+            KernelClock::Sysclk => Some(0b01),
+            KernelClock::Hsi => Some(0b10),
+            KernelClock::Lse => Some(0b11),
+            _ => None,
+        },
     }
 }
 
@@ -1495,6 +2654,9 @@ impl Default for Clocks {
             clk48_src: Clk48Src::Hsi48,
             hse_bypass: false,
             security_system: false,
+            lse_security_system: false,
+            #[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
+            msi_pll_mode: false,
             #[cfg(not(any(feature = "g0", feature = "wl")))]
             hsi48_on: false,
             #[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
@@ -1503,10 +2665,80 @@ impl Default for Clocks {
             rf_wakeup_src: RfWakeupSrc::Lse,
             #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
             sai1_src: SaiSrc::Pllp,
+            kernel_clocks: Default::default(),
             #[cfg(feature = "g4")]
             boost_mode: true,
+            #[cfg(feature = "l4")]
+            voltage_range: VoltageRange::Range1,
+        }
+    }
+}
+
+/// Check if a clock security system has detected an oscillator failure. Run this in the NMI
+/// handler, (HSE) or the LSE CSS interrupt handler, then clear the flag with `clear_osc_failure()`.
+/// An HSE failure triggers the NMI again if its flag isn't cleared.
+pub fn osc_failure() -> Option<OscFailure> {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    let cifr = rcc.cifr.read();
+
+    cfg_if! {
+        if #[cfg(any(feature = "wb", feature = "wl"))] {
+            let hse_failed = cifr.hsecssf().bit_is_set();
+        } else {
+            let hse_failed = cifr.cssf().bit_is_set();
         }
     }
+
+    if hse_failed {
+        Some(OscFailure::Hse)
+    } else if cifr.lsecssf().bit_is_set() {
+        Some(OscFailure::Lse)
+    } else {
+        None
+    }
+}
+
+/// Clear an oscillator failure flag, as reported by `osc_failure()`.
+pub fn clear_osc_failure(failure: OscFailure) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    match failure {
+        #[cfg(any(feature = "wb", feature = "wl"))]
+        OscFailure::Hse => rcc.cicr.write(|w| w.hsecssc().set_bit()),
+        #[cfg(not(any(feature = "wb", feature = "wl")))]
+        OscFailure::Hse => rcc.cicr.write(|w| w.cssc().set_bit()),
+        OscFailure::Lse => rcc.cicr.write(|w| w.lsecssc().set_bit()),
+    }
+}
+
+/// Adjust the HSI16 trim, eg to compensate for temperature, or for an error measured with
+/// `Timer::measure_lse()`. This is added to the factory calibration, and is 7 bits wide, or 5 bits
+/// on some L4 variants; see the ICSCR register in your RM. It resets to the middle of its range.
+/// Each step is about 0.3% on L4 and WB, and 0.2% on G0 and G4; see your datasheet.
+pub fn set_hsi_trim(trim: u8) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.icscr.modify(|_, w| unsafe { w.hsitrim().bits(trim) });
+}
+
+/// Read the current HSI16 trim value. See `set_hsi_trim()`.
+pub fn hsi_trim() -> u8 {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.icscr.read().hsitrim().bits()
+}
+
+#[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
+/// Adjust the MSI trim. This is added to the factory calibration (MSICAL), and resets to 0. Don't
+/// use this while `Clocks::msi_pll_mode` is active; hardware trims the MSI in that case.
+pub fn set_msi_trim(trim: u8) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.icscr.modify(|_, w| unsafe { w.msitrim().bits(trim) });
+}
+
+#[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
+/// Read the current MSI trim value. See `set_msi_trim()`.
+pub fn msi_trim() -> u8 {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.icscr.read().msitrim().bits()
 }
 
 #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "wb"))]
@@ -1518,6 +2750,7 @@ impl Default for Clocks {
 /// external signal on CRS_SYNC pin or generated by user software. For faster lock-in during
 /// startup it is also possible to combine automatic trimming with manual trimming action."
 /// Note: This is for HSI48 only. Note that the HSI will turn off after entering Stop or Standby.
+/// This uses default sync settings; see `crs::Crs` for polarity, divider, interrupts and status.
 pub fn enable_crs(sync_src: CrsSyncSrc) {
     let crs = unsafe { &(*CRS::ptr()) };
     let rcc = unsafe { &(*RCC::ptr()) };
@@ -1549,3 +2782,303 @@ pub fn enable_crs(sync_src: CrsSyncSrc) {
     // Standby mode. When the CRS is not used, the HSI48 RC oscillator runs on its default
     // frequency which is subject to manufacturing process variations
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_valid() {
+        assert_eq!(Clocks::default().validate_speeds(), Ok(()));
+    }
+
+    #[test]
+    fn from_target_max_sysclk() {
+        let clocks = Clocks::from_target(PllSrc::Hsi, MAX_SYSCLK, false).unwrap();
+
+        assert_eq!(clocks.sysclk(), MAX_SYSCLK);
+        assert_eq!(clocks.validate_speeds(), Ok(()));
+    }
+
+    #[cfg(not(any(feature = "g0", feature = "wl")))]
+    #[test]
+    fn from_target_usb() {
+        let clocks = Clocks::from_target(PllSrc::Hse(16_000_000), 48_000_000, true).unwrap();
+
+        assert_eq!(clocks.sysclk(), 48_000_000);
+        assert_eq!(clocks.usb(), 48_000_000);
+        assert_eq!(clocks.validate_speeds(), Ok(()));
+    }
+
+    #[test]
+    fn from_target_errors() {
+        assert_eq!(
+            Clocks::from_target(PllSrc::None, 16_000_000, false).err(),
+            Some(PllSolveError::NoPllSource)
+        );
+
+        assert_eq!(
+            Clocks::from_target(PllSrc::Hsi, MAX_SYSCLK + 1, false).err(),
+            Some(PllSolveError::AboveMax {
+                requested: MAX_SYSCLK + 1,
+                max: MAX_SYSCLK,
+            })
+        );
+
+        // No PLLM brings 1Mhz up to the minimum VCO input.
+        assert_eq!(
+            Clocks::from_target(PllSrc::Hse(1_000_000), 16_000_000, false).err(),
+            Some(PllSolveError::VcoInputUnreachable {
+                src_freq: 1_000_000
+            })
+        );
+
+        // Below the lowest VCO output, divided by the highest PLLR.
+        assert_eq!(
+            Clocks::from_target(PllSrc::Hsi, 1_000_000, false).err(),
+            Some(PllSolveError::NoSolution { closest: 0 })
+        );
+    }
+
+    #[test]
+    fn plln_out_of_range() {
+        let mut clocks = Clocks::default();
+        clocks.pll.divn = PLLN_MAX + 1;
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::PllDividerOutOfRange {
+                pll: 1,
+                divider: PllDivider::N,
+                value: PLLN_MAX as u16 + 1,
+                min: PLLN_MIN as u16,
+                max: PLLN_MAX as u16,
+            })
+        );
+    }
+
+    #[test]
+    fn vco_limits() {
+        let mut clocks = Clocks {
+            input_src: InputSrc::Pll(PllSrc::Hse(32_000_000)),
+            ..Default::default()
+        };
+        clocks.pll.divm = Pllm::Div1;
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::VcoInputOutOfRange {
+                pll: 1,
+                freq: 32_000_000,
+                min: VCO_IN_MIN,
+                max: VCO_IN_MAX,
+            })
+        );
+
+        clocks.input_src = InputSrc::Pll(PllSrc::Hsi);
+        clocks.pll.divn = PLLN_MAX;
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::VcoOutputOutOfRange {
+                pll: 1,
+                freq: 16_000_000 * PLLN_MAX as u32,
+                min: VCO_OUT_MIN,
+                max: VCO_OUT_MAX,
+            })
+        );
+    }
+
+    #[test]
+    fn pll_output_over_max() {
+        let mut clocks = Clocks::default();
+        // A 344Mhz VCO output.
+        clocks.pll.divm = Pllm::Div4;
+        clocks.pll.divn = 86;
+        clocks.pll.divr = Pllr::Div2;
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::PllOutputOverMax {
+                pll: 1,
+                output: PllDivider::R,
+                freq: 172_000_000,
+                max: MAX_SYSCLK,
+            })
+        );
+    }
+
+    #[test]
+    fn sysclk_over_max() {
+        let clocks = Clocks {
+            input_src: InputSrc::Hse(MAX_SYSCLK + 1_000_000),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::SysclkOverMax {
+                freq: MAX_SYSCLK + 1_000_000,
+                max: MAX_SYSCLK,
+            })
+        );
+    }
+
+    #[cfg(not(any(feature = "g0", feature = "wl")))]
+    #[test]
+    fn usb_not_48mhz() {
+        let mut clocks = Clocks {
+            clk48_src: Clk48Src::Pllq,
+            ..Default::default()
+        };
+        clocks.pll.pllq_en = true;
+
+        assert!(matches!(
+            clocks.validate_speeds(),
+            Err(SpeedError::UsbNot48Mhz { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_kernel_clock() {
+        let mut clocks = Clocks::default();
+        clocks.kernel_clocks.i2c1 = Some(KernelClock::Lse);
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::InvalidKernelClock {
+                periph: KernelClockPeriph::I2c1,
+                src: KernelClock::Lse,
+            })
+        );
+    }
+
+    #[test]
+    fn kernel_clocks_unconfigured() {
+        let mut clocks = Clocks::default();
+        assert_eq!(clocks.kernel_clocks.get(KernelClockPeriph::Usart1), None);
+        assert_eq!(
+            clocks.kernel_speed(KernelClockPeriph::Usart1),
+            clocks.apb2()
+        );
+        assert_eq!(clocks.kernel_speed(KernelClockPeriph::I2c1), clocks.apb1());
+
+        clocks.kernel_clocks.usart1 = Some(KernelClock::Hsi);
+        assert_eq!(clocks.kernel_speed(KernelClockPeriph::Usart1), 16_000_000);
+    }
+
+    #[test]
+    fn wait_states() {
+        let hsi = Clocks {
+            input_src: InputSrc::Hsi,
+            ..Default::default()
+        };
+        assert_eq!(hsi.wait_states() as u8, 0);
+
+        // The default config runs at the max SYSCLK, so needs the most wait states.
+        cfg_if! {
+            if #[cfg(feature = "l5")] {
+                let max_ws = 5;
+            } else if #[cfg(any(feature = "l4", feature = "g4"))] {
+                let max_ws = 4;
+            } else if #[cfg(feature = "wb")] {
+                let max_ws = 3;
+            } else {
+                let max_ws = 2;
+            }
+        }
+        assert_eq!(Clocks::default().wait_states() as u8, max_ws);
+    }
+
+    #[cfg(feature = "g4")]
+    #[test]
+    fn g4_normal_mode_limit() {
+        let clocks = Clocks {
+            boost_mode: false,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::WaitStatesInsufficient {
+                hclk: 170_000_000,
+                max: 150_000_000,
+            })
+        );
+    }
+
+    #[cfg(feature = "l4")]
+    #[test]
+    fn l4_range2_limits() {
+        let mut clocks = Clocks {
+            voltage_range: VoltageRange::Range2,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::VcoOutputOutOfRange {
+                pll: 1,
+                freq: 160_000_000,
+                min: VCO_OUT_MIN,
+                max: 128_000_000,
+            })
+        );
+
+        // 112Mhz VCO output, for 28Mhz on PLLR.
+        clocks.pll.divn = 28;
+        clocks.pll.divr = Pllr::Div4;
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::PllOutputOverMax {
+                pll: 1,
+                output: PllDivider::R,
+                freq: 28_000_000,
+                max: 26_000_000,
+            })
+        );
+
+        clocks.pll.divn = 26;
+        assert_eq!(clocks.validate_speeds(), Ok(()));
+        assert_eq!(clocks.wait_states() as u8, 3);
+
+        let hse = Clocks {
+            input_src: InputSrc::Hse(32_000_000),
+            voltage_range: VoltageRange::Range2,
+            ..Default::default()
+        };
+        assert_eq!(
+            hse.validate_speeds(),
+            Err(SpeedError::SysclkOverMax {
+                freq: 32_000_000,
+                max: 26_000_000,
+            })
+        );
+
+        let hsi = Clocks {
+            input_src: InputSrc::Hsi,
+            voltage_range: VoltageRange::Range2,
+            ..Default::default()
+        };
+        assert_eq!(hsi.validate_speeds(), Ok(()));
+        assert_eq!(hsi.wait_states() as u8, 2);
+    }
+
+    #[cfg(feature = "wb")]
+    #[test]
+    fn wb_hclk2_limit() {
+        let clocks = Clocks {
+            hclk2_prescaler: HclkPrescaler::Div1,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::HclkOverMax {
+                bus: 2,
+                freq: 64_000_000,
+                max: 32_000_000,
+            })
+        );
+    }
+}
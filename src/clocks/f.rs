@@ -1,9 +1,20 @@
 use crate::{
-    clocks::SpeedError,
+    clocks::{
+        rtc_clock, ClockReport, KernelClockPeriph, KernelClocks, OscFailure, PllSolveError,
+        PllSpeeds, SpeedError,
+    },
+    gpio::{OutputSpeed, Pin, PinMode},
     pac::{self, FLASH, RCC},
+    rtc::RtcClockSource,
     util::rcc_en_reset,
 };
 
+#[cfg(feature = "f3")]
+use crate::clocks::{KernelClock, KERNEL_CLOCK_PERIPHS};
+
+#[cfg(feature = "f4")]
+use crate::clocks::PllDivider;
+
 use cfg_if::cfg_if;
 
 cfg_if! {
@@ -185,6 +196,47 @@ impl PllMul {
     }
 }
 
+#[cfg(feature = "f3")]
+/// PREDIV values, in the order `Clocks::from_target` tries them.
+const PREDIV_DIVS: [Prediv; 16] = [
+    Prediv::Div1,
+    Prediv::Div2,
+    Prediv::Div3,
+    Prediv::Div4,
+    Prediv::Div5,
+    Prediv::Div6,
+    Prediv::Div7,
+    Prediv::Div8,
+    Prediv::Div9,
+    Prediv::Div10,
+    Prediv::Div11,
+    Prediv::Div12,
+    Prediv::Div13,
+    Prediv::Div14,
+    Prediv::Div15,
+    Prediv::Div16,
+];
+
+#[cfg(feature = "f3")]
+/// PLLMUL values, in the order `Clocks::from_target` tries them.
+const PLL_MULS: [PllMul; 15] = [
+    PllMul::Mul2,
+    PllMul::Mul3,
+    PllMul::Mul4,
+    PllMul::Mul5,
+    PllMul::Mul6,
+    PllMul::Mul7,
+    PllMul::Mul8,
+    PllMul::Mul9,
+    PllMul::Mul10,
+    PllMul::Mul11,
+    PllMul::Mul12,
+    PllMul::Mul13,
+    PllMul::Mul14,
+    PllMul::Mul15,
+    PllMul::Mul16,
+];
+
 #[cfg(feature = "f4")]
 #[derive(Clone, Copy)]
 #[repr(u8)]
@@ -207,6 +259,10 @@ impl Pllp {
     }
 }
 
+#[cfg(feature = "f4")]
+/// PLLP values, in the order `Clocks::from_target` tries them.
+const PLLP_DIVS: [Pllp; 4] = [Pllp::Div2, Pllp::Div4, Pllp::Div6, Pllp::Div8];
+
 #[derive(Clone, Copy)]
 #[repr(u8)]
 /// Division factor for the AHB clock. Also known as AHB Prescaler.
@@ -236,6 +292,21 @@ impl HclkPrescaler {
             Self::Div512 => 512,
         }
     }
+
+    /// Convert from the HPRE register field.
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0b1000 => Self::Div2,
+            0b1001 => Self::Div4,
+            0b1010 => Self::Div8,
+            0b1011 => Self::Div16,
+            0b1100 => Self::Div64,
+            0b1101 => Self::Div128,
+            0b1110 => Self::Div256,
+            0b1111 => Self::Div512,
+            _ => Self::Div1,
+        }
+    }
 }
 
 // f3 uses 0 - 2 only. F4 uses up to 7.
@@ -279,6 +350,17 @@ impl ApbPrescaler {
             Self::Div16 => 16,
         }
     }
+
+    /// Convert from a PPRE register field.
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0b100 => Self::Div2,
+            0b101 => Self::Div4,
+            0b110 => Self::Div8,
+            0b111 => Self::Div16,
+            _ => Self::Div1,
+        }
+    }
 }
 
 #[cfg(feature = "f3")]
@@ -328,6 +410,25 @@ pub enum Pllq {
     Div15 = 0b1111,
 }
 
+#[cfg(feature = "f4")]
+/// PLLQ values, in the order `Clocks::from_target` tries them.
+const PLLQ_DIVS: [Pllq; 14] = [
+    Pllq::Div2,
+    Pllq::Div3,
+    Pllq::Div4,
+    Pllq::Div5,
+    Pllq::Div6,
+    Pllq::Div7,
+    Pllq::Div8,
+    Pllq::Div9,
+    Pllq::Div10,
+    Pllq::Div11,
+    Pllq::Div12,
+    Pllq::Div13,
+    Pllq::Div14,
+    Pllq::Div15,
+];
+
 #[cfg(feature = "f4")]
 impl Pllq {
     pub fn value(&self) -> u8 {
@@ -350,9 +451,174 @@ impl Pllq {
     }
 }
 
+#[cfg(feature = "f3")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Clock output on the MCO pin. Sets RCC_CFGR register, MCO field.
+pub enum McoSrc {
+    Lsi = 0b010,
+    Lse = 0b011,
+    Sysclk = 0b100,
+    Hsi = 0b101,
+    Hse = 0b110,
+    /// The PLL output, divided by 2.
+    PllDiv2 = 0b111,
+}
+
+#[cfg(feature = "f4")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Clock output on the MCO1 pin. Sets RCC_CFGR register, MCO1 field.
+pub enum Mco1Src {
+    Hsi = 0b00,
+    Lse = 0b01,
+    Hse = 0b10,
+    /// The main PLL output. (PLLCLK)
+    Pll = 0b11,
+}
+
+#[cfg(feature = "f4")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Clock output on the MCO2 pin. Sets RCC_CFGR register, MCO2 field.
+pub enum Mco2Src {
+    Sysclk = 0b00,
+    #[cfg(not(feature = "f410"))]
+    PllI2s = 0b01,
+    Hse = 0b10,
+    /// The main PLL output. (PLLCLK)
+    Pll = 0b11,
+}
+
+#[cfg(feature = "f4")]
+#[derive(Clone, Copy, PartialEq)]
+/// Clock output on an MCO pin. The source determines which pin is used: MCO1 on PA8, or MCO2
+/// on PC9.
+pub enum McoSrc {
+    Mco1(Mco1Src),
+    Mco2(Mco2Src),
+}
+
+#[cfg(feature = "f4")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Divides the MCO source clock. Sets RCC_CFGR register, MCO1PRE and MCO2PRE fields.
+pub enum McoPrescaler {
+    Div1 = 0b000,
+    Div2 = 0b100,
+    Div3 = 0b101,
+    Div4 = 0b110,
+    Div5 = 0b111,
+}
+
+#[cfg(feature = "f4")]
+impl McoPrescaler {
+    pub fn value(&self) -> u8 {
+        match self {
+            Self::Div1 => 1,
+            Self::Div2 => 2,
+            Self::Div3 => 3,
+            Self::Div4 => 4,
+            Self::Div5 => 5,
+        }
+    }
+}
+
+/// Microcontroller Clock Output (MCO) configuration. See the `clocks` module documentation.
+/// There's no MCO prescaler on F3.
+#[derive(Clone, Copy)]
+pub struct Mco {
+    pub src: McoSrc,
+    #[cfg(feature = "f4")]
+    pub prescaler: McoPrescaler,
+}
+
+impl Mco {
+    /// Configure `pin` for MCO, and start outputting the clock on it. MCO (MCO1 on F4) is
+    /// alternate function 0 on PA8. MCO2 is alternate function 0 on PC9.
+    pub fn enable(&self, pin: &mut Pin) {
+        pin.mode(PinMode::Alt(0));
+        pin.output_speed(OutputSpeed::High);
+
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        #[cfg(feature = "f3")]
+        rcc.cfgr
+            .modify(|_, w| unsafe { w.mco().bits(self.src as u8) });
+
+        #[cfg(feature = "f4")]
+        match self.src {
+            McoSrc::Mco1(src) => rcc.cfgr.modify(|_, w| unsafe {
+                w.mco1pre().bits(self.prescaler as u8);
+                w.mco1().bits(src as u8)
+            }),
+            McoSrc::Mco2(src) => rcc.cfgr.modify(|_, w| unsafe {
+                w.mco2pre().bits(self.prescaler as u8);
+                w.mco2().bits(src as u8)
+            }),
+        }
+    }
+}
+
+#[cfg(feature = "f3")]
+/// Stop outputting a clock on the MCO pin.
+pub fn disable_mco() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.cfgr.modify(|_, w| unsafe { w.mco().bits(0) });
+}
+
+#[cfg(feature = "f4")]
+/// Stop outputting a clock on an MCO pin. MCO1 and MCO2 have no source setting that turns them
+/// off, so this takes `pin` out of its MCO alternate function, and sets it to analog mode.
+pub fn disable_mco(pin: &mut Pin) {
+    pin.mode(PinMode::Analog);
+}
+
+// SYSCLK, APB and PLL limits, in Hz, used by `Clocks::from_target`. On F3, the PLL input is
+// after PREDIV (F303 datasheet, table 43). On F4, the VCO input is after PLLM, and the VCO output
+// is VCO input x PLLN. (F4 RMs, RCC_PLLCFGR register description)
+cfg_if! {
+    if #[cfg(feature = "f3")] {
+        const MAX_SYSCLK: u32 = 72_000_000;
+        const MAX_APB1: u32 = 36_000_000;
+        const MAX_APB2: u32 = 72_000_000;
+        const PLL_IN_MIN: u32 = 1_000_000;
+        const PLL_IN_MAX: u32 = 24_000_000;
+        const PLL_OUT_MIN: u32 = 16_000_000;
+    } else if #[cfg(feature = "f401")] {
+        const MAX_SYSCLK: u32 = 84_000_000;
+        const MAX_APB1: u32 = 42_000_000;
+        const MAX_APB2: u32 = 84_000_000;
+        const VCO_OUT_MIN: u32 = 192_000_000;
+    } else if #[cfg(any(feature = "f410", feature = "f411", feature = "f412", feature = "f413"))] {
+        const MAX_SYSCLK: u32 = 100_000_000;
+        const MAX_APB1: u32 = 50_000_000;
+        const MAX_APB2: u32 = 100_000_000;
+        const VCO_OUT_MIN: u32 = 100_000_000;
+    } else if #[cfg(any(feature = "f405", feature = "f407"))] {
+        const MAX_SYSCLK: u32 = 168_000_000;
+        const MAX_APB1: u32 = 42_000_000;
+        const MAX_APB2: u32 = 84_000_000;
+        const VCO_OUT_MIN: u32 = 100_000_000;
+    } else {
+        const MAX_SYSCLK: u32 = 180_000_000;
+        const MAX_APB1: u32 = 45_000_000;
+        const MAX_APB2: u32 = 90_000_000;
+        const VCO_OUT_MIN: u32 = 100_000_000;
+    }
+}
+
+#[cfg(feature = "f4")]
+const VCO_IN_MIN: u32 = 1_000_000;
+#[cfg(feature = "f4")]
+const VCO_IN_MAX: u32 = 2_000_000;
+#[cfg(feature = "f4")]
+const VCO_OUT_MAX: u32 = 432_000_000;
+
 /// Settings used to configure clocks. Create this struct by using its `Default::default()`
 /// implementation, then modify as required, referencing your RM's clock tree,
 /// or Stm32Cube IDE's interactive clock manager. Apply settings by running `.setup()`.
+#[derive(Clone)]
 pub struct Clocks {
     /// The input source for the system and peripheral clocks. Eg HSE, HSI, PLL etc
     pub input_src: InputSrc,
@@ -383,7 +649,12 @@ pub struct Clocks {
     /// Bypass the HSE output, for use with oscillators that don't need it. Saves power, and
     /// frees up the pin for use as GPIO.
     pub hse_bypass: bool,
+    /// Enable the HSE clock security system (CSS). If the HSE fails, hardware switches SYSCLK to
+    /// HSI, and triggers an NMI. See `osc_failure()` and `Clocks::fail_over()`. Defaults to false.
     pub security_system: bool,
+    /// Kernel clock selection for USART and I2C peripherals. (F3 only; these always use their
+    /// APB clock on F4)
+    pub kernel_clocks: KernelClocks,
 }
 
 impl Clocks {
@@ -401,39 +672,23 @@ impl Clocks {
 
         // Adjust flash wait states according to the HCLK frequency.
         // We need to do this before enabling PLL, or it won't enable.
-        let sysclk = self.sysclk();
+        flash
+            .acr
+            .modify(|_, w| unsafe { w.latency().bits(self.wait_states() as u8) });
 
-        // todo: We don't yet take into account other voltage settings for f4 wait states.
-        let hclk = sysclk / self.hclk_prescaler.value() as u32;
-        cfg_if! {
-            if #[cfg(feature = "f3")] {  // RM section 4.5.1
-                flash.acr.modify(|_, w| unsafe {
-                    if hclk <= 24_000_000 {
-                        w.latency().bits(WaitState::W0 as u8)
-                    } else if hclk <= 48_000_000 {
-                        w.latency().bits(WaitState::W1 as u8)
-                    } else {
-                        w.latency().bits(WaitState::W2 as u8)
-                    }
-                });
-            } else {  // F4
-                flash.acr.modify(|_, w| unsafe {
-                    if hclk <= 30_000_000 {
-                        w.latency().bits(WaitState::W0 as u8)
-                    } else if hclk <= 60_000_000 {
-                        w.latency().bits(WaitState::W1 as u8)
-                    } else if hclk <= 90_000_000 {
-                        w.latency().bits(WaitState::W2 as u8)
-                    } else if hclk <= 120_000_000 {
-                        w.latency().bits(WaitState::W3 as u8)
-                    } else if hclk <= 150_000_000 {
-                        w.latency().bits(WaitState::W4 as u8)
-                    } else {
-                        w.latency().bits(WaitState::W5 as u8)
-                    }
-                });
-            }
-        }
+        self.configure();
+
+        // Enable and reset System Configuration Controller, ie for interrupts.
+        // todo: Is this the right module to do this in?
+        rcc_en_reset!(apb2, syscfg, rcc);
+
+        Ok(())
+    }
+
+    /// Enable oscillators and the PLL, and set clock sources and prescalers. Flash wait states
+    /// must be high enough for both the current and new HCLK before running this.
+    fn configure(&self) {
+        let rcc = unsafe { &(*RCC::ptr()) };
 
         // 303 RM, 9.2.3:
         // The internal PLL can be used to multiply the HSI or HSE output clock frequency. Refer to
@@ -531,6 +786,34 @@ impl Clocks {
 
         rcc.cr.modify(|_, w| w.csson().bit(self.security_system));
 
+        // `validate_speeds()` has already rejected sources a peripheral can't use.
+        #[cfg(feature = "f3")]
+        {
+            // Fields that aren't configured keep their current value.
+            let kernel_bits = |periph, current| {
+                self.kernel_clocks
+                    .get(periph)
+                    .and_then(|src| kernel_clock_bits(periph, src))
+                    .unwrap_or(current)
+            };
+
+            rcc.cfgr3.modify(|r, w| unsafe {
+                #[cfg(not(feature = "f301"))]
+                w.usart2sw()
+                    .bits(kernel_bits(KernelClockPeriph::Usart2, r.usart2sw().bits()));
+                #[cfg(not(feature = "f301"))]
+                w.usart3sw()
+                    .bits(kernel_bits(KernelClockPeriph::Usart3, r.usart3sw().bits()));
+                #[cfg(not(feature = "f3x4"))]
+                w.i2c2sw()
+                    .bit(kernel_bits(KernelClockPeriph::I2c2, r.i2c2sw().bit() as u8) != 0);
+                w.usart1sw()
+                    .bits(kernel_bits(KernelClockPeriph::Usart1, r.usart1sw().bits()));
+                w.i2c1sw()
+                    .bit(kernel_bits(KernelClockPeriph::I2c1, r.i2c1sw().bit() as u8) != 0)
+            });
+        }
+
         // If we're not using the default clock source as input source or for PLL, turn it off.
         match self.input_src {
             InputSrc::Hsi => (),
@@ -547,14 +830,145 @@ impl Clocks {
                 rcc.cr.modify(|_, w| w.hsion().clear_bit());
             }
         }
+    }
 
-        // Enable and reset System Configuration Controller, ie for interrupts.
-        // todo: Is this the right module to do this in?
-        rcc_en_reset!(apb2, syscfg, rcc);
+    /// Switch to a new clock configuration at runtime, eg to scale frequency up or down. Flash
+    /// wait states are raised before the clocks speed up, and lowered after they slow down. If
+    /// the PLL is the current system clock, HSI is used as the system clock while the PLL is
+    /// reconfigured. Returns an error without making changes if `new_cfg` is invalid.
+    /// Peripherals that derive timing from clock speeds must be updated after this; see
+    /// `Usart::retime`, `I2c::retime`, `Spi::retime`, and `Timer::retime`.
+    pub fn switch_to(&mut self, new_cfg: Self) -> Result<(), SpeedError> {
+        new_cfg.validate_speeds()?;
+
+        let rcc = unsafe { &(*RCC::ptr()) };
+        let flash = unsafe { &(*FLASH::ptr()) };
+
+        let wait_states_prev = flash.acr.read().latency().bits();
+        let wait_states = new_cfg.wait_states() as u8;
+
+        if wait_states > wait_states_prev {
+            flash
+                .acr
+                .modify(|_, w| unsafe { w.latency().bits(wait_states) });
+            // Make sure the new setting is in effect before increasing speed.
+            while flash.acr.read().latency().bits() != wait_states {}
+        }
+
+        // The PLL can't be reconfigured while it's the system clock.
+        if let InputSrc::Pll(_) = self.input_src {
+            rcc.cr.modify(|_, w| w.hsion().bit(true));
+            while rcc.cr.read().hsirdy().is_not_ready() {}
+
+            rcc.cfgr
+                .modify(|_, w| unsafe { w.sw().bits(InputSrc::Hsi.bits()) });
+            while rcc.cfgr.read().sws().bits() != InputSrc::Hsi.bits() {}
+        }
+
+        new_cfg.configure();
+
+        // Don't lower wait states until the switch to the new system clock is complete.
+        while rcc.cfgr.read().sws().bits() != new_cfg.input_src.bits() {}
+
+        if wait_states < wait_states_prev {
+            flash
+                .acr
+                .modify(|_, w| unsafe { w.latency().bits(wait_states) });
+        }
+
+        *self = new_cfg;
 
         Ok(())
     }
 
+    /// Reconfigure clocks after an HSE failure, so they no longer use the HSE. The PLL is kept
+    /// at the same SYSCLK if it can be, using the HSI as its source. Otherwise, HSI is used
+    /// directly as SYSCLK. Takes no action if the HSE isn't used. Returns the new SYSCLK frequency
+    /// in Hz; peripherals should be re-timed, as after `switch_to()`.
+    pub fn fail_over(&mut self) -> Result<u32, SpeedError> {
+        match self.input_src {
+            InputSrc::Hse(_) | InputSrc::Pll(PllSrc::Hse(_)) => (),
+            _ => return Ok(self.sysclk()),
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "f3")] {
+                if let Ok(solved) = Self::from_target(PllSrc::HsiDiv2, self.sysclk(), false) {
+                    let with_pll = Self {
+                        input_src: solved.input_src,
+                        prediv: solved.prediv,
+                        pll_mul: solved.pll_mul,
+                        ..self.clone()
+                    };
+                    // `switch_to` makes no changes if the config is invalid.
+                    if self.switch_to(with_pll).is_ok() {
+                        return Ok(self.sysclk());
+                    }
+                }
+            } else {
+                // Try to keep the 48Mhz USB clock on PLLQ.
+                let solved = Self::from_target(PllSrc::Hsi, self.sysclk(), true)
+                    .or_else(|_| Self::from_target(PllSrc::Hsi, self.sysclk(), false));
+
+                if let Ok(solved) = solved {
+                    let with_pll = Self {
+                        input_src: solved.input_src,
+                        pllm: solved.pllm,
+                        plln: solved.plln,
+                        pllp: solved.pllp,
+                        pllq: solved.pllq,
+                        ..self.clone()
+                    };
+                    if self.switch_to(with_pll).is_ok() {
+                        return Ok(self.sysclk());
+                    }
+                }
+            }
+        }
+
+        self.switch_to(Self {
+            input_src: InputSrc::Hsi,
+            ..self.clone()
+        })?;
+
+        Ok(self.sysclk())
+    }
+
+    /// The flash wait states required for this config's HCLK.
+    fn wait_states(&self) -> WaitState {
+        let sysclk = self.sysclk();
+
+        // todo: We don't yet take into account other voltage settings for f4 wait states.
+        let hclk = sysclk / self.hclk_prescaler.value() as u32;
+        cfg_if! {
+            if #[cfg(feature = "f3")] {  // RM section 4.5.1
+                let wait_states = if hclk <= 24_000_000 {
+                    WaitState::W0
+                } else if hclk <= 48_000_000 {
+                    WaitState::W1
+                } else {
+                    WaitState::W2
+                };
+            } else {  // F4
+                let wait_states = if hclk <= 30_000_000 {
+                    WaitState::W0
+                } else if hclk <= 60_000_000 {
+                    WaitState::W1
+                } else if hclk <= 90_000_000 {
+                    WaitState::W2
+                } else if hclk <= 120_000_000 {
+                    WaitState::W3
+                } else if hclk <= 150_000_000 {
+                    WaitState::W4
+                } else {
+                    WaitState::W5
+                };
+            }
+        }
+
+        wait_states
+    }
+
     /// Re-select innput source; used on Stop and Standby modes, where the system reverts
     /// to HSI after wake.
     pub fn reselect_input(&self) {
@@ -643,9 +1057,25 @@ impl Clocks {
 
     pub fn usb(&self) -> u32 {
         #[cfg(feature = "f3")]
-        return self.sysclk() / self.usb_pre.value() as u32;
+        return (self.sysclk() as f32 / self.usb_pre.value()) as u32;
         #[cfg(feature = "f4")]
-        return 0; // todo
+        return self.vco_output_freq() / self.pllq.value() as u32;
+    }
+
+    #[cfg(feature = "f4")]
+    /// Calculate the PLL's VCO input frequency, in Hz. (After PLLM)
+    pub fn vco_input_freq(&self) -> u32 {
+        let input_freq = match self.input_src {
+            InputSrc::Pll(PllSrc::Hse(freq)) => freq,
+            _ => 16_000_000,
+        };
+        input_freq / self.pllm as u32
+    }
+
+    #[cfg(feature = "f4")]
+    /// Calculate the PLL's VCO output frequency, in Hz. (After PLLN)
+    pub fn vco_output_freq(&self) -> u32 {
+        self.vco_input_freq() * self.plln as u32
     }
 
     pub fn apb1(&self) -> u32 {
@@ -672,47 +1102,586 @@ impl Clocks {
         }
     }
 
+    /// Get the frequency output on an MCO pin by `mco`, in hz. `None` if this config doesn't
+    /// determine its source's speed; see the `clocks` module documentation. The PLL's speed is
+    /// only known if SYSCLK is derived from it.
+    pub fn mco_speed(&self, mco: &Mco) -> Option<u32> {
+        let hse_freq = match self.input_src {
+            InputSrc::Hse(freq) | InputSrc::Pll(PllSrc::Hse(freq)) => Some(freq),
+            _ => None,
+        };
+        let pll_freq = match self.input_src {
+            InputSrc::Pll(_) => Some(self.sysclk()),
+            _ => None,
+        };
+
+        #[cfg(feature = "f3")]
+        return match mco.src {
+            McoSrc::Lsi => Some(40_000),
+            McoSrc::Lse => Some(32_768),
+            McoSrc::Sysclk => Some(self.sysclk()),
+            McoSrc::Hsi => Some(8_000_000),
+            McoSrc::Hse => hse_freq,
+            McoSrc::PllDiv2 => pll_freq.map(|freq| freq / 2),
+        };
+
+        #[cfg(feature = "f4")]
+        return match mco.src {
+            McoSrc::Mco1(Mco1Src::Hsi) => Some(16_000_000),
+            McoSrc::Mco1(Mco1Src::Lse) => Some(32_768),
+            McoSrc::Mco1(Mco1Src::Hse) | McoSrc::Mco2(Mco2Src::Hse) => hse_freq,
+            McoSrc::Mco1(Mco1Src::Pll) | McoSrc::Mco2(Mco2Src::Pll) => pll_freq,
+            McoSrc::Mco2(Mco2Src::Sysclk) => Some(self.sysclk()),
+            // The PLLI2S isn't configured by `Clocks`.
+            #[cfg(not(feature = "f410"))]
+            McoSrc::Mco2(Mco2Src::PllI2s) => None,
+        }
+        .map(|freq| freq / mco.prescaler.value() as u32);
+    }
+
+    /// Get the kernel clock frequency of a peripheral, in hz. On F3, this is as selected in
+    /// `kernel_clocks`. On F4, it's the peripheral's APB clock.
+    pub fn kernel_speed(&self, periph: KernelClockPeriph) -> u32 {
+        #[cfg(feature = "f3")]
+        match periph {
+            KernelClockPeriph::Usart1
+            | KernelClockPeriph::Usart2
+            | KernelClockPeriph::Usart3
+            | KernelClockPeriph::I2c1
+            | KernelClockPeriph::I2c2 => {
+                return match self.kernel_clocks.source(periph) {
+                    KernelClock::Apb => match periph {
+                        KernelClockPeriph::Usart1 => self.apb2(),
+                        _ => self.apb1(),
+                    },
+                    KernelClock::Sysclk => self.sysclk(),
+                    KernelClock::Hsi => 8_000_000,
+                    KernelClock::Lse => 32_768,
+                    KernelClock::Lsi => 40_000,
+                };
+            }
+            // In its synchronous clock modes, the ADC is clocked from HCLK.
+            KernelClockPeriph::Adc => return self.hclk(),
+            _ => (),
+        }
+
+        match periph {
+            KernelClockPeriph::Usart1 | KernelClockPeriph::Adc => self.apb2(),
+            _ => self.apb1(),
+        }
+    }
+
+    /// Check that the PLL, and bus clocks this struct configures are within limits. Note that
+    /// the USB clock isn't checked, since it's only relevant if USB is used.
     pub fn validate_speeds(&self) -> Result<(), SpeedError> {
-        cfg_if! {
-            if #[cfg(feature = "f3")] {
-                let max_clock = 72_000_000;
-            } else if #[cfg(feature = "f401")] {
-                let max_clock = 84_000_000;
-            } else if #[cfg(any(feature = "f410", feature = "f411", feature = "f412", feature = "f413"))] {
-                let max_clock = 110_000_000;
-            } else if #[cfg(any(feature = "f405", feature = "f407"))] {
-                let max_clock = 168_000_000;
-            } else {
-                let max_clock = 180_000_000;
+        #[cfg(feature = "f3")]
+        if let InputSrc::Pll(pll_src) = self.input_src {
+            let pll_in = match pll_src {
+                PllSrc::HsiDiv2 => 4_000_000,
+                PllSrc::Hse(freq) => freq / self.prediv.value() as u32,
+            };
+            if pll_in < PLL_IN_MIN || pll_in > PLL_IN_MAX {
+                return Err(SpeedError::VcoInputOutOfRange {
+                    pll: 1,
+                    freq: pll_in,
+                    min: PLL_IN_MIN,
+                    max: PLL_IN_MAX,
+                });
+            }
+
+            // 303 RM, 9.2.3: "The PLL output frequency must be set in the range 16-72 MHz."
+            let pll_out = self.sysclk();
+            if pll_out < PLL_OUT_MIN || pll_out > MAX_SYSCLK {
+                return Err(SpeedError::VcoOutputOutOfRange {
+                    pll: 1,
+                    freq: pll_out,
+                    min: PLL_OUT_MIN,
+                    max: MAX_SYSCLK,
+                });
             }
         }
 
         #[cfg(feature = "f4")]
-        if self.plln < 50 || self.plln > 432 || self.pllm < 2 || self.pllm > 63 {
-            return Err(SpeedError::new("A PLL divider is out of limits"));
+        if let InputSrc::Pll(_) = self.input_src {
+            if self.pllm < 2 || self.pllm > 63 {
+                return Err(SpeedError::PllDividerOutOfRange {
+                    pll: 1,
+                    divider: PllDivider::M,
+                    value: self.pllm as u16,
+                    min: 2,
+                    max: 63,
+                });
+            }
+
+            if self.plln < 50 || self.plln > 432 {
+                return Err(SpeedError::PllDividerOutOfRange {
+                    pll: 1,
+                    divider: PllDivider::N,
+                    value: self.plln,
+                    min: 50,
+                    max: 432,
+                });
+            }
+
+            let vco_in = self.vco_input_freq();
+            if vco_in < VCO_IN_MIN || vco_in > VCO_IN_MAX {
+                return Err(SpeedError::VcoInputOutOfRange {
+                    pll: 1,
+                    freq: vco_in,
+                    min: VCO_IN_MIN,
+                    max: VCO_IN_MAX,
+                });
+            }
+
+            let vco_out = self.vco_output_freq();
+            if vco_out < VCO_OUT_MIN || vco_out > VCO_OUT_MAX {
+                return Err(SpeedError::VcoOutputOutOfRange {
+                    pll: 1,
+                    freq: vco_out,
+                    min: VCO_OUT_MIN,
+                    max: VCO_OUT_MAX,
+                });
+            }
+
+            // F4 RMs, RCC_PLLCFGR: "The USB OTG FS requires a 48 MHz clock to work correctly. The
+            // SDIO and the random number generator need a frequency lower than or equal to 48 MHz"
+            let pllq_out = self.usb();
+            if pllq_out > 48_000_000 {
+                return Err(SpeedError::PllOutputOverMax {
+                    pll: 1,
+                    output: PllDivider::Q,
+                    freq: pllq_out,
+                    max: 48_000_000,
+                });
+            }
         }
 
-        let max_hclk = max_clock;
+        let sysclk = self.sysclk();
+        if sysclk > MAX_SYSCLK {
+            return Err(SpeedError::SysclkOverMax {
+                freq: sysclk,
+                max: MAX_SYSCLK,
+            });
+        }
 
-        // todo: min clock? eg for apxb?
-        if self.sysclk() > max_hclk {
-            return Err(SpeedError::new("Sysclk out of limits"));
+        let hclk = self.hclk();
+        if hclk > MAX_SYSCLK {
+            return Err(SpeedError::HclkOverMax {
+                bus: 1,
+                freq: hclk,
+                max: MAX_SYSCLK,
+            });
         }
 
-        if self.hclk() > max_hclk {
-            return Err(SpeedError::new("Hclk out of limits"));
+        let apb1 = self.apb1();
+        if apb1 > MAX_APB1 {
+            return Err(SpeedError::ApbOverMax {
+                bus: 1,
+                freq: apb1,
+                max: MAX_APB1,
+            });
         }
 
-        if self.apb1() > max_clock {
-            return Err(SpeedError::new("Apb1 out of limits"));
+        let apb2 = self.apb2();
+        if apb2 > MAX_APB2 {
+            return Err(SpeedError::ApbOverMax {
+                bus: 2,
+                freq: apb2,
+                max: MAX_APB2,
+            });
         }
 
-        if self.apb2() > max_clock {
-            return Err(SpeedError::new("Apb2 out of limits"));
+        #[cfg(feature = "f3")]
+        for periph in KERNEL_CLOCK_PERIPHS.iter() {
+            if let Some(src) = self.kernel_clocks.get(*periph) {
+                if kernel_clock_bits(*periph, src).is_none() {
+                    return Err(SpeedError::InvalidKernelClock {
+                        periph: *periph,
+                        src,
+                    });
+                }
+            }
         }
 
         Ok(())
     }
+
+    #[cfg(feature = "f3")]
+    /// Create a config that runs SYSCLK from the PLL at `sysclk` Hz, by searching the PREDIV
+    /// and PLLMUL values within the PLL's input and output ranges. If `usb_48mhz_required` is true,
+    /// SYSCLK must be 48 or 72Mhz, so the USB prescaler can produce 48Mhz. APB prescalers are
+    /// set to the lowest values that keep APB1 and APB2 within limits. Other fields are left at
+    /// their `Default` values; modify as required, then run `setup()`.
+    pub fn from_target(
+        pll_src: PllSrc,
+        sysclk: u32,
+        usb_48mhz_required: bool,
+    ) -> Result<Self, PllSolveError> {
+        if sysclk > MAX_SYSCLK {
+            return Err(PllSolveError::AboveMax {
+                requested: sysclk,
+                max: MAX_SYSCLK,
+            });
+        }
+
+        // The USB prescaler can only divide the PLL output by 1 or 1.5.
+        let usb_pre = match sysclk {
+            48_000_000 => Some(UsbPrescaler::Div1),
+            72_000_000 => Some(UsbPrescaler::Div1_5),
+            _ => None,
+        };
+
+        if usb_48mhz_required && usb_pre.is_none() {
+            return Err(PllSolveError::Usb48Unreachable);
+        }
+
+        // Note that HSI / 2 bypasses PREDIV.
+        let (input_freq, predivs) = match pll_src {
+            PllSrc::HsiDiv2 => (4_000_000, &PREDIV_DIVS[..1]),
+            PllSrc::Hse(freq) => (freq, &PREDIV_DIVS[..]),
+        };
+
+        let mut pll_in_reachable = false;
+        let mut closest = 0;
+
+        for prediv in predivs {
+            let pll_in = input_freq / prediv.value() as u32;
+            if pll_in < PLL_IN_MIN || pll_in > PLL_IN_MAX {
+                continue;
+            }
+            pll_in_reachable = true;
+
+            for pll_mul in PLL_MULS.iter() {
+                // Matches the calculation in `sysclk()`.
+                let freq = pll_in * pll_mul.value() as u32;
+                if freq < PLL_OUT_MIN {
+                    continue;
+                }
+
+                if freq != sysclk {
+                    if freq < sysclk && freq > closest {
+                        closest = freq;
+                    }
+                    continue;
+                }
+
+                return Ok(Self {
+                    input_src: InputSrc::Pll(pll_src),
+                    prediv: *prediv,
+                    pll_mul: *pll_mul,
+                    usb_pre: usb_pre.unwrap_or(UsbPrescaler::Div1),
+                    apb1_prescaler: apb_prescaler_for(sysclk, MAX_APB1),
+                    apb2_prescaler: apb_prescaler_for(sysclk, MAX_APB2),
+                    ..Default::default()
+                });
+            }
+        }
+
+        if !pll_in_reachable {
+            Err(PllSolveError::VcoInputUnreachable {
+                src_freq: input_freq,
+            })
+        } else {
+            Err(PllSolveError::NoSolution { closest })
+        }
+    }
+
+    #[cfg(feature = "f4")]
+    /// Create a config that runs SYSCLK from the PLL at `sysclk` Hz, by searching the PLLM, PLLN
+    /// and PLLP values within the VCO's input and output ranges. If `usb_48mhz_required` is true,
+    /// PLLQ is also set to 48Mhz. Lower PLLM values are tried first, since a higher VCO input
+    /// frequency reduces jitter. APB prescalers are set to the lowest values that keep APB1 and APB2
+    /// within limits. Other fields are left at their `Default` values; modify as required, then
+    /// run `setup()`.
+    pub fn from_target(
+        pll_src: PllSrc,
+        sysclk: u32,
+        usb_48mhz_required: bool,
+    ) -> Result<Self, PllSolveError> {
+        if sysclk > MAX_SYSCLK {
+            return Err(PllSolveError::AboveMax {
+                requested: sysclk,
+                max: MAX_SYSCLK,
+            });
+        }
+
+        let input_freq = match pll_src {
+            PllSrc::Hsi => 16_000_000,
+            PllSrc::Hse(freq) => freq,
+        };
+
+        let mut vco_in_reachable = false;
+        let mut usb_unreachable = false;
+        let mut closest = 0;
+
+        for pllm in 2..=63 {
+            let vco_in = input_freq / pllm as u32;
+            if vco_in < VCO_IN_MIN || vco_in > VCO_IN_MAX {
+                continue;
+            }
+            vco_in_reachable = true;
+
+            for plln in 50..=432 {
+                let vco_out = vco_in * plln as u32;
+                if vco_out < VCO_OUT_MIN || vco_out > VCO_OUT_MAX {
+                    continue;
+                }
+
+                for pllp in PLLP_DIVS.iter() {
+                    // Matches the calculation in `sysclk()`.
+                    let freq = vco_out / pllp.value() as u32;
+                    if freq != sysclk {
+                        if freq < sysclk && freq > closest {
+                            closest = freq;
+                        }
+                        continue;
+                    }
+
+                    let pllq = PLLQ_DIVS
+                        .iter()
+                        .find(|pllq| vco_out / pllq.value() as u32 == 48_000_000);
+
+                    if usb_48mhz_required && pllq.is_none() {
+                        usb_unreachable = true;
+                        continue;
+                    }
+
+                    return Ok(Self {
+                        input_src: InputSrc::Pll(pll_src),
+                        pllm,
+                        plln,
+                        pllp: *pllp,
+                        pllq: pllq.copied().unwrap_or(Pllq::Div8),
+                        apb1_prescaler: apb_prescaler_for(sysclk, MAX_APB1),
+                        apb2_prescaler: apb_prescaler_for(sysclk, MAX_APB2),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        if !vco_in_reachable {
+            Err(PllSolveError::VcoInputUnreachable {
+                src_freq: input_freq,
+            })
+        } else if usb_unreachable {
+            Err(PllSolveError::Usb48Unreachable)
+        } else {
+            Err(PllSolveError::NoSolution { closest })
+        }
+    }
+
+    /// Calculate every clock speed this config produces, eg for logging. Use with
+    /// `Clocks::read_active()` to report the speeds the MCU is actually running at. This doesn't
+    /// read registers; `rtc` is `None`. See `rtc_clock()`.
+    pub fn report(&self) -> ClockReport {
+        let pll = match self.input_src {
+            InputSrc::Pll(_) => {
+                #[cfg(feature = "f3")]
+                let pll = PllSpeeds {
+                    p: Some(self.sysclk()),
+                    ..Default::default()
+                };
+                #[cfg(feature = "f4")]
+                let pll = PllSpeeds {
+                    p: Some(self.sysclk()),
+                    q: Some(self.usb()),
+                    r: None,
+                };
+
+                Some(pll)
+            }
+            _ => None,
+        };
+
+        cfg_if! {
+            if #[cfg(any(feature = "f301", feature = "f3x4"))] {
+                let usb = None;
+            } else if #[cfg(feature = "f3")] {
+                let usb = Some(self.usb());
+            } else {
+                // The F4 USB clock is PLLQ.
+                let usb = pll.and_then(|p| p.q);
+            }
+        }
+
+        ClockReport {
+            sysclk: self.sysclk(),
+            hclk: self.hclk(),
+            systick: self.systick(),
+            apb1: self.apb1(),
+            apb1_timer: self.apb1_timer(),
+            apb2: self.apb2(),
+            apb2_timer: self.apb2_timer(),
+            apb3: None,
+            apb4: None,
+            usb,
+            sai1: None,
+            adc: Some(self.kernel_speed(KernelClockPeriph::Adc)),
+            plls: [pll, None, None],
+            rtc: None,
+        }
+    }
+
+    /// Read the RTC clock source from the RCC BDCR register, and calculate its speed, using this
+    /// config's HSE frequency. The RTC clock is set up by `Rtc::new()`, so `report()` doesn't
+    /// include it; eg set `ClockReport::rtc` with this.
+    pub fn rtc_clock(&self) -> Option<(RtcClockSource, u32)> {
+        let hse_freq = match self.input_src {
+            InputSrc::Hse(freq) | InputSrc::Pll(PllSrc::Hse(freq)) => freq,
+            _ => 0,
+        };
+
+        rtc_clock(hse_freq)
+    }
+
+    /// Read the active clock configuration from RCC registers, eg to find what a bootloader set
+    /// up, or to check speeds with `Clocks::read_active(hse_freq).report()`. `hse_freq` is in Hz;
+    /// it can't be read from registers, and is ignored if the HSE isn't used. The input source,
+    /// PLL, bus prescalers, and oscillator settings are read; other fields keep their `Default`
+    /// values.
+    pub fn read_active(hse_freq: u32) -> Self {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        let cr = rcc.cr.read();
+        let cfgr = rcc.cfgr.read();
+
+        let default = Self::default();
+
+        cfg_if! {
+            if #[cfg(any(feature = "f301", feature = "f373", feature = "f3x4"))] {
+                let pll_src_hse = cfgr.pllsrc().bit_is_set();
+            } else if #[cfg(feature = "f3")] {
+                let pll_src_hse = cfgr.pllsrc().bits() != 0;
+            } else {
+                let pll_src_hse = rcc.pllcfgr.read().pllsrc().bit_is_set();
+            }
+        }
+
+        let pll_src = if pll_src_hse {
+            PllSrc::Hse(hse_freq)
+        } else {
+            #[cfg(feature = "f3")]
+            let pll_src = PllSrc::HsiDiv2;
+            #[cfg(feature = "f4")]
+            let pll_src = PllSrc::Hsi;
+            pll_src
+        };
+
+        let input_src = match cfgr.sws().bits() {
+            0b01 => InputSrc::Hse(hse_freq),
+            0b10 => InputSrc::Pll(pll_src),
+            _ => InputSrc::Hsi,
+        };
+
+        #[cfg(feature = "f3")]
+        let (prediv_bits, pllmul_bits) = (rcc.cfgr2.read().prediv().bits(), cfgr.pllmul().bits());
+        #[cfg(feature = "f4")]
+        let pllcfgr = rcc.pllcfgr.read();
+
+        Self {
+            input_src,
+            #[cfg(feature = "f3")]
+            prediv: PREDIV_DIVS
+                .iter()
+                .find(|d| **d as u8 == prediv_bits)
+                .copied()
+                .unwrap_or(default.prediv),
+            // PLLMUL values above 0b1110 also multiply by 16.
+            #[cfg(feature = "f3")]
+            pll_mul: PLL_MULS
+                .iter()
+                .find(|m| **m as u8 == pllmul_bits)
+                .copied()
+                .unwrap_or(PllMul::Mul16),
+            #[cfg(feature = "f4")]
+            pllm: pllcfgr.pllm().bits(),
+            #[cfg(feature = "f4")]
+            plln: pllcfgr.plln().bits(),
+            #[cfg(feature = "f4")]
+            pllp: PLLP_DIVS
+                .iter()
+                .find(|d| **d as u8 == pllcfgr.pllp().bits())
+                .copied()
+                .unwrap_or(default.pllp),
+            #[cfg(feature = "f4")]
+            pllq: PLLQ_DIVS
+                .iter()
+                .find(|d| **d as u8 == pllcfgr.pllq().bits())
+                .copied()
+                .unwrap_or(default.pllq),
+            #[cfg(not(any(feature = "f301", feature = "f3x4", feature = "f4")))]
+            usb_pre: if cfgr.usbpre().bit_is_set() {
+                UsbPrescaler::Div1
+            } else {
+                UsbPrescaler::Div1_5
+            },
+            hclk_prescaler: HclkPrescaler::from_bits(cfgr.hpre().bits()),
+            apb1_prescaler: ApbPrescaler::from_bits(cfgr.ppre1().bits()),
+            apb2_prescaler: ApbPrescaler::from_bits(cfgr.ppre2().bits()),
+            hse_bypass: cr.hsebyp().bit_is_set(),
+            security_system: cr.csson().bit_is_set(),
+            ..default
+        }
+    }
+}
+
+/// Check if the clock security system has detected an HSE failure. Run this in the NMI handler,
+/// then clear the flag with `clear_osc_failure()`; the NMI fires again if it isn't cleared.
+pub fn osc_failure() -> Option<OscFailure> {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    if rcc.cir.read().cssf().bit_is_set() {
+        Some(OscFailure::Hse)
+    } else {
+        None
+    }
+}
+
+/// Clear an oscillator failure flag, as reported by `osc_failure()`.
+pub fn clear_osc_failure(failure: OscFailure) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    match failure {
+        OscFailure::Hse => rcc.cir.modify(|_, w| w.cssc().set_bit()),
+    }
+}
+
+/// Find the lowest APB prescaler that keeps the APB clock at or below `max`, from `hclk`.
+fn apb_prescaler_for(hclk: u32, max: u32) -> ApbPrescaler {
+    [
+        ApbPrescaler::Div1,
+        ApbPrescaler::Div2,
+        ApbPrescaler::Div4,
+        ApbPrescaler::Div8,
+    ]
+    .iter()
+    .find(|p| hclk / p.value() as u32 <= max)
+    .copied()
+    .unwrap_or(ApbPrescaler::Div16)
+}
+
+#[cfg(feature = "f3")]
+/// The RCC_CFGR3 field value that selects `src` as `periph`'s kernel clock, or `None` if
+/// `periph` can't use it.
+fn kernel_clock_bits(periph: KernelClockPeriph, src: KernelClock) -> Option<u8> {
+    match periph {
+        KernelClockPeriph::Usart1 | KernelClockPeriph::Usart2 | KernelClockPeriph::Usart3 => {
+            match src {
+                KernelClock::Apb => Some(0b00),
+                KernelClock::Sysclk => Some(0b01),
+                KernelClock::Lse => Some(0b10),
+                KernelClock::Hsi => Some(0b11),
+                _ => None,
+            }
+        }
+        KernelClockPeriph::I2c1 | KernelClockPeriph::I2c2 => match src {
+            KernelClock::Hsi => Some(0),
+            KernelClock::Sysclk => Some(1),
+            _ => None,
+        },
+        // The rest don't exist on F3, or don't have a selectable clock; their fields are ignored.
+        _ => Some(0),
+    }
 }
 
 impl Default for Clocks {
@@ -730,6 +1699,7 @@ impl Default for Clocks {
             apb2_prescaler: ApbPrescaler::Div1,
             hse_bypass: false,
             security_system: false,
+            kernel_clocks: Default::default(),
         }
     }
 
@@ -743,7 +1713,8 @@ impl Default for Clocks {
     fn default() -> Self {
         cfg_if! {
             if #[cfg(feature = "f401")] {
-                let plln = 84;
+                // F401's VCO output must be at least 192Mhz.
+                let plln = 168;
             } else if #[cfg(any(feature = "f410", feature = "f411", feature = "f412", feature = "f413"))] {
                 let plln = 100;
             } else if #[cfg(any(feature = "f405", feature = "f407"))] {
@@ -757,6 +1728,9 @@ impl Default for Clocks {
             input_src: InputSrc::Pll(PllSrc::Hsi),
             pllm: 8,
             plln,
+            #[cfg(feature = "f401")]
+            pllp: Pllp::Div4,
+            #[cfg(not(feature = "f401"))]
             pllp: Pllp::Div2,
             pllq: Pllq::Div8, // Note that this produces an invalid USB speed.
             hclk_prescaler: HclkPrescaler::Div1,
@@ -770,6 +1744,163 @@ impl Default for Clocks {
             apb2_prescaler: ApbPrescaler::Div2,
             hse_bypass: false,
             security_system: false,
+            kernel_clocks: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_valid() {
+        assert_eq!(Clocks::default().validate_speeds(), Ok(()));
+    }
+
+    #[test]
+    fn from_target_max_sysclk() {
+        let clocks = Clocks::from_target(PllSrc::Hse(8_000_000), MAX_SYSCLK, false).unwrap();
+
+        assert_eq!(clocks.sysclk(), MAX_SYSCLK);
+        assert_eq!(clocks.validate_speeds(), Ok(()));
+    }
+
+    #[test]
+    fn from_target_usb() {
+        let clocks = Clocks::from_target(PllSrc::Hse(8_000_000), 48_000_000, true).unwrap();
+
+        assert_eq!(clocks.sysclk(), 48_000_000);
+        assert_eq!(clocks.usb(), 48_000_000);
+        assert_eq!(clocks.validate_speeds(), Ok(()));
+    }
+
+    #[test]
+    fn from_target_errors() {
+        assert_eq!(
+            Clocks::from_target(PllSrc::Hse(8_000_000), MAX_SYSCLK + 1, false).err(),
+            Some(PllSolveError::AboveMax {
+                requested: MAX_SYSCLK + 1,
+                max: MAX_SYSCLK,
+            })
+        );
+
+        // F3 can only make 48Mhz for USB from a 48 or 72Mhz PLL output.
+        #[cfg(feature = "f3")]
+        assert_eq!(
+            Clocks::from_target(PllSrc::Hse(8_000_000), 64_000_000, true).err(),
+            Some(PllSolveError::Usb48Unreachable)
+        );
+
+        // Below the PLL input range, with any input divider.
+        assert_eq!(
+            Clocks::from_target(PllSrc::Hse(500_000), 48_000_000, false).err(),
+            Some(PllSolveError::VcoInputUnreachable { src_freq: 500_000 })
+        );
+    }
+
+    #[test]
+    fn apb_limits() {
+        let clocks = Clocks {
+            apb1_prescaler: ApbPrescaler::Div1,
+            ..Clocks::from_target(PllSrc::Hse(8_000_000), MAX_SYSCLK, false).unwrap()
+        };
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::ApbOverMax {
+                bus: 1,
+                freq: MAX_SYSCLK,
+                max: MAX_APB1,
+            })
+        );
+    }
+
+    #[test]
+    fn sysclk_over_max() {
+        let clocks = Clocks {
+            input_src: InputSrc::Hse(MAX_SYSCLK + 1_000_000),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::SysclkOverMax {
+                freq: MAX_SYSCLK + 1_000_000,
+                max: MAX_SYSCLK,
+            })
+        );
+    }
+
+    #[cfg(feature = "f3")]
+    #[test]
+    fn pll_input_out_of_range() {
+        let clocks = Clocks {
+            input_src: InputSrc::Pll(PllSrc::Hse(32_000_000)),
+            prediv: Prediv::Div1,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::VcoInputOutOfRange {
+                pll: 1,
+                freq: 32_000_000,
+                min: PLL_IN_MIN,
+                max: PLL_IN_MAX,
+            })
+        );
+    }
+
+    #[cfg(feature = "f4")]
+    #[test]
+    fn pll_limits() {
+        let mut clocks = Clocks {
+            pllm: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::PllDividerOutOfRange {
+                pll: 1,
+                divider: PllDivider::M,
+                value: 1,
+                min: 2,
+                max: 63,
+            })
+        );
+
+        // A 4Mhz VCO input, from the 16Mhz HSI.
+        clocks.pllm = 4;
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::VcoInputOutOfRange {
+                pll: 1,
+                freq: 4_000_000,
+                min: VCO_IN_MIN,
+                max: VCO_IN_MAX,
+            })
+        );
+
+        // A 100Mhz VCO output, from a 2Mhz VCO input.
+        clocks.pllm = 8;
+        clocks.plln = 50;
+
+        let result = clocks.validate_speeds();
+        if VCO_OUT_MIN > 100_000_000 {
+            assert_eq!(
+                result,
+                Err(SpeedError::VcoOutputOutOfRange {
+                    pll: 1,
+                    freq: 100_000_000,
+                    min: VCO_OUT_MIN,
+                    max: VCO_OUT_MAX,
+                })
+            );
+        } else {
+            assert_eq!(result, Ok(()));
         }
     }
 }
@@ -5,8 +5,14 @@
 // Similar in from to the `baseline` clocks module, but includes notable differendes.
 
 use crate::{
-    clocks::SpeedError,
+    clocks::{
+        rtc_clock, switch_steps, ClockReport, KernelClock, KernelClockPeriph, KernelClocks,
+        OscFailure, PllDivider, PllSolveError, PllSpeeds, SpeedError, SwitchStep,
+        KERNEL_CLOCK_PERIPHS,
+    },
+    gpio::{OutputSpeed, Pin, PinMode},
     pac::{CRS, FLASH, PWR, RCC, SYSCFG},
+    rtc::RtcClockSource,
 };
 
 use cfg_if::cfg_if;
@@ -92,9 +98,9 @@ impl InputSrc {
 
 /// Configures the speeds, and enable status of an individual PLL. Note that the `enable`
 /// field has no effect for PLL1.
+#[derive(Clone, Copy)]
 pub struct PllCfg {
     pub enabled: bool,
-    // pub fractional: bool,
     pub pllp_en: bool,
     pub pllq_en: bool,
     pub pllr_en: bool,
@@ -103,6 +109,10 @@ pub struct PllCfg {
     pub divp: u8,
     pub divq: u8,
     pub divr: u8,
+    /// Fractional part of the multiplication factor, in steps of 1/8192. (0 - 8191) The VCO output
+    /// is `input / divm * (divn + fracn / 8192)`. 0 disables fractional mode. Sets RCC_PLLxFRACR
+    /// register, FRACNx field.
+    pub fracn: u16,
 }
 
 impl Default for PllCfg {
@@ -110,7 +120,6 @@ impl Default for PllCfg {
     fn default() -> Self {
         Self {
             enabled: true,
-            // fractional: false,
             pllp_en: true,
             pllq_en: false,
             pllr_en: false,
@@ -123,6 +132,7 @@ impl Default for PllCfg {
             divp: 2,
             divq: 2, // Allows <150Mhz SAI clock, if it's configureud for PLL1Q.
             divr: 2,
+            fracn: 0,
         }
     }
 }
@@ -168,6 +178,21 @@ impl HclkPrescaler {
             Self::Div512 => 512,
         }
     }
+
+    /// Convert from a D1CPRE or HPRE register field.
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0b1000 => Self::Div2,
+            0b1001 => Self::Div4,
+            0b1010 => Self::Div8,
+            0b1011 => Self::Div16,
+            0b1100 => Self::Div64,
+            0b1101 => Self::Div128,
+            0b1110 => Self::Div256,
+            0b1111 => Self::Div512,
+            _ => Self::Div1,
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -191,6 +216,17 @@ impl ApbPrescaler {
             Self::Div16 => 16,
         }
     }
+
+    /// Convert from a DxPPRE register field.
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0b100 => Self::Div2,
+            0b101 => Self::Div4,
+            0b110 => Self::Div8,
+            0b111 => Self::Div16,
+            _ => Self::Div1,
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -201,7 +237,72 @@ pub enum SaiSrc {
     Pll2P = 0b001,
     Pll3P = 0b010,
     I2sCkin = 0b011,
-    PerClk = 0100,
+    PerClk = 0b100,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// SPI1, 2, and 3 kernel clock source selection. Sets RCC_D2CCIP1R register, SPI123SEL field.
+pub enum Spi123Src {
+    Pll1Q = 0b000,
+    Pll2P = 0b001,
+    Pll3P = 0b010,
+    I2sCkin = 0b011,
+    PerClk = 0b100,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// SPI4 and 5 kernel clock source selection. Sets RCC_D2CCIP1R register, SPI45SEL field. Also used
+/// for SPI6, which sets RCC_D3CCIPR register, SPI6SEL field; `Apb` selects APB4 there.
+pub enum Spi45Src {
+    /// APB2 clock (default after reset)
+    Apb = 0b000,
+    Pll2Q = 0b001,
+    Pll3Q = 0b010,
+    Hsi = 0b011,
+    Csi = 0b100,
+    Hse = 0b101,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// ADC kernel clock source selection. Sets RCC_D3CCIPR register, ADCSEL field.
+pub enum AdcSrc {
+    Pll2P = 0b00,
+    Pll3R = 0b01,
+    PerClk = 0b10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// FDCAN kernel clock source selection. Sets RCC_D2CCIP1R register, FDCANSEL field.
+pub enum FdcanSrc {
+    Hse = 0b00,
+    Pll1Q = 0b01,
+    Pll2Q = 0b10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// QUADSPI (OCTOSPI on H723-735) kernel clock source selection. Sets RCC_D1CCIPR register,
+/// QSPISEL or OCTOSPISEL field.
+pub enum QspiSrc {
+    /// HCLK3 (default after reset)
+    Hclk = 0b00,
+    Pll1Q = 0b01,
+    Pll2R = 0b10,
+    PerClk = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Source of the `per_ck` clock, which peripherals can select as their kernel clock. Sets
+/// RCC_D1CCIPR register, CKPERSEL field.
+pub enum CkPerSrc {
+    Hsi = 0b00,
+    Csi = 0b01,
+    Hse = 0b10,
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -233,6 +334,16 @@ impl HsiDiv {
             Self::Div8 => 8,
         }
     }
+
+    /// Convert from the HSIDIV register field.
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0b01 => Self::Div2,
+            0b10 => Self::Div4,
+            0b11 => Self::Div8,
+            _ => Self::Div1,
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -253,6 +364,108 @@ pub enum VosRange {
 }
 
 impl VosRange {
+    /// The maximum SYSCLK frequency supported in this range, in Hz.
+    pub fn max_sysclk(&self) -> u32 {
+        cfg_if! {
+            if #[cfg(feature = "h735")] {
+                match self {
+                    Self::VOS0 => 520_000_000,
+                    Self::VOS1 => 400_000_000,
+                    Self::VOS2 => 300_000_000,
+                    Self::VOS3 => 170_000_000,
+                }
+            } else if #[cfg(feature = "h7b3")] {
+                match self {
+                    Self::VOS1 => 280_000_000,
+                    Self::VOS2 => 225_000_000,
+                    Self::VOS3 => 160_000_000,
+                }
+            } else {
+                match self {
+                    Self::VOS0 => 480_000_000,
+                    Self::VOS1 => 400_000_000,
+                    Self::VOS2 => 300_000_000,
+                    Self::VOS3 => 200_000_000,
+                }
+            }
+        }
+    }
+
+    /// The maximum HCLK frequency supported in this range, in Hz. See the general operating
+    /// conditions table in your MCU's datasheet.
+    pub fn max_hclk(&self) -> u32 {
+        cfg_if! {
+            if #[cfg(feature = "h735")] {
+                match self {
+                    Self::VOS0 => 275_000_000,
+                    Self::VOS1 => 200_000_000,
+                    Self::VOS2 => 150_000_000,
+                    Self::VOS3 => 85_000_000,
+                }
+            } else if #[cfg(feature = "h7b3")] {
+                match self {
+                    Self::VOS1 => 280_000_000,
+                    Self::VOS2 => 225_000_000,
+                    Self::VOS3 => 160_000_000,
+                }
+            } else {
+                match self {
+                    Self::VOS0 => 240_000_000,
+                    Self::VOS1 => 200_000_000,
+                    Self::VOS2 => 150_000_000,
+                    Self::VOS3 => 100_000_000,
+                }
+            }
+        }
+    }
+
+    /// The highest HCLK frequency `wait_states()` has a setting for, in Hz. This may be above
+    /// `max_hclk()`; eg the H743 table goes to 225Mhz in each range below VOS0.
+    pub fn max_wait_state_hclk(&self) -> u32 {
+        #[cfg(not(feature = "h735"))]
+        match self {
+            #[cfg(not(feature = "h7b3"))]
+            Self::VOS0 => 240_000_000,
+            _ => 225_000_000,
+        }
+
+        #[cfg(feature = "h735")]
+        match self {
+            Self::VOS0 => 275_000_000,
+            Self::VOS1 => 200_000_000,
+            Self::VOS2 => 150_000_000,
+            Self::VOS3 => 85_000_000,
+        }
+    }
+
+    /// The maximum APB (rcc_pclk1-4) frequency supported in this range, in Hz. See the general
+    /// operating conditions table in your MCU's datasheet.
+    pub fn max_apb(&self) -> u32 {
+        cfg_if! {
+            if #[cfg(feature = "h735")] {
+                match self {
+                    Self::VOS0 => 137_500_000,
+                    Self::VOS1 => 100_000_000,
+                    Self::VOS2 => 75_000_000,
+                    Self::VOS3 => 42_500_000,
+                }
+            } else if #[cfg(feature = "h7b3")] {
+                match self {
+                    Self::VOS1 => 140_000_000,
+                    Self::VOS2 => 112_500_000,
+                    Self::VOS3 => 80_000_000,
+                }
+            } else {
+                match self {
+                    Self::VOS0 => 120_000_000,
+                    Self::VOS1 => 100_000_000,
+                    Self::VOS2 => 75_000_000,
+                    Self::VOS3 => 50_000_000,
+                }
+            }
+        }
+    }
+
     /// Power regulator voltage scale.
     /// Choose the wait states based on VSO range and hclk frequency.. See H743 RM, Table 17: FLASH,
     /// or RM0468, table 16.
@@ -328,9 +541,83 @@ impl VosRange {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Clock output on the MCO1 pin. Sets RCC_CFGR register, MCO1 field.
+pub enum Mco1Src {
+    /// HSI, after HSIDIV.
+    Hsi = 0b000,
+    Lse = 0b001,
+    Hse = 0b010,
+    Pll1Q = 0b011,
+    Hsi48 = 0b100,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Clock output on the MCO2 pin. Sets RCC_CFGR register, MCO2 field.
+pub enum Mco2Src {
+    Sysclk = 0b000,
+    Pll2P = 0b001,
+    Hse = 0b010,
+    Pll1P = 0b011,
+    Csi = 0b100,
+    Lsi = 0b101,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Clock output on an MCO pin. The source determines which pin is used: MCO1 on PA8, or MCO2
+/// on PC9.
+pub enum McoSrc {
+    Mco1(Mco1Src),
+    Mco2(Mco2Src),
+}
+
+/// Microcontroller Clock Output (MCO) configuration. See the `clocks` module documentation.
+#[derive(Clone, Copy)]
+pub struct Mco {
+    pub src: McoSrc,
+    /// Divides the source clock. 1 - 15. Sets RCC_CFGR register, MCO1PRE and MCO2PRE fields.
+    pub prescaler: u8,
+}
+
+/// Stop outputting a clock on an MCO pin. MCO1 and MCO2 have no source setting that turns them
+/// off, so this takes `pin` out of its MCO alternate function, and sets it to analog mode.
+pub fn disable_mco(pin: &mut Pin) {
+    pin.mode(PinMode::Analog);
+}
+
+impl Mco {
+    /// Configure `pin` for MCO, and start outputting the clock on it. MCO1 is alternate function
+    /// 0 on PA8, and MCO2 is alternate function 0 on PC9.
+    pub fn enable(&self, pin: &mut Pin) {
+        assert!(
+            self.prescaler >= 1 && self.prescaler <= 15,
+            "MCO prescaler must be 1 - 15."
+        );
+
+        pin.mode(PinMode::Alt(0));
+        pin.output_speed(OutputSpeed::High);
+
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        match self.src {
+            McoSrc::Mco1(src) => rcc.cfgr.modify(|_, w| unsafe {
+                w.mco1pre().bits(self.prescaler);
+                w.mco1().bits(src as u8)
+            }),
+            McoSrc::Mco2(src) => rcc.cfgr.modify(|_, w| unsafe {
+                w.mco2pre().bits(self.prescaler);
+                w.mco2().bits(src as u8)
+            }),
+        }
+    }
+}
+
 /// Settings used to configure clocks. Create this struct by using its `Default::default()`
 /// implementation, then modify as required, referencing your RM's clock tree,
 /// or Stm32Cube IDE's interactive clock manager. Apply settings by running `.setup()`.
+#[derive(Clone)]
 pub struct Clocks {
     /// The main input source
     pub input_src: InputSrc,
@@ -360,7 +647,13 @@ pub struct Clocks {
     pub hse_bypass: bool,
     /// USBOTG kernel clock selection. Defaults to HSI48.
     pub usb_src: UsbSrc,
+    /// Enable the HSE clock security system (CSS). If the HSE fails, hardware switches SYSCLK to
+    /// HSI, and triggers an NMI. See `osc_failure()` and `Clocks::fail_over()`. Defaults to false.
     pub security_system: bool,
+    /// Enable the LSE clock security system. This only takes effect if the LSE is running and
+    /// selected as the RTC clock (eg by `Rtc::new()`) when `setup()` is run. Once enabled, only a
+    /// backup domain reset disables it. Defaults to false.
+    pub lse_security_system: bool,
     pub hsi48_on: bool,
     pub stop_wuck: StopWuck,
     pub vos_range: VosRange,
@@ -373,6 +666,25 @@ pub struct Clocks {
     pub sai4b_src: SaiSrc,
     /// DFSDM1 kernel clock source selection
     pub dfsdm1_src: DfsdmSrc,
+    /// SPI1, 2, and 3 kernel clock source selection
+    pub spi123_src: Spi123Src,
+    /// SPI4 and 5 kernel clock source selection
+    pub spi45_src: Spi45Src,
+    /// SPI6 kernel clock source selection
+    pub spi6_src: Spi45Src,
+    /// ADC kernel clock source selection. Only used in the ADC's async clock mode.
+    pub adc_src: AdcSrc,
+    /// FDCAN kernel clock source selection
+    pub fdcan_src: FdcanSrc,
+    /// QUADSPI or OCTOSPI kernel clock source selection
+    pub qspi_src: QspiSrc,
+    /// `per_ck` source selection. Used by peripherals whose source is set to `PerClk`.
+    pub ckper_src: CkPerSrc,
+    /// The frequency of the external clock on the I2S_CKIN pin, in Hz. This can't be read from
+    /// registers, so set it if a SAI or SPI1-3 source is `I2sCkin`. Defaults to 0; ie unused.
+    pub i2s_ckin_freq: u32,
+    /// Kernel clock selection for USART, LPUART, I2C and LPTIM peripherals.
+    pub kernel_clocks: KernelClocks,
 }
 
 impl Clocks {
@@ -388,8 +700,6 @@ impl Clocks {
         }
 
         let rcc = unsafe { &(*RCC::ptr()) };
-        let flash = unsafe { &(*FLASH::ptr()) };
-        let pwr = unsafe { &(*PWR::ptr()) };
 
         // Enable and reset System Configuration Controller, ie for interrupts.
         // todo: Is this the right module to do this in?
@@ -397,62 +707,17 @@ impl Clocks {
         rcc.apb4rstr.modify(|_, w| w.syscfgrst().set_bit());
         rcc.apb4rstr.modify(|_, w| w.syscfgrst().clear_bit());
 
-        // H743 RM, sefction 6.8.6, and section 6.6.2: Voltage Scaling
-        //  Voltage scaling selection according to performance
-        // These bits control the VCORE voltage level and allow to obtains the best trade-off between
-        // power consumption and performance:
-        // – When increasing the performance, the voltage scaling shall be changed before increasing
-        // the system frequency.
-        // – When decreasing performance, the system frequency shall first be decreased before
-        // changing the voltage scaling.
-        match self.vos_range {
-            #[cfg(not(any(feature = "h7b3", feature = "h735")))]
-            // Note:H735 etc have VOS0, but not oden; the RM doesn't list these steps.
-            VosRange::VOS0 => {
-                let syscfg = unsafe { &(*SYSCFG::ptr()) };
-
-                // VOS0 activation/deactivation sequence: H743 HRM, section 6.6.2:
-                // The system maximum frequency can be reached by boosting the voltage scaling level to
-                // VOS0. This is done through the ODEN bit in the SYSCFG_PWRCR register.
-                // The sequence to activate the VOS0 is the following:
-                // 1. Ensure that the system voltage scaling is set to VOS1 by checking the VOS bits in
-                // PWR D3 domain control register (PWR D3 domain control register (PWR_D3CR))
-                pwr.d3cr
-                    .modify(|_, w| unsafe { w.vos().bits(VosRange::VOS1 as u8) });
-                while pwr.d3cr.read().vosrdy().bit_is_clear() {}
-
-                // 2. Enable the SYSCFG clock in the RCC by setting the SYSCFGEN bit in the
-                // RCC_APB4ENR register.
-                // (Handled above)
-
-                // 3. Enable the ODEN bit in the SYSCFG_PWRCR register.
-                syscfg.pwrcr.modify(|_, w| w.oden().set_bit());
-
-                // 4. Wait for VOSRDY to be set.
-                while pwr.d3cr.read().vosrdy().bit_is_clear() {}
+        self.set_vos();
+        self.set_wait_states();
+        self.configure();
 
-                // Once the VCORE supply has reached the required level, the system frequency can be
-                // increased. Figure 31 shows the recommended sequence for switching VCORE from VOS1 to
-                // VOS0 sequence.
-                // The sequence to deactivate the VOS0 is the following:
-                // 1. Ensure that the system frequency was decreased.
-                // 2. Ensure that the SYSCFG clock is enabled in the RCC by setting the SYSCFGEN bit set
-                // in the RCC_APB4ENR register.
-                // 3. Reset the ODEN bit in the SYSCFG_PWRCR register to disable VOS0.
-            }
-            _ => pwr
-                .d3cr
-                .modify(|_, w| unsafe { w.vos().bits(self.vos_range as u8) }),
-        }
+        Ok(())
+    }
 
-        // Adjust flash wait states according to the HCLK frequency.
-        // We need to do this before enabling PLL, or it won't enable.
-        // H742 RM, Table 17.
-        let wait_states = self.vos_range.wait_states(self.hclk());
-        flash.acr.modify(|_, w| unsafe {
-            w.latency().bits(wait_states.0);
-            w.wrhighfreq().bits(wait_states.1)
-        });
+    /// Enable oscillators and PLLs, and set clock sources and prescalers. VOS and flash wait
+    /// states must support both the current and new HCLK before running this.
+    fn configure(&self) {
+        let rcc = unsafe { &(*RCC::ptr()) };
 
         // Enable oscillators, and wait until ready.
         match self.input_src {
@@ -500,10 +765,8 @@ impl Clocks {
             w.hsebyp().bit(self.hse_bypass)
         });
 
-        rcc.cfgr.modify(|_, w| unsafe {
-            w.sw().bits(self.input_src.bits());
-            w.stopwuck().bit(self.stop_wuck as u8 != 0)
-        });
+        rcc.cfgr
+            .modify(|_, w| w.stopwuck().bit(self.stop_wuck as u8 != 0));
 
         rcc.d1cfgr.modify(|_, w| unsafe {
             w.d1cpre().bits(self.d1_core_prescaler as u8);
@@ -519,31 +782,90 @@ impl Clocks {
         rcc.d3cfgr
             .modify(|_, w| unsafe { w.d3ppre().bits(self.d3_prescaler as u8) });
 
+        #[cfg(not(feature = "h7b3"))]
+        rcc.d1ccipr.modify(|_, w| unsafe {
+            w.ckpersel().bits(self.ckper_src as u8);
+            #[cfg(feature = "h735")]
+            return w.octospisel().bits(self.qspi_src as u8);
+            #[cfg(not(feature = "h735"))]
+            w.qspisel().bits(self.qspi_src as u8)
+        });
+
         #[cfg(not(feature = "h7b3"))]
         rcc.d2ccip1r.modify(|_, w| unsafe {
             w.sai1sel().bits(self.sai1_src as u8);
             #[cfg(not(feature = "h735"))]
             w.sai23sel().bits(self.sai23_src as u8);
+            w.spi123sel().bits(self.spi123_src as u8);
+            w.spi45sel().bits(self.spi45_src as u8);
+            w.fdcansel().bits(self.fdcan_src as u8);
             w.dfsdm1sel().bit(self.dfsdm1_src as u8 != 0)
         });
 
-        // Set USART2 to HSI, and USB to HSI48. Temp hardcoded.
-        // todo: Add config enums for these, and add them as Clocks fields.
+        // `validate_speeds()` has already rejected sources a peripheral can't use.
+        #[cfg(not(feature = "h7b3"))]
+        // Fields that aren't configured keep their current value.
+        let kernel_bits = |periph, current| {
+            self.kernel_clocks
+                .get(periph)
+                .and_then(|src| kernel_clock_bits(periph, src))
+                .unwrap_or(current)
+        };
+
         #[cfg(not(feature = "h7b3"))]
-        rcc.d2ccip2r.modify(|_, w| unsafe {
-            w.usart234578sel().bits(0b111);
+        rcc.d2ccip2r.modify(|r, w| unsafe {
+            #[cfg(feature = "h735")]
+            w.usart16910sel().bits(kernel_bits(
+                KernelClockPeriph::Usart1,
+                r.usart16910sel().bits(),
+            ));
+            #[cfg(not(feature = "h735"))]
+            w.usart16sel().bits(kernel_bits(
+                KernelClockPeriph::Usart1,
+                r.usart16sel().bits(),
+            ));
+            w.usart234578sel().bits(kernel_bits(
+                KernelClockPeriph::Usart2,
+                r.usart234578sel().bits(),
+            ));
+            w.i2c123sel()
+                .bits(kernel_bits(KernelClockPeriph::I2c1, r.i2c123sel().bits()));
+            w.lptim1sel()
+                .bits(kernel_bits(KernelClockPeriph::Lptim1, r.lptim1sel().bits()));
             w.usbsel().bits(self.usb_src as u8)
         });
 
         #[cfg(not(feature = "h7b3"))]
-        rcc.d3ccipr.modify(|_, w| unsafe {
+        rcc.d3ccipr.modify(|r, w| unsafe {
             w.sai4asel().bits(self.sai4a_src as u8);
-            w.sai4bsel().bits(self.sai4b_src as u8)
+            w.sai4bsel().bits(self.sai4b_src as u8);
+            w.adcsel().bits(self.adc_src as u8);
+            w.spi6sel().bits(self.spi6_src as u8);
+            w.lpuart1sel().bits(kernel_bits(
+                KernelClockPeriph::Lpuart1,
+                r.lpuart1sel().bits(),
+            ));
+            w.lptim2sel()
+                .bits(kernel_bits(KernelClockPeriph::Lptim2, r.lptim2sel().bits()))
         });
 
         rcc.cr.modify(|_, w| w.hsecsson().bit(self.security_system));
 
-        // todo: Allow configuring the PLL in fractional mode.
+        // LSECSSON must be set after the LSE and LSI are ready, and the LSE is selected as the RTC
+        // clock.
+        if self.lse_security_system
+            && rcc.bdcr.read().lserdy().bit_is_set()
+            && rcc.bdcr.read().rtcsel().bits() == 0b01
+        {
+            rcc.csr.modify(|_, w| w.lsion().set_bit());
+            while rcc.csr.read().lsirdy().bit_is_clear() {}
+
+            // BDCR is write-protected unless backup domain access is enabled.
+            let pwr = unsafe { &(*PWR::ptr()) };
+            pwr.cr1.modify(|_, w| w.dbp().set_bit());
+
+            rcc.bdcr.modify(|_, w| w.lsecsson().set_bit());
+        }
 
         rcc.pllckselr
             .modify(|_, w| w.pllsrc().bits(self.pll_src.bits()));
@@ -566,10 +888,6 @@ impl Clocks {
                 _ => panic!("PLL1 input source must be between 1Mhz and 16Mhz."),
             };
 
-            // todo: Don't enable all these pqr etc by default!
-            // todo don't enable pll2 and 3 by default!!
-            // todo: This has an impact on power consumption.
-
             // todo: MOre DRY
             // H743 RM:
             // 0: Wide VCO range: 192 to 836 MHz (default after reset)
@@ -596,7 +914,12 @@ impl Clocks {
             // we expect the user to run `.setup()` only once.
             rcc.pllckselr.modify(|_, w| w.divm1().bits(self.pll1.divm));
 
+            // FRACN is latched when PLL1FRACEN is set; the PLL is off here, so we can write it directly.
+            rcc.pll1fracr
+                .modify(|_, w| unsafe { w.fracn1().bits(self.pll1.fracn) });
+
             rcc.pllcfgr.modify(|_, w| {
+                w.pll1fracen().bit(self.pll1.fracn != 0);
                 w.pll1rge().bits(pll1_rng_val);
                 w.pll1vcosel().bit(pll1_vco != 0);
                 w.divp1en().bit(true);
@@ -616,6 +939,12 @@ impl Clocks {
             while rcc.cr.read().pll1rdy().bit_is_clear() {}
         }
 
+        // Select the system clock only once PLL1 is locked with its new settings. If SW selected
+        // PLL1 while it was still running with its old settings, PLL1ON couldn't be cleared above.
+        rcc.cfgr
+            .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+        while rcc.cfgr.read().sws().bits() != self.input_src.bits() {}
+
         // todo DRY
         if self.pll2.enabled {
             rcc.cr.modify(|_, w| w.pll2on().clear_bit());
@@ -636,7 +965,12 @@ impl Clocks {
 
             rcc.pllckselr.modify(|_, w| w.divm2().bits(self.pll2.divm));
 
+            // FRACN is latched when PLL2FRACEN is set; the PLL is off here, so we can write it directly.
+            rcc.pll2fracr
+                .modify(|_, w| unsafe { w.fracn2().bits(self.pll2.fracn) });
+
             rcc.pllcfgr.modify(|_, w| {
+                w.pll2fracen().bit(self.pll2.fracn != 0);
                 w.pll2rge().bits(pll2_rng_val);
                 w.pll2vcosel().bit(pll2_vco != 0);
                 w.divp2en().bit(self.pll2.pllp_en);
@@ -674,7 +1008,12 @@ impl Clocks {
 
             rcc.pllckselr.modify(|_, w| w.divm3().bits(self.pll3.divm));
 
+            // FRACN is latched when PLL3FRACEN is set; the PLL is off here, so we can write it directly.
+            rcc.pll3fracr
+                .modify(|_, w| unsafe { w.fracn3().bits(self.pll3.fracn) });
+
             rcc.pllcfgr.modify(|_, w| {
+                w.pll3fracen().bit(self.pll3.fracn != 0);
                 w.pll3rge().bits(pll3_rng_val);
                 w.pll3vcosel().bit(pll3_vco != 0);
                 w.divp3en().bit(self.pll3.pllp_en);
@@ -697,99 +1036,356 @@ impl Clocks {
             rcc.cr.modify(|_, w| w.hsi48on().set_bit());
             while rcc.cr.read().hsi48rdy().bit_is_clear() {}
         }
-
-        Ok(())
     }
 
-    /// Re-select input source; used on Stop and Standby modes, where the system reverts
-    /// to HSI after wake.
-    pub fn reselect_input(&self) {
-        // Re-select the input source; it will revert to HSI during `Stop` or `Standby` mode.
+    /// Switch to a new clock configuration at runtime, eg to scale frequency up or down. VOS
+    /// and flash wait states are raised before the clocks speed up, and lowered after they slow
+    /// down. If PLL1 is the current system clock, CSI is used as the system clock while the PLLs
+    /// are reconfigured. Returns an error without making changes if `new_cfg` is invalid.
+    /// Peripherals that derive timing from clock speeds must be updated after this; see
+    /// `Usart::retime`, `I2c::retime`, `Spi::retime`, and `Timer::retime`.
+    pub fn switch_to(&mut self, new_cfg: Self) -> Result<(), SpeedError> {
+        new_cfg.validate_speeds()?;
 
         let rcc = unsafe { &(*RCC::ptr()) };
+        let flash = unsafe { &(*FLASH::ptr()) };
 
-        // Note: It would save code repetition to pass the `Clocks` struct in and re-run setup
-        // todo: But this saves a few reg writes.
-        match self.input_src {
-            InputSrc::Hse(_) => {
-                rcc.cr.modify(|_, w| w.hseon().set_bit());
-                while rcc.cr.read().hserdy().bit_is_clear() {}
-
-                rcc.cfgr
-                    .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
-            }
-            InputSrc::Pll1 => {
-                // todo: DRY with above.
-                match self.pll_src {
-                    PllSrc::Hse(_) => {
-                        rcc.cr.modify(|_, w| w.hseon().set_bit());
-                        while rcc.cr.read().hserdy().bit_is_clear() {}
-                    }
-                    PllSrc::Hsi(div) => {
-                        // Generally reverts to Csi (see note below)
-                        rcc.cr.modify(|_, w| {
-                            w.hsidiv().bits(div as u8); // todo: Do we need to reset the HSI div after low power?
-                            w.hsion().bit(true)
-                        });
-                        while rcc.cr.read().hsirdy().bit_is_clear() {}
-                    }
-                    PllSrc::Csi => (), // todo
-                    PllSrc::None => (),
-                }
+        let vos_max_prev = self.vos_range.max_sysclk();
+        let vos_max = new_cfg.vos_range.max_sysclk();
 
-                // todo: PLL 2 and 3?
-                rcc.cr.modify(|_, w| w.pll1on().clear_bit());
-                while rcc.cr.read().pll1rdy().bit_is_set() {}
+        if vos_max > vos_max_prev {
+            new_cfg.set_vos();
+        }
 
-                rcc.cfgr
-                    .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+        let wait_states_prev = flash.acr.read().latency().bits();
+        let wait_states = new_cfg.vos_range.wait_states(new_cfg.hclk()).0;
+        let from_pll = matches!(self.input_src, InputSrc::Pll1);
 
-                rcc.cr.modify(|_, w| w.pll1on().set_bit());
-                while rcc.cr.read().pll1rdy().bit_is_clear() {}
-            }
-            InputSrc::Hsi(div) => {
-                {
-                    // From Reference Manual, RCC_CFGR register section:
-                    // "Configured by HW to force Csi oscillator selection when exiting Standby or Shutdown mode.
-                    // Configured by HW to force Csi or HSI16 oscillator selection when exiting Stop mode or in
-                    // case of failure of the HSE oscillator, depending on STOPWUCK value."
-                    // In tests, from stop, it tends to revert to Csi.
-                    rcc.cr.modify(|_, w| {
-                        w.hsidiv().bits(div as u8); // todo: Do we need to reset the HSI div after low power?
-                        w.hsion().bit(true)
-                    });
-                    while rcc.cr.read().hsirdy().bit_is_clear() {}
+        for step in switch_steps(from_pll, wait_states_prev, wait_states) {
+            match step {
+                SwitchStep::RaiseWaitStates => {
+                    new_cfg.set_wait_states();
+                    // Make sure the new setting is in effect before increasing speed.
+                    while flash.acr.read().latency().bits() != wait_states {}
+                }
+                // CSI doesn't depend on the PLL source, and is slow enough for any VOS and wait
+                // state setting.
+                SwitchStep::SysclkToInternal => {
+                    rcc.cr.modify(|_, w| w.csion().set_bit());
+                    while rcc.cr.read().csirdy().bit_is_clear() {}
+
+                    rcc.cfgr
+                        .modify(|_, w| unsafe { w.sw().bits(InputSrc::Csi.bits()) });
+                    while rcc.cfgr.read().sws().bits() != InputSrc::Csi.bits() {}
+                }
+                SwitchStep::StopPll => {
+                    rcc.cr.modify(|_, w| w.pll1on().clear_bit());
+                    while rcc.cr.read().pll1rdy().bit_is_set() {}
                 }
+                SwitchStep::Configure => {
+                    new_cfg.configure();
+                    // Don't lower wait states or VOS until the switch to the new system clock is
+                    // complete.
+                    while rcc.cfgr.read().sws().bits() != new_cfg.input_src.bits() {}
+                }
+                SwitchStep::LowerWaitStates => new_cfg.set_wait_states(),
             }
-            InputSrc::Csi => (), // ?
         }
+
+        if vos_max < vos_max_prev {
+            new_cfg.set_vos();
+        }
+
+        *self = new_cfg;
+
+        Ok(())
     }
 
-    /// Calculate the input speed to the PLL. This must be between 1 and 16 Mhz. Called `refx_ck`
-    /// in the RM.
-    pub fn pll_input_speed(&self, pll_src: PllSrc, pll_num: u8) -> u32 {
-        let input_freq = match pll_src {
-            PllSrc::Csi => 4_000_000,
-            PllSrc::Hsi(div) => 64_000_000 / (div.value() as u32),
-            PllSrc::Hse(freq) => freq,
-            PllSrc::None => 0,
+    /// Reconfigure clocks after an HSE failure, so they no longer use the HSE. PLL1 is kept at
+    /// the same SYSCLK if it can be, using the HSI as its source. Otherwise, HSI is used directly
+    /// as SYSCLK. Takes no action if the HSE isn't used. Returns the new SYSCLK frequency in Hz;
+    /// peripherals should be re-timed, as after `switch_to()`. Note that PLL2 and PLL3 share
+    /// PLL1's source, so their outputs will change if they were also running from the HSE.
+    pub fn fail_over(&mut self) -> Result<u32, SpeedError> {
+        let hse_used = match self.input_src {
+            InputSrc::Hse(_) => true,
+            InputSrc::Pll1 => matches!(self.pll_src, PllSrc::Hse(_)),
+            _ => false,
         };
 
-        match pll_num {
-            1 => input_freq / (self.pll1.divm as u32),
-            2 => input_freq / (self.pll2.divm as u32),
-            3 => input_freq / (self.pll3.divm as u32),
-            _ => panic!("Pll num must be between 1 and 3."),
+        if !hse_used {
+            return Ok(self.sysclk());
+        }
+
+        let pll_src = PllSrc::Hsi(HsiDiv::Div1);
+
+        if let InputSrc::Pll1 = self.input_src {
+            if let Ok(solved) = Self::from_target(pll_src, self.sysclk(), self.pll1.pllq_en) {
+                let with_pll = Self {
+                    pll_src,
+                    pll1: solved.pll1,
+                    ..self.clone()
+                };
+                // `switch_to` makes no changes if the config is invalid, eg due to prescalers.
+                if self.switch_to(with_pll).is_ok() {
+                    return Ok(self.sysclk());
+                }
+            }
+        }
+
+        self.switch_to(Self {
+            input_src: InputSrc::Hsi(HsiDiv::Div1),
+            pll_src,
+            ..self.clone()
+        })?;
+
+        Ok(self.sysclk())
+    }
+
+    /// Set the PWR VOS setting, including the VOS0 activation sequence where required.
+    fn set_vos(&self) {
+        let pwr = unsafe { &(*PWR::ptr()) };
+
+        // H743 RM, sefction 6.8.6, and section 6.6.2: Voltage Scaling
+        //  Voltage scaling selection according to performance
+        // These bits control the VCORE voltage level and allow to obtains the best trade-off between
+        // power consumption and performance:
+        // – When increasing the performance, the voltage scaling shall be changed before increasing
+        // the system frequency.
+        // – When decreasing performance, the system frequency shall first be decreased before
+        // changing the voltage scaling.
+        match self.vos_range {
+            #[cfg(not(any(feature = "h7b3", feature = "h735")))]
+            // Note:H735 etc have VOS0, but not oden; the RM doesn't list these steps.
+            VosRange::VOS0 => {
+                let syscfg = unsafe { &(*SYSCFG::ptr()) };
+
+                // VOS0 activation/deactivation sequence: H743 HRM, section 6.6.2:
+                // The system maximum frequency can be reached by boosting the voltage scaling level to
+                // VOS0. This is done through the ODEN bit in the SYSCFG_PWRCR register.
+                // The sequence to activate the VOS0 is the following:
+                // 1. Ensure that the system voltage scaling is set to VOS1 by checking the VOS bits in
+                // PWR D3 domain control register (PWR D3 domain control register (PWR_D3CR))
+                pwr.d3cr
+                    .modify(|_, w| unsafe { w.vos().bits(VosRange::VOS1 as u8) });
+                while pwr.d3cr.read().vosrdy().bit_is_clear() {}
+
+                // 2. Enable the SYSCFG clock in the RCC by setting the SYSCFGEN bit in the
+                // RCC_APB4ENR register.
+                // (Handled in `setup()`)
+
+                // 3. Enable the ODEN bit in the SYSCFG_PWRCR register.
+                syscfg.pwrcr.modify(|_, w| w.oden().set_bit());
+
+                // 4. Wait for VOSRDY to be set.
+                while pwr.d3cr.read().vosrdy().bit_is_clear() {}
+
+                // Once the VCORE supply has reached the required level, the system frequency can be
+                // increased. Figure 31 shows the recommended sequence for switching VCORE from VOS1 to
+                // VOS0 sequence.
+                // The sequence to deactivate the VOS0 is the following:
+                // 1. Ensure that the system frequency was decreased.
+                // 2. Ensure that the SYSCFG clock is enabled in the RCC by setting the SYSCFGEN bit set
+                // in the RCC_APB4ENR register.
+                // 3. Reset the ODEN bit in the SYSCFG_PWRCR register to disable VOS0.
+            }
+            _ => {
+                // Deactivate VOS0 if it was active, per the sequence above.
+                #[cfg(not(any(feature = "h7b3", feature = "h735")))]
+                {
+                    let syscfg = unsafe { &(*SYSCFG::ptr()) };
+                    syscfg.pwrcr.modify(|_, w| w.oden().clear_bit());
+                }
+
+                pwr.d3cr
+                    .modify(|_, w| unsafe { w.vos().bits(self.vos_range as u8) });
+            }
+        }
+    }
+
+    /// Set flash wait states and programming delay for this config's HCLK and VOS setting.
+    fn set_wait_states(&self) {
+        let flash = unsafe { &(*FLASH::ptr()) };
+
+        // Adjust flash wait states according to the HCLK frequency.
+        // We need to do this before enabling PLL, or it won't enable.
+        // H742 RM, Table 17.
+        let wait_states = self.vos_range.wait_states(self.hclk());
+        flash.acr.modify(|_, w| unsafe {
+            w.latency().bits(wait_states.0);
+            w.wrhighfreq().bits(wait_states.1)
+        });
+    }
+
+    /// Re-select input source; used on Stop and Standby modes, where the system reverts
+    /// to HSI after wake.
+    pub fn reselect_input(&self) {
+        // Re-select the input source; it will revert to HSI during `Stop` or `Standby` mode.
+
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        // Note: It would save code repetition to pass the `Clocks` struct in and re-run setup
+        // todo: But this saves a few reg writes.
+        match self.input_src {
+            InputSrc::Hse(_) => {
+                rcc.cr.modify(|_, w| w.hseon().set_bit());
+                while rcc.cr.read().hserdy().bit_is_clear() {}
+
+                rcc.cfgr
+                    .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+            }
+            InputSrc::Pll1 => {
+                // todo: DRY with above.
+                match self.pll_src {
+                    PllSrc::Hse(_) => {
+                        rcc.cr.modify(|_, w| w.hseon().set_bit());
+                        while rcc.cr.read().hserdy().bit_is_clear() {}
+                    }
+                    PllSrc::Hsi(div) => {
+                        // Generally reverts to Csi (see note below)
+                        rcc.cr.modify(|_, w| {
+                            w.hsidiv().bits(div as u8); // todo: Do we need to reset the HSI div after low power?
+                            w.hsion().bit(true)
+                        });
+                        while rcc.cr.read().hsirdy().bit_is_clear() {}
+                    }
+                    PllSrc::Csi => (), // todo
+                    PllSrc::None => (),
+                }
+
+                // todo: PLL 2 and 3?
+                rcc.cr.modify(|_, w| w.pll1on().clear_bit());
+                while rcc.cr.read().pll1rdy().bit_is_set() {}
+
+                rcc.cfgr
+                    .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+
+                rcc.cr.modify(|_, w| w.pll1on().set_bit());
+                while rcc.cr.read().pll1rdy().bit_is_clear() {}
+            }
+            InputSrc::Hsi(div) => {
+                {
+                    // From Reference Manual, RCC_CFGR register section:
+                    // "Configured by HW to force Csi oscillator selection when exiting Standby or Shutdown mode.
+                    // Configured by HW to force Csi or HSI16 oscillator selection when exiting Stop mode or in
+                    // case of failure of the HSE oscillator, depending on STOPWUCK value."
+                    // In tests, from stop, it tends to revert to Csi.
+                    rcc.cr.modify(|_, w| {
+                        w.hsidiv().bits(div as u8); // todo: Do we need to reset the HSI div after low power?
+                        w.hsion().bit(true)
+                    });
+                    while rcc.cr.read().hsirdy().bit_is_clear() {}
+                }
+            }
+            InputSrc::Csi => (), // ?
         }
     }
 
-    /// Calculate VCO output frequency: = Fref1_ck x DIVN1
+    /// Calculate the input speed to the PLL. This must be between 1 and 16 Mhz. Called `refx_ck`
+    /// in the RM.
+    pub fn pll_input_speed(&self, pll_src: PllSrc, pll_num: u8) -> u32 {
+        let input_freq = match pll_src {
+            PllSrc::Csi => 4_000_000,
+            PllSrc::Hsi(div) => 64_000_000 / (div.value() as u32),
+            PllSrc::Hse(freq) => freq,
+            PllSrc::None => 0,
+        };
+
+        match pll_num {
+            1 => input_freq / (self.pll1.divm as u32),
+            2 => input_freq / (self.pll2.divm as u32),
+            3 => input_freq / (self.pll3.divm as u32),
+            _ => panic!("Pll num must be between 1 and 3."),
+        }
+    }
+
+    /// Calculate VCO output frequency: = Fref1_ck x (DIVN1 + FRACN1 / 2^13)
     pub fn vco_output_freq(&self, pll_src: PllSrc, pll_num: u8) -> u32 {
-        let input_speed = self.pll_input_speed(pll_src, pll_num);
+        let input_speed = self.pll_input_speed(pll_src, pll_num) as u64;
+        let cfg = match pll_num {
+            1 => &self.pll1,
+            2 => &self.pll2,
+            3 => &self.pll3,
+            _ => panic!("Pll num must be between 1 and 3."),
+        };
+
+        (input_speed * cfg.divn as u64 + input_speed * cfg.fracn as u64 / 8_192) as u32
+    }
+
+    /// Get the PLL1 P output frequency, in hz. (Doesn't check if the output is enabled)
+    pub fn pll1_p(&self) -> u32 {
+        self.vco_output_freq(self.pll_src, 1) / self.pll1.divp as u32
+    }
+
+    /// Get the PLL1 Q output frequency, in hz. (Doesn't check if the output is enabled)
+    pub fn pll1_q(&self) -> u32 {
+        self.vco_output_freq(self.pll_src, 1) / self.pll1.divq as u32
+    }
+
+    /// Get the PLL1 R output frequency, in hz. (Doesn't check if the output is enabled)
+    pub fn pll1_r(&self) -> u32 {
+        self.vco_output_freq(self.pll_src, 1) / self.pll1.divr as u32
+    }
+
+    /// Get the PLL2 P output frequency, in hz. (Doesn't check if the output is enabled)
+    pub fn pll2_p(&self) -> u32 {
+        self.vco_output_freq(self.pll_src, 2) / self.pll2.divp as u32
+    }
+
+    /// Get the PLL2 Q output frequency, in hz. (Doesn't check if the output is enabled)
+    pub fn pll2_q(&self) -> u32 {
+        self.vco_output_freq(self.pll_src, 2) / self.pll2.divq as u32
+    }
+
+    /// Get the PLL2 R output frequency, in hz. (Doesn't check if the output is enabled)
+    pub fn pll2_r(&self) -> u32 {
+        self.vco_output_freq(self.pll_src, 2) / self.pll2.divr as u32
+    }
+
+    /// Get the PLL3 P output frequency, in hz. (Doesn't check if the output is enabled)
+    pub fn pll3_p(&self) -> u32 {
+        self.vco_output_freq(self.pll_src, 3) / self.pll3.divp as u32
+    }
+
+    /// Get the PLL3 Q output frequency, in hz. (Doesn't check if the output is enabled)
+    pub fn pll3_q(&self) -> u32 {
+        self.vco_output_freq(self.pll_src, 3) / self.pll3.divq as u32
+    }
+
+    /// Get the PLL3 R output frequency, in hz. (Doesn't check if the output is enabled)
+    pub fn pll3_r(&self) -> u32 {
+        self.vco_output_freq(self.pll_src, 3) / self.pll3.divr as u32
+    }
+
+    /// Change a PLL's fractional multiplier while it's running, eg to switch an audio clock
+    /// between 48kHz and 44.1kHz families without stopping the PLL. `pll_num` is 1, 2, or 3.
+    /// H743 RM, section 8.5.5: "PLLxFRACEN must be set to 0 before writing FRACNx, then set
+    /// to 1 to latch the new value."
+    pub fn set_fracn(&mut self, pll_num: u8, fracn: u16) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
         match pll_num {
-            1 => input_speed * self.pll1.divn as u32,
-            2 => input_speed * self.pll2.divn as u32,
-            3 => input_speed * self.pll3.divn as u32,
+            1 => {
+                rcc.pllcfgr.modify(|_, w| w.pll1fracen().clear_bit());
+                rcc.pll1fracr
+                    .modify(|_, w| unsafe { w.fracn1().bits(fracn) });
+                rcc.pllcfgr.modify(|_, w| w.pll1fracen().set_bit());
+                self.pll1.fracn = fracn;
+            }
+            2 => {
+                rcc.pllcfgr.modify(|_, w| w.pll2fracen().clear_bit());
+                rcc.pll2fracr
+                    .modify(|_, w| unsafe { w.fracn2().bits(fracn) });
+                rcc.pllcfgr.modify(|_, w| w.pll2fracen().set_bit());
+                self.pll2.fracn = fracn;
+            }
+            3 => {
+                rcc.pllcfgr.modify(|_, w| w.pll3fracen().clear_bit());
+                rcc.pll3fracr
+                    .modify(|_, w| unsafe { w.fracn3().bits(fracn) });
+                rcc.pllcfgr.modify(|_, w| w.pll3fracen().set_bit());
+                self.pll3.fracn = fracn;
+            }
             _ => panic!("Pll num must be between 1 and 3."),
         }
     }
@@ -839,11 +1435,26 @@ impl Clocks {
         self.d1cpreclk()
     }
 
-    /// Get the USB clock frequency, in hz
+    /// Get the USB clock frequency, in hz. Returns 0 if the selected PLL output isn't enabled.
     pub fn usb(&self) -> u32 {
-        // let (input_freq, _) = sysclock(self.input_src, self.divm1, self.divn1, self.divp1);
-        // (input_freq * 1_000_000) as u32 / self.divm1 as u32 * self.pll_sai1_mul as u32 / 2
-        0 // todo
+        match self.usb_src {
+            UsbSrc::Disabled => 0,
+            UsbSrc::Pll1Q => {
+                if self.pll1.pllq_en {
+                    self.pll1_q()
+                } else {
+                    0
+                }
+            }
+            UsbSrc::Pll3Q => {
+                if self.pll3.enabled && self.pll3.pllq_en {
+                    self.pll3_q()
+                } else {
+                    0
+                }
+            }
+            UsbSrc::Hsi48 => 48_000_000,
+        }
     }
 
     pub fn apb1(&self) -> u32 {
@@ -872,116 +1483,697 @@ impl Clocks {
         }
     }
 
+    /// Get the APB3 frequency, in hz
+    pub fn apb3(&self) -> u32 {
+        self.hclk() / self.d1_prescaler.value() as u32
+    }
+
+    /// Get the APB4 frequency, in hz
+    pub fn apb4(&self) -> u32 {
+        self.hclk() / self.d3_prescaler.value() as u32
+    }
+
     /// Get the SAI1 audio clock frequency, in hz
     pub fn sai1_speed(&self) -> u32 {
-        let pll_src = match self.input_src {
-            InputSrc::Pll1 => self.pll_src,
-            InputSrc::Csi => PllSrc::Csi,
-            InputSrc::Hsi(div) => PllSrc::Hsi(div),
-            InputSrc::Hse(freq) => PllSrc::Hse(freq),
-        };
+        self.sai_speed(self.sai1_src)
+    }
 
-        match self.sai1_src {
-            SaiSrc::Pll1Q => {
-                self.pll_input_speed(pll_src, 1) * self.pll1.divn as u32 / self.pll1.divq as u32
-            }
-            SaiSrc::Pll2P => {
-                self.pll_input_speed(pll_src, 1) * self.pll2.divn as u32 / self.pll2.divp as u32
+    /// Get the SAI2 and SAI3 audio clock frequency, in hz
+    #[cfg(not(feature = "h735"))]
+    pub fn sai23_speed(&self) -> u32 {
+        self.sai_speed(self.sai23_src)
+    }
+
+    /// Get the SAI4 block A audio clock frequency, in hz
+    pub fn sai4a_speed(&self) -> u32 {
+        self.sai_speed(self.sai4a_src)
+    }
+
+    /// Get the SAI4 block B audio clock frequency, in hz
+    pub fn sai4b_speed(&self) -> u32 {
+        self.sai_speed(self.sai4b_src)
+    }
+
+    fn sai_speed(&self, src: SaiSrc) -> u32 {
+        match src {
+            SaiSrc::Pll1Q => self.pll1_q(),
+            SaiSrc::Pll2P => self.pll2_p(),
+            SaiSrc::Pll3P => self.pll3_p(),
+            SaiSrc::I2sCkin => self.i2s_ckin_freq,
+            SaiSrc::PerClk => self.per_ck(),
+        }
+    }
+
+    /// The HSI frequency after HSIDIV, in hz.
+    fn hsi_freq(&self) -> u32 {
+        match (self.input_src, self.pll_src) {
+            (InputSrc::Hsi(div), _) | (_, PllSrc::Hsi(div)) => 64_000_000 / div.value() as u32,
+            _ => 64_000_000,
+        }
+    }
+
+    /// The HSE frequency, in hz, or 0 if neither the input source or PLL source is HSE.
+    fn hse_freq(&self) -> u32 {
+        match (self.input_src, self.pll_src) {
+            (InputSrc::Hse(freq), _) | (_, PllSrc::Hse(freq)) => freq,
+            _ => 0,
+        }
+    }
+
+    /// Get the `per_ck` frequency, in hz.
+    pub fn per_ck(&self) -> u32 {
+        match self.ckper_src {
+            CkPerSrc::Hsi => self.hsi_freq(),
+            CkPerSrc::Csi => 4_000_000,
+            CkPerSrc::Hse => self.hse_freq(),
+        }
+    }
+
+    /// Get the SPI1, 2, and 3 kernel clock frequency, in hz
+    pub fn spi123_speed(&self) -> u32 {
+        match self.spi123_src {
+            Spi123Src::Pll1Q => self.pll1_q(),
+            Spi123Src::Pll2P => self.pll2_p(),
+            Spi123Src::Pll3P => self.pll3_p(),
+            Spi123Src::I2sCkin => self.i2s_ckin_freq,
+            Spi123Src::PerClk => self.per_ck(),
+        }
+    }
+
+    /// Get the SPI4 and 5 kernel clock frequency, in hz
+    pub fn spi45_speed(&self) -> u32 {
+        self.spi456_speed(self.spi45_src, self.apb2())
+    }
+
+    /// Get the SPI6 kernel clock frequency, in hz
+    pub fn spi6_speed(&self) -> u32 {
+        self.spi456_speed(self.spi6_src, self.apb4())
+    }
+
+    fn spi456_speed(&self, src: Spi45Src, apb: u32) -> u32 {
+        match src {
+            Spi45Src::Apb => apb,
+            Spi45Src::Pll2Q => self.pll2_q(),
+            Spi45Src::Pll3Q => self.pll3_q(),
+            Spi45Src::Hsi => self.hsi_freq(),
+            Spi45Src::Csi => 4_000_000,
+            Spi45Src::Hse => self.hse_freq(),
+        }
+    }
+
+    /// Get the ADC kernel clock frequency, in hz. Only used in the ADC's async clock mode.
+    pub fn adc_speed(&self) -> u32 {
+        match self.adc_src {
+            AdcSrc::Pll2P => self.pll2_p(),
+            AdcSrc::Pll3R => self.pll3_r(),
+            AdcSrc::PerClk => self.per_ck(),
+        }
+    }
+
+    /// Get the FDCAN kernel clock frequency, in hz
+    pub fn fdcan_speed(&self) -> u32 {
+        match self.fdcan_src {
+            FdcanSrc::Hse => self.hse_freq(),
+            FdcanSrc::Pll1Q => self.pll1_q(),
+            FdcanSrc::Pll2Q => self.pll2_q(),
+        }
+    }
+
+    /// Get the QUADSPI or OCTOSPI kernel clock frequency, in hz
+    pub fn qspi_speed(&self) -> u32 {
+        match self.qspi_src {
+            QspiSrc::Hclk => self.hclk(),
+            QspiSrc::Pll1Q => self.pll1_q(),
+            QspiSrc::Pll2R => self.pll2_r(),
+            QspiSrc::PerClk => self.per_ck(),
+        }
+    }
+
+    /// Get the frequency output on an MCO pin by `mco`, in hz. `None` if this config doesn't
+    /// determine its source's speed (see the `clocks` module documentation), if the source is a
+    /// PLL output that isn't enabled, or if the prescaler isn't 1 - 15, which `Mco::enable()`
+    /// rejects.
+    pub fn mco_speed(&self, mco: &Mco) -> Option<u32> {
+        if mco.prescaler < 1 || mco.prescaler > 15 {
+            return None;
+        }
+
+        let output = |enabled: bool, freq| if enabled { Some(freq) } else { None };
+        // PLL1 is only enabled if it's the input source; see `setup()`.
+        let pll1_on = matches!(self.input_src, InputSrc::Pll1);
+
+        let src_freq = match mco.src {
+            McoSrc::Mco1(Mco1Src::Hsi) => Some(self.hsi_freq()),
+            McoSrc::Mco1(Mco1Src::Lse) => Some(32_768),
+            McoSrc::Mco1(Mco1Src::Hse) | McoSrc::Mco2(Mco2Src::Hse) => {
+                output(self.hse_freq() != 0, self.hse_freq())
             }
-            SaiSrc::Pll3P => {
-                self.pll_input_speed(pll_src, 1) * self.pll3.divn as u32 / self.pll3.divp as u32
+            McoSrc::Mco1(Mco1Src::Pll1Q) => output(pll1_on && self.pll1.pllq_en, self.pll1_q()),
+            McoSrc::Mco1(Mco1Src::Hsi48) => Some(48_000_000),
+            McoSrc::Mco2(Mco2Src::Sysclk) => Some(self.sysclk()),
+            McoSrc::Mco2(Mco2Src::Pll2P) => {
+                output(self.pll2.enabled && self.pll2.pllp_en, self.pll2_p())
             }
-            SaiSrc::I2sCkin => unimplemented!(),
-            SaiSrc::PerClk => unimplemented!(),
+            McoSrc::Mco2(Mco2Src::Pll1P) => output(pll1_on && self.pll1.pllp_en, self.pll1_p()),
+            McoSrc::Mco2(Mco2Src::Csi) => Some(4_000_000),
+            McoSrc::Mco2(Mco2Src::Lsi) => Some(32_000),
+        };
+
+        src_freq.map(|freq| freq / mco.prescaler as u32)
+    }
+
+    /// Get the kernel clock frequency of a peripheral, in hz, as selected in `kernel_clocks`.
+    /// For the ADC, this is the same as `adc_speed()`.
+    pub fn kernel_speed(&self, periph: KernelClockPeriph) -> u32 {
+        // Some peripherals share a selection; see `KernelClocks`.
+        let periph = match periph {
+            KernelClockPeriph::Usart3 => KernelClockPeriph::Usart2,
+            KernelClockPeriph::I2c2 | KernelClockPeriph::I2c3 => KernelClockPeriph::I2c1,
+            KernelClockPeriph::Adc => return self.adc_speed(),
+            p => p,
+        };
+
+        match self.kernel_clocks.source(periph) {
+            KernelClock::Apb => match periph {
+                KernelClockPeriph::Usart1 => self.apb2(),
+                KernelClockPeriph::Lpuart1 | KernelClockPeriph::Lptim2 => self.apb4(),
+                _ => self.apb1(),
+            },
+            KernelClock::Sysclk => self.sysclk(),
+            KernelClock::Hsi => self.hsi_freq(),
+            KernelClock::Lse => 32_768,
+            KernelClock::Lsi => 32_000,
+            KernelClock::Csi => 4_000_000,
+            KernelClock::Pll2P => self.pll2_p(),
+            KernelClock::Pll2Q => self.pll2_q(),
+            KernelClock::Pll3Q => self.pll3_q(),
+            KernelClock::Pll3R => self.pll3_r(),
+            KernelClock::PerCk => self.per_ck(),
         }
     }
 
-    pub fn validate_speeds(&self) -> Result<(), SpeedError> {
-        cfg_if! {
-            if #[cfg(feature = "h735")] {
-                let max_sysclk = 480_000_000;
-            } else {
-                let max_sysclk = 550_000_000;
+    /// Check a PLL's dividers, reference clock and VCO speeds, and enabled outputs.
+    fn validate_pll(&self, pll_num: u8, cfg: &PllCfg) -> Result<(), SpeedError> {
+        let dividers = [
+            (PllDivider::M, cfg.divm as u16, 1, 63),
+            (PllDivider::N, cfg.divn, 4, 512),
+            (PllDivider::P, cfg.divp as u16, 1, 128),
+            (PllDivider::Q, cfg.divq as u16, 1, 128),
+            (PllDivider::R, cfg.divr as u16, 1, 128),
+            (PllDivider::FracN, cfg.fracn, 0, 8_191),
+        ];
+
+        for (divider, value, min, max) in dividers.iter() {
+            if value < min || value > max {
+                return Err(SpeedError::PllDividerOutOfRange {
+                    pll: pll_num,
+                    divider: *divider,
+                    value: *value,
+                    min: *min,
+                    max: *max,
+                });
             }
         }
-        // #[cfg(feature = "h743")]
-        let max_hclk = 240_000_000;
-        // #[cfg(feature = "h743")]
-        let max_apb = 120_000_000; // todo: Different depending on apb
-
-        // todo: PLL input (Post DIVM1, pre DIVN1) must be between 1Mhz and 16 Mhz
-
-        // todo after pool today: FIgure out what other bit needs to be set to reflect this.
-
-        // todo: Are these valid for all H7 configs?
-        if self.pll1.divm > 63
-            || self.pll1.divm > 63
-            || self.pll3.divm > 63
-            || self.pll1.divn > 512
-            || self.pll2.divn > 512
-            || self.pll3.divn > 512
-            || self.pll1.divp > 128
-            || self.pll2.divp > 128
-            || self.pll3.divp > 128
-            || self.pll1.divp < 2
-            || self.pll2.divp < 2
-            || self.pll3.divp < 2
-        // todo: R and Q
-        {
-            return Err(SpeedError::new("A PLL divider is out of limits"));
+
+        // DIVP1 only allows even values. (RM0468 also allows 1, on H735)
+        #[cfg(feature = "h735")]
+        let divp_ok = cfg.divp % 2 == 0 || cfg.divp == 1;
+        #[cfg(not(feature = "h735"))]
+        let divp_ok = cfg.divp % 2 == 0;
+
+        if pll_num == 1 && !divp_ok {
+            return Err(SpeedError::PllDividerOdd {
+                pll: pll_num,
+                divider: PllDivider::P,
+                value: cfg.divp as u16,
+            });
+        }
+
+        let pll_input_speed = self.pll_input_speed(self.pll_src, pll_num);
+        if pll_input_speed < 1_000_000 || pll_input_speed > 16_000_000 {
+            return Err(SpeedError::VcoInputOutOfRange {
+                pll: pll_num,
+                freq: pll_input_speed,
+                min: 1_000_000,
+                max: 16_000_000,
+            });
+        }
+
+        // VCO0: Wide VCO range: 192 to 836 MHz (default after reset) (VCOH)
+        // Note: The RM appears out of date: Revision "V" allgedly supports 960_000_000
+        // VCO speed, to allow a max core speed of 480Mhz.
+        // 1: Medium VCO range: 150 to 420 MHz. (VCOL)
+        // This matches the `pllxvcosel` setting in `setup()`.
+        let (vco_min, vco_max) = if pll_input_speed <= 2_000_000 {
+            (192_000_000, 960_000_000)
+        } else {
+            (150_000_000, 420_000_000)
+        };
+
+        let vco_speed = self.vco_output_freq(self.pll_src, pll_num);
+        if vco_speed < vco_min || vco_speed > vco_max {
+            return Err(SpeedError::VcoOutputOutOfRange {
+                pll: pll_num,
+                freq: vco_speed,
+                min: vco_min,
+                max: vco_max,
+            });
         }
 
+        let max = self.vos_range.max_sysclk();
+        let outputs = [
+            (cfg.pllp_en, PllDivider::P, cfg.divp),
+            (cfg.pllq_en, PllDivider::Q, cfg.divq),
+            (cfg.pllr_en, PllDivider::R, cfg.divr),
+        ];
+
+        for (enabled, output, div) in outputs.iter() {
+            let freq = vco_speed / *div as u32;
+            if *enabled && freq > max {
+                return Err(SpeedError::PllOutputOverMax {
+                    pll: pll_num,
+                    output: *output,
+                    freq,
+                    max,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that every PLL, bus and kernel clock this struct configures is within limits, for
+    /// the selected VOS range.
+    pub fn validate_speeds(&self) -> Result<(), SpeedError> {
+        // PLL1 is only configured if it's used as the input source; see `setup()`.
         if let InputSrc::Pll1 = self.input_src {
-            let pll_input_speed = self.pll_input_speed(self.pll_src, 1);
-            if pll_input_speed < 1_000_000 || pll_input_speed > 16_000_000 {
-                return Err(SpeedError::new("Invalid PLL input speed"));
+            self.validate_pll(1, &self.pll1)?;
+        }
+
+        if self.pll2.enabled {
+            self.validate_pll(2, &self.pll2)?;
+        }
+
+        if self.pll3.enabled {
+            self.validate_pll(3, &self.pll3)?;
+        }
+
+        let max_sysclk = self.vos_range.max_sysclk();
+        let sysclk = self.sysclk();
+        if sysclk > max_sysclk {
+            return Err(SpeedError::SysclkOverMax {
+                freq: sysclk,
+                max: max_sysclk,
+            });
+        }
+
+        let max_hclk = self.vos_range.max_hclk();
+        let hclk = self.hclk();
+        if hclk > max_hclk {
+            return Err(SpeedError::HclkOverMax {
+                bus: 1,
+                freq: hclk,
+                max: max_hclk,
+            });
+        }
+
+        // Checking this here prevents `VosRange::wait_states()` from panicking during setup.
+        let max_wait_state_hclk = self.vos_range.max_wait_state_hclk();
+        if hclk > max_wait_state_hclk {
+            return Err(SpeedError::WaitStatesInsufficient {
+                hclk,
+                max: max_wait_state_hclk,
+            });
+        }
+
+        let max_apb = self.vos_range.max_apb();
+        let apbs = [
+            (1, self.apb1()),
+            (2, self.apb2()),
+            (3, self.apb3()),
+            (4, self.apb4()),
+        ];
+
+        for (bus, freq) in apbs.iter() {
+            if *freq > max_apb {
+                return Err(SpeedError::ApbOverMax {
+                    bus: *bus,
+                    freq: *freq,
+                    max: max_apb,
+                });
             }
-            // VCO0: Wide VCO range: 192 to 836 MHz (default after reset) (VCOH)
-            // Note: The RM appears out of date: Revision "V" allgedly supports 960_000_000
-            // VCO speed, to allow a max core speed of 480Mhz.
-            let vco_speed = self.vco_output_freq(self.pll_src, 1);
-            if pll_input_speed <= 2_000_000 && (vco_speed < 192_000_000 || vco_speed > 960_000_000)
-            {
-                return Err(SpeedError::new("Invalid wide VCO speed"));
+        }
+
+        match self.usb_src {
+            UsbSrc::Pll1Q | UsbSrc::Pll3Q => {
+                let usb = self.usb();
+                if usb != 48_000_000 {
+                    return Err(SpeedError::UsbNot48Mhz { freq: usb });
+                }
             }
-            // 1: Medium VCO range: 150 to 420 MHz. (VCOL)
-            // Note: You may get power savings
-            if pll_input_speed > 2_000_000 && (vco_speed < 150_000_000 || vco_speed > 420_000_000) {
-                return Err(SpeedError::new("Invalid medium VCO speed"));
+            _ => (),
+        }
+
+        for periph in KERNEL_CLOCK_PERIPHS.iter() {
+            if let Some(src) = self.kernel_clocks.get(*periph) {
+                if kernel_clock_bits(*periph, src).is_none() {
+                    return Err(SpeedError::InvalidKernelClock {
+                        periph: *periph,
+                        src,
+                    });
+                }
             }
         }
 
-        // todo: Validate PLL2 and PLL3 speeds.
+        Ok(())
+    }
 
-        // todo: More work on this, including feature gates
+    /// Create a config that runs SYSCLK from PLL1 at `sysclk` Hz, by searching the DIVM1, DIVN1
+    /// and DIVP1 values within the PLL's reference clock (1 - 16Mhz) and VCO ranges. If
+    /// `usb_48mhz_required` is true, PLL1Q is also set to 48Mhz, and selected as the USB source.
+    /// Lower DIVM1 values are tried first, since a higher reference clock reduces jitter.
+    /// VOS range, and AHB and APB prescalers are set to the lowest values that keep
+    /// their clocks within limits. Other fields are left at their `Default` values; modify as
+    /// required, then run `setup()`.
+    pub fn from_target(
+        pll_src: PllSrc,
+        sysclk: u32,
+        usb_48mhz_required: bool,
+    ) -> Result<Self, PllSolveError> {
+        let input_freq = match pll_src {
+            PllSrc::Csi => 4_000_000,
+            PllSrc::Hsi(div) => 64_000_000 / (div.value() as u32),
+            PllSrc::Hse(freq) => freq,
+            PllSrc::None => return Err(PllSolveError::NoPllSource),
+        };
+
+        #[cfg(not(feature = "h7b3"))]
+        let vos_range = if sysclk > VosRange::VOS1.max_sysclk() {
+            VosRange::VOS0
+        } else {
+            VosRange::VOS1
+        };
+        #[cfg(feature = "h7b3")]
+        let vos_range = VosRange::VOS1;
+
+        if sysclk > vos_range.max_sysclk() {
+            return Err(PllSolveError::AboveMax {
+                requested: sysclk,
+                max: vos_range.max_sysclk(),
+            });
+        }
+
+        let max_hclk = vos_range.max_hclk().min(vos_range.max_wait_state_hclk());
+        let hclk_prescaler = if sysclk > max_hclk {
+            HclkPrescaler::Div2
+        } else {
+            HclkPrescaler::Div1
+        };
+        let apb_prescaler =
+            apb_prescaler_for(sysclk / hclk_prescaler.value() as u32, vos_range.max_apb());
+
+        let mut vco_in_reachable = false;
+        let mut usb_unreachable = false;
+        let mut closest = 0;
+
+        for divm in 1..=63 {
+            let pll_input_speed = input_freq / divm as u32;
+            if pll_input_speed < 1_000_000 || pll_input_speed > 16_000_000 {
+                continue;
+            }
+            vco_in_reachable = true;
+
+            // See the VCO ranges in `validate_speeds()`.
+            let (vco_min, vco_max) = if pll_input_speed <= 2_000_000 {
+                (192_000_000, 960_000_000)
+            } else {
+                (150_000_000, 420_000_000)
+            };
+
+            for divn in 4..=512 {
+                let vco_speed = pll_input_speed * divn as u32;
+                if vco_speed < vco_min || vco_speed > vco_max {
+                    continue;
+                }
+
+                // DIVP1 only allows even values.
+                for divp in (2..=128).step_by(2) {
+                    // Matches the calculation in `sysclk()`.
+                    let freq = vco_speed / divp as u32;
+                    if freq != sysclk {
+                        if freq < sysclk && freq > closest {
+                            closest = freq;
+                        }
+                        continue;
+                    }
+
+                    let divq = (1..=128).find(|divq| vco_speed / *divq as u32 == 48_000_000);
+
+                    if usb_48mhz_required && divq.is_none() {
+                        usb_unreachable = true;
+                        continue;
+                    }
+
+                    return Ok(Self {
+                        input_src: InputSrc::Pll1,
+                        pll_src,
+                        pll1: PllCfg {
+                            pllq_en: usb_48mhz_required,
+                            divm,
+                            divn,
+                            divp,
+                            divq: divq.unwrap_or(2),
+                            ..Default::default()
+                        },
+                        hclk_prescaler,
+                        d1_prescaler: apb_prescaler,
+                        d2_prescaler1: apb_prescaler,
+                        d2_prescaler2: apb_prescaler,
+                        d3_prescaler: apb_prescaler,
+                        usb_src: if usb_48mhz_required {
+                            UsbSrc::Pll1Q
+                        } else {
+                            UsbSrc::Hsi48
+                        },
+                        vos_range,
+                        ..Default::default()
+                    });
+                }
+            }
+        }
 
-        // todo: QC these limits
-        // todo: Note that this involves repeatedly calculating sysclk.
-        // todo. We could work around thsi by calcing it once here.
-        if self.sysclk() > max_sysclk {
-            return Err(SpeedError::new("Sysclock out of limits"));
+        if !vco_in_reachable {
+            Err(PllSolveError::VcoInputUnreachable {
+                src_freq: input_freq,
+            })
+        } else if usb_unreachable {
+            Err(PllSolveError::Usb48Unreachable)
+        } else {
+            Err(PllSolveError::NoSolution { closest })
         }
+    }
+
+    /// Calculate every clock speed this config produces, eg for logging. Use with
+    /// `Clocks::read_active()` to report the speeds the MCU is actually running at. This doesn't
+    /// read registers; `rtc` is `None`. See `rtc_clock()`.
+    pub fn report(&self) -> ClockReport {
+        let usb = match self.usb_src {
+            UsbSrc::Disabled => None,
+            _ => Some(self.usb()),
+        };
+
+        let sai1 = match self.sai1_src {
+            SaiSrc::I2sCkin if self.i2s_ckin_freq == 0 => None,
+            _ => Some(self.sai1_speed()),
+        };
 
-        if self.hclk() > max_hclk {
-            return Err(SpeedError::new("Hclk out of limits"));
+        ClockReport {
+            sysclk: self.sysclk(),
+            hclk: self.hclk(),
+            systick: self.systick(),
+            apb1: self.apb1(),
+            apb1_timer: self.apb1_timer(),
+            apb2: self.apb2(),
+            apb2_timer: self.apb2_timer(),
+            apb3: Some(self.apb3()),
+            apb4: Some(self.apb4()),
+            usb,
+            sai1,
+            adc: Some(self.adc_speed()),
+            plls: [
+                self.pll_speeds(1, &self.pll1, self.input_src == InputSrc::Pll1),
+                self.pll_speeds(2, &self.pll2, self.pll2.enabled),
+                self.pll_speeds(3, &self.pll3, self.pll3.enabled),
+            ],
+            rtc: None,
         }
+    }
+
+    /// Read the RTC clock source from the RCC BDCR register, and calculate its speed, using this
+    /// config's HSE frequency. The RTC clock is set up by `Rtc::new()`, so `report()` doesn't
+    /// include it; eg set `ClockReport::rtc` with this.
+    pub fn rtc_clock(&self) -> Option<(RtcClockSource, u32)> {
+        rtc_clock(self.hse_freq())
+    }
 
-        if self.apb1() > max_apb {
-            return Err(SpeedError::new("APB1 out of limits"));
+    /// Output speeds of a PLL, or `None` if it isn't running.
+    fn pll_speeds(&self, pll_num: u8, cfg: &PllCfg, enabled: bool) -> Option<PllSpeeds> {
+        if !enabled || self.pll_src == PllSrc::None {
+            return None;
         }
 
-        if self.apb2() > max_apb {
-            return Err(SpeedError::new("APB2 out of limits"));
+        let vco_out = self.vco_output_freq(self.pll_src, pll_num);
+        let output = |enabled, div: u8| {
+            if enabled {
+                Some(vco_out / div as u32)
+            } else {
+                None
+            }
+        };
+
+        Some(PllSpeeds {
+            p: output(cfg.pllp_en, cfg.divp),
+            q: output(cfg.pllq_en, cfg.divq),
+            r: output(cfg.pllr_en, cfg.divr),
+        })
+    }
+
+    /// Read the active clock configuration from RCC registers, eg to find what a bootloader set
+    /// up, or to check speeds with `Clocks::read_active(hse_freq).report()`. `hse_freq` is in Hz;
+    /// it can't be read from registers, and is ignored if the HSE isn't used. The input source,
+    /// PLLs, bus prescalers, and oscillator settings are read; other fields, including
+    /// `vos_range` and kernel clock sources, keep their `Default` values.
+    pub fn read_active(hse_freq: u32) -> Self {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        let cr = rcc.cr.read();
+        let cfgr = rcc.cfgr.read();
+        let pllckselr = rcc.pllckselr.read();
+        let pllcfgr = rcc.pllcfgr.read();
+
+        let hsi_div = HsiDiv::from_bits(cr.hsidiv().bits());
+
+        let pll_src = match pllckselr.pllsrc().bits() {
+            0b00 => PllSrc::Hsi(hsi_div),
+            0b01 => PllSrc::Csi,
+            0b10 => PllSrc::Hse(hse_freq),
+            _ => PllSrc::None,
+        };
+
+        let input_src = match cfgr.sws().bits() {
+            0b001 => InputSrc::Csi,
+            0b010 => InputSrc::Hse(hse_freq),
+            0b011 => InputSrc::Pll1,
+            _ => InputSrc::Hsi(hsi_div),
+        };
+
+        // Divider fields hold the division factor minus 1. FRACN only applies if FRACEN is set.
+        macro_rules! read_pll {
+            ($num:expr, $enabled:expr) => {
+                paste::paste! {{
+                    let divr = rcc.[<pll $num divr>].read();
+                    PllCfg {
+                        enabled: $enabled,
+                        pllp_en: pllcfgr.[<divp $num en>]().bit_is_set(),
+                        pllq_en: pllcfgr.[<divq $num en>]().bit_is_set(),
+                        pllr_en: pllcfgr.[<divr $num en>]().bit_is_set(),
+                        divm: pllckselr.[<divm $num>]().bits(),
+                        divn: divr.[<divn $num>]().bits() + 1,
+                        divp: divr.[<divp $num>]().bits() + 1,
+                        divq: divr.[<divq $num>]().bits() + 1,
+                        divr: divr.[<divr $num>]().bits() + 1,
+                        fracn: if pllcfgr.[<pll $num fracen>]().bit_is_set() {
+                            rcc.[<pll $num fracr>].read().[<fracn $num>]().bits()
+                        } else {
+                            0
+                        },
+                    }
+                }}
+            };
         }
 
-        // todo: Apb3/4
+        let d1cfgr = rcc.d1cfgr.read();
+        let d2cfgr = rcc.d2cfgr.read();
 
-        Ok(())
+        Self {
+            input_src,
+            pll_src,
+            pll1: read_pll!(1, true),
+            pll2: read_pll!(2, cr.pll2on().bit_is_set()),
+            pll3: read_pll!(3, cr.pll3on().bit_is_set()),
+            d1_core_prescaler: HclkPrescaler::from_bits(d1cfgr.d1cpre().bits()),
+            hclk_prescaler: HclkPrescaler::from_bits(d1cfgr.hpre().bits()),
+            d1_prescaler: ApbPrescaler::from_bits(d1cfgr.d1ppre().bits()),
+            d2_prescaler1: ApbPrescaler::from_bits(d2cfgr.d2ppre1().bits()),
+            d2_prescaler2: ApbPrescaler::from_bits(d2cfgr.d2ppre2().bits()),
+            d3_prescaler: ApbPrescaler::from_bits(rcc.d3cfgr.read().d3ppre().bits()),
+            hse_bypass: cr.hsebyp().bit_is_set(),
+            security_system: cr.hsecsson().bit_is_set(),
+            hsi48_on: cr.hsi48on().bit_is_set(),
+            stop_wuck: if cfgr.stopwuck().bit_is_set() {
+                StopWuck::Csi
+            } else {
+                StopWuck::Hsi
+            },
+            ..Default::default()
+        }
     }
 }
 
+/// Find the lowest APB prescaler that keeps the APB clock at or below `max`, from `hclk`.
+fn apb_prescaler_for(hclk: u32, max: u32) -> ApbPrescaler {
+    [
+        ApbPrescaler::Div1,
+        ApbPrescaler::Div2,
+        ApbPrescaler::Div4,
+        ApbPrescaler::Div8,
+    ]
+    .iter()
+    .find(|p| hclk / p.value() as u32 <= max)
+    .copied()
+    .unwrap_or(ApbPrescaler::Div16)
+}
+
 // todo: support default for 280Mhz variants.
 
+/// The DxCCIPR field value that selects `src` as `periph`'s kernel clock, or `None` if `periph`
+/// can't use it.
+fn kernel_clock_bits(periph: KernelClockPeriph, src: KernelClock) -> Option<u8> {
+    match periph {
+        KernelClockPeriph::Usart1 | KernelClockPeriph::Usart2 | KernelClockPeriph::Lpuart1 => {
+            match src {
+                KernelClock::Apb => Some(0b000),
+                KernelClock::Pll2Q => Some(0b001),
+                KernelClock::Pll3Q => Some(0b010),
+                KernelClock::Hsi => Some(0b011),
+                KernelClock::Csi => Some(0b100),
+                KernelClock::Lse => Some(0b101),
+                _ => None,
+            }
+        }
+        KernelClockPeriph::I2c1 => match src {
+            KernelClock::Apb => Some(0b00),
+            KernelClock::Pll3R => Some(0b01),
+            KernelClock::Hsi => Some(0b10),
+            KernelClock::Csi => Some(0b11),
+            _ => None,
+        },
+        KernelClockPeriph::Lptim1 | KernelClockPeriph::Lptim2 => match src {
+            KernelClock::Apb => Some(0b000),
+            KernelClock::Pll2P => Some(0b001),
+            KernelClock::Pll3R => Some(0b010),
+            KernelClock::Lse => Some(0b011),
+            KernelClock::Lsi => Some(0b100),
+            KernelClock::PerCk => Some(0b101),
+            _ => None,
+        },
+        // These fields are ignored on H7.
+        KernelClockPeriph::Usart3
+        | KernelClockPeriph::I2c2
+        | KernelClockPeriph::I2c3
+        | KernelClockPeriph::Adc => Some(0),
+    }
+}
+
 impl Default for Clocks {
     /// This default configures clocks with the HSI, and a 400Mhz sysclock speed. (280Mhz sysclock
     /// on variants that only go that high). Note that H723-745 still use this default speed
@@ -1008,6 +2200,7 @@ impl Default for Clocks {
             hse_bypass: false,
             usb_src: UsbSrc::Hsi48,
             security_system: false,
+            lse_security_system: false,
             /// Enable the HSI48.
             hsi48_on: false,
             /// Select the input source to use after waking up from `stop` mode. Eg HSI or MSI.
@@ -1019,6 +2212,15 @@ impl Default for Clocks {
             sai4a_src: SaiSrc::Pll1Q,
             sai4b_src: SaiSrc::Pll1Q,
             dfsdm1_src: DfsdmSrc::Pclk2,
+            spi123_src: Spi123Src::Pll1Q,
+            spi45_src: Spi45Src::Apb,
+            spi6_src: Spi45Src::Apb,
+            adc_src: AdcSrc::Pll2P,
+            fdcan_src: FdcanSrc::Hse,
+            qspi_src: QspiSrc::Hclk,
+            ckper_src: CkPerSrc::Hsi,
+            i2s_ckin_freq: 0,
+            kernel_clocks: Default::default(),
         }
     }
 }
@@ -1071,6 +2273,32 @@ impl Clocks {
     }
 }
 
+/// Check if a clock security system has detected an oscillator failure. Run this in the NMI
+/// handler, (HSE) or the LSE CSS interrupt handler, then clear the flag with `clear_osc_failure()`.
+/// An HSE failure triggers the NMI again if its flag isn't cleared.
+pub fn osc_failure() -> Option<OscFailure> {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    let cifr = rcc.cifr.read();
+
+    if cifr.hsecssf().bit_is_set() {
+        Some(OscFailure::Hse)
+    } else if cifr.lsecssf().bit_is_set() {
+        Some(OscFailure::Lse)
+    } else {
+        None
+    }
+}
+
+/// Clear an oscillator failure flag, as reported by `osc_failure()`.
+pub fn clear_osc_failure(failure: OscFailure) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    match failure {
+        OscFailure::Hse => rcc.cicr.write(|w| w.hsecssc().set_bit()),
+        OscFailure::Lse => rcc.cicr.write(|w| w.lsecssc().set_bit()),
+    }
+}
+
 // todo
 /// Enable the Clock Recovery System.
 /// "The STM32L443xx devices embed a special block which allows automatic trimming of the
@@ -1080,6 +2308,7 @@ impl Clocks {
 /// external signal on CRS_SYNC pin or generated by user software. For faster lock-in during
 /// startup it is also possible to combine automatic trimming with manual trimming action."
 /// Note: This is for HSI48 only. Note that the HSI will turn off after entering Stop or Standby.
+/// This uses default sync settings; see `crs::Crs` for polarity, divider, interrupts and status.
 pub fn enable_crs(sync_src: CrsSyncSrc) {
     let crs = unsafe { &(*CRS::ptr()) };
     let rcc = unsafe { &(*RCC::ptr()) };
@@ -1103,3 +2332,293 @@ pub fn enable_crs(sync_src: CrsSyncSrc) {
     // Standby mode. When the CRS is not used, the HSI48 RC oscillator runs on its default
     // frequency which is subject to manufacturing process variations
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_valid() {
+        assert_eq!(Clocks::default().validate_speeds(), Ok(()));
+    }
+
+    #[cfg(not(feature = "h7b3"))]
+    #[test]
+    fn full_speed_is_valid() {
+        assert_eq!(Clocks::full_speed().validate_speeds(), Ok(()));
+    }
+
+    #[test]
+    fn from_target() {
+        let clocks = Clocks::from_target(PllSrc::Hse(8_000_000), 200_000_000, false).unwrap();
+
+        assert_eq!(clocks.sysclk(), 200_000_000);
+        assert_eq!(clocks.validate_speeds(), Ok(()));
+    }
+
+    #[cfg(not(feature = "h7b3"))]
+    #[test]
+    fn from_target_usb() {
+        // This requires VOS0, and an HCLK prescaler.
+        let clocks = Clocks::from_target(PllSrc::Hse(8_000_000), 480_000_000, true).unwrap();
+
+        assert_eq!(clocks.sysclk(), 480_000_000);
+        assert_eq!(clocks.usb(), 48_000_000);
+        assert_eq!(clocks.validate_speeds(), Ok(()));
+    }
+
+    #[test]
+    fn from_target_errors() {
+        assert_eq!(
+            Clocks::from_target(PllSrc::None, 200_000_000, false).err(),
+            Some(PllSolveError::NoPllSource)
+        );
+
+        #[cfg(not(feature = "h7b3"))]
+        let max = VosRange::VOS0.max_sysclk();
+        #[cfg(feature = "h7b3")]
+        let max = VosRange::VOS1.max_sysclk();
+
+        assert_eq!(
+            Clocks::from_target(PllSrc::Hse(8_000_000), max + 1, false).err(),
+            Some(PllSolveError::AboveMax {
+                requested: max + 1,
+                max,
+            })
+        );
+
+        // No VCO output in range is an even multiple of 400Mhz, and also of 48Mhz.
+        assert_eq!(
+            Clocks::from_target(PllSrc::Hse(8_000_000), 200_000_000, true).err(),
+            Some(PllSolveError::Usb48Unreachable)
+        );
+    }
+
+    #[test]
+    fn pll_limits() {
+        let mut clocks = Clocks::default();
+        clocks.pll1.divm = 0;
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::PllDividerOutOfRange {
+                pll: 1,
+                divider: PllDivider::M,
+                value: 0,
+                min: 1,
+                max: 63,
+            })
+        );
+
+        // The 64Mhz HSI, undivided.
+        clocks.pll1.divm = 1;
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::VcoInputOutOfRange {
+                pll: 1,
+                freq: 64_000_000,
+                min: 1_000_000,
+                max: 16_000_000,
+            })
+        );
+
+        // A 4Mhz reference clock uses the medium VCO range.
+        clocks.pll1.divm = 16;
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::VcoOutputOutOfRange {
+                pll: 1,
+                freq: 4_000_000 * clocks.pll1.divn as u32,
+                min: 150_000_000,
+                max: 420_000_000,
+            })
+        );
+    }
+
+    #[test]
+    fn vos_limits() {
+        let clocks = Clocks {
+            vos_range: VosRange::VOS3,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::PllOutputOverMax {
+                pll: 1,
+                output: PllDivider::P,
+                freq: clocks.sysclk(),
+                max: VosRange::VOS3.max_sysclk(),
+            })
+        );
+
+        let clocks = Clocks {
+            hclk_prescaler: HclkPrescaler::Div1,
+            ..Default::default()
+        };
+
+        #[cfg(not(feature = "h7b3"))]
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::HclkOverMax {
+                bus: 1,
+                freq: clocks.sysclk(),
+                max: VosRange::VOS1.max_hclk(),
+            })
+        );
+
+        // H7B3 allows HCLK at its full 280Mhz SYSCLK, but the wait state table stops at 225Mhz.
+        #[cfg(feature = "h7b3")]
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::WaitStatesInsufficient {
+                hclk: clocks.sysclk(),
+                max: VosRange::VOS1.max_wait_state_hclk(),
+            })
+        );
+    }
+
+    #[test]
+    fn apb_limits() {
+        // H7B3's default 140Mhz HCLK is within its VOS1 APB limit.
+        #[cfg(not(feature = "h7b3"))]
+        {
+            let clocks = Clocks {
+                d2_prescaler1: ApbPrescaler::Div1,
+                ..Default::default()
+            };
+
+            assert_eq!(
+                clocks.validate_speeds(),
+                Err(SpeedError::ApbOverMax {
+                    bus: 1,
+                    freq: clocks.hclk(),
+                    max: VosRange::VOS1.max_apb(),
+                })
+            );
+        }
+
+        // 225Mhz HCLK is within the wait state table for VOS2, but not the datasheet's 150Mhz
+        // limit. At 150Mhz, the APB limit is 75Mhz.
+        #[cfg(not(any(feature = "h735", feature = "h7b3")))]
+        {
+            let mut clocks = Clocks {
+                vos_range: VosRange::VOS2,
+                hclk_prescaler: HclkPrescaler::Div1,
+                ..Default::default()
+            };
+            clocks.pll1.divn = 225;
+
+            assert_eq!(
+                clocks.validate_speeds(),
+                Err(SpeedError::HclkOverMax {
+                    bus: 1,
+                    freq: 225_000_000,
+                    max: 150_000_000,
+                })
+            );
+
+            clocks.pll1.divn = 150;
+            assert_eq!(clocks.validate_speeds(), Ok(()));
+
+            clocks.d2_prescaler1 = ApbPrescaler::Div1;
+            assert_eq!(
+                clocks.validate_speeds(),
+                Err(SpeedError::ApbOverMax {
+                    bus: 1,
+                    freq: 150_000_000,
+                    max: 75_000_000,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn pll1_divp_even() {
+        let mut clocks = Clocks::default();
+        clocks.pll1.divp = 3;
+
+        assert_eq!(
+            clocks.validate_speeds(),
+            Err(SpeedError::PllDividerOdd {
+                pll: 1,
+                divider: PllDivider::P,
+                value: 3,
+            })
+        );
+
+        // PLL2 and PLL3 allow odd values.
+        let mut clocks = Clocks::default();
+        clocks.pll2.divp = 3;
+        assert_eq!(clocks.validate_pll(2, &clocks.pll2), Ok(()));
+    }
+
+    #[test]
+    fn wait_states() {
+        let ranges = [
+            #[cfg(not(feature = "h7b3"))]
+            VosRange::VOS0,
+            VosRange::VOS1,
+            VosRange::VOS2,
+            VosRange::VOS3,
+        ];
+
+        for range in ranges.iter() {
+            assert_eq!(range.wait_states(0).0, 0);
+            // This is the highest setting the table has; it mustn't panic.
+            range.wait_states(range.max_wait_state_hclk());
+        }
+    }
+
+    #[test]
+    fn usb_not_48mhz() {
+        let mut clocks = Clocks {
+            usb_src: UsbSrc::Pll1Q,
+            ..Default::default()
+        };
+        clocks.pll1.pllq_en = true;
+
+        assert!(matches!(
+            clocks.validate_speeds(),
+            Err(SpeedError::UsbNot48Mhz { .. })
+        ));
+    }
+
+    #[test]
+    fn mco_speed() {
+        let mut clocks = Clocks::default();
+        clocks.pll1.pllq_en = true;
+
+        let mco = Mco {
+            src: McoSrc::Mco1(Mco1Src::Pll1Q),
+            prescaler: 4,
+        };
+        assert_eq!(clocks.mco_speed(&mco), Some(clocks.pll1_q() / 4));
+
+        // `Mco::enable()` rejects a prescaler of 0.
+        let mco_no_pre = Mco {
+            prescaler: 0,
+            ..mco
+        };
+        assert_eq!(clocks.mco_speed(&mco_no_pre), None);
+
+        // PLL1 isn't running unless it's the input source.
+        clocks.input_src = InputSrc::Hsi(HsiDiv::Div1);
+        assert_eq!(clocks.mco_speed(&mco), None);
+    }
+
+    #[test]
+    fn i2s_ckin() {
+        let clocks = Clocks {
+            sai1_src: SaiSrc::I2sCkin,
+            spi123_src: Spi123Src::I2sCkin,
+            i2s_ckin_freq: 12_288_000,
+            ..Default::default()
+        };
+
+        assert_eq!(clocks.sai1_speed(), 12_288_000);
+        assert_eq!(clocks.spi123_speed(), 12_288_000);
+    }
+}
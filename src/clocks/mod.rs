@@ -12,6 +12,16 @@
 //! settings are available, and validating them.
 //!
 //! See the Reference Manuals for non-interactive visualizations.
+//!
+//! Microcontroller Clock Output (MCO) outputs an internal clock on a GPIO pin, eg for measuring it
+//! with an oscilloscope, or to clock external devices. Configure it with an `Mco`, and start it
+//! with `Mco::enable()`. `Clocks::mco_speed()` returns its output frequency, or `None` if the
+//! `Clocks` config doesn't determine its source's speed; eg the source is HSE, but nothing in the
+//! config uses it, so its frequency isn't known.
+
+use core::fmt;
+
+use crate::{pac::RCC, rtc::RtcClockSource};
 
 cfg_if::cfg_if! {
     if #[cfg(any(feature = "f3", feature = "f4"))] {
@@ -35,21 +45,534 @@ cfg_if::cfg_if! {
 
 // todo: Continue working through DRY between the clock modules.
 
-/// Speed out of limits.
-#[derive(Debug)]
-pub struct SpeedError {
-    pub error_msg: &'static str,
+/// Identifies one of a PLL's dividers or multipliers (or its matching output), for use
+/// in `SpeedError`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PllDivider {
+    M,
+    N,
+    P,
+    Q,
+    R,
+    /// The fractional part of N, on PLLs that support it.
+    FracN,
+}
+
+/// A clock speed or divider that's out of limits, as reported by `Clocks::validate_speeds()`.
+/// Frequencies are in Hz. `pll` fields identify the PLL: 1 is the main PLL (PLL1 on H7). 2 and 3
+/// are PLLSAI1 and PLLSAI2, or PLL2 and PLL3 on H7.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpeedError {
+    /// A PLL divider or multiplier is outside the range its register field allows.
+    PllDividerOutOfRange {
+        pll: u8,
+        divider: PllDivider,
+        value: u16,
+        min: u16,
+        max: u16,
+    },
+    /// A PLL divider is odd, but its register field only allows even values. (eg H7 PLL1 DIVP)
+    PllDividerOdd {
+        pll: u8,
+        divider: PllDivider,
+        value: u16,
+    },
+    /// A PLL's input, after its input divider (eg PLLM), is outside the allowed range.
+    VcoInputOutOfRange {
+        pll: u8,
+        freq: u32,
+        min: u32,
+        max: u32,
+    },
+    /// A PLL's VCO output is outside the allowed range.
+    VcoOutputOutOfRange {
+        pll: u8,
+        freq: u32,
+        min: u32,
+        max: u32,
+    },
+    /// An enabled PLL output is above its maximum.
+    PllOutputOverMax {
+        pll: u8,
+        output: PllDivider,
+        freq: u32,
+        max: u32,
+    },
+    /// The system clock is above its maximum.
+    SysclkOverMax { freq: u32, max: u32 },
+    /// An AHB clock is above its maximum. `bus` is 1 for HCLK, or the number of an additional
+    /// HCLK, eg HCLK2 (CPU2) on WB.
+    HclkOverMax { bus: u8, freq: u32, max: u32 },
+    /// An APB clock is above its maximum. `bus` is the APB number.
+    ApbOverMax { bus: u8, freq: u32, max: u32 },
+    /// A PLL output is selected as the USB clock, but isn't 48Mhz.
+    UsbNot48Mhz { freq: u32 },
+    /// No flash wait state setting supports this HCLK at the selected voltage scale. (Or
+    /// boost mode setting, on G4)
+    WaitStatesInsufficient { hclk: u32, max: u32 },
+    /// A peripheral's kernel clock is set to a source it can't use on this MCU.
+    InvalidKernelClock {
+        periph: KernelClockPeriph,
+        src: KernelClock,
+    },
+}
+
+impl fmt::Display for SpeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::PllDividerOutOfRange {
+                pll,
+                divider,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "PLL{} {:?} divider is {}; must be between {} and {}",
+                pll, divider, value, min, max
+            ),
+            Self::PllDividerOdd {
+                pll,
+                divider,
+                value,
+            } => write!(
+                f,
+                "PLL{} {:?} divider is {}; must be even",
+                pll, divider, value
+            ),
+            Self::VcoInputOutOfRange {
+                pll,
+                freq,
+                min,
+                max,
+            } => write!(
+                f,
+                "PLL{} VCO input is {}Hz; must be between {}Hz and {}Hz",
+                pll, freq, min, max
+            ),
+            Self::VcoOutputOutOfRange {
+                pll,
+                freq,
+                min,
+                max,
+            } => write!(
+                f,
+                "PLL{} VCO output is {}Hz; must be between {}Hz and {}Hz",
+                pll, freq, min, max
+            ),
+            Self::PllOutputOverMax {
+                pll,
+                output,
+                freq,
+                max,
+            } => write!(
+                f,
+                "PLL{} {:?} output is {}Hz; max is {}Hz",
+                pll, output, freq, max
+            ),
+            Self::SysclkOverMax { freq, max } => {
+                write!(f, "SYSCLK is {}Hz; max is {}Hz", freq, max)
+            }
+            Self::HclkOverMax { bus, freq, max } => {
+                write!(f, "HCLK{} is {}Hz; max is {}Hz", bus, freq, max)
+            }
+            Self::ApbOverMax { bus, freq, max } => {
+                write!(f, "APB{} is {}Hz; max is {}Hz", bus, freq, max)
+            }
+            Self::UsbNot48Mhz { freq } => write!(f, "USB clock is {}Hz; must be 48Mhz", freq),
+            Self::WaitStatesInsufficient { hclk, max } => write!(
+                f,
+                "HCLK is {}Hz; max for this voltage scale is {}Hz",
+                hclk, max
+            ),
+            Self::InvalidKernelClock { periph, src } => {
+                write!(f, "{:?} can't use {:?} as its kernel clock", periph, src)
+            }
+        }
+    }
+}
+
+/// A step of `Clocks::switch_to()`. The order is worked out by `switch_steps()`, separately from
+/// the register writes, so it can be checked without hardware.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SwitchStep {
+    /// Raise flash wait states to the new config's, before speeding up.
+    RaiseWaitStates,
+    /// Select the internal oscillator (HSI, or CSI on H7) as the system clock, since the PLL
+    /// can't be reconfigured while it's the system clock.
+    SysclkToInternal,
+    /// Stop the PLL, and wait until it's unlocked. If it were left running, `configure()` could
+    /// switch the system clock back to it, then be unable to turn it off to change its settings.
+    StopPll,
+    /// Enable oscillators and the PLL, then select the new system clock.
+    Configure,
+    /// Lower flash wait states to the new config's, once the new system clock is selected.
+    LowerWaitStates,
+}
+
+/// The steps `Clocks::switch_to()` takes, in order. `from_pll` is true if the PLL (PLL1 on H7) is
+/// the current system clock.
+pub(crate) fn switch_steps(
+    from_pll: bool,
+    wait_states_prev: u8,
+    wait_states: u8,
+) -> impl Iterator<Item = SwitchStep> {
+    [
+        (wait_states > wait_states_prev, SwitchStep::RaiseWaitStates),
+        (from_pll, SwitchStep::SysclkToInternal),
+        (from_pll, SwitchStep::StopPll),
+        (true, SwitchStep::Configure),
+        (wait_states < wait_states_prev, SwitchStep::LowerWaitStates),
+    ]
+    .into_iter()
+    .filter(|(run, _)| *run)
+    .map(|(_, step)| step)
+}
+
+/// A kernel clock source: The clock a peripheral derives its timing from, when this can be
+/// selected independently of the APB clock used to access its registers. Not every source is
+/// available for every peripheral; see the CCIPR (or CFGR3 on F3, DxCCIPR on H7) register
+/// descriptions in your RM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KernelClock {
+    /// The APB clock of the bus the peripheral is on. (PCLK)
+    Apb,
+    /// The system clock.
+    Sysclk,
+    /// HSI16. (HSI on F3, and HSI after HSIDIV on H7)
+    Hsi,
+    /// LSE, at 32.768kHz.
+    Lse,
+    /// LSI, at 32kHz.
+    Lsi,
+    #[cfg(feature = "h7")]
+    /// CSI, at 4Mhz.
+    Csi,
+    #[cfg(feature = "h7")]
+    Pll2P,
+    #[cfg(feature = "h7")]
+    Pll2Q,
+    #[cfg(feature = "h7")]
+    Pll3Q,
+    #[cfg(feature = "h7")]
+    Pll3R,
+    #[cfg(feature = "h7")]
+    /// `per_ck`. See `Clocks::ckper_src`.
+    PerCk,
+}
+
+/// Identifies a field of `KernelClocks`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KernelClockPeriph {
+    Usart1,
+    Usart2,
+    Usart3,
+    Lpuart1,
+    I2c1,
+    I2c2,
+    I2c3,
+    Lptim1,
+    Lptim2,
+    Adc,
+}
+
+/// All `KernelClockPeriph` variants; used for validation.
+#[cfg(not(feature = "f4"))]
+pub(crate) const KERNEL_CLOCK_PERIPHS: [KernelClockPeriph; 10] = [
+    KernelClockPeriph::Usart1,
+    KernelClockPeriph::Usart2,
+    KernelClockPeriph::Usart3,
+    KernelClockPeriph::Lpuart1,
+    KernelClockPeriph::I2c1,
+    KernelClockPeriph::I2c2,
+    KernelClockPeriph::I2c3,
+    KernelClockPeriph::Lptim1,
+    KernelClockPeriph::Lptim2,
+    KernelClockPeriph::Adc,
+];
+
+/// Kernel clock selection for peripherals that support it. This is the `kernel_clocks` field
+/// of `Clocks`. Fields left as `None` aren't written by `setup()`, so keep their reset value, or
+/// whatever was set previously. Fields for peripherals your MCU doesn't have, or can't select a
+/// clock for (eg all of them on F4) are ignored. On H7, USART1 and 6 use the `usart1` field.
+/// USART2 and 3, and UART4, 5, 7 and 8 use the `usart2` field. I2C1, 2, and 3 use the `i2c1`
+/// field. `adc` is ignored on F3, F4, and H7; H7 uses `Clocks::adc_src` instead.
+#[derive(Clone, Copy, Default)]
+pub struct KernelClocks {
+    pub usart1: Option<KernelClock>,
+    pub usart2: Option<KernelClock>,
+    pub usart3: Option<KernelClock>,
+    pub lpuart1: Option<KernelClock>,
+    pub i2c1: Option<KernelClock>,
+    pub i2c2: Option<KernelClock>,
+    pub i2c3: Option<KernelClock>,
+    pub lptim1: Option<KernelClock>,
+    pub lptim2: Option<KernelClock>,
+    /// Only used in the ADC's async clock mode.
+    pub adc: Option<KernelClock>,
+}
+
+impl KernelClocks {
+    /// Get the source configured for a given peripheral, or `None` if it's left unchanged.
+    pub fn get(&self, periph: KernelClockPeriph) -> Option<KernelClock> {
+        match periph {
+            KernelClockPeriph::Usart1 => self.usart1,
+            KernelClockPeriph::Usart2 => self.usart2,
+            KernelClockPeriph::Usart3 => self.usart3,
+            KernelClockPeriph::Lpuart1 => self.lpuart1,
+            KernelClockPeriph::I2c1 => self.i2c1,
+            KernelClockPeriph::I2c2 => self.i2c2,
+            KernelClockPeriph::I2c3 => self.i2c3,
+            KernelClockPeriph::Lptim1 => self.lptim1,
+            KernelClockPeriph::Lptim2 => self.lptim2,
+            KernelClockPeriph::Adc => self.adc,
+        }
+    }
+
+    /// Get the source a given peripheral uses, for calculating its speed. If it's not configured,
+    /// this is the reset value: The APB clock for all peripherals, except I2C on F3, which resets
+    /// to HSI. The ADC is assumed to use SYSCLK.
+    pub fn source(&self, periph: KernelClockPeriph) -> KernelClock {
+        if let Some(src) = self.get(periph) {
+            return src;
+        }
+
+        match periph {
+            #[cfg(feature = "f3")]
+            KernelClockPeriph::I2c1 | KernelClockPeriph::I2c2 | KernelClockPeriph::I2c3 => {
+                KernelClock::Hsi
+            }
+            KernelClockPeriph::Adc => KernelClock::Sysclk,
+            _ => KernelClock::Apb,
+        }
+    }
+}
+
+/// An oscillator failure, as detected by a clock security system. See `Clocks::security_system`,
+/// `Clocks::lse_security_system`, and `osc_failure()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OscFailure {
+    /// The HSE failed. SYSCLK has been switched to an internal oscillator by hardware.
+    Hse,
+    #[cfg(not(any(feature = "f3", feature = "f4")))]
+    /// The LSE failed. Peripherals clocked by it, eg the RTC, are no longer clocked.
+    Lse,
+}
+
+/// Output speeds of a PLL, in Hz. Outputs that are disabled, or that the PLL doesn't have,
+/// are `None`. On F3, the PLL's single output is reported as `p`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PllSpeeds {
+    pub p: Option<u32>,
+    pub q: Option<u32>,
+    pub r: Option<u32>,
+}
+
+/// Every clock speed derived from a `Clocks` config, in Hz, as returned by `Clocks::report()`.
+/// Speeds that are disabled, or not present on this MCU are `None`. Use the `Display` impl
+/// (or `uDisplay`, with the `ufmt` feature) to log it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockReport {
+    pub sysclk: u32,
+    pub hclk: u32,
+    pub systick: u32,
+    pub apb1: u32,
+    pub apb1_timer: u32,
+    pub apb2: u32,
+    pub apb2_timer: u32,
+    /// H7 only.
+    pub apb3: Option<u32>,
+    /// H7 only.
+    pub apb4: Option<u32>,
+    pub usb: Option<u32>,
+    pub sai1: Option<u32>,
+    pub adc: Option<u32>,
+    /// The main PLL, (PLL1 on H7) then PLLSAI1 and PLLSAI2, or PLL2 and PLL3 on H7. `None` for
+    /// PLLs that aren't running.
+    pub plls: [Option<PllSpeeds>; 3],
+    /// The RTC clock source and speed. It's set up by `Rtc::new()`, not `Clocks`, so `report()`
+    /// leaves this `None`; read it from the RCC BDCR register with `Clocks::rtc_clock()`.
+    pub rtc: Option<(RtcClockSource, u32)>,
 }
 
-impl SpeedError {
-    pub(crate) fn new(error_msg: &'static str) -> Self {
-        Self { error_msg }
+impl ClockReport {
+    /// Labelled speeds, in the order they're displayed. The RTC is handled separately, since its
+    /// label depends on its source.
+    fn speeds(&self) -> [(&'static str, Option<u32>); 21] {
+        let pll = |i: usize| self.plls[i].unwrap_or_default();
+
+        [
+            ("SYSCLK", Some(self.sysclk)),
+            ("HCLK", Some(self.hclk)),
+            ("Systick", Some(self.systick)),
+            ("APB1", Some(self.apb1)),
+            ("APB1 timers", Some(self.apb1_timer)),
+            ("APB2", Some(self.apb2)),
+            ("APB2 timers", Some(self.apb2_timer)),
+            ("APB3", self.apb3),
+            ("APB4", self.apb4),
+            ("USB", self.usb),
+            ("SAI1", self.sai1),
+            ("ADC", self.adc),
+            ("PLL1 P", pll(0).p),
+            ("PLL1 Q", pll(0).q),
+            ("PLL1 R", pll(0).r),
+            ("PLL2 P", pll(1).p),
+            ("PLL2 Q", pll(1).q),
+            ("PLL2 R", pll(1).r),
+            ("PLL3 P", pll(2).p),
+            ("PLL3 Q", pll(2).q),
+            ("PLL3 R", pll(2).r),
+        ]
+    }
+}
+
+fn rtc_src_label(src: RtcClockSource) -> &'static str {
+    match src {
+        RtcClockSource::Lse => "RTC (LSE)",
+        RtcClockSource::Lsi => "RTC (LSI)",
+        RtcClockSource::Hse => "RTC (HSE)",
+    }
+}
+
+impl fmt::Display for ClockReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (label, speed) in self.speeds().iter() {
+            if let Some(speed) = speed {
+                writeln!(f, "{}: {}Hz", label, speed)?;
+            }
+        }
+
+        if let Some((src, speed)) = self.rtc {
+            writeln!(f, "{}: {}Hz", rtc_src_label(src), speed)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "ufmt")]
+impl ufmt::uDisplay for ClockReport {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        for (label, speed) in self.speeds().iter() {
+            if let Some(speed) = speed {
+                ufmt::uwriteln!(f, "{}: {}Hz", *label, *speed)?;
+            }
+        }
+
+        if let Some((src, speed)) = self.rtc {
+            ufmt::uwriteln!(f, "{}: {}Hz", rtc_src_label(src), speed)?;
+        }
+
+        Ok(())
     }
 }
 
+/// Read the RTC clock source from the RCC BDCR register, and calculate its speed, in Hz.
+/// `hse_freq` is only used if the source is HSE.
+pub(crate) fn rtc_clock(hse_freq: u32) -> Option<(RtcClockSource, u32)> {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    let src = match rcc.bdcr.read().rtcsel().bits() {
+        0b01 => RtcClockSource::Lse,
+        0b10 => RtcClockSource::Lsi,
+        0b11 => RtcClockSource::Hse,
+        _ => return None,
+    };
+
+    // On F4 and H7, the HSE is divided by RTCPRE, where values below 2 gate the clock. Other
+    // families divide it by 32.
+    cfg_if::cfg_if! {
+        if #[cfg(any(feature = "f4", feature = "h7"))] {
+            let hse_div = rcc.cfgr.read().rtcpre().bits() as u32;
+        } else {
+            let hse_div = 32;
+        }
+    }
+
+    let speed = match src {
+        RtcClockSource::Lse => 32_768,
+        #[cfg(any(feature = "f3", feature = "f4"))]
+        RtcClockSource::Lsi => 40_000,
+        #[cfg(not(any(feature = "f3", feature = "f4")))]
+        RtcClockSource::Lsi => 32_000,
+        RtcClockSource::Hse => {
+            if hse_div < 2 {
+                0
+            } else {
+                hse_freq / hse_div
+            }
+        }
+    };
+
+    Some((src, speed))
+}
+
+/// The reason `Clocks::from_target` was unable to find a valid PLL configuration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PllSolveError {
+    /// The PLL source can't drive the PLL. (eg `PllSrc::None`)
+    NoPllSource,
+    /// The requested system clock is above this MCU's maximum. Values are in Hz.
+    AboveMax { requested: u32, max: u32 },
+    /// No input divider (PLLM or PREDIV) brings the source into the PLL's input range.
+    /// `src_freq` is the PLL source frequency, in Hz.
+    VcoInputUnreachable { src_freq: u32 },
+    /// No divider and multiplier combination produces the requested system clock exactly.
+    /// `closest` is the highest achievable frequency below the target, in Hz, or 0 if none.
+    NoSolution { closest: u32 },
+    /// The requested system clock is achievable, but not while also producing 48Mhz for USB.
+    Usb48Unreachable,
+}
+
 // #[derive(Clone, Copy)]
 // #[repr(u8)]
 // pub enum ClocksValid {
 //     Valid,
 //     NotValid,
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switch_pll_to_pll() {
+        // Speeding up: The PLL must be stopped before it's reconfigured, or `configure()` would
+        // switch back to it while it's still running with its old settings.
+        let steps: Vec<_> = switch_steps(true, 2, 4).collect();
+        assert_eq!(
+            steps,
+            [
+                SwitchStep::RaiseWaitStates,
+                SwitchStep::SysclkToInternal,
+                SwitchStep::StopPll,
+                SwitchStep::Configure,
+            ]
+        );
+
+        // Slowing down.
+        let steps: Vec<_> = switch_steps(true, 4, 2).collect();
+        assert_eq!(
+            steps,
+            [
+                SwitchStep::SysclkToInternal,
+                SwitchStep::StopPll,
+                SwitchStep::Configure,
+                SwitchStep::LowerWaitStates,
+            ]
+        );
+    }
+
+    #[test]
+    fn switch_not_from_pll() {
+        let steps: Vec<_> = switch_steps(false, 0, 4).collect();
+        assert_eq!(steps, [SwitchStep::RaiseWaitStates, SwitchStep::Configure]);
+
+        let steps: Vec<_> = switch_steps(false, 2, 2).collect();
+        assert_eq!(steps, [SwitchStep::Configure]);
+    }
+}
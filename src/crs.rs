@@ -0,0 +1,251 @@
+//! Support for the Clock Recovery System (CRS), which trims the HSI48 against a synchronization
+//! signal; eg USB SOF packets, or the LSE. This lets USB run without an external crystal. The
+//! HSI48 must be enabled (`Clocks::hsi48_on`) separately.
+
+use cortex_m::interrupt::free;
+
+use crate::pac::{CRS, RCC};
+
+pub use crate::clocks::CrsSyncSrc;
+
+use cfg_if::cfg_if;
+
+/// The HSI48 frequency the CRS trims towards, in Hz.
+const TARGET_FREQ: u32 = 48_000_000;
+
+/// HSI48 trimming step, in hundredths of a percent. (0.14%) Used to calculate FELIM. See the
+/// HSI48 characteristics table in your datasheet.
+const TRIM_STEP: u32 = 14;
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Which SYNC signal edge to synchronize on. Sets the CRS_CFGR register, SYNCPOL field.
+pub enum CrsSyncPolarity {
+    Rising = 0,
+    Falling = 1,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Divider applied to the SYNC signal, eg to bring a fast signal on the CRS_SYNC pin into
+/// range. Sets the CRS_CFGR register, SYNCDIV field.
+pub enum CrsSyncDivider {
+    Div1 = 0b000,
+    Div2 = 0b001,
+    Div4 = 0b010,
+    Div8 = 0b011,
+    Div16 = 0b100,
+    Div32 = 0b101,
+    Div64 = 0b110,
+    Div128 = 0b111,
+}
+
+impl CrsSyncDivider {
+    pub fn value(&self) -> u32 {
+        1 << (*self as u8)
+    }
+}
+
+#[derive(Clone, Copy)]
+/// CRS interrupts. Enabled in the CRS_CR register, and cleared in the CRS_ICR register.
+pub enum CrsInterrupt {
+    /// The frequency error is below the `felim` limit at a SYNC event.
+    SyncOk,
+    /// The frequency error is between 1x and 3x `felim`, at a SYNC event.
+    SyncWarning,
+    /// A sync error, sync miss, or trim overflow occurred. See `CrsStatus`.
+    Error,
+    /// The counter reached 0 without a SYNC event.
+    ExpectedSync,
+}
+
+/// Configuration for the CRS. Create it using its `Default::default()` implementation, then
+/// modify as required.
+#[derive(Clone)]
+pub struct CrsConfig {
+    /// The SYNC signal source. Defaults to USB SOF.
+    pub sync_src: CrsSyncSrc,
+    /// Defaults to rising edge.
+    pub polarity: CrsSyncPolarity,
+    /// Defaults to no division.
+    pub divider: CrsSyncDivider,
+    /// The frequency of the SYNC signal, in Hz, before `divider`. Used to calculate the RELOAD
+    /// and FELIM values. Defaults to 1kHz, for USB SOF. Use 32_768 for LSE.
+    pub sync_freq: u32,
+    /// Trim the HSI48 automatically at each SYNC event. If false, the error is measured, but
+    /// the trim is only changed by `Crs::set_trim()`. Defaults to true.
+    pub autotrim: bool,
+}
+
+impl Default for CrsConfig {
+    fn default() -> Self {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                let sync_src = CrsSyncSrc::OtgHs;
+            } else {
+                let sync_src = CrsSyncSrc::Usb;
+            }
+        }
+
+        Self {
+            sync_src,
+            polarity: CrsSyncPolarity::Rising,
+            divider: CrsSyncDivider::Div1,
+            sync_freq: 1_000,
+            autotrim: true,
+        }
+    }
+}
+
+/// CRS status, read from the CRS_ISR register.
+#[derive(Clone, Copy, Debug)]
+pub struct CrsStatus {
+    /// The frequency error was within `felim` at the last SYNC event.
+    pub sync_ok: bool,
+    /// The frequency error was between 1x and 3x `felim` at the last SYNC event.
+    pub sync_warning: bool,
+    /// One of `sync_error`, `sync_missed`, or `trim_overflow` is set.
+    pub error: bool,
+    /// The counter reached 0 without a SYNC event.
+    pub expected_sync: bool,
+    /// The frequency error was over 3x `felim` at a SYNC event.
+    pub sync_error: bool,
+    /// No SYNC event arrived before the counter reached its maximum error limit.
+    pub sync_missed: bool,
+    /// Automatic trimming tried to move the trim out of range.
+    pub trim_overflow: bool,
+    /// The HSI48 was running slow at the last SYNC event. (FEDIR)
+    pub hsi48_slow: bool,
+    /// The counter value captured at the last SYNC event, in HSI48 cycles. (FECAP) Its
+    /// distance from 0 is the frequency error.
+    pub error_capture: u16,
+}
+
+/// Calculate the RELOAD and FELIM values for a SYNC signal frequency, in Hz, after the SYNC
+/// divider. L4 RM, section 7.4.3: "RELOAD = (fTARGET / fSYNC) - 1", and "FELIM = (fTARGET /
+/// fSYNC) * STEP[%] / 100% / 2". FELIM is rounded up.
+pub fn reload_felim(sync_freq: u32) -> (u16, u8) {
+    let cycles = (TARGET_FREQ + sync_freq / 2) / sync_freq;
+    let felim = (cycles * TRIM_STEP + 19_999) / 20_000;
+
+    ((cycles - 1) as u16, felim.max(1).min(255) as u8)
+}
+
+/// Represents the Clock Recovery System peripheral.
+pub struct Crs {
+    pub regs: CRS,
+    pub cfg: CrsConfig,
+}
+
+impl Crs {
+    /// Initialize the CRS, including enabling its RCC peripheral clock. This configures the
+    /// SYNC signal, sets RELOAD and FELIM from `cfg.sync_freq`, and starts the frequency
+    /// error counter.
+    pub fn new(regs: CRS, cfg: CrsConfig) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+
+            // todo: CRSEN missing on l4x5 pac: https://github.com/stm32-rs/stm32-rs/issues/572
+            cfg_if! {
+                if #[cfg(feature = "h7")] {
+                    rcc.apb1henr.modify(|_, w| w.crsen().set_bit());
+                } else if #[cfg(feature = "l4x5")] {
+                    let val = rcc.apb1enr1.read().bits();
+                    rcc.apb1enr1.write(|w| unsafe { w.bits(val | (1 << 24)) });
+                } else {
+                    rcc.apb1enr1.modify(|_, w| w.crsen().set_bit());
+                }
+            }
+        });
+
+        let (reload, felim) = reload_felim(cfg.sync_freq / cfg.divider.value());
+
+        // CFGR must not be written while the counter is enabled.
+        regs.cr.modify(|_, w| w.cen().clear_bit());
+
+        regs.cfgr.modify(|_, w| unsafe {
+            w.syncsrc().bits(cfg.sync_src as u8);
+            w.syncpol().bit(cfg.polarity as u8 != 0);
+            w.syncdiv().bits(cfg.divider as u8);
+            w.reload().bits(reload);
+            w.felim().bits(felim)
+        });
+
+        regs.cr.modify(|_, w| {
+            w.autotrimen().bit(cfg.autotrim);
+            w.cen().set_bit()
+        });
+
+        Self { regs, cfg }
+    }
+
+    /// Stop the frequency error counter. The HSI48 keeps its current trim.
+    pub fn disable(&mut self) {
+        self.regs.cr.modify(|_, w| w.cen().clear_bit());
+    }
+
+    /// Read the current HSI48 trim value. This is updated by hardware if `autotrim` is set.
+    pub fn trim(&self) -> u8 {
+        self.regs.cr.read().trim().bits()
+    }
+
+    /// Set the HSI48 trim manually, eg to speed up lock-in at startup, or if `autotrim` is
+    /// disabled. The value is 6 bits, (7 on some variants) and resets to the middle of its range.
+    pub fn set_trim(&mut self, trim: u8) {
+        self.regs.cr.modify(|_, w| unsafe { w.trim().bits(trim) });
+    }
+
+    /// Generate a SYNC event in software. Useful if the SYNC signal is irregular, or for testing.
+    pub fn generate_sync(&mut self) {
+        self.regs.cr.modify(|_, w| w.swsync().set_bit());
+    }
+
+    /// Read status and error flags.
+    pub fn status(&self) -> CrsStatus {
+        let isr = self.regs.isr.read();
+
+        CrsStatus {
+            sync_ok: isr.syncokf().bit_is_set(),
+            sync_warning: isr.syncwarnf().bit_is_set(),
+            error: isr.errf().bit_is_set(),
+            expected_sync: isr.esyncf().bit_is_set(),
+            sync_error: isr.syncerr().bit_is_set(),
+            sync_missed: isr.syncmiss().bit_is_set(),
+            trim_overflow: isr.trimovf().bit_is_set(),
+            hsi48_slow: isr.fedir().bit_is_set(),
+            error_capture: isr.fecap().bits(),
+        }
+    }
+
+    /// Enable a CRS interrupt. These are handled by the `CRS` interrupt vector. (Shared with RCC
+    /// on some variants)
+    pub fn enable_interrupt(&mut self, interrupt: CrsInterrupt) {
+        self.regs.cr.modify(|_, w| match interrupt {
+            CrsInterrupt::SyncOk => w.syncokie().set_bit(),
+            CrsInterrupt::SyncWarning => w.syncwarnie().set_bit(),
+            CrsInterrupt::Error => w.errie().set_bit(),
+            CrsInterrupt::ExpectedSync => w.esyncie().set_bit(),
+        });
+    }
+
+    /// Disable a CRS interrupt.
+    pub fn disable_interrupt(&mut self, interrupt: CrsInterrupt) {
+        self.regs.cr.modify(|_, w| match interrupt {
+            CrsInterrupt::SyncOk => w.syncokie().clear_bit(),
+            CrsInterrupt::SyncWarning => w.syncwarnie().clear_bit(),
+            CrsInterrupt::Error => w.errie().clear_bit(),
+            CrsInterrupt::ExpectedSync => w.esyncie().clear_bit(),
+        });
+    }
+
+    /// Clear an interrupt flag. Clearing `Error` also clears the sync error, sync missed, and
+    /// trim overflow flags.
+    pub fn clear_interrupt(&mut self, interrupt: CrsInterrupt) {
+        self.regs.icr.write(|w| match interrupt {
+            CrsInterrupt::SyncOk => w.syncokc().set_bit(),
+            CrsInterrupt::SyncWarning => w.syncwarnc().set_bit(),
+            CrsInterrupt::Error => w.errc().set_bit(),
+            CrsInterrupt::ExpectedSync => w.esyncc().set_bit(),
+        });
+    }
+}
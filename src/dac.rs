@@ -23,15 +23,16 @@ cfg_if! {
 cfg_if! {
     if #[cfg(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1"))))] {
         use crate::pac::dma as dma_p;
-    } else if #[cfg(feature = "f4")] {} else {
+    } else if #[cfg(feature = "f4")] {
+        use crate::pac::dma2 as dma_p;
+    } else {
         use crate::pac::dma1 as dma_p;
     }
 }
 
-#[cfg(not(any(feature = "f4", feature = "l552")))]
-use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
+use crate::dma::{self, ChannelCfg, Dma, DmaChannel, ReadBuffer, Transfer};
 
-#[cfg(any(feature = "f3", feature = "l4"))]
+#[cfg(any(feature = "f3", feature = "f4", feature = "l4"))]
 use crate::dma::DmaInput;
 
 #[derive(Clone, Copy)]
@@ -333,32 +334,44 @@ where
 
     /// Send values to the DAC using DMA. Each trigger (Eg using a timer; the basic timers Tim6
     /// and Tim7 are designed for DAC triggering) sends one word from the buffer to the DAC's
-    /// output.
-    #[cfg(not(any(feature = "f4", feature = "l552")))]
-    pub unsafe fn write_dma<D>(
+    /// output. Returns a `Transfer` that holds `buf` until the transfer is complete. For continuous
+    /// output with a circular channel, stop it with `Transfer::abort`. On F4, `dma_channel` is the
+    /// DMA1 stream to use: Stream 5 for channel 1, and stream 6 for channel 2.
+    pub fn write_dma<B, D>(
         &mut self,
-        buf: &[u16],
+        buf: B,
         dac_channel: DacChannel,
         dma_channel: DmaChannel,
         channel_cfg: ChannelCfg,
         dma: &mut Dma<D>,
-    ) where
+    ) -> Transfer<B>
+    where
+        B: ReadBuffer<Word = u16>,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
-        let (ptr, len) = (buf.as_ptr(), buf.len());
+        let (ptr, len) = unsafe { buf.read_buffer() };
 
-        #[cfg(any(feature = "f3", feature = "l4"))]
-        let dma_channel = match dac_channel {
-            DacChannel::C1 => DmaInput::Dac1Ch1.dma1_channel(),
-            DacChannel::C2 => DmaInput::Dac1Ch2.dma1_channel(),
-        };
+        #[cfg(feature = "f3")]
+        let dma_channel = dma.select_channel(
+            match dac_channel {
+                DacChannel::C1 => DmaInput::Dac1Ch1.dma1_channel(),
+                DacChannel::C2 => DmaInput::Dac1Ch2.dma1_channel(),
+            },
+            dma_channel,
+        );
 
         #[cfg(feature = "l4")]
-        match dac_channel {
+        let dma_channel = match dac_channel {
             DacChannel::C1 => dma.channel_select(DmaInput::Dac1Ch1),
             DacChannel::C2 => dma.channel_select(DmaInput::Dac1Ch2),
         };
 
+        #[cfg(feature = "f4")]
+        match dac_channel {
+            DacChannel::C1 => dma.channel_select(dma_channel, DmaInput::Dac1Ch1),
+            DacChannel::C2 => dma.channel_select(dma_channel, DmaInput::Dac1Ch2),
+        };
+
         // H743 RM, section 26.4.8: DMA requests
         // Each DAC channel has a DMA capability. Two DMA channels are used to service DAC
         // channel DMA requests.
@@ -448,6 +461,8 @@ where
             dma::DataSize::S16,
             channel_cfg,
         );
+
+        Transfer::new(buf, dma_channel, dma.periph)
     }
 
     /// Set the DAC output voltage.
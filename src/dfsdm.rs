@@ -24,13 +24,12 @@ cfg_if! {
 cfg_if! {
     if #[cfg(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1"))))] {
         use crate::pac::dma as dma_p;
-    } else if #[cfg(any(feature = "f4", feature = "l552"))] {} else {
+    } else {
         use crate::pac::dma1 as dma_p;
     }
 }
 
-#[cfg(not(any(feature = "f4", feature = "l552")))]
-use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
+use crate::dma::{self, ChannelCfg, Dma, DmaChannel, Transfer, WriteBuffer};
 
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::dma::DmaInput;
@@ -1169,29 +1168,33 @@ where
     /// You need to shift the result 8 bits to the result after reading the values from memory
     /// to discard the other fields. (The integer signing is unchanged, since the 24-bit integer data
     /// is aligned to the left of the 32-bit register, which maps to an `i32` here.)
-    #[cfg(not(any(feature = "f4", feature = "l552")))]
-    pub unsafe fn read_dma<D>(
+    ///
+    /// Returns a `Transfer` that holds `buf` until the transfer is complete.
+    pub fn read_dma<B, D>(
         &mut self,
-        buf: &mut [i32],
+        mut buf: B,
         filter: Filter,
         dma_channel: DmaChannel,
         channel_cfg: ChannelCfg,
         dma: &mut Dma<D>,
-    ) where
+    ) -> Transfer<B>
+    where
+        B: WriteBuffer<Word = i32>,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
-        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
-
-        // todo: DMA2 support.
+        let (ptr, len) = unsafe { buf.write_buffer() };
 
         #[cfg(feature = "f3")]
-        let dma_channel = match filter {
-            Filter::F0 => DmaInput::Dfsdm1F0.dma1_channel(),
-            Filter::F1 => DmaInput::Dfsdm1F1.dma1_channel(),
-        };
+        let dma_channel = dma.select_channel(
+            match filter {
+                Filter::F0 => DmaInput::Dfsdm1F0.dma1_channel(),
+                Filter::F1 => DmaInput::Dfsdm1F1.dma1_channel(),
+            },
+            dma_channel,
+        );
 
         #[cfg(feature = "l4")]
-        match filter {
+        let dma_channel = match filter {
             Filter::F0 => dma.channel_select(DmaInput::Dfsdm1F0),
             Filter::F1 => dma.channel_select(DmaInput::Dfsdm1F1),
             Filter::F2 => dma.channel_select(DmaInput::Dfsdm1F2),
@@ -1330,6 +1333,8 @@ where
             dma::DataSize::S32,
             channel_cfg,
         );
+
+        Transfer::new(buf, dma_channel, dma.periph)
     }
 
     /// Enable a specific type of interrupt. See H743 RM, section 30.5: DFSDM interrupts
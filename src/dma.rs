@@ -1,11 +1,14 @@
 //! Support for the Direct Memory Access (DMA) peripheral. This module handles initialization, and transfer
 //! configuration for DMA. The `Dma::cfg_channel` method is called by modules that use DMA.
+//! Peripheral DMA methods return a `Transfer`, which holds the buffer until the transfer is done.
 
 // todo: This module could be greatly simplified if [this issue](https://github.com/stm32-rs/stm32-rs/issues/610)
 // todo is addressed: Ie H7 PAC approach adopted by other modules.
 
 use core::{
+    mem::ManuallyDrop,
     ops::Deref,
+    ptr,
     sync::atomic::{self, Ordering},
 };
 
@@ -19,6 +22,9 @@ use crate::{
 cfg_if! {
     if #[cfg(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1"))))] {
         use crate::pac::dma as dma1;
+    } else if #[cfg(feature = "f4")] {
+        // F4's PAC derives DMA1 from DMA2.
+        use crate::pac::dma2 as dma1;
     } else {
         use crate::pac::dma1;
     }
@@ -27,24 +33,24 @@ cfg_if! {
 #[cfg(any(feature = "g0", feature = "g4", feature = "wl"))]
 use pac::DMAMUX;
 
-// todo: DMAMUX2 support (Not sure if WB has it, but H7 has both).
 #[cfg(any(feature = "l5", feature = "wb", feature = "h7"))]
 use pac::DMAMUX1 as DMAMUX;
 
 #[cfg(feature = "h7")]
-use pac::DMAMUX2;
+use pac::{bdma, BDMA, DMAMUX2};
 
-// use embedded_dma::{ReadBuffer, WriteBuffer};
+pub use embedded_dma::{ReadBuffer, WriteBuffer};
 
 use cfg_if::cfg_if;
 
-// todo: Several sections of this are only correct for DMA1.
-
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum DmaPeriph {
     Dma1,
     #[cfg(not(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1")))))]
     Dma2,
+    #[cfg(feature = "h7")]
+    /// H7's Basic DMA, for D3-domain peripherals. See `Bdma`.
+    Bdma,
 }
 
 #[derive(Copy, Clone)]
@@ -230,16 +236,18 @@ pub enum DmaInput {
 #[derive(Copy, Clone)]
 #[repr(usize)]
 #[cfg(feature = "h7")]
-/// A list of DMA input sources for DMAMUX2. Used for BDMA. See H742 RM, Table 124.
+/// A list of DMA input sources for DMAMUX2. Used for BDMA, which serves the D3-domain
+/// peripherals: LPUART1, SPI6, I2C4, SAI4, and ADC3. See H742 RM, Table 124.
 pub enum DmaInput2 {
     Lpuart1Rx = 9,
     Lpuart1Tx = 10,
     Spi6Rx = 11,
     Spi6Tx = 12,
     I2c4Rx = 13,
-    I3crTx = 14,
+    I2c4Tx = 14,
     Sai4A = 15,
     Sai4B = 16,
+    Adc3 = 17,
 }
 
 impl DmaInput {
@@ -293,6 +301,35 @@ impl DmaInput {
         }
     }
 
+    #[cfg(feature = "l4")]
+    /// Find the channel and CSELR value for a given input on DMA2, for L4. Returns `None` if the
+    /// input isn't available on DMA2. See L44 RM, Table 42.
+    pub fn dma2_channel_select(&self) -> Option<(DmaChannel, u8)> {
+        use DmaChannel::*;
+
+        let val = match self {
+            Self::Adc1 => (C3, 0b0000),
+            Self::Adc2 => (C4, 0b0000),
+            Self::Sai1A => (C1, 0b0001),
+            Self::Sai1B => (C2, 0b0001),
+            Self::Usart1Tx => (C6, 0b0010),
+            Self::Usart1Rx => (C7, 0b0010),
+            Self::Spi3Rx => (C1, 0b0011),
+            Self::Spi3Tx => (C2, 0b0011),
+            Self::Dac1Ch1 => (C4, 0b0011),
+            Self::Dac1Ch2 => (C5, 0b0011),
+            Self::Spi1Rx => (C3, 0b0100),
+            Self::Spi1Tx => (C4, 0b0100),
+            Self::Lpuart1Tx => (C6, 0b0100),
+            Self::Lpuart1Rx => (C7, 0b0100),
+            Self::I2c1Rx => (C7, 0b0101),
+            Self::I2c1Tx => (C6, 0b0101),
+            _ => return None,
+        };
+
+        Some(val)
+    }
+
     #[cfg(feature = "l4")]
     /// Find the value to set in the DMA_CSELR register, for L4. Ie, channel select value for a given DMA input.
     /// See L44 RM, Table 41.
@@ -340,6 +377,54 @@ impl DmaInput {
             _ => unimplemented!(),
         }
     }
+
+    #[cfg(feature = "f4")]
+    /// Find the value to set in a stream's CHSEL field for a given DMA input, on F4. Returns `None`
+    /// if the input isn't available on that controller and stream. See F4 RM0090, Tables 42 and 43.
+    pub fn stream_channel_select(&self, periph: DmaPeriph, stream: DmaChannel) -> Option<u8> {
+        use DmaChannel::*;
+
+        let val = match periph {
+            DmaPeriph::Dma1 => match (self, stream) {
+                (Self::Spi3Rx, C0 | C2) => 0,
+                (Self::Spi2Rx, C3) => 0,
+                (Self::Spi2Tx, C4) => 0,
+                (Self::Spi3Tx, C5 | C7) => 0,
+                (Self::I2c1Rx, C0 | C5) => 1,
+                (Self::Tim7Up, C2 | C4) => 1,
+                (Self::I2c1Tx, C6 | C7) => 1,
+                (Self::I2c3Rx, C2) => 3,
+                (Self::I2c3Tx, C4) => 3,
+                (Self::Uart5Rx, C0) => 4,
+                (Self::Usart3Rx, C1) => 4,
+                (Self::Uart4Rx, C2) => 4,
+                (Self::Usart3Tx, C3) => 4,
+                (Self::Uart4Tx, C4) => 4,
+                (Self::Usart2Rx, C5) => 4,
+                (Self::Usart2Tx, C6) => 4,
+                (Self::Uart5Tx, C7) => 4,
+                (Self::Tim6Up, C1) => 7,
+                (Self::I2c2Rx, C2 | C3) => 7,
+                (Self::Usart3Tx, C4) => 7,
+                (Self::Dac1Ch1, C5) => 7,
+                (Self::Dac1Ch2, C6) => 7,
+                (Self::I2c2Tx, C7) => 7,
+                _ => return None,
+            },
+            DmaPeriph::Dma2 => match (self, stream) {
+                (Self::Adc1, C0 | C4) => 0,
+                (Self::Adc2, C2 | C3) => 1,
+                (Self::Adc3, C0 | C1) => 2,
+                (Self::Spi1Rx, C0 | C2) => 3,
+                (Self::Spi1Tx, C3 | C5) => 3,
+                (Self::Usart1Rx, C2 | C5) => 4,
+                (Self::Usart1Tx, C7) => 4,
+                _ => return None,
+            },
+        };
+
+        Some(val)
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -365,11 +450,11 @@ pub enum Priority {
 #[derive(Copy, Clone)]
 #[repr(u8)]
 /// Represents a DMA channel to select, eg when configuring for use with a peripheral.
-/// u8 representation is used to index registers on H7 PAC (And hopefully on future PACs if they
-/// adopt H7's approach)
+/// u8 representation is used to index registers on H7 and F4 PACs (And hopefully on future PACs if
+/// they adopt H7's approach)
 pub enum DmaChannel {
-    // H7 calls these Streams. We use the `Channel` name for consistency.
-    #[cfg(feature = "h7")]
+    // H7 and F4 call these Streams. We use the `Channel` name for consistency.
+    #[cfg(any(feature = "f4", feature = "h7"))]
     C0 = 0,
     C1 = 1,
     C2 = 2,
@@ -427,6 +512,29 @@ pub enum DataSize {
     S32 = 0b10,
 }
 
+#[cfg(any(feature = "f4", feature = "h7"))]
+#[derive(Copy, Clone)]
+#[repr(u8)]
+/// The FIFO threshold level, at which the stream transfers the FIFO's contents to memory.
+/// (SxFCR FTH bits)
+pub enum FifoThreshold {
+    Quarter = 0b00,
+    Half = 0b01,
+    ThreeQuarters = 0b10,
+    Full = 0b11,
+}
+
+#[cfg(any(feature = "f4", feature = "h7"))]
+#[derive(Copy, Clone, PartialEq)]
+#[repr(u8)]
+/// The number of beats in a burst transfer. (SxCR MBURST and PBURST bits)
+pub enum Burst {
+    Single = 0b00,
+    Incr4 = 0b01,
+    Incr8 = 0b10,
+    Incr16 = 0b11,
+}
+
 #[derive(Copy, Clone)]
 /// Interrupt type. Set in CCR using TEIE, HTIE, and TCIE bits.
 /// Can only be set when channel is disabled.
@@ -434,16 +542,16 @@ pub enum DmaInterrupt {
     TransferError,
     HalfTransfer,
     TransferComplete,
-    #[cfg(feature = "h7")]
+    #[cfg(any(feature = "f4", feature = "h7"))]
     DirectModeError,
-    #[cfg(feature = "h7")]
+    #[cfg(any(feature = "f4", feature = "h7"))]
     FifoError,
 }
 
 /// Reduce DRY over channels when configuring a channel's CCR.
 /// We must use a macro here, since match arms balk at the incompatible
 /// types of `CCR1`, `CCR2` etc.
-#[cfg(not(feature = "h7"))]
+#[cfg(not(any(feature = "f4", feature = "h7")))]
 macro_rules! set_ccr {
     ($ccr:expr, $priority:expr, $direction:expr, $circular:expr, $periph_incr:expr, $mem_incr:expr, $periph_size:expr, $mem_size:expr) => {
         // "The register fields/bits MEM2MEM, PL[1:0], MSIZE[1:0], PSIZE[1:0], MINC, PINC, and DIR
@@ -451,17 +559,15 @@ macro_rules! set_ccr {
         $ccr.modify(|_, w| w.en().clear_bit());
         while $ccr.read().en().bit_is_set() {}
 
-        if let Circular::Enabled = $circular {
-            $ccr.modify(|_, w| w.mem2mem().clear_bit());
-        }
-
         $ccr.modify(|_, w| unsafe {
             // – the channel priority
             w.pl().bits($priority as u8);
+            // – memory-to-memory mode. This must be clear in circular mode.
+            w.mem2mem().bit($direction as u8 == Direction::MemToMem as u8);
             // – the data transfer direction
-            // This bit [DIR] must be set only in memory-to-peripheral and peripheral-to-memory modes.
-            // 0: read from peripheral
-            w.dir().bit($direction as u8 != 0);
+            // 0: read from peripheral. In memory-to-memory mode, this reads from CPAR, and writes
+            // to CMAR, as on H7 and F4.
+            w.dir().bit($direction as u8 == Direction::ReadFromMem as u8);
             // – the circular mode
             w.circ().bit($circular as u8 != 0);
             // – the peripheral and memory incremented mode
@@ -479,7 +585,7 @@ macro_rules! set_ccr {
 }
 
 /// Reduce DRY over channels when configuring a channel's interrupts.
-#[cfg(not(feature = "h7"))]
+#[cfg(not(any(feature = "f4", feature = "h7")))]
 macro_rules! enable_interrupt {
     ($ccr:expr, $interrupt_type:expr) => {
         let originally_enabled = $ccr.read().en().bit_is_set();
@@ -516,6 +622,22 @@ pub struct ChannelCfg {
     /// Whether we increment the buffer address on data word transfer; generally (and by default)
     /// enabled.
     pub mem_incr: IncrMode,
+    /// Enable double-buffer mode. The buffer is split into two halves, addressed by the `M0AR` and
+    /// `M1AR` registers, which the DMA fills alternately; this implies circular mode. Process the
+    /// halves with `PingPong`. Defaults to disabled.
+    #[cfg(any(feature = "f4", feature = "h7"))]
+    pub double_buffer: bool,
+    /// Use the FIFO, with a given threshold. If `None`, the stream is in direct mode, where each
+    /// data item is written out as soon as it's read. The FIFO is required for bursts, and for
+    /// transfers where the peripheral and memory data sizes differ. Defaults to `None`.
+    #[cfg(any(feature = "f4", feature = "h7"))]
+    pub fifo_threshold: Option<FifoThreshold>,
+    /// The memory burst size. Defaults to single transfers.
+    #[cfg(any(feature = "f4", feature = "h7"))]
+    pub mem_burst: Burst,
+    /// The peripheral burst size. Defaults to single transfers.
+    #[cfg(any(feature = "f4", feature = "h7"))]
+    pub periph_burst: Burst,
 }
 
 impl Default for ChannelCfg {
@@ -526,6 +648,14 @@ impl Default for ChannelCfg {
             // Increment the buffer address, not the peripheral address.
             periph_incr: IncrMode::Disabled,
             mem_incr: IncrMode::Enabled,
+            #[cfg(any(feature = "f4", feature = "h7"))]
+            double_buffer: false,
+            #[cfg(any(feature = "f4", feature = "h7"))]
+            fifo_threshold: None,
+            #[cfg(any(feature = "f4", feature = "h7"))]
+            mem_burst: Burst::Single,
+            #[cfg(any(feature = "f4", feature = "h7"))]
+            periph_burst: Burst::Single,
         }
     }
 }
@@ -533,6 +663,8 @@ impl Default for ChannelCfg {
 /// Represents a Direct Memory Access (DMA) peripheral.
 pub struct Dma<D> {
     pub regs: D,
+    /// Which controller this is; DMA1 or DMA2. Set in `new()`.
+    pub periph: DmaPeriph,
 }
 
 // We use this macro to differentiate DMA1 and DMA2.
@@ -545,28 +677,74 @@ pub struct Dma<D> {
             D: Deref<Target = dma1::RegisterBlock>,
         {
             /// Initialize a DMA peripheral, including enabling and resetting
-            /// its RCC peripheral clock.
+            /// its RCC peripheral clock. Works with DMA1 or DMA2, eg `Dma::new(dp.DMA2)`.
             pub fn new(regs: D) -> Self {
-                // todo: Enable RCC for DMA 2 etc!
+                // Both controllers share a register block type, so tell them apart by address.
+                cfg_if! {
+                    if #[cfg(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1"))))] {
+                        let periph = DmaPeriph::Dma1;
+                    } else {
+                        let periph = if &*regs as *const _ == pac::DMA1::ptr() {
+                            DmaPeriph::Dma1
+                        } else {
+                            DmaPeriph::Dma2
+                        };
+                    }
+                }
+
                 free(|_| {
                     let rcc = unsafe { &(*RCC::ptr()) };
-                    cfg_if! {
-                        if #[cfg(feature = "f3")] {
-                            rcc.ahbenr.modify(|_, w| w.dma1en().set_bit()); // no dmarst on F3.
-                        } else if #[cfg(feature = "g0")] {
-                            rcc_en_reset!(ahb1, dma, rcc);
-                        } else {
-                            rcc_en_reset!(ahb1, dma1, rcc);
+
+                    match periph {
+                        DmaPeriph::Dma1 => {
+                            cfg_if! {
+                                if #[cfg(feature = "f3")] {
+                                    rcc.ahbenr.modify(|_, w| w.dma1en().set_bit()); // no dmarst on F3.
+                                } else if #[cfg(feature = "g0")] {
+                                    rcc_en_reset!(ahb1, dma, rcc);
+                                } else {
+                                    rcc_en_reset!(ahb1, dma1, rcc);
+                                }
+                            }
+                        }
+                        #[cfg(not(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1")))))]
+                        DmaPeriph::Dma2 => {
+                            cfg_if! {
+                                if #[cfg(any(feature = "f301", feature = "f3x4"))] {
+                                    panic!("DMA2 isn't available on this MCU.");
+                                } else if #[cfg(feature = "f3")] {
+                                    rcc.ahbenr.modify(|_, w| w.dma2en().set_bit());
+                                } else {
+                                    rcc_en_reset!(ahb1, dma2, rcc);
+                                }
+                            }
                         }
+                        #[cfg(feature = "h7")]
+                        DmaPeriph::Bdma => unreachable!(),
                     }
                 });
 
-                Self { regs }
+                Self { regs, periph }
+            }
+
+            #[cfg(feature = "f3")]
+            /// On F3, each peripheral's DMA1 requests are hard-wired to a channel, which
+            /// peripheral modules look up. DMA2 requests use the channel passed by the caller.
+            /// (On L4, `channel_select` returns the channel for both controllers)
+            pub(crate) fn select_channel(
+                &self,
+                dma1_channel: DmaChannel,
+                channel: DmaChannel,
+            ) -> DmaChannel {
+                match self.periph {
+                    DmaPeriph::Dma1 => dma1_channel,
+                    DmaPeriph::Dma2 => channel,
+                }
             }
 
             /// Configure a DMA channel. See L4 RM 0394, section 11.4.4. Sets the Transfer Complete
             /// interrupt.
-            #[cfg(not(feature = "h7"))]
+            #[cfg(not(any(feature = "f4", feature = "h7")))]
             pub fn cfg_channel(
                 &mut self,
                 channel: DmaChannel,
@@ -580,6 +758,9 @@ pub struct Dma<D> {
             ) {
                 // See the comments in the H7 variant for a description of what's going on.
 
+                // Clear a stale flag from a previous transfer, so `Transfer::wait` doesn't return early.
+                self.clear_interrupt(channel, DmaInterrupt::TransferComplete);
+
                 unsafe {
                     match channel {
                         DmaChannel::C1 => {
@@ -1001,23 +1182,39 @@ pub struct Dma<D> {
                 }
             }
 
-            /// Configure a DMA channel. See L4 RM 0394, section 11.4.4. Sets the Transfer Complete
-            /// interrupt.
-            #[cfg(feature = "h7")]
+            /// Configure a DMA stream. See H743 RM, section 15.3.19, or F4 RM 0090, section 10.3.18.
+            /// Sets the Transfer Complete interrupt. On F4, select the stream's request channel
+            /// with `channel_select` first.
+            #[cfg(any(feature = "f4", feature = "h7"))]
             pub fn cfg_channel(
                 &mut self,
                 channel: DmaChannel,
                 periph_addr: u32,
                 mem_addr: u32,
-                num_data: u32,
+                #[cfg(feature = "h7")] num_data: u32,
+                #[cfg(feature = "f4")] num_data: u16,
                 direction: Direction,
                 periph_size: DataSize,
                 mem_size: DataSize,
                 cfg: ChannelCfg,
             ) {
+                // NDTR is 16 bits on F4.
+                #[cfg(feature = "f4")]
+                let num_data = num_data as u32;
+
+                if cfg.fifo_threshold.is_none() {
+                    assert!(
+                        cfg.mem_burst == Burst::Single && cfg.periph_burst == Burst::Single,
+                        "Burst transfers require the FIFO."
+                    );
+                }
+
                 // todo: The H7 sections are different, but we consolidated the comments. Figure out
                 // todo what's different and fix it by following the steps
 
+                // Clear a stale flag from a previous transfer, so `Transfer::wait` doesn't return early.
+                self.clear_interrupt(channel, DmaInterrupt::TransferComplete);
+
                 self.regs.st[channel as usize]
                     .cr
                     .modify(|_, w| w.en().clear_bit());
@@ -1040,7 +1237,26 @@ pub struct Dma<D> {
                     .m0ar
                     .write(|w| unsafe { w.bits(mem_addr) });
 
-                // todo: m1ar too, if in double-buffer mode.
+                // In double-buffer mode, the second half of the buffer goes in M1AR, and the
+                // number of data applies to each half.
+                let num_data = if cfg.double_buffer {
+                    assert!(num_data % 2 == 0, "Double-buffer mode requires an even length.");
+                    let half = num_data / 2;
+
+                    let word_size = match mem_size {
+                        DataSize::S8 => 1,
+                        DataSize::S16 => 2,
+                        DataSize::S32 => 4,
+                    };
+
+                    self.regs.st[channel as usize]
+                        .m1ar
+                        .write(|w| unsafe { w.bits(mem_addr + half * word_size) });
+
+                    half
+                } else {
+                    num_data
+                };
 
                 // 3. Configure the total number of data to transfer in the DMA_CNDTRx register.
                 // After each data transfer, this value is decremented.
@@ -1071,7 +1287,7 @@ pub struct Dma<D> {
 
                 // (See remainder of steps in `set_ccr()!` macro.
 
-                // todo: Let user set mem2mem mode?
+                // For memory-to-memory transfers, see `memcpy` and `memset`.
 
                 // See the [Embedonomicon section on DMA](https://docs.rust-embedded.org/embedonomicon/dma.html)
                 // for info on why we use `compiler_fence` here:
@@ -1082,6 +1298,16 @@ pub struct Dma<D> {
                 cr.modify(|_, w| w.en().clear_bit());
                 while cr.read().en().bit_is_set() {}
 
+                // – the FIFO: Setting DMDIS disables direct mode, and uses the FIFO.
+                let fcr = &self.regs.st[channel as usize].fcr;
+                match cfg.fifo_threshold {
+                    Some(threshold) => fcr.modify(|_, w| unsafe {
+                        w.dmdis().set_bit();
+                        w.fth().bits(threshold as u8)
+                    }),
+                    None => fcr.modify(|_, w| w.dmdis().clear_bit()),
+                }
+
                 cr.modify(|_, w| unsafe {
                     // – the channel priority
                     w.pl().bits(cfg.priority as u8);
@@ -1091,12 +1317,18 @@ pub struct Dma<D> {
                     w.dir().bits(direction as u8);
                     // – the circular mode
                     w.circ().bit(cfg.circular as u8 != 0);
+                    // – double-buffer mode, starting with the buffer at M0AR
+                    w.dbm().bit(cfg.double_buffer);
+                    w.ct().clear_bit();
                     // – the peripheral and memory incremented mode
                     w.pinc().bit(cfg.periph_incr as u8 != 0);
                     w.minc().bit(cfg.mem_incr as u8 != 0);
                     // – the peripheral and memory data size
                     w.psize().bits(periph_size as u8);
                     w.msize().bits(mem_size as u8);
+                    // – the memory and peripheral burst sizes
+                    w.mburst().bits(cfg.mem_burst as u8);
+                    w.pburst().bits(cfg.periph_burst as u8);
                     // – the interrupt enable at half and/or full transfer and/or transfer error
                     w.tcie().set_bit();
                     // (See `Step 5` above.)
@@ -1105,7 +1337,7 @@ pub struct Dma<D> {
             }
 
             /// Stop DMA.
-            #[cfg(not(feature = "h7"))]
+            #[cfg(not(any(feature = "f4", feature = "h7")))]
             pub fn stop(&mut self, channel: DmaChannel) {
                 // L4 RM:
                 // Once the software activates a channel, it waits for the completion of the programmed
@@ -1217,7 +1449,7 @@ pub struct Dma<D> {
             }
 
             /// Stop DMA.
-            #[cfg(feature = "h7")]
+            #[cfg(any(feature = "f4", feature = "h7"))]
             pub fn stop(&mut self, channel: DmaChannel) {
                 // L4 RM:
                 // Once the software activates a channel, it waits for the completion of the programmed
@@ -1244,8 +1476,15 @@ pub struct Dma<D> {
                 // TEIFx bit of the DMA_ISR register is set
             }
 
-            // todo: G0 removed from this fn due to a bug introduced in PAC 0.13
-            #[cfg(not(any(feature = "h7", feature = "g0")))]
+            #[cfg(feature = "g0")]
+            pub fn transfer_is_complete(&mut self, channel: DmaChannel) -> bool {
+                // G0 uses raw bits, due to a bug in the ISR fields introduced in PAC 0.13.
+                // TCIFx is bit 1 of each channel's 4-bit group.
+                let tcif = 1 << ((channel as u8 - 1) * 4 + 1);
+                self.regs.isr.read().bits() & tcif != 0
+            }
+
+            #[cfg(not(any(feature = "f4", feature = "h7", feature = "g0")))]
             pub fn transfer_is_complete(&mut self, channel: DmaChannel) -> bool {
                 let isr_val = self.regs.isr.read();
                 match channel {
@@ -1263,7 +1502,7 @@ pub struct Dma<D> {
                 }
             }
 
-            #[cfg(feature = "h7")]
+            #[cfg(any(feature = "f4", feature = "h7"))]
             pub fn transfer_is_complete(&mut self, channel: DmaChannel) -> bool {
                 match channel {
                     DmaChannel::C0 => self.regs.lisr.read().tcif0().bit_is_set(),
@@ -1277,13 +1516,61 @@ pub struct Dma<D> {
                 }
             }
 
+            /// Check if one of a channel's interrupt flags is set. Flags are set whether or not
+            /// their interrupt is enabled, so this can be used to poll for events, eg a half
+            /// transfer.
+            pub fn flag_is_set(&mut self, channel: DmaChannel, interrupt: DmaInterrupt) -> bool {
+                // We use raw bits here, since ISR field names vary by PAC.
+                cfg_if! {
+                    if #[cfg(any(feature = "f4", feature = "h7"))] {
+                        // LISR holds streams 0 - 3, and HISR streams 4 - 7. Each stream's flags
+                        // start at bit 0, 6, 16, or 22.
+                        let base = [0, 6, 16, 22][channel as usize % 4];
+                        let offset = match interrupt {
+                            DmaInterrupt::FifoError => 0,
+                            DmaInterrupt::DirectModeError => 2,
+                            DmaInterrupt::TransferError => 3,
+                            DmaInterrupt::HalfTransfer => 4,
+                            DmaInterrupt::TransferComplete => 5,
+                        };
+
+                        let isr = if (channel as u8) < 4 {
+                            self.regs.lisr.read().bits()
+                        } else {
+                            self.regs.hisr.read().bits()
+                        };
+                    } else {
+                        // Each channel has a 4-bit group, starting at channel 1.
+                        let base = (channel as u8 - 1) * 4;
+                        let offset = match interrupt {
+                            DmaInterrupt::TransferComplete => 1,
+                            DmaInterrupt::HalfTransfer => 2,
+                            DmaInterrupt::TransferError => 3,
+                        };
+
+                        let isr = self.regs.isr.read().bits();
+                    }
+                }
+
+                isr & (1 << (base + offset)) != 0
+            }
+
             #[cfg(feature = "l4")] // Only required on L4
-            /// Select which peripheral on a given channel we're using.
-            /// See L44 RM, Table 41.
-            pub fn channel_select(&mut self, input: DmaInput) {
+            /// Select which peripheral on a given channel we're using. Each input is wired to a
+            /// fixed channel on each controller; this returns that channel, which peripheral DMA
+            /// methods use in place of the one passed to them. Panics if `input` isn't available
+            /// on DMA2. See L44 RM, Tables 41 and 42.
+            pub fn channel_select(&mut self, input: DmaInput) -> DmaChannel {
+                let (channel, val) = match self.periph {
+                    DmaPeriph::Dma1 => (input.dma1_channel(), input.dma1_channel_select()),
+                    DmaPeriph::Dma2 => match input.dma2_channel_select() {
+                        Some(v) => v,
+                        None => panic!("This DMA input isn't available on DMA2."),
+                    },
+                };
+
                 // todo: Allow selecting channels in pairs to save a write.
-                let val = input.dma1_channel_select();
-                self.regs.cselr.modify(|_, w| match input.dma1_channel() {
+                self.regs.cselr.modify(|_, w| match channel {
                     DmaChannel::C1 => w.c1s().bits(val),
                     DmaChannel::C2 => w.c2s().bits(val),
                     DmaChannel::C3 => w.c3s().bits(val),
@@ -1292,11 +1579,31 @@ pub struct Dma<D> {
                     DmaChannel::C6 => w.c6s().bits(val),
                     DmaChannel::C7 => w.c7s().bits(val),
                 });
+
+                channel
+            }
+
+            #[cfg(feature = "f4")]
+            /// Select which peripheral request a stream serves, by setting its CHSEL field. Each
+            /// request is only available on certain streams of one controller; this panics if
+            /// `input` isn't available on `channel`. See F4 RM0090, Tables 42 and 43.
+            pub fn channel_select(&mut self, channel: DmaChannel, input: DmaInput) {
+                let val = match input.stream_channel_select(self.periph, channel) {
+                    Some(v) => v,
+                    None => panic!("This DMA input isn't available on this controller and stream."),
+                };
+
+                // CHSEL can only be written while the stream is disabled.
+                let cr = &self.regs.st[channel as usize].cr;
+                cr.modify(|_, w| w.en().clear_bit());
+                while cr.read().en().bit_is_set() {}
+
+                cr.modify(|_, w| unsafe { w.chsel().bits(val) });
             }
 
             /// Enable a specific type of interrupt. Note that the `TransferComplete` interrupt
             /// is enabled automatically, by the `cfg_channel` method.
-            #[cfg(not(feature = "h7"))]
+            #[cfg(not(any(feature = "f4", feature = "h7")))]
             pub fn enable_interrupt(&mut self, channel: DmaChannel, interrupt: DmaInterrupt) {
                 // Can only be set when the channel is disabled.
                 match channel {
@@ -1382,7 +1689,7 @@ pub struct Dma<D> {
 
             /// Enable a specific type of interrupt. Note that the `TransferComplete` interrupt
             /// is enabled automatically, by the `cfg_channel` method.
-            #[cfg(feature = "h7")]
+            #[cfg(any(feature = "f4", feature = "h7"))]
             pub fn enable_interrupt(&mut self, channel: DmaChannel, interrupt: DmaInterrupt) {
                 // Can only be set when the channel is disabled.
                 let cr = &self.regs.st[channel as usize].cr;
@@ -1413,7 +1720,7 @@ pub struct Dma<D> {
             /// Disable a specific type of interrupt. Note that the `TransferComplete` interrupt
             /// is enabled automatically, by the `cfg_channel` method.
             /// todo: Non-H7 version too!
-            #[cfg(feature = "h7")]
+            #[cfg(any(feature = "f4", feature = "h7"))]
             pub fn disable_interrupt(&mut self, channel: DmaChannel, interrupt: DmaInterrupt) {
                 // Can only be set when the channel is disabled.
                 // todo: Is this true for disabling interrupts true, re the channel must be disabled?
@@ -1488,7 +1795,7 @@ pub struct Dma<D> {
                                 DmaInterrupt::TransferComplete => w.tcif8().set_bit(),
                             }
                         });
-                    } else if #[cfg(feature = "h7")] {
+                    } else if #[cfg(any(feature = "f4", feature = "h7"))] {
                         match channel {
                             DmaChannel::C0 => match interrupt {
                                 DmaInterrupt::TransferError => self.regs.lifcr.write(|w| w.cteif0().set_bit()),
@@ -1547,8 +1854,18 @@ pub struct Dma<D> {
                                 DmaInterrupt::FifoError => self.regs.hifcr.write(|w| w.cfeif7().set_bit()),
                             }
                         }
-                        // todo: G0 PAC 0.14 had a reversion where these flags used to work, but now don't.
-                    } else if #[cfg(not(feature = "g0"))] {
+                    } else if #[cfg(feature = "g0")] {
+                        // G0 PAC 0.14 had a reversion where the IFCR fields used to work, but now
+                        // don't, so we write raw bits. Each channel has a 4-bit group.
+                        let offset = match interrupt {
+                            DmaInterrupt::TransferComplete => 1,
+                            DmaInterrupt::HalfTransfer => 2,
+                            DmaInterrupt::TransferError => 3,
+                        };
+                        self.regs
+                            .ifcr
+                            .write(|w| unsafe { w.bits(1 << ((channel as u8 - 1) * 4 + offset)) });
+                    } else {
                         self.regs.ifcr.write(|w| match channel {
                             DmaChannel::C1 => match interrupt {
                                 DmaInterrupt::TransferError => w.cteif1().set_bit(),
@@ -1601,6 +1918,556 @@ pub struct Dma<D> {
     // }
 // }
 
+/// A DMA transfer in progress. It owns the buffer being transferred, so the buffer can't be read,
+/// written, moved, or dropped while the DMA is using it. Returned by peripheral DMA methods, eg
+/// `Usart::write_dma` and `Spi::read_dma`. Get the buffer back with `wait()` or `abort()`.
+///
+/// Buffers implement `ReadBuffer` (memory to peripheral) or `WriteBuffer` (peripheral to memory).
+/// These are implemented for `&'static` and `&'static mut` arrays and slices, eg from
+/// `cortex_m::singleton!`, and for other stable pointers to `'static` memory.
+///
+/// Dropping a `Transfer` stops its channel. If it's leaked instead, eg with `mem::forget()`, the
+/// channel keeps running, and the DMA may keep writing to the buffer after it's been reused.
+pub struct Transfer<BUF> {
+    buf: BUF,
+    channel: DmaChannel,
+    periph: DmaPeriph,
+}
+
+impl<BUF> Transfer<BUF> {
+    /// Called by peripheral modules after starting the channel.
+    pub(crate) fn new(buf: BUF, channel: DmaChannel, periph: DmaPeriph) -> Self {
+        Self {
+            buf,
+            channel,
+            periph,
+        }
+    }
+
+    /// The channel this transfer runs on. On F3 and L4, this is the peripheral's fixed channel,
+    /// which may differ from the one passed to the DMA method.
+    pub fn channel(&self) -> DmaChannel {
+        self.channel
+    }
+
+    /// The DMA controller this transfer runs on.
+    pub fn periph(&self) -> DmaPeriph {
+        self.periph
+    }
+
+    /// Check if the transfer is complete, without blocking. Eg use this in the DMA interrupt
+    /// handler before calling `wait()`. `dma` must be the controller the transfer runs on.
+    pub fn is_complete<C: DmaController>(&self, dma: &mut C) -> bool {
+        check_periph(self.periph, dma);
+        dma.transfer_is_complete(self.channel)
+    }
+
+    /// Block until the transfer is complete, then stop the channel and return the buffer. In
+    /// circular mode, the transfer-complete flag is set at the end of each pass through the
+    /// buffer, so this returns after the current pass. `dma` must be the controller the transfer
+    /// runs on.
+    pub fn wait<C: DmaController>(self, dma: &mut C) -> BUF {
+        check_periph(self.periph, dma);
+
+        while !dma.transfer_is_complete(self.channel) {}
+        dma.clear_interrupt(self.channel, DmaInterrupt::TransferComplete);
+
+        self.abort(dma)
+    }
+
+    /// Stop the transfer, and return the buffer. Some of the buffer may not have been transferred.
+    /// `dma` must be the controller the transfer runs on.
+    pub fn abort<C: DmaController>(self, dma: &mut C) -> BUF {
+        check_periph(self.periph, dma);
+
+        dma.stop(self.channel);
+
+        // Don't let reads of the buffer be moved before the DMA has stopped writing to it.
+        atomic::compiler_fence(Ordering::Acquire);
+
+        self.into_buf()
+    }
+
+    /// Take the buffer, without stopping the channel again in `drop()`.
+    fn into_buf(self) -> BUF {
+        let transfer = ManuallyDrop::new(self);
+        unsafe { ptr::read(&transfer.buf) }
+    }
+}
+
+impl<BUF> Drop for Transfer<BUF> {
+    fn drop(&mut self) {
+        stop_channel(self.periph, self.channel);
+    }
+}
+
+/// The channel operations a `Transfer` uses on its controller. Implemented by `Dma`, and on H7,
+/// by `Bdma`.
+pub trait DmaController {
+    /// Which controller this is.
+    fn periph(&self) -> DmaPeriph;
+    fn stop(&mut self, channel: DmaChannel);
+    fn transfer_is_complete(&mut self, channel: DmaChannel) -> bool;
+    fn clear_interrupt(&mut self, channel: DmaChannel, interrupt: DmaInterrupt);
+}
+
+impl<D> DmaController for Dma<D>
+where
+    D: Deref<Target = dma1::RegisterBlock>,
+{
+    fn periph(&self) -> DmaPeriph {
+        self.periph
+    }
+
+    fn stop(&mut self, channel: DmaChannel) {
+        Dma::stop(self, channel)
+    }
+
+    fn transfer_is_complete(&mut self, channel: DmaChannel) -> bool {
+        Dma::transfer_is_complete(self, channel)
+    }
+
+    fn clear_interrupt(&mut self, channel: DmaChannel, interrupt: DmaInterrupt) {
+        Dma::clear_interrupt(self, channel, interrupt)
+    }
+}
+
+#[cfg(feature = "h7")]
+impl DmaController for Bdma {
+    fn periph(&self) -> DmaPeriph {
+        DmaPeriph::Bdma
+    }
+
+    fn stop(&mut self, channel: DmaChannel) {
+        Bdma::stop(self, channel)
+    }
+
+    fn transfer_is_complete(&mut self, channel: DmaChannel) -> bool {
+        Bdma::transfer_is_complete(self, channel)
+    }
+
+    fn clear_interrupt(&mut self, channel: DmaChannel, interrupt: DmaInterrupt) {
+        Bdma::clear_interrupt(self, channel, interrupt)
+    }
+}
+
+/// Panic if `dma` isn't the controller `periph`; eg if a transfer started on DMA1 is passed DMA2.
+fn check_periph<C: DmaController>(periph: DmaPeriph, dma: &C) {
+    assert!(
+        dma.periph() == periph,
+        "This transfer runs on a different DMA controller."
+    );
+}
+
+/// Stop a channel, without its `Dma` struct. Used when dropping a transfer, so the DMA doesn't
+/// keep using a buffer that's been released.
+fn stop_channel(periph: DmaPeriph, channel: DmaChannel) {
+    let regs = match periph {
+        #[cfg(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1"))))]
+        DmaPeriph::Dma1 => pac::DMA::ptr() as *const dma1::RegisterBlock,
+        #[cfg(not(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1")))))]
+        DmaPeriph::Dma1 => pac::DMA1::ptr() as *const dma1::RegisterBlock,
+        #[cfg(any(feature = "f301", feature = "f3x4"))]
+        DmaPeriph::Dma2 => panic!("DMA2 isn't available on this MCU."),
+        #[cfg(not(any(
+            feature = "f301",
+            feature = "f3x4",
+            all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1")))
+        )))]
+        DmaPeriph::Dma2 => pac::DMA2::ptr() as *const dma1::RegisterBlock,
+        #[cfg(feature = "h7")]
+        DmaPeriph::Bdma => {
+            stop_bdma_channel(unsafe { &*BDMA::ptr() }, channel);
+            atomic::compiler_fence(Ordering::Acquire);
+            return;
+        }
+    };
+
+    let mut dma = Dma {
+        regs: unsafe { &*regs },
+        periph,
+    };
+    dma.stop(channel);
+
+    atomic::compiler_fence(Ordering::Acquire);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Errors when starting a memory-to-memory transfer.
+pub enum DmaError {
+    /// A buffer's address isn't aligned to its word size.
+    Misaligned,
+    /// A buffer is empty, the destination is shorter than the source, or the transfer is longer
+    /// than 65,535 words.
+    InvalidLength,
+    /// The word size isn't 8, 16, or 32 bits, or this controller can't transfer memory to memory.
+    /// (DMA1 on F4)
+    Unsupported,
+}
+
+/// The transfer data size for a buffer's word type.
+fn word_size<W>() -> Result<DataSize, DmaError> {
+    match core::mem::size_of::<W>() {
+        1 => Ok(DataSize::S8),
+        2 => Ok(DataSize::S16),
+        4 => Ok(DataSize::S32),
+        _ => Err(DmaError::Unsupported),
+    }
+}
+
+impl<D> Dma<D>
+where
+    D: Deref<Target = dma1::RegisterBlock>,
+{
+    /// Copy `src` to the start of `dst` in the background, eg to move a framebuffer or audio
+    /// block without CPU involvement. Data is moved in the buffers' word size. Use the returned
+    /// `Transfer` to check for completion; it returns both buffers. No peripheral request is
+    /// involved, so the channel doesn't need to be routed or selected. On F4, only DMA2 can do
+    /// this. On H7, DMA1 and DMA2 can't access DTCM, so keep the buffers elsewhere, eg AXI SRAM.
+    pub fn memcpy<S, T>(
+        &mut self,
+        channel: DmaChannel,
+        src: S,
+        mut dst: T,
+    ) -> Result<Transfer<(S, T)>, DmaError>
+    where
+        S: ReadBuffer,
+        T: WriteBuffer<Word = S::Word>,
+    {
+        let (src_ptr, src_len) = unsafe { src.read_buffer() };
+        let (dst_ptr, dst_len) = unsafe { dst.write_buffer() };
+
+        if dst_len < src_len {
+            return Err(DmaError::InvalidLength);
+        }
+
+        let size = self.check_mem_to_mem::<S::Word>(src_ptr as u32, dst_ptr as u32, src_len)?;
+
+        self.start_mem_to_mem(
+            channel,
+            src_ptr as u32,
+            dst_ptr as u32,
+            src_len,
+            size,
+            IncrMode::Enabled,
+        );
+
+        Ok(Transfer::new((src, dst), channel, self.periph))
+    }
+
+    /// Fill `dst` with `value` in the background. The CPU writes the first word, and the DMA
+    /// copies it over the rest of the buffer. See `memcpy()`.
+    pub fn memset<B>(
+        &mut self,
+        channel: DmaChannel,
+        value: B::Word,
+        mut dst: B,
+    ) -> Result<Transfer<B>, DmaError>
+    where
+        B: WriteBuffer,
+    {
+        let (ptr, len) = unsafe { dst.write_buffer() };
+
+        // Check before writing the first word, so we don't write through a misaligned pointer.
+        let size = self.check_mem_to_mem::<B::Word>(ptr as u32, ptr as u32, len)?;
+
+        unsafe { ptr.write_volatile(value) };
+
+        // The DMA reads the value back from memory.
+        atomic::compiler_fence(Ordering::Release);
+
+        // The source address stays on the first word. Copying it over itself is harmless, and
+        // keeps this working for single-word buffers.
+        self.start_mem_to_mem(
+            channel,
+            ptr as u32,
+            ptr as u32,
+            len,
+            size,
+            IncrMode::Disabled,
+        );
+
+        Ok(Transfer::new(dst, channel, self.periph))
+    }
+
+    /// Check that a memory-to-memory transfer is possible, and return its data size.
+    fn check_mem_to_mem<W>(
+        &self,
+        src_addr: u32,
+        dst_addr: u32,
+        len: usize,
+    ) -> Result<DataSize, DmaError> {
+        let size = word_size::<W>()?;
+
+        #[cfg(feature = "f4")]
+        if self.periph != DmaPeriph::Dma2 {
+            return Err(DmaError::Unsupported);
+        }
+
+        let align = core::mem::size_of::<W>() as u32;
+        if src_addr % align != 0 || dst_addr % align != 0 {
+            return Err(DmaError::Misaligned);
+        }
+
+        // NDTR is 16 bits.
+        if len == 0 || len > u16::MAX as usize {
+            return Err(DmaError::InvalidLength);
+        }
+
+        Ok(size)
+    }
+
+    /// Start a memory-to-memory transfer. The source goes in the peripheral address register.
+    fn start_mem_to_mem(
+        &mut self,
+        channel: DmaChannel,
+        src_addr: u32,
+        dst_addr: u32,
+        len: usize,
+        size: DataSize,
+        src_incr: IncrMode,
+    ) {
+        #[cfg(feature = "h7")]
+        let num_data = len as u32;
+        #[cfg(not(feature = "h7"))]
+        let num_data = len as u16;
+
+        let cfg = ChannelCfg {
+            // Circular mode isn't allowed in memory-to-memory mode.
+            circular: Circular::Disabled,
+            periph_incr: src_incr,
+            mem_incr: IncrMode::Enabled,
+            // Neither is direct mode.
+            #[cfg(any(feature = "f4", feature = "h7"))]
+            fifo_threshold: Some(FifoThreshold::Full),
+            ..Default::default()
+        };
+
+        self.cfg_channel(
+            channel,
+            src_addr,
+            dst_addr,
+            num_data,
+            Direction::MemToMem,
+            size,
+            size,
+            cfg,
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// One half of a `PingPong` buffer. In H7 double-buffer mode, `First` is the half at `M0AR`, and
+/// `Second` is the half at `M1AR`.
+pub enum BufHalf {
+    First,
+    Second,
+}
+
+impl BufHalf {
+    fn other(self) -> Self {
+        match self {
+            Self::First => Self::Second,
+            Self::Second => Self::First,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Errors reported while streaming with `PingPong`. `R` is the result of the function passed to
+/// `process()`.
+pub enum StreamError<R = ()> {
+    /// The DMA wrote to a half of the buffer before its previous data was processed, so some data
+    /// was lost. Process halves faster, or use a larger buffer. Streaming continues. Contains the
+    /// result of the processing function, which ran on the possibly-overwritten half.
+    Overrun(R),
+    /// The DMA reported a transfer error, and has stopped the channel.
+    TransferError,
+}
+
+/// Continuous streaming through a circular buffer, one half at a time: While the DMA fills one
+/// half, the other can be processed. Eg for continuous ADC, SAI, or DFSDM capture. Create it from
+/// a `Transfer` started with `Circular::Enabled`, or with `double_buffer` set on H7.
+///
+/// Halves are ready when the half-transfer and transfer-complete flags are set (or, in H7
+/// double-buffer mode, the transfer-complete flag and `CT` bit). The flags are set whether or not
+/// the interrupts are enabled, so `process()` can be polled, or called from the DMA interrupt
+/// after enabling `DmaInterrupt::HalfTransfer` and `TransferComplete`.
+///
+/// This also works for output, eg to the DAC: Start the transfer with a `&'static mut` buffer, and
+/// refill each half in `process()` after it's been sent.
+///
+/// As with `Transfer`, dropping a `PingPong` stops its channel.
+pub struct PingPong<BUF> {
+    buf: BUF,
+    channel: DmaChannel,
+    periph: DmaPeriph,
+    /// The half we expect to be ready next.
+    next: BufHalf,
+    double_buffer: bool,
+}
+
+impl<BUF> PingPong<BUF>
+where
+    BUF: WriteBuffer,
+{
+    /// Start processing a circular transfer one half at a time. `dma` must be the controller the
+    /// transfer runs on.
+    pub fn new<D>(transfer: Transfer<BUF>, dma: &mut Dma<D>) -> Self
+    where
+        D: Deref<Target = dma1::RegisterBlock>,
+    {
+        check_periph(transfer.periph, dma);
+
+        #[cfg(any(feature = "f4", feature = "h7"))]
+        let double_buffer = dma.regs.st[transfer.channel as usize]
+            .cr
+            .read()
+            .dbm()
+            .bit_is_set();
+        #[cfg(not(any(feature = "f4", feature = "h7")))]
+        let double_buffer = {
+            let _ = dma;
+            false
+        };
+
+        let channel = transfer.channel;
+        let periph = transfer.periph;
+
+        Self {
+            buf: transfer.into_buf(),
+            channel,
+            periph,
+            next: BufHalf::First,
+            double_buffer,
+        }
+    }
+
+    /// The flag that is set when a half is complete.
+    fn flag(&self, half: BufHalf) -> DmaInterrupt {
+        match half {
+            BufHalf::First if !self.double_buffer => DmaInterrupt::HalfTransfer,
+            _ => DmaInterrupt::TransferComplete,
+        }
+    }
+
+    /// Check which half is ready to process, if any, without clearing its flag.
+    pub fn ready<D>(&self, dma: &mut Dma<D>) -> Option<BufHalf>
+    where
+        D: Deref<Target = dma1::RegisterBlock>,
+    {
+        check_periph(self.periph, dma);
+
+        if self.double_buffer {
+            if !dma.flag_is_set(self.channel, DmaInterrupt::TransferComplete) {
+                return None;
+            }
+
+            // CT is the half the DMA is filling now; the other one is ready.
+            #[cfg(any(feature = "f4", feature = "h7"))]
+            let filling_second = dma.regs.st[self.channel as usize]
+                .cr
+                .read()
+                .ct()
+                .bit_is_set();
+            #[cfg(not(any(feature = "f4", feature = "h7")))]
+            let filling_second = false;
+
+            return Some(if filling_second {
+                BufHalf::First
+            } else {
+                BufHalf::Second
+            });
+        }
+
+        let next_ready = dma.flag_is_set(self.channel, self.flag(self.next));
+        let other_ready = dma.flag_is_set(self.channel, self.flag(self.next.other()));
+
+        if next_ready {
+            Some(self.next)
+        } else if other_ready {
+            Some(self.next.other())
+        } else {
+            None
+        }
+    }
+
+    /// If a half of the buffer is ready, clear its flag, and pass it to `f`, eg to filter or copy
+    /// the data. Returns the result of `f`, or `None` if no half is ready. Returns
+    /// `StreamError::Overrun`, with the result of `f`, if a half was skipped, or if the DMA started
+    /// refilling the half while `f` was running; the data passed to `f` may not be valid in that
+    /// case.
+    pub fn process<D, F, R>(&mut self, dma: &mut Dma<D>, f: F) -> Result<Option<R>, StreamError<R>>
+    where
+        D: Deref<Target = dma1::RegisterBlock>,
+        F: FnOnce(BufHalf, &mut [BUF::Word]) -> R,
+    {
+        check_periph(self.periph, dma);
+
+        if dma.flag_is_set(self.channel, DmaInterrupt::TransferError) {
+            return Err(StreamError::TransferError);
+        }
+
+        let half = match self.ready(dma) {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+
+        // If both halves are ready, the DMA has already moved past the one we're about to process.
+        let mut overrun = half != self.next
+            || (!self.double_buffer && dma.flag_is_set(self.channel, self.flag(half.other())));
+
+        dma.clear_interrupt(self.channel, self.flag(half));
+        self.next = half.other();
+
+        // Don't let reads of the buffer be moved before the flag check.
+        atomic::compiler_fence(Ordering::Acquire);
+
+        let (ptr, len) = unsafe { self.buf.write_buffer() };
+        let half_len = len / 2;
+        let offset = match half {
+            BufHalf::First => 0,
+            BufHalf::Second => half_len,
+        };
+
+        let data = unsafe { core::slice::from_raw_parts_mut(ptr.add(offset), half_len) };
+        let result = f(half, data);
+
+        atomic::compiler_fence(Ordering::Release);
+
+        // If the other half completed while `f` was running, the DMA is now writing to this one.
+        if dma.flag_is_set(self.channel, self.flag(half.other())) {
+            overrun = true;
+        }
+
+        if overrun {
+            Err(StreamError::Overrun(result))
+        } else {
+            Ok(Some(result))
+        }
+    }
+
+    /// Stop streaming, and return the buffer.
+    pub fn stop<D>(self, dma: &mut Dma<D>) -> BUF
+    where
+        D: Deref<Target = dma1::RegisterBlock>,
+    {
+        check_periph(self.periph, dma);
+
+        dma.stop(self.channel);
+        atomic::compiler_fence(Ordering::Acquire);
+
+        let stream = ManuallyDrop::new(self);
+        unsafe { ptr::read(&stream.buf) }
+    }
+}
+
+impl<BUF> Drop for PingPong<BUF> {
+    fn drop(&mut self) {
+        stop_channel(self.periph, self.channel);
+    }
+}
+
 #[cfg(any(
     feature = "l5",
     feature = "g0",
@@ -1642,36 +2509,182 @@ pub fn mux(periph: DmaPeriph, channel: DmaChannel, input: DmaInput) {
             }
             #[cfg(not(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1")))))]
             DmaPeriph::Dma2 => {
-                #[cfg(not(feature = "h7"))]
+                // DMA2's DMAMUX channels follow directly after DMA1's. DMA1 has 7 channels on
+                // WB, WL, and G0, 6 on G431 and G441, and 8 on L5 and other G4 variants.
+                #[cfg(any(feature = "wb", feature = "wl", feature = "g0"))]
+                match channel {
+                    DmaChannel::C1 => mux.c7cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                    DmaChannel::C2 => mux.c8cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                    DmaChannel::C3 => mux.c9cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                    DmaChannel::C4 => mux.c10cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                    DmaChannel::C5 => mux.c11cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                    #[cfg(not(feature = "g0"))]
+                    DmaChannel::C6 => mux.c12cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                    #[cfg(not(feature = "g0"))]
+                    DmaChannel::C7 => mux.c13cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                }
+
+                #[cfg(any(feature = "g431", feature = "g441"))]
+                match channel {
+                    DmaChannel::C1 => mux.c6cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                    DmaChannel::C2 => mux.c7cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                    DmaChannel::C3 => mux.c8cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                    DmaChannel::C4 => mux.c9cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                    DmaChannel::C5 => mux.c10cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                    DmaChannel::C6 => mux.c11cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
+                    DmaChannel::C7 | DmaChannel::C8 => {
+                        panic!("DMA2 only has channels 1 - 6 on this MCU.")
+                    }
+                }
+
+                #[cfg(any(
+                    feature = "l5",
+                    all(feature = "g4", not(any(feature = "g431", feature = "g441")))
+                ))]
                 match channel {
                     DmaChannel::C1 => mux.c8cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
                     DmaChannel::C2 => mux.c9cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
                     DmaChannel::C3 => mux.c10cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
                     DmaChannel::C4 => mux.c11cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
                     DmaChannel::C5 => mux.c12cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
-                    #[cfg(not(feature = "g0"))]
                     DmaChannel::C6 => mux.c13cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
-                    #[cfg(not(any(feature = "g0", feature = "wb", feature = "wl")))]
                     DmaChannel::C7 => mux.c14cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
-                    #[cfg(any(feature = "wb", feature = "wl"))]
-                    DmaChannel::C7 => (), // Maybe no channel 7 on DMA2 on these platforms.
-                    #[cfg(any(feature = "l5", feature = "g4"))]
                     DmaChannel::C8 => mux.c15cr.modify(|_, w| w.dmareq_id().bits(input as u8)),
                 }
         
                 #[cfg(feature = "h7")]
                 mux.ccr[channel as usize + 8].modify(|_, w| w.dmareq_id().bits(input as u8));
             }
+            #[cfg(feature = "h7")]
+            DmaPeriph::Bdma => panic!("BDMA requests are routed by DMAMUX2; use `mux2()`."),
         }      
     }
 }
 
 #[cfg(feature = "h7")]
-/// Configure a specific DMA channel to work with a specific peripheral, on DMAMUX2.
-pub fn mux2(periph: DmaPeriph, channel: DmaChannel, input: DmaInput2, mux: &mut DMAMUX2) {
+/// Configure a specific DMA channel to work with a specific peripheral, on DMAMUX2. DMAMUX2 only
+/// routes requests to BDMA channels.
+pub fn mux2(channel: DmaChannel, input: DmaInput2, mux: &mut DMAMUX2) {
     mux.ccr[channel as usize].modify(|_, w| unsafe { w.dmareq_id().bits(input as u8) });
 }
 
+#[cfg(feature = "h7")]
+/// Represents the H7's Basic DMA (BDMA) controller. It serves the D3-domain peripherals (LPUART1,
+/// SPI6, I2C4, SAI4, and ADC3), which DMA1 and DMA2 can't reach. Route requests to its channels
+/// with `mux2()`. BDMA can only access D3-domain memory, so buffers must be in SRAM4; eg place
+/// them in a `.sram4` link section. `Spi::write_bdma` (SPI6) and `Sai::write_bdma` (SAI4), and
+/// their `read_bdma` counterparts, return a `Transfer` that takes this in place of `Dma`.
+pub struct Bdma {
+    pub regs: BDMA,
+}
+
+#[cfg(feature = "h7")]
+impl Bdma {
+    /// Initialize the BDMA peripheral, including enabling and resetting its RCC peripheral clock.
+    pub fn new(regs: BDMA) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.ahb4enr.modify(|_, w| w.bdmaen().set_bit());
+            rcc.ahb4rstr.modify(|_, w| w.bdmarst().set_bit());
+            rcc.ahb4rstr.modify(|_, w| w.bdmarst().clear_bit());
+        });
+
+        Self { regs }
+    }
+
+    /// Configure a BDMA channel. Channels are `C0` through `C7`. This works like
+    /// `Dma::cfg_channel`, but BDMA has no FIFO, and the number of data is limited to 16 bits.
+    /// Sets the Transfer Complete interrupt. See H743 RM, section 17.4.
+    pub fn cfg_channel(
+        &mut self,
+        channel: DmaChannel,
+        periph_addr: u32,
+        mem_addr: u32,
+        num_data: u16,
+        direction: Direction,
+        periph_size: DataSize,
+        mem_size: DataSize,
+        cfg: ChannelCfg,
+    ) {
+        // Clear a stale flag from a previous transfer, so `is_complete` doesn't return early.
+        self.clear_interrupt(channel, DmaInterrupt::TransferComplete);
+
+        let ch = &self.regs.ch[channel as usize];
+
+        // The CR register is read-only, other than EN, while the channel is enabled.
+        ch.cr.modify(|_, w| w.en().clear_bit());
+        while ch.cr.read().en().bit_is_set() {}
+
+        ch.par.write(|w| unsafe { w.bits(periph_addr) });
+        atomic::compiler_fence(Ordering::SeqCst);
+        ch.m0ar.write(|w| unsafe { w.bits(mem_addr) });
+        ch.ndtr.write(|w| unsafe { w.bits(num_data as u32) });
+
+        // Field positions are the same as on DMA1 and DMA2 on non-H7 MCUs.
+        let mut cr = 1 << 1 // TCIE
+            | ((direction as u32 != 0) as u32) << 4
+            | (cfg.circular as u32) << 5
+            | (cfg.periph_incr as u32) << 6
+            | (cfg.mem_incr as u32) << 7
+            | (periph_size as u32) << 8
+            | (mem_size as u32) << 10
+            | (cfg.priority as u32) << 12;
+
+        if let Direction::MemToMem = direction {
+            cr |= 1 << 14; // MEM2MEM
+        }
+
+        ch.cr.write(|w| unsafe { w.bits(cr) });
+
+        atomic::compiler_fence(Ordering::Release);
+        ch.cr.modify(|_, w| w.en().set_bit());
+    }
+
+    /// Stop a BDMA channel.
+    pub fn stop(&mut self, channel: DmaChannel) {
+        stop_bdma_channel(&self.regs, channel);
+    }
+
+    pub fn transfer_is_complete(&mut self, channel: DmaChannel) -> bool {
+        self.regs.isr.read().bits() & (1 << (channel as u8 * 4 + 1)) != 0
+    }
+
+    /// Clear an interrupt flag. `DirectModeError` and `FifoError` don't apply to BDMA, and are
+    /// ignored.
+    pub fn clear_interrupt(&mut self, channel: DmaChannel, interrupt: DmaInterrupt) {
+        let offset = match interrupt {
+            DmaInterrupt::TransferComplete => 1,
+            DmaInterrupt::HalfTransfer => 2,
+            DmaInterrupt::TransferError => 3,
+            _ => return,
+        };
+        self.regs
+            .ifcr
+            .write(|w| unsafe { w.bits(1 << (channel as u8 * 4 + offset)) });
+    }
+}
+
+#[cfg(feature = "h7")]
+/// Convert a buffer length to a BDMA transfer count, which is limited to 16 bits. Used by
+/// peripheral BDMA methods.
+pub(crate) fn bdma_num_data(len: usize) -> u16 {
+    assert!(
+        len <= u16::MAX as usize,
+        "BDMA transfers are limited to 65,535 items."
+    );
+    len as u16
+}
+
+#[cfg(feature = "h7")]
+/// Stop a BDMA channel. Shared by `Bdma::stop()`, and dropping a transfer.
+fn stop_bdma_channel(regs: &bdma::RegisterBlock, channel: DmaChannel) {
+    let cr = &regs.ch[channel as usize].cr;
+    cr.modify(|_, w| w.en().clear_bit());
+    while cr.read().en().bit_is_set() {}
+
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
 // todo: Enable this for other MCUs as requried
 /// Enable the DMA mux RCC clock. Applicable to some variants, but no others. (H7 and G0 don't use it,
 /// for example)
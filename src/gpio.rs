@@ -2,10 +2,6 @@
 //! including all GPIOx register functions. It also configures GPIO interrupts using SYSCFG and EXTI
 //! registers as appropriate.
 
-// todo: WL is missing port C here due to some pins being missing, and this being tough
-// todo to change with our current model. Note sure if PAC, or MCU limitation
-// todo: WL is also missing interrupt support.
-
 #[cfg(feature = "embedded-hal")]
 use core::convert::Infallible;
 
@@ -39,9 +35,29 @@ cfg_if! {
 #[cfg(not(any(feature = "f4", feature = "l552")))]
 use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
 
+#[cfg(not(any(feature = "f4", feature = "l5", feature = "f3", feature = "l4")))]
+use crate::dma::{ReadBuffer, Transfer, WriteBuffer};
+
 use cfg_if::cfg_if;
 use paste::paste;
 
+pub mod af;
+pub mod bus;
+pub mod capture;
+pub mod exti;
+pub mod input;
+pub mod lock;
+pub mod registry;
+pub mod snapshot;
+pub mod typestate;
+
+pub use af::{af_for, AfError, Signal};
+pub use bus::PortBus;
+pub use exti::{Exti, ExtiHandler};
+pub use lock::{lock_pins, locked_pins, LockError};
+pub use registry::{claim, claim_pins, is_claimed, release, release_pins, ClaimError};
+pub use snapshot::PortSnapshot;
+
 #[derive(Copy, Clone)]
 #[repr(u8)]
 /// Values for `GPIOx_MODER`. Sets pin to input, output, and other functionality.
@@ -127,12 +143,11 @@ pub enum ResetState {
 }
 
 // todo: If you get rid of Port struct, rename this enum Port
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 /// GPIO port letter
 pub enum Port {
     A,
     B,
-    #[cfg(not(feature = "wl"))]
     C,
     #[cfg(not(any(feature = "f410", feature = "wl")))]
     D,
@@ -179,8 +194,7 @@ pub enum Port {
         feature = "l4",
         feature = "g0",
         feature = "g4",
-        feature = "wb",
-        feature = "wl"
+        feature = "wb"
     )))]
     H,
 }
@@ -191,7 +205,6 @@ impl Port {
         match self {
             Self::A => 0,
             Self::B => 1,
-            #[cfg(not(feature = "wl"))]
             Self::C => 2,
             #[cfg(not(any(feature = "f410", feature = "wl")))]
             Self::D => 3,
@@ -238,8 +251,7 @@ impl Port {
                 feature = "l4",
                 feature = "g0",
                 feature = "g4",
-                feature = "wb",
-                feature = "wl"
+                feature = "wb"
             )))]
             Self::H => 7,
         }
@@ -336,7 +348,7 @@ macro_rules! set_exti {
             match $pin {
                 $(
                     $num => {
-                    // todo: Core 2 interrupts for wb. (?)
+                    // For core 2 interrupts on wb, see `Exti::enable_core2`.
                         cfg_if! {
                             if #[cfg(all(feature = "h7", not(any(feature = "h747cm4", feature = "h747cm7"))))] {
                                 exti.cpuimr1.modify(|_, w| w.[<mr $num>]().set_bit());
@@ -534,7 +546,6 @@ impl Pin {
                         }
                     }
                 }
-                #[cfg(not(feature = "wl"))]
                 Port::C => {
                     cfg_if! {
                         if #[cfg(feature = "f3")] {
@@ -732,8 +743,7 @@ impl Pin {
                     feature = "l4",
                     feature = "g0",
                     feature = "g4",
-                    feature = "wb",
-                    feature = "wl"
+                    feature = "wb"
                 )))]
                 Port::H => {
                     cfg_if! {
@@ -757,9 +767,9 @@ impl Pin {
                                 rcc.ioprstr.modify(|_, w| w.iophrst().set_bit());
                                 rcc.ioprstr.modify(|_, w| w.iophrst().clear_bit());
                             }
-                        } else { // L4, L5, G4
+                        } else { // L4, L5, G4, WL
                             if rcc.ahb2enr.read().gpiohen().bit_is_clear() {
-                                rcc_en_reset!(ahb2, gpioa, rcc);
+                                rcc_en_reset!(ahb2, gpioh, rcc);
                             }
                         }
                     }
@@ -790,6 +800,16 @@ impl Pin {
         }
     }
 
+    /// Set the pin to alternate function mode, using the AF number that connects it to `signal`.
+    /// Returns an error if the pin can't carry the signal. See the `af` module for which signals
+    /// are covered.
+    pub fn set_alt_for(&mut self, signal: Signal) -> Result<(), AfError> {
+        let af = af_for(self.port, self.pin, signal).ok_or(AfError { signal })?;
+        self.mode(PinMode::Alt(af));
+
+        Ok(())
+    }
+
     /// Set output type. Sets the `OTYPER` register.
     pub fn output_type(&mut self, value: OutputType) {
         set_field!(
@@ -829,19 +849,38 @@ impl Pin {
         );
     }
 
-    // TODO: F373 doesn't have LOCKR on ports C, E, F. You can impl for others
-    #[cfg(not(feature = "f373"))]
-    /// Lock or unlock a port configuration. Sets the `LCKR` register.
+    /// Lock or unlock a port configuration. Sets the `LCKR` register. This only sets the pin's
+    /// `LCK` bit; the lock takes effect after the key write sequence. See `lock()` and
+    /// `gpio::lock_pins()`, which run the sequence. F373 ports C, E, and F don't have an `LCKR`
+    /// register; this panics for their pins.
     pub fn cfg_lock(&mut self, value: CfgLock) {
-        set_field!(
-            self.regs(),
-            self.pin,
-            lckr,
-            lck,
-            bit,
-            value as u8 != 0,
-            [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+        assert!(
+            lock::lock_supported(self.port),
+            "This GPIO port doesn't have a lock register."
         );
+
+        cfg_if! {
+            if #[cfg(feature = "f373")] {
+                // Only some F373 ports have `LCKR`, so set the pin's bit directly.
+                let regs = unsafe { &(*self.regs()) };
+                regs.lckr.modify(|r, w| unsafe {
+                    match value {
+                        CfgLock::Locked => w.bits(r.bits() | (1 << self.pin)),
+                        CfgLock::NotLocked => w.bits(r.bits() & !(1 << self.pin)),
+                    }
+                });
+            } else {
+                set_field!(
+                    self.regs(),
+                    self.pin,
+                    lckr,
+                    lck,
+                    bit,
+                    value as u8 != 0,
+                    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+                );
+            }
+        }
     }
 
     /// Read the input data register. Eg determine if the pin is high or low. See also `is_high()`
@@ -896,8 +935,10 @@ impl Pin {
         }
     }
 
-    #[cfg(not(any(feature = "f373", feature = "wl")))]
+    #[cfg(not(feature = "f373"))]
     /// Configure this pin as an interrupt source. Set the edge as Rising or Falling.
+    /// On WB, this unmasks the interrupt for core 1 (Cortex-M4) only; see `Exti::enable_core2`
+    /// to receive it on core 2 as well.
     pub fn enable_interrupt(&mut self, edge: Edge) {
         let rising = match edge {
             Edge::Falling => false,
@@ -1216,7 +1257,6 @@ const fn regs(port: Port) -> *const pac::gpioa::RegisterBlock {
     match port {
         Port::A => crate::pac::GPIOA::ptr(),
         Port::B => crate::pac::GPIOB::ptr() as _,
-        #[cfg(not(feature = "wl"))]
         Port::C => crate::pac::GPIOC::ptr() as _,
         #[cfg(not(any(feature = "f410", feature = "wl")))]
         Port::D => crate::pac::GPIOD::ptr() as _,
@@ -1263,8 +1303,7 @@ const fn regs(port: Port) -> *const pac::gpioa::RegisterBlock {
             feature = "l4",
             feature = "g0",
             feature = "g4",
-            feature = "wb",
-            feature = "wl"
+            feature = "wb"
         )))]
         Port::H => crate::pac::GPIOH::ptr() as _,
     }
@@ -1272,21 +1311,22 @@ const fn regs(port: Port) -> *const pac::gpioa::RegisterBlock {
 
 #[cfg(not(any(feature = "f4", feature = "l5", feature = "f3", feature = "l4")))]
 /// Write a series of words to the BSRR (atomic output) register. Note that these are direct writes
-/// to the full, 2-sided register - not a series of low/high values.
-pub unsafe fn write_dma<D>(
-    buf: &[u32],
+/// to the full, 2-sided register - not a series of low/high values. Returns a `Transfer` that holds
+/// `buf` until the transfer is complete.
+pub fn write_dma<B, D>(
+    buf: B,
     port: Port,
     dma_channel: DmaChannel,
     channel_cfg: ChannelCfg,
     dma: &mut Dma<D>,
-) where
+) -> Transfer<B>
+where
+    B: ReadBuffer<Word = u32>,
     D: Deref<Target = dma_p::RegisterBlock>,
 {
-    let (ptr, len) = (buf.as_ptr(), buf.len());
+    let (ptr, len) = unsafe { buf.read_buffer() };
 
-    // todo: DMA2 support.
-
-    let periph_addr = &(*(regs(port))).bsrr as *const _ as u32;
+    let periph_addr = unsafe { &(*(regs(port))).bsrr as *const _ as u32 };
 
     #[cfg(feature = "h7")]
     let num_data = len as u32;
@@ -1303,4 +1343,47 @@ pub unsafe fn write_dma<D>(
         dma::DataSize::S32,
         channel_cfg,
     );
+
+    Transfer::new(buf, dma_channel, dma.periph)
+}
+
+#[cfg(not(any(feature = "f4", feature = "l5", feature = "f3", feature = "l4")))]
+/// Read a series of samples of a port's input levels (the `IDR` register) into a buffer. Each
+/// half-word holds all 16 pins of one sample; bit 0 is pin 0, etc. Samples are taken on each request
+/// of the DMA input the channel is routed to; eg a timer update. See `Timer::capture_port_dma` to
+/// sample at a fixed rate, and `gpio::capture::Capture` to decode the result. Returns a `Transfer`
+/// that holds `buf` until the capture is complete.
+pub fn read_dma<B, D>(
+    mut buf: B,
+    port: Port,
+    dma_channel: DmaChannel,
+    channel_cfg: ChannelCfg,
+    dma: &mut Dma<D>,
+) -> Transfer<B>
+where
+    B: WriteBuffer<Word = u16>,
+    D: Deref<Target = dma_p::RegisterBlock>,
+{
+    let (ptr, len) = unsafe { buf.write_buffer() };
+
+    let periph_addr = unsafe { &(*(regs(port))).idr as *const _ as u32 };
+
+    #[cfg(feature = "h7")]
+    let num_data = len as u32;
+    #[cfg(not(feature = "h7"))]
+    let num_data = len as u16;
+
+    dma.cfg_channel(
+        dma_channel,
+        periph_addr,
+        ptr as u32,
+        num_data,
+        dma::Direction::ReadFromPeriph,
+        // GPIO registers support half-word access; IDR's upper half is reserved.
+        dma::DataSize::S16,
+        dma::DataSize::S16,
+        channel_cfg,
+    );
+
+    Transfer::new(buf, dma_channel, dma.periph)
 }
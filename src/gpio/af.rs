@@ -0,0 +1,341 @@
+//! Alternate function tables: Which AF number connects a peripheral signal to a pin. These cover
+//! common signals on each supported family. Some variants within a family don't have all of these
+//! peripherals or pins, so check your MCU's datasheet (Alternate Function table) for edge
+//! cases, and for signals not listed here. You can always set AFs directly with `PinMode::Alt`.
+
+use super::Port;
+
+use cfg_if::cfg_if;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A peripheral signal that can be routed to a pin using an alternate function.
+pub enum Signal {
+    Usart1Tx,
+    Usart1Rx,
+    Usart2Tx,
+    Usart2Rx,
+    Usart3Tx,
+    Usart3Rx,
+    Lpuart1Tx,
+    Lpuart1Rx,
+    Spi1Sck,
+    Spi1Miso,
+    Spi1Mosi,
+    Spi2Sck,
+    Spi2Miso,
+    Spi2Mosi,
+    I2c1Scl,
+    I2c1Sda,
+    I2c2Scl,
+    I2c2Sda,
+    Tim1Ch1,
+    Tim1Ch2,
+    Tim1Ch3,
+    Tim1Ch4,
+    Tim2Ch1,
+    Tim2Ch2,
+    Tim2Ch3,
+    Tim2Ch4,
+    Tim3Ch1,
+    Tim3Ch2,
+    Tim3Ch3,
+    Tim3Ch4,
+    /// Microcontroller clock output. (MCO1 on F4 and H7)
+    Mco,
+    /// Microcontroller clock output 2. (F4 and H7 only)
+    Mco2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The pin can't carry this signal, or it isn't in our tables. Returned by `Pin::set_alt_for()`.
+pub struct AfError {
+    pub signal: Signal,
+}
+
+/// Find the alternate function number that connects `signal` to a pin. Returns `None` if the pin
+/// can't carry the signal, or if the mapping isn't in our tables. Eg on L4,
+/// `af_for(Port::A, 9, Signal::Usart1Tx)` returns `Some(7)`. This is a `const fn`, so the type-state
+/// pins in `gpio::typestate` take their AF numbers from it.
+pub const fn af_for(port: Port, pin: u8, signal: Signal) -> Option<u8> {
+    use Signal::*;
+
+    cfg_if! {
+        if #[cfg(feature = "g0")] {
+            let af = match (port, pin, signal) {
+                (Port::A, 9, Usart1Tx) | (Port::A, 10, Usart1Rx) => 1,
+                (Port::B, 6, Usart1Tx) | (Port::B, 7, Usart1Rx) => 0,
+                (Port::A, 2, Usart2Tx) | (Port::A, 14, Usart2Tx) => 1,
+                (Port::A, 3, Usart2Rx) | (Port::A, 15, Usart2Rx) => 1,
+                #[cfg(any(
+                    feature = "g070",
+                    feature = "g071",
+                    feature = "g081",
+                    feature = "g0b0",
+                    feature = "g0b1",
+                    feature = "g0c1"
+                ))]
+                (Port::B, 10, Usart3Tx) | (Port::B, 11, Usart3Rx) => 4,
+                #[cfg(any(
+                    feature = "g070",
+                    feature = "g071",
+                    feature = "g081",
+                    feature = "g0b0",
+                    feature = "g0b1",
+                    feature = "g0c1"
+                ))]
+                (Port::D, 8, Usart3Tx) | (Port::D, 9, Usart3Rx) => 0,
+                // Value line G0 variants don't have LPUART.
+                #[cfg(not(any(
+                    feature = "g030",
+                    feature = "g050",
+                    feature = "g070",
+                    feature = "g0b0"
+                )))]
+                (Port::A, 2, Lpuart1Tx) | (Port::A, 3, Lpuart1Rx) => 6,
+                #[cfg(not(any(
+                    feature = "g030",
+                    feature = "g050",
+                    feature = "g070",
+                    feature = "g0b0"
+                )))]
+                (Port::B, 11, Lpuart1Tx)
+                | (Port::C, 1, Lpuart1Tx)
+                | (Port::B, 10, Lpuart1Rx)
+                | (Port::C, 0, Lpuart1Rx) => 1,
+                (Port::A, 1, Spi1Sck) | (Port::A, 5, Spi1Sck) | (Port::B, 3, Spi1Sck) => 0,
+                (Port::A, 6, Spi1Miso) | (Port::A, 11, Spi1Miso) | (Port::B, 4, Spi1Miso) => 0,
+                (Port::A, 7, Spi1Mosi) | (Port::A, 12, Spi1Mosi) | (Port::B, 5, Spi1Mosi) => 0,
+                (Port::B, 13, Spi2Sck) | (Port::B, 14, Spi2Miso) | (Port::B, 15, Spi2Mosi) => 0,
+                (Port::A, 9, I2c1Scl) | (Port::B, 6, I2c1Scl) | (Port::B, 8, I2c1Scl) => 6,
+                (Port::A, 10, I2c1Sda) | (Port::B, 7, I2c1Sda) | (Port::B, 9, I2c1Sda) => 6,
+                (Port::A, 11, I2c2Scl) | (Port::B, 10, I2c2Scl) | (Port::B, 13, I2c2Scl) => 6,
+                (Port::A, 12, I2c2Sda) | (Port::B, 11, I2c2Sda) | (Port::B, 14, I2c2Sda) => 6,
+                (Port::A, 8, Tim1Ch1)
+                | (Port::A, 9, Tim1Ch2)
+                | (Port::A, 10, Tim1Ch3)
+                | (Port::A, 11, Tim1Ch4) => 2,
+                #[cfg(not(any(
+                    feature = "g030",
+                    feature = "g050",
+                    feature = "g070",
+                    feature = "g0b0"
+                )))]
+                (Port::A, 0, Tim2Ch1)
+                | (Port::A, 5, Tim2Ch1)
+                | (Port::A, 15, Tim2Ch1)
+                | (Port::A, 1, Tim2Ch2)
+                | (Port::B, 3, Tim2Ch2)
+                | (Port::A, 2, Tim2Ch3)
+                | (Port::B, 10, Tim2Ch3)
+                | (Port::A, 3, Tim2Ch4)
+                | (Port::B, 11, Tim2Ch4) => 2,
+                (Port::A, 6, Tim3Ch1)
+                | (Port::B, 4, Tim3Ch1)
+                | (Port::C, 6, Tim3Ch1)
+                | (Port::A, 7, Tim3Ch2)
+                | (Port::B, 5, Tim3Ch2)
+                | (Port::C, 7, Tim3Ch2)
+                | (Port::B, 0, Tim3Ch3)
+                | (Port::C, 8, Tim3Ch3)
+                | (Port::B, 1, Tim3Ch4)
+                | (Port::C, 9, Tim3Ch4) => 1,
+                (Port::A, 8, Mco) => 0,
+                _ => return None,
+            };
+        } else {
+            // F3, F4, L4, L5, G4, WB, WL and H7 share most of their mappings for these signals.
+            let af = match (port, pin, signal) {
+                (Port::A, 9, Usart1Tx) | (Port::B, 6, Usart1Tx) => 7,
+                (Port::A, 10, Usart1Rx) | (Port::B, 7, Usart1Rx) => 7,
+                #[cfg(not(feature = "wb"))]
+                (Port::A, 2, Usart2Tx) | (Port::A, 3, Usart2Rx) => 7,
+                #[cfg(not(any(feature = "f410", feature = "wb", feature = "wl")))]
+                (Port::D, 5, Usart2Tx) | (Port::D, 6, Usart2Rx) => 7,
+                #[cfg(any(
+                    feature = "f3",
+                    all(feature = "f4", not(any(feature = "f401", feature = "f410", feature = "f411"))),
+                    all(feature = "l4", not(feature = "l4x2")),
+                    feature = "l5",
+                    feature = "g4",
+                    feature = "h7"
+                ))]
+                (Port::B, 10, Usart3Tx)
+                | (Port::C, 10, Usart3Tx)
+                | (Port::D, 8, Usart3Tx)
+                | (Port::B, 11, Usart3Rx)
+                | (Port::C, 11, Usart3Rx)
+                | (Port::D, 9, Usart3Rx) => 7,
+                #[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
+                (Port::A, 2, Lpuart1Tx) | (Port::A, 3, Lpuart1Rx) => 8,
+                #[cfg(feature = "g4")]
+                (Port::A, 2, Lpuart1Tx) | (Port::A, 3, Lpuart1Rx) => 12,
+                #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+                (Port::B, 11, Lpuart1Tx)
+                | (Port::C, 1, Lpuart1Tx)
+                | (Port::B, 10, Lpuart1Rx)
+                | (Port::C, 0, Lpuart1Rx) => 8,
+                #[cfg(feature = "h7")]
+                (Port::A, 9, Lpuart1Tx) | (Port::A, 10, Lpuart1Rx) => 3,
+                #[cfg(feature = "h7")]
+                (Port::B, 6, Lpuart1Tx) | (Port::B, 7, Lpuart1Rx) => 8,
+                (Port::A, 5, Spi1Sck) | (Port::B, 3, Spi1Sck) => 5,
+                (Port::A, 6, Spi1Miso) | (Port::B, 4, Spi1Miso) => 5,
+                (Port::A, 7, Spi1Mosi) | (Port::B, 5, Spi1Mosi) => 5,
+                #[cfg(not(any(
+                    feature = "f373",
+                    feature = "f3x4",
+                    feature = "l4x2",
+                    feature = "wl"
+                )))]
+                (Port::B, 13, Spi2Sck) | (Port::B, 14, Spi2Miso) | (Port::B, 15, Spi2Mosi) => 5,
+                #[cfg(not(feature = "g4"))]
+                (Port::B, 6, I2c1Scl) => 4,
+                (Port::B, 8, I2c1Scl) | (Port::B, 7, I2c1Sda) => 4,
+                #[cfg(not(feature = "wl"))]
+                (Port::B, 9, I2c1Sda) => 4,
+                #[cfg(any(
+                    all(feature = "f4", not(any(feature = "f401", feature = "f410", feature = "f411"))),
+                    all(feature = "l4", not(feature = "l4x2")),
+                    feature = "l5",
+                    feature = "h7"
+                ))]
+                (Port::B, 10, I2c2Scl) | (Port::B, 11, I2c2Sda) => 4,
+                #[cfg(any(all(feature = "f3", not(feature = "f373")), feature = "g4"))]
+                (Port::A, 8, Tim1Ch1) | (Port::A, 9, Tim1Ch2) | (Port::A, 10, Tim1Ch3) => 6,
+                #[cfg(any(all(feature = "f3", not(feature = "f373")), feature = "g4"))]
+                (Port::A, 11, Tim1Ch4) => 11,
+                #[cfg(not(any(feature = "f3", feature = "g4")))]
+                (Port::A, 8, Tim1Ch1)
+                | (Port::A, 9, Tim1Ch2)
+                | (Port::A, 10, Tim1Ch3)
+                | (Port::A, 11, Tim1Ch4) => 1,
+                #[cfg(not(feature = "f410"))]
+                (Port::A, 0, Tim2Ch1)
+                | (Port::A, 5, Tim2Ch1)
+                | (Port::A, 15, Tim2Ch1)
+                | (Port::A, 1, Tim2Ch2)
+                | (Port::B, 3, Tim2Ch2)
+                | (Port::A, 2, Tim2Ch3)
+                | (Port::A, 3, Tim2Ch4) => 1,
+                #[cfg(not(any(feature = "f410", feature = "wb", feature = "wl")))]
+                (Port::B, 10, Tim2Ch3) | (Port::B, 11, Tim2Ch4) => 1,
+                #[cfg(any(
+                    all(feature = "f3", not(feature = "f301")),
+                    all(feature = "f4", not(feature = "f410")),
+                    feature = "l4x5",
+                    feature = "l4x6",
+                    feature = "l5",
+                    feature = "g4",
+                    feature = "h7"
+                ))]
+                (Port::A, 6, Tim3Ch1)
+                | (Port::B, 4, Tim3Ch1)
+                | (Port::C, 6, Tim3Ch1)
+                | (Port::A, 7, Tim3Ch2)
+                | (Port::B, 5, Tim3Ch2)
+                | (Port::C, 7, Tim3Ch2)
+                | (Port::B, 0, Tim3Ch3)
+                | (Port::C, 8, Tim3Ch3)
+                | (Port::B, 1, Tim3Ch4)
+                | (Port::C, 9, Tim3Ch4) => 2,
+                (Port::A, 8, Mco) => 0,
+                #[cfg(any(feature = "f4", feature = "h7"))]
+                (Port::C, 9, Mco2) => 0,
+                _ => return None,
+            };
+        }
+    }
+
+    Some(af)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unmapped() {
+        assert_eq!(af_for(Port::A, 9, Signal::Spi1Sck), None);
+        assert_eq!(af_for(Port::C, 13, Signal::Usart1Tx), None);
+    }
+
+    #[test]
+    #[cfg(feature = "g0")]
+    fn g0() {
+        assert_eq!(af_for(Port::A, 9, Signal::Usart1Tx), Some(1));
+        assert_eq!(af_for(Port::B, 6, Signal::Usart1Tx), Some(0));
+        assert_eq!(af_for(Port::A, 5, Signal::Spi1Sck), Some(0));
+        assert_eq!(af_for(Port::B, 8, Signal::I2c1Scl), Some(6));
+        assert_eq!(af_for(Port::A, 6, Signal::Tim3Ch1), Some(1));
+        assert_eq!(af_for(Port::A, 8, Signal::Mco), Some(0));
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "g0",
+        not(any(feature = "g030", feature = "g050", feature = "g070", feature = "g0b0"))
+    ))]
+    fn g0_lpuart() {
+        assert_eq!(af_for(Port::A, 2, Signal::Lpuart1Tx), Some(6));
+        assert_eq!(af_for(Port::A, 3, Signal::Lpuart1Rx), Some(6));
+        assert_eq!(af_for(Port::B, 11, Signal::Lpuart1Tx), Some(1));
+        assert_eq!(af_for(Port::C, 0, Signal::Lpuart1Rx), Some(1));
+    }
+
+    #[test]
+    #[cfg(not(feature = "g0"))]
+    fn shared() {
+        assert_eq!(af_for(Port::A, 9, Signal::Usart1Tx), Some(7));
+        assert_eq!(af_for(Port::B, 7, Signal::Usart1Rx), Some(7));
+        assert_eq!(af_for(Port::A, 5, Signal::Spi1Sck), Some(5));
+        assert_eq!(af_for(Port::B, 8, Signal::I2c1Scl), Some(4));
+        assert_eq!(af_for(Port::A, 8, Signal::Mco), Some(0));
+    }
+
+    #[test]
+    #[cfg(all(feature = "f3", not(feature = "f373")))]
+    fn f3() {
+        assert_eq!(af_for(Port::A, 8, Signal::Tim1Ch1), Some(6));
+        assert_eq!(af_for(Port::A, 11, Signal::Tim1Ch4), Some(11));
+    }
+
+    #[test]
+    #[cfg(feature = "f4")]
+    fn f4() {
+        assert_eq!(af_for(Port::A, 8, Signal::Tim1Ch1), Some(1));
+        assert_eq!(af_for(Port::C, 9, Signal::Mco2), Some(0));
+        assert_eq!(af_for(Port::A, 2, Signal::Lpuart1Tx), None);
+    }
+
+    #[test]
+    #[cfg(any(feature = "l4", feature = "l5"))]
+    fn l4_l5() {
+        assert_eq!(af_for(Port::A, 2, Signal::Lpuart1Tx), Some(8));
+        assert_eq!(af_for(Port::C, 0, Signal::Lpuart1Rx), Some(8));
+        assert_eq!(af_for(Port::A, 11, Signal::Tim1Ch4), Some(1));
+    }
+
+    #[test]
+    #[cfg(feature = "g4")]
+    fn g4() {
+        assert_eq!(af_for(Port::A, 2, Signal::Lpuart1Tx), Some(12));
+        assert_eq!(af_for(Port::B, 11, Signal::Lpuart1Tx), Some(8));
+        assert_eq!(af_for(Port::A, 11, Signal::Tim1Ch4), Some(11));
+        assert_eq!(af_for(Port::B, 6, Signal::I2c1Scl), None);
+    }
+
+    #[test]
+    #[cfg(feature = "h7")]
+    fn h7() {
+        assert_eq!(af_for(Port::A, 9, Signal::Lpuart1Tx), Some(3));
+        assert_eq!(af_for(Port::B, 7, Signal::Lpuart1Rx), Some(8));
+        assert_eq!(af_for(Port::C, 9, Signal::Mco2), Some(0));
+    }
+
+    #[test]
+    #[cfg(any(feature = "wb", feature = "wl"))]
+    fn wb_wl() {
+        assert_eq!(af_for(Port::A, 2, Signal::Lpuart1Tx), Some(8));
+        assert_eq!(af_for(Port::C, 9, Signal::Mco2), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! Parallel buses: Groups of pins on one port, written and read together. Useful for parallel
+//! LCD interfaces, and parallel ADCs. Writes go through the `BSRR` register, so all bus pins change
+//! in the same cycle, and pins on the port outside the bus aren't affected.
+//!
+//! Bus pins don't have to be contiguous. Bit 0 of a bus value maps to the lowest pin in the mask,
+//! bit 1 to the next-lowest, etc. Eg a mask of `0b1111_0000_0011_0000` maps value bits 0 - 1 to
+//! pins 4 - 5, and value bits 2 - 5 to pins 12 - 15.
+
+use super::{claim_pins, regs, release_pins, ClaimError, Pin, PinMode, Port};
+
+#[cfg(not(any(feature = "f4", feature = "l5", feature = "f3", feature = "l4")))]
+use core::ops::Deref;
+
+#[cfg(not(any(feature = "f4", feature = "l5", feature = "f3", feature = "l4")))]
+use super::dma_p;
+#[cfg(not(any(feature = "f4", feature = "l5", feature = "f3", feature = "l4")))]
+use crate::dma::{ChannelCfg, Dma, DmaChannel, ReadBuffer, Transfer};
+
+/// Spread the low bits of `value` across the set bits of `mask`, in order.
+fn deposit(mask: u16, value: u16) -> u16 {
+    let mut result = 0;
+    let mut value_bit = 0;
+
+    for pin in 0..16 {
+        if mask & (1 << pin) != 0 {
+            if value & (1 << value_bit) != 0 {
+                result |= 1 << pin;
+            }
+            value_bit += 1;
+        }
+    }
+
+    result
+}
+
+/// Gather the bits of `port_val` at the set bits of `mask` into the low bits of the result.
+fn extract(mask: u16, port_val: u16) -> u16 {
+    let mut result = 0;
+    let mut value_bit = 0;
+
+    for pin in 0..16 {
+        if mask & (1 << pin) != 0 {
+            if port_val & (1 << pin) != 0 {
+                result |= 1 << value_bit;
+            }
+            value_bit += 1;
+        }
+    }
+
+    result
+}
+
+/// Represents a group of pins on one port, used as a parallel bus.
+pub struct PortBus {
+    /// The GPIO port the bus's pins are on.
+    pub port: Port,
+    /// Which pins are part of the bus. Bit 0 is pin 0, etc.
+    pub mask: u16,
+    /// Whether the bus's pins were claimed in the pin registry by `claim()`.
+    claimed: bool,
+}
+
+impl PortBus {
+    /// Create a bus from the pins set in `mask`, and set them all to `mode`. Eg
+    /// `PortBus::new(Port::B, 0x00ff, PinMode::Output)` for an 8-bit output bus on PB0 - PB7.
+    /// Enables the port's RCC clock if required.
+    pub fn new(port: Port, mask: u16, mode: PinMode) -> Self {
+        assert!(mask != 0, "A bus must contain at least one pin.");
+
+        let mut result = Self {
+            port,
+            mask,
+            claimed: false,
+        };
+        result.mode(mode);
+
+        result
+    }
+
+    /// Claim the bus's pins in the pin registry, then create it as with `new()`. Returns an
+    /// error, and claims no pins, if any are already claimed.
+    pub fn claim(port: Port, mask: u16, mode: PinMode) -> Result<Self, ClaimError> {
+        claim_pins(port, mask)?;
+
+        let mut result = Self::new(port, mask, mode);
+        result.claimed = true;
+
+        Ok(result)
+    }
+
+    /// Release the bus's pins in the pin registry, if it was created with `claim()`. A bus
+    /// created with `new()` doesn't own its pins' claims, so this leaves them unchanged.
+    pub fn release(self) {
+        if self.claimed {
+            release_pins(self.port, self.mask);
+        }
+    }
+
+    /// Set the mode of all pins in the bus. Eg switch between `Input` and `Output` for a
+    /// bidirectional bus.
+    pub fn mode(&mut self, mode: PinMode) {
+        for pin in 0..16 {
+            if self.mask & (1 << pin) != 0 {
+                Pin::new(self.port, pin, mode);
+            }
+        }
+    }
+
+    /// The number of pins in the bus.
+    pub fn width(&self) -> u8 {
+        self.mask.count_ones() as u8
+    }
+
+    /// Compute the `BSRR` word that sets the bus to `value`: Set bits in the lower half, and reset
+    /// bits in the upper half. Bits of `value` above the bus width are ignored.
+    pub fn bsrr_word(&self, value: u16) -> u32 {
+        let set = deposit(self.mask, value);
+        let reset = !set & self.mask;
+
+        set as u32 | ((reset as u32) << 16)
+    }
+
+    /// Set all bus pins to the bits of `value` in a single, atomic write to `BSRR`.
+    pub fn write(&mut self, value: u16) {
+        let word = self.bsrr_word(value);
+        unsafe { (*regs(self.port)).bsrr.write(|w| w.bits(word)) };
+    }
+
+    /// Read the bus's input levels from `IDR`, in a single read.
+    pub fn read(&self) -> u16 {
+        let port_val = unsafe { (*regs(self.port)).idr.read().bits() } as u16;
+        extract(self.mask, port_val)
+    }
+
+    /// Read back the bus's output levels from `ODR`.
+    pub fn read_output(&self) -> u16 {
+        let port_val = unsafe { (*regs(self.port)).odr.read().bits() } as u16;
+        extract(self.mask, port_val)
+    }
+
+    /// Convert a series of bus values to `BSRR` words, eg to prepare a buffer for `write_dma()`.
+    /// Converts as many values as fit in `buf`.
+    pub fn fill_bsrr(&self, values: &[u16], buf: &mut [u32]) {
+        for (word, value) in buf.iter_mut().zip(values) {
+            *word = self.bsrr_word(*value);
+        }
+    }
+
+    #[cfg(not(any(feature = "f4", feature = "l5", feature = "f3", feature = "l4")))]
+    /// Stream a series of bus values out using DMA. `buf` contains `BSRR` words; build it with
+    /// `fill_bsrr()`. The pace is set by the DMA request the channel is routed to, eg a timer's
+    /// update event. See `gpio::write_dma`.
+    pub fn write_dma<B, D>(
+        &mut self,
+        buf: B,
+        dma_channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma: &mut Dma<D>,
+    ) -> Transfer<B>
+    where
+        B: ReadBuffer<Word = u32>,
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        super::write_dma(buf, self.port, dma_channel, channel_cfg, dma)
+    }
+}
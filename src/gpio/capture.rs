@@ -0,0 +1,83 @@
+//! Decode port captures taken with `Timer::capture_port_dma` (or `gpio::read_dma`), eg to inspect
+//! a protocol on the bench, like a basic logic analyzer. Each sample is a half-word holding the
+//! levels of all 16 pins on the port at one instant; bit 0 is pin 0, etc.
+
+/// A completed capture of port samples, taken at a fixed rate.
+pub struct Capture<'a> {
+    pub samples: &'a [u16],
+    /// Samples per second; the timer's update frequency.
+    pub sample_rate: f32,
+}
+
+impl<'a> Capture<'a> {
+    pub fn new(samples: &'a [u16], sample_rate: f32) -> Self {
+        Self {
+            samples,
+            sample_rate,
+        }
+    }
+
+    /// The level of a pin in a given sample.
+    pub fn level(&self, index: usize, pin: u8) -> bool {
+        self.samples[index] & (1 << pin) != 0
+    }
+
+    /// The time of a sample, in seconds after the first sample.
+    pub fn time(&self, index: usize) -> f32 {
+        index as f32 / self.sample_rate
+    }
+
+    /// Iterate over a pin's level changes, as `(sample index, new level)`.
+    pub fn edges(&self, pin: u8) -> Edges<'_> {
+        Edges {
+            samples: self.samples,
+            mask: 1 << pin,
+            index: 1,
+        }
+    }
+
+    /// Pack a single pin's levels, one bit per sample, into `buf`. Bit 0 of byte 0 is sample 0.
+    /// Packs as many samples as fit; returns the number packed.
+    pub fn pack_pin(&self, pin: u8, buf: &mut [u8]) -> usize {
+        let count = self.samples.len().min(buf.len() * 8);
+
+        for byte in buf.iter_mut().take((count + 7) / 8) {
+            *byte = 0;
+        }
+
+        for i in 0..count {
+            if self.level(i, pin) {
+                buf[i / 8] |= 1 << (i % 8);
+            }
+        }
+
+        count
+    }
+}
+
+/// An iterator over one pin's level changes in a `Capture`. See `Capture::edges()`.
+pub struct Edges<'a> {
+    samples: &'a [u16],
+    mask: u16,
+    index: usize,
+}
+
+impl Iterator for Edges<'_> {
+    type Item = (usize, bool);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.samples.len() {
+            let i = self.index;
+            self.index += 1;
+
+            let prev = self.samples[i - 1] & self.mask;
+            let current = self.samples[i] & self.mask;
+
+            if prev != current {
+                return Some((i, current != 0));
+            }
+        }
+
+        None
+    }
+}
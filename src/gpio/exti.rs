@@ -0,0 +1,312 @@
+//! An EXTI manager for GPIO interrupts (lines 0 - 15). It owns the EXTI peripheral, routes lines
+//! to ports, and dispatches pending lines to handlers registered per line. This is an alternative
+//! to setting up interrupts with `Pin::enable_interrupt()`, and clearing them with
+//! `gpio::clear_exti_interrupt()` in hand-written handlers.
+//!
+//! Several lines share an interrupt vector on most families. Call `dispatch()` with the lines
+//! the vector covers, from its handler. Eg: `EXTI9_5` on F3, F4, L4, G4, WB, WL and H7 covers
+//! lines 5 - 9, and `EXTI15_10` covers 10 - 15. G0 uses `EXTI0_1`, `EXTI2_3`, and `EXTI4_15`. L5
+//! has one vector per line. `dispatch_all()` checks every line.
+//!
+//! Example, with the manager stored in a global:
+//! ```
+//! make_globals!((EXTI_MGR, Exti));
+//!
+//! fn button_pressed(line: u8) {
+//!     defmt::println!("Line {} triggered", line);
+//! }
+//!
+//! let mut exti = Exti::new(dp.EXTI);
+//! exti.listen(Port::B, 7, Edge::Falling, button_pressed);
+//! free(|cs| EXTI_MGR.borrow(cs).replace(Some(exti)));
+//!
+//! #[interrupt]
+//! fn EXTI9_5() {
+//!     free(|cs| {
+//!         access_global!(EXTI_MGR, exti, cs);
+//!         exti.dispatch(5..=9);
+//!     });
+//! }
+//! ```
+
+use core::ops::RangeInclusive;
+
+use super::{Edge, Port};
+
+use crate::pac::EXTI;
+
+#[cfg(not(any(feature = "l5", feature = "g0")))]
+use crate::pac::SYSCFG;
+
+use cfg_if::cfg_if;
+
+/// A function called when its EXTI line triggers. Its argument is the line number.
+pub type ExtiHandler = fn(u8);
+
+/// Set or clear one bit of an EXTI register. Uses raw bits, since field names vary by family.
+macro_rules! set_line_bit {
+    ($reg:expr, $line:expr, $val:expr) => {
+        $reg.modify(|r, w| unsafe {
+            if $val {
+                w.bits(r.bits() | (1 << $line))
+            } else {
+                w.bits(r.bits() & !(1 << $line))
+            }
+        })
+    };
+}
+
+/// Set a line's port selection in the EXTICR registers. These are in SYSCFG on most families,
+/// with 4 bits per line, and in EXTI on L5 and G0, with 8 bits per line.
+macro_rules! set_exticr {
+    ($regs:expr, $line:expr, $val:expr, $width:expr) => {{
+        let shift = ($line % 4) as u32 * $width;
+        let mask = ((1 << $width) - 1) << shift;
+        let val = ($val as u32) << shift;
+
+        match $line / 4 {
+            0 => $regs
+                .exticr1
+                .modify(|r, w| unsafe { w.bits((r.bits() & !mask) | val) }),
+            1 => $regs
+                .exticr2
+                .modify(|r, w| unsafe { w.bits((r.bits() & !mask) | val) }),
+            2 => $regs
+                .exticr3
+                .modify(|r, w| unsafe { w.bits((r.bits() & !mask) | val) }),
+            _ => $regs
+                .exticr4
+                .modify(|r, w| unsafe { w.bits((r.bits() & !mask) | val) }),
+        }
+    }};
+}
+
+/// Panic if `line` isn't a GPIO EXTI line.
+fn check_line(line: u8) {
+    assert!(line <= 15, "GPIO EXTI lines must be 0 - 15.");
+}
+
+/// Represents the EXTI peripheral, configured for GPIO interrupts.
+pub struct Exti {
+    pub regs: EXTI,
+    handlers: [Option<ExtiHandler>; 16],
+}
+
+impl Exti {
+    /// Take ownership of the EXTI peripheral. Its clock is always enabled. Note that the SYSCFG
+    /// clock, used for port selection on most families, is enabled in `Clocks::setup()`.
+    pub fn new(regs: EXTI) -> Self {
+        Self {
+            regs,
+            handlers: [None; 16],
+        }
+    }
+
+    /// Configure a GPIO line as an interrupt source, and register a function to run when it
+    /// triggers. The pin's port is selected for this line; only one port can use each line.
+    /// Calling this again for the same line replaces the previous configuration and handler.
+    pub fn listen(&mut self, port: Port, line: u8, edge: Edge, handler: ExtiHandler) {
+        self.configure(port, line, edge);
+        self.handlers[line as usize] = Some(handler);
+    }
+
+    /// Route a line to a port, set its trigger edges, and unmask it, without changing its
+    /// handler. Can be used to reconfigure a line, eg to switch edges or ports.
+    pub fn configure(&mut self, port: Port, line: u8, edge: Edge) {
+        check_line(line);
+
+        // Mask the line while changing its configuration, so we don't get spurious interrupts.
+        self.set_mask(line, false);
+
+        cfg_if! {
+            if #[cfg(any(feature = "l5", feature = "g0"))] {
+                set_exticr!(self.regs, line, port.cr_val(), 8);
+            } else {
+                let syscfg = unsafe { &(*SYSCFG::ptr()) };
+                set_exticr!(syscfg, line, port.cr_val(), 4);
+            }
+        }
+
+        self.set_edge(line, edge);
+        self.clear(line);
+        self.set_mask(line, true);
+    }
+
+    /// Change which edges trigger an interrupt on a line.
+    pub fn set_edge(&mut self, line: u8, edge: Edge) {
+        check_line(line);
+        let rising = !matches!(edge, Edge::Falling);
+        let falling = !matches!(edge, Edge::Rising);
+
+        cfg_if! {
+            if #[cfg(any(feature = "f373", feature = "f4"))] {
+                set_line_bit!(self.regs.rtsr, line, rising);
+                set_line_bit!(self.regs.ftsr, line, falling);
+            } else {
+                set_line_bit!(self.regs.rtsr1, line, rising);
+                set_line_bit!(self.regs.ftsr1, line, falling);
+            }
+        }
+    }
+
+    /// Register, replace, or remove (with `None`) the handler for a line, without changing its
+    /// configuration.
+    pub fn set_handler(&mut self, line: u8, handler: Option<ExtiHandler>) {
+        check_line(line);
+        self.handlers[line as usize] = handler;
+    }
+
+    /// Stop a line from generating interrupts: Mask it (for both cores, on WB), disable its
+    /// triggers, clear any pending interrupt, and remove its handler.
+    pub fn disable(&mut self, line: u8) {
+        check_line(line);
+        self.set_mask(line, false);
+        #[cfg(feature = "wb")]
+        self.disable_core2(line);
+
+        cfg_if! {
+            if #[cfg(any(feature = "f373", feature = "f4"))] {
+                set_line_bit!(self.regs.rtsr, line, false);
+                set_line_bit!(self.regs.ftsr, line, false);
+            } else {
+                set_line_bit!(self.regs.rtsr1, line, false);
+                set_line_bit!(self.regs.ftsr1, line, false);
+            }
+        }
+
+        self.clear(line);
+        self.handlers[line as usize] = None;
+    }
+
+    /// Trigger a line's interrupt from software. The line must be unmasked. Its handler runs
+    /// from the interrupt vector, as with a pin edge.
+    pub fn trigger(&mut self, line: u8) {
+        check_line(line);
+        cfg_if! {
+            if #[cfg(any(feature = "f373", feature = "f4"))] {
+                self.regs.swier.write(|w| unsafe { w.bits(1 << line) });
+            } else {
+                self.regs.swier1.write(|w| unsafe { w.bits(1 << line) });
+            }
+        }
+    }
+
+    /// Read which of lines 0 - 15 have a pending interrupt, as a bit mask.
+    pub fn pending(&self) -> u16 {
+        cfg_if! {
+            if #[cfg(any(feature = "h747cm4", feature = "h747cm7"))] {
+                let bits = self.regs.c1pr1.read().bits();
+            } else if #[cfg(feature = "h7")] {
+                let bits = self.regs.cpupr1.read().bits();
+            } else if #[cfg(any(feature = "l5", feature = "g0"))] {
+                // Rising and falling edges have separate pending registers.
+                let bits = self.regs.rpr1.read().bits() | self.regs.fpr1.read().bits();
+            } else if #[cfg(any(feature = "f373", feature = "f4"))] {
+                let bits = self.regs.pr.read().bits();
+            } else {
+                let bits = self.regs.pr1.read().bits();
+            }
+        }
+
+        bits as u16
+    }
+
+    /// Check if a line has a pending interrupt.
+    pub fn is_pending(&self, line: u8) -> bool {
+        check_line(line);
+        self.pending() & (1 << line) != 0
+    }
+
+    /// Clear a line's pending interrupt. Unlike `gpio::clear_exti_interrupt()`, this doesn't
+    /// affect other lines' pending bits.
+    pub fn clear(&mut self, line: u8) {
+        check_line(line);
+        cfg_if! {
+            if #[cfg(any(feature = "h747cm4", feature = "h747cm7"))] {
+                self.regs.c1pr1.write(|w| unsafe { w.bits(1 << line) });
+            } else if #[cfg(feature = "h7")] {
+                self.regs.cpupr1.write(|w| unsafe { w.bits(1 << line) });
+            } else if #[cfg(any(feature = "l5", feature = "g0"))] {
+                self.regs.rpr1.write(|w| unsafe { w.bits(1 << line) });
+                self.regs.fpr1.write(|w| unsafe { w.bits(1 << line) });
+            } else if #[cfg(any(feature = "f373", feature = "f4"))] {
+                self.regs.pr.write(|w| unsafe { w.bits(1 << line) });
+            } else {
+                self.regs.pr1.write(|w| unsafe { w.bits(1 << line) });
+            }
+        }
+    }
+
+    /// Handle pending interrupts for a range of lines: Clear each pending line, then run its
+    /// handler, if one is registered. Call this from the interrupt vector that covers these
+    /// lines; eg `exti.dispatch(10..=15)` in `EXTI15_10`. Lines must be 0 - 15.
+    pub fn dispatch(&mut self, lines: RangeInclusive<u8>) {
+        check_line(*lines.end());
+
+        let pending = self.pending();
+
+        for line in lines {
+            if pending & (1 << line) == 0 {
+                continue;
+            }
+
+            self.clear(line);
+
+            if let Some(handler) = self.handlers[line as usize] {
+                handler(line);
+            }
+        }
+    }
+
+    /// Handle pending interrupts on all GPIO lines. Useful if several vectors share one handler.
+    pub fn dispatch_all(&mut self) {
+        self.dispatch(0..=15);
+    }
+
+    #[cfg(feature = "wb")]
+    /// Unmask a line for the second core (Cortex-M0+), so its interrupt is received there too.
+    /// Sets the `C2IMR1` register. Configure the line with `listen()` or `configure()` first.
+    pub fn enable_core2(&mut self, line: u8) {
+        check_line(line);
+        set_line_bit!(self.regs.c2imr1, line, true);
+    }
+
+    #[cfg(feature = "wb")]
+    /// Mask a line for the second core. Sets the `C2IMR1` register.
+    pub fn disable_core2(&mut self, line: u8) {
+        check_line(line);
+        set_line_bit!(self.regs.c2imr1, line, false);
+    }
+
+    /// Mask or unmask a line's interrupt for this core.
+    fn set_mask(&mut self, line: u8, unmasked: bool) {
+        cfg_if! {
+            if #[cfg(any(feature = "h747cm4", feature = "h747cm7"))] {
+                set_line_bit!(self.regs.c1imr1, line, unmasked);
+            } else if #[cfg(feature = "h7")] {
+                set_line_bit!(self.regs.cpuimr1, line, unmasked);
+            } else if #[cfg(any(feature = "f373", feature = "f4"))] {
+                set_line_bit!(self.regs.imr, line, unmasked);
+            } else {
+                set_line_bit!(self.regs.imr1, line, unmasked);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_in_range() {
+        check_line(0);
+        check_line(15);
+    }
+
+    #[test]
+    #[should_panic]
+    fn line_out_of_range() {
+        check_line(16);
+    }
+}
@@ -0,0 +1,414 @@
+//! Input helpers built on `Pin`: A debouncer for buttons and switches, and a software quadrature
+//! decoder for rotary encoders on pins that can't use a timer's encoder mode. The state machines
+//! only use the levels and timestamps you pass in, so they can be driven from a timer interrupt,
+//! from EXTI edges, or from a polling loop.
+//!
+//! Timestamps are `u32` values in any unit you like, eg milliseconds or timer ticks, and may wrap.
+
+use super::Pin;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A debounced level change.
+pub enum DebounceEvent {
+    /// The input settled high, after being low.
+    Rising,
+    /// The input settled low, after being high.
+    Falling,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A quadrature encoder movement of one detent.
+pub enum EncoderEvent {
+    /// Channel A leads channel B.
+    Clockwise,
+    /// Channel B leads channel A.
+    CounterClockwise,
+}
+
+/// A fixed-capacity FIFO of input events. When full, new events are dropped, and `overflowed()`
+/// reports it until the queue is cleared.
+pub struct EventQueue<T: Copy, const N: usize> {
+    buf: [Option<T>; N],
+    head: usize,
+    len: usize,
+    overflowed: bool,
+}
+
+impl<T: Copy, const N: usize> EventQueue<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [None; N],
+            head: 0,
+            len: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Add an event to the back of the queue. Returns false, and drops the event, if the queue
+    /// is full.
+    pub fn push(&mut self, event: T) -> bool {
+        if self.len == N {
+            self.overflowed = true;
+            return false;
+        }
+
+        self.buf[(self.head + self.len) % N] = Some(event);
+        self.len += 1;
+        true
+    }
+
+    /// Remove and return the oldest event.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let event = self.buf[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        event
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// True if an event was dropped because the queue was full.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    /// Remove all events, and reset the overflow flag.
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl<T: Copy, const N: usize> Default for EventQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Debounces a digital input. A level change is reported once the raw input has stayed at the
+/// new level for the debounce window.
+///
+/// Timer-driven: Call `update()` periodically, with the sampled level. EXTI-driven: Call
+/// `update()` from the pin's interrupt with the new level, then call `poll()` after the window
+/// elapses (eg from a one-shot timer) to report the final, settled level.
+pub struct Debouncer<const N: usize> {
+    /// How long the raw input must be stable before a change is reported, in timestamp units.
+    pub window: u32,
+    stable: bool,
+    raw: bool,
+    last_change: u32,
+    /// Debounced events, oldest first.
+    pub events: EventQueue<DebounceEvent, N>,
+}
+
+impl<const N: usize> Debouncer<N> {
+    /// Create a debouncer, with the input's current level.
+    pub fn new(window: u32, initial_level: bool) -> Self {
+        Self {
+            window,
+            stable: initial_level,
+            raw: initial_level,
+            last_change: 0,
+            events: EventQueue::new(),
+        }
+    }
+
+    /// Feed in a raw input level, sampled at `now`. Returns an event if the debounced level
+    /// changed; it's also added to `events`.
+    pub fn update(&mut self, level: bool, now: u32) -> Option<DebounceEvent> {
+        if level != self.raw {
+            self.raw = level;
+            self.last_change = now;
+        }
+
+        self.poll(now)
+    }
+
+    /// Read the level of a pin, and feed it in. See `update()`.
+    pub fn update_from_pin(&mut self, pin: &Pin, now: u32) -> Option<DebounceEvent> {
+        self.update(pin.is_high(), now)
+    }
+
+    /// Check if the raw input has been stable for the window, without a new sample. Returns an
+    /// event if the debounced level changed.
+    pub fn poll(&mut self, now: u32) -> Option<DebounceEvent> {
+        if self.raw == self.stable || now.wrapping_sub(self.last_change) < self.window {
+            return None;
+        }
+
+        self.stable = self.raw;
+
+        let event = if self.stable {
+            DebounceEvent::Rising
+        } else {
+            DebounceEvent::Falling
+        };
+
+        self.events.push(event);
+        Some(event)
+    }
+
+    /// The debounced level.
+    pub fn is_high(&self) -> bool {
+        self.stable
+    }
+
+    pub fn is_low(&self) -> bool {
+        !self.stable
+    }
+}
+
+/// Position change for each (previous state, current state) pair, indexed by
+/// `previous << 2 | current`. States are `A << 1 | B`. `None` marks an invalid transition, where
+/// both channels changed at once; usually a missed sample.
+const QUAD_TABLE: [Option<i8>; 16] = [
+    Some(0),
+    Some(-1),
+    Some(1),
+    None,
+    Some(1),
+    Some(0),
+    None,
+    Some(-1),
+    Some(-1),
+    None,
+    Some(0),
+    Some(1),
+    None,
+    Some(1),
+    Some(-1),
+    Some(0),
+];
+
+/// Panic if `steps` isn't a valid number of quadrature steps per detent. Partial detents are
+/// counted in an `i8`.
+fn check_steps_per_detent(steps: u8) {
+    assert!(
+        (1..=i8::MAX as u8).contains(&steps),
+        "Steps per detent must be 1 - 127."
+    );
+}
+
+/// Decodes a quadrature encoder's A and B channels in software. Feed it the channel levels on
+/// every edge, (eg from EXTI interrupts on both pins, with `Edge::Either`) or by polling faster than
+/// the encoder's maximum edge rate. Debounce isn't required for most encoders, since a bouncing
+/// channel moves back and forth between adjacent states.
+pub struct QuadDecoder<const N: usize> {
+    state: u8,
+    /// Count of quadrature steps within the current detent.
+    sub_steps: i8,
+    /// Quadrature steps per detent. See `set_steps_per_detent()`.
+    steps_per_detent: u8,
+    position: i32,
+    errors: u32,
+    /// Detent movements, oldest first.
+    pub events: EventQueue<EncoderEvent, N>,
+}
+
+impl<const N: usize> QuadDecoder<N> {
+    /// Create a decoder, with the channels' current levels. See `set_steps_per_detent()` for
+    /// valid `steps_per_detent` values.
+    pub fn new(steps_per_detent: u8, a: bool, b: bool) -> Self {
+        check_steps_per_detent(steps_per_detent);
+
+        Self {
+            state: ((a as u8) << 1) | b as u8,
+            sub_steps: 0,
+            steps_per_detent,
+            position: 0,
+            errors: 0,
+            events: EventQueue::new(),
+        }
+    }
+
+    /// Feed in the current channel levels. Returns an event if the encoder moved a full detent;
+    /// it's also added to `events`.
+    pub fn update(&mut self, a: bool, b: bool) -> Option<EncoderEvent> {
+        let new_state = ((a as u8) << 1) | b as u8;
+        let step = QUAD_TABLE[((self.state << 2) | new_state) as usize];
+        self.state = new_state;
+
+        let step = match step {
+            Some(s) => s,
+            None => {
+                self.errors = self.errors.wrapping_add(1);
+                return None;
+            }
+        };
+
+        self.sub_steps += step;
+
+        let event = if self.sub_steps >= self.steps_per_detent as i8 {
+            self.position = self.position.wrapping_add(1);
+            EncoderEvent::Clockwise
+        } else if self.sub_steps <= -(self.steps_per_detent as i8) {
+            self.position = self.position.wrapping_sub(1);
+            EncoderEvent::CounterClockwise
+        } else {
+            return None;
+        };
+
+        self.sub_steps = 0;
+        self.events.push(event);
+        Some(event)
+    }
+
+    /// Quadrature steps per detent.
+    pub fn steps_per_detent(&self) -> u8 {
+        self.steps_per_detent
+    }
+
+    /// Set the quadrature steps per detent: Usually 4 for full-cycle encoders, or 2 or 1 for
+    /// others. Must be 1 - 127. Discards any partial detent.
+    pub fn set_steps_per_detent(&mut self, steps_per_detent: u8) {
+        check_steps_per_detent(steps_per_detent);
+
+        self.steps_per_detent = steps_per_detent;
+        self.sub_steps = 0;
+    }
+
+    /// Read the levels of the channel pins, and feed them in. See `update()`.
+    pub fn update_from_pins(&mut self, a: &Pin, b: &Pin) -> Option<EncoderEvent> {
+        self.update(a.is_high(), b.is_high())
+    }
+
+    /// The position, in detents. Clockwise is positive.
+    pub fn position(&self) -> i32 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: i32) {
+        self.position = position;
+        self.sub_steps = 0;
+    }
+
+    /// The number of invalid transitions seen, where both channels changed between updates. If
+    /// this increases, the decoder is being updated too slowly.
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Channel states for one clockwise cycle, as (A, B), starting from both low.
+    const CW: [(bool, bool); 4] = [(true, false), (true, true), (false, true), (false, false)];
+
+    #[test]
+    fn event_queue() {
+        let mut queue = EventQueue::<u8, 2>::new();
+        assert!(queue.push(1));
+        assert!(queue.push(2));
+        assert!(!queue.push(3));
+        assert!(queue.overflowed());
+        assert_eq!(queue.pop(), Some(1));
+        assert!(queue.push(4));
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(4));
+        assert_eq!(queue.pop(), None);
+
+        queue.clear();
+        assert!(!queue.overflowed());
+    }
+
+    #[test]
+    fn debounce() {
+        let mut deb = Debouncer::<4>::new(10, false);
+
+        // A bounce shorter than the window isn't reported.
+        assert_eq!(deb.update(true, 100), None);
+        assert_eq!(deb.update(false, 105), None);
+        assert_eq!(deb.update(false, 120), None);
+        assert!(deb.is_low());
+
+        // A level held for the window is reported once.
+        assert_eq!(deb.update(true, 130), None);
+        assert_eq!(deb.update(true, 139), None);
+        assert_eq!(deb.update(true, 140), Some(DebounceEvent::Rising));
+        assert_eq!(deb.update(true, 150), None);
+        assert!(deb.is_high());
+
+        // Settling is detected by `poll()`, without new samples.
+        assert_eq!(deb.update(false, 200), None);
+        assert_eq!(deb.poll(205), None);
+        assert_eq!(deb.poll(210), Some(DebounceEvent::Falling));
+
+        assert_eq!(deb.events.pop(), Some(DebounceEvent::Rising));
+        assert_eq!(deb.events.pop(), Some(DebounceEvent::Falling));
+        assert!(deb.events.is_empty());
+    }
+
+    #[test]
+    fn debounce_wrapping() {
+        let mut deb = Debouncer::<4>::new(10, false);
+
+        assert_eq!(deb.update(true, u32::MAX - 4), None);
+        assert_eq!(deb.poll(4), None);
+        assert_eq!(deb.poll(5), Some(DebounceEvent::Rising));
+    }
+
+    #[test]
+    fn quadrature() {
+        let mut dec = QuadDecoder::<4>::new(4, false, false);
+
+        for (i, (a, b)) in CW.into_iter().enumerate() {
+            let expected = if i == 3 {
+                Some(EncoderEvent::Clockwise)
+            } else {
+                None
+            };
+            assert_eq!(dec.update(a, b), expected);
+        }
+        assert_eq!(dec.position(), 1);
+
+        for (a, b) in CW.into_iter().rev().skip(1).chain([(false, false)]) {
+            dec.update(a, b);
+        }
+        assert_eq!(dec.position(), 0);
+        assert_eq!(dec.events.pop(), Some(EncoderEvent::Clockwise));
+        assert_eq!(dec.events.pop(), Some(EncoderEvent::CounterClockwise));
+
+        // Both channels changing at once is an error, and doesn't move the position.
+        assert_eq!(dec.update(true, true), None);
+        assert_eq!(dec.errors(), 1);
+        assert_eq!(dec.position(), 0);
+    }
+
+    #[test]
+    fn quadrature_steps_per_detent() {
+        let mut dec = QuadDecoder::<4>::new(1, false, false);
+        assert_eq!(dec.update(true, false), Some(EncoderEvent::Clockwise));
+
+        dec.set_steps_per_detent(2);
+        assert_eq!(dec.update(true, true), None);
+        assert_eq!(dec.update(false, true), Some(EncoderEvent::Clockwise));
+        assert_eq!(dec.position(), 2);
+
+        dec.set_steps_per_detent(127);
+        assert_eq!(dec.steps_per_detent(), 127);
+    }
+
+    #[test]
+    #[should_panic]
+    fn quadrature_zero_steps() {
+        QuadDecoder::<4>::new(0, false, false);
+    }
+
+    #[test]
+    #[should_panic]
+    fn quadrature_too_many_steps() {
+        let mut dec = QuadDecoder::<4>::new(4, false, false);
+        dec.set_steps_per_detent(128);
+    }
+}
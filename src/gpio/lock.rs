@@ -0,0 +1,99 @@
+//! Lock port configuration, so it can't change until the next reset. Useful for safety-critical
+//! pins. Once locked, writes to a pin's `MODER`, `OTYPER`, `OSPEEDR`, `PUPDR`, and `AFR` fields are
+//! ignored; output levels can still be set. See the Reference Manual section on `GPIOx_LCKR`.
+
+use cortex_m::interrupt::free;
+
+use super::{regs, Pin, Port};
+
+/// The lock key bit in `LCKR`.
+const LCKK: u32 = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LockError {
+    /// This port has no `LCKR` register. (Ports C, E, and F on F373)
+    Unsupported,
+    /// The lock key sequence didn't complete, eg because it was interrupted by another write to
+    /// `LCKR`, or `mask` changed between writes.
+    SequenceFailed,
+    /// The sequence completed, but some pins in the mask didn't lock. Contains those pins.
+    NotLocked(u16),
+}
+
+/// Check if a port has a lock register.
+#[allow(unused_variables)]
+pub(super) fn lock_supported(port: Port) -> bool {
+    #[cfg(feature = "f373")]
+    if matches!(port, Port::C | Port::E | Port::F) {
+        return false;
+    }
+
+    true
+}
+
+/// Lock the configuration of all pins in `mask` on a port, by running the `LCKK` write sequence
+/// once, then verify the lock took effect. Bit 0 is pin 0, etc. Note that once a port is locked,
+/// its `LCKR` register can't change until reset, so lock all the pins you need to in one call.
+pub fn lock_pins(port: Port, mask: u16) -> Result<(), LockError> {
+    if !lock_supported(port) {
+        return Err(LockError::Unsupported);
+    }
+
+    let regs = unsafe { &(*regs(port)) };
+
+    let mask = mask as u32;
+
+    // RM: "LOCK key write sequence: WR LCKR[16] = 1 + LCKR[15:0], WR LCKR[16] = 0 + LCKR[15:0],
+    // WR LCKR[16] = 1 + LCKR[15:0], RD LCKR, RD LCKR[16] = 1 (this read operation is optional
+    // but it confirms that the lock is active)". The value of LCKR[15:0] must not change during
+    // the sequence, and it must not be interrupted.
+    let key_set = free(|_| unsafe {
+        regs.lckr.write(|w| w.bits(LCKK | mask));
+        regs.lckr.write(|w| w.bits(mask));
+        regs.lckr.write(|w| w.bits(LCKK | mask));
+        let _ = regs.lckr.read().bits();
+
+        regs.lckr.read().bits() & LCKK != 0
+    });
+
+    if !key_set {
+        return Err(LockError::SequenceFailed);
+    }
+
+    let unlocked = (mask & !regs.lckr.read().bits()) as u16;
+    if unlocked != 0 {
+        return Err(LockError::NotLocked(unlocked));
+    }
+
+    Ok(())
+}
+
+/// Read which pins on a port are locked, as a bit mask. Returns 0 on ports without a lock
+/// register.
+pub fn locked_pins(port: Port) -> u16 {
+    if !lock_supported(port) {
+        return 0;
+    }
+
+    let regs = unsafe { &(*regs(port)) };
+
+    // The lock bits only apply once the key is active.
+    let lckr = regs.lckr.read().bits();
+    if lckr & LCKK == 0 {
+        return 0;
+    }
+
+    lckr as u16
+}
+
+impl Pin {
+    /// Lock this pin's configuration until the next reset. See `lock_pins()`.
+    pub fn lock(&mut self) -> Result<(), LockError> {
+        lock_pins(self.port, 1 << self.pin)
+    }
+
+    /// Check if this pin's configuration is locked.
+    pub fn is_locked(&self) -> bool {
+        locked_pins(self.port) & (1 << self.pin) != 0
+    }
+}
@@ -0,0 +1,107 @@
+//! An optional registry of which pins are in use, to catch two modules configuring the same
+//! physical pin. Pins created with `Pin::new()` aren't tracked; use `Pin::claim()` (or
+//! `claim()` directly) for pins you'd like checked. Claims last until released.
+
+use core::cell::Cell;
+
+use cortex_m::interrupt::{free, Mutex};
+
+use super::{Pin, PinMode, Port};
+
+/// One bit per pin, indexed by port.
+static CLAIMED: Mutex<Cell<[u16; 8]>> = Mutex::new(Cell::new([0; 8]));
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A pin was claimed while already in use. `pins` is a mask of the conflicting pins on the port.
+pub struct ClaimError {
+    pub port: Port,
+    pub pins: u16,
+}
+
+/// Claim all pins in `mask` on a port. If any are already claimed, none are claimed, and the
+/// conflicting pins are returned in the error.
+pub fn claim_pins(port: Port, mask: u16) -> Result<(), ClaimError> {
+    free(|cs| {
+        let cell = CLAIMED.borrow(cs);
+        let mut claimed = cell.get();
+        let i = port.cr_val() as usize;
+
+        let conflicts = claimed[i] & mask;
+        if conflicts != 0 {
+            return Err(ClaimError {
+                port,
+                pins: conflicts,
+            });
+        }
+
+        claimed[i] |= mask;
+        cell.set(claimed);
+        Ok(())
+    })
+}
+
+/// Release all pins in `mask` on a port, so they can be claimed again.
+pub fn release_pins(port: Port, mask: u16) {
+    free(|cs| {
+        let cell = CLAIMED.borrow(cs);
+        let mut claimed = cell.get();
+        claimed[port.cr_val() as usize] &= !mask;
+        cell.set(claimed);
+    })
+}
+
+/// Claim a single pin. See `claim_pins()`.
+pub fn claim(port: Port, pin: u8) -> Result<(), ClaimError> {
+    assert!(pin <= 15, "Pin must be 0 - 15.");
+    claim_pins(port, 1 << pin)
+}
+
+/// Release a single pin. See `release_pins()`.
+pub fn release(port: Port, pin: u8) {
+    assert!(pin <= 15, "Pin must be 0 - 15.");
+    release_pins(port, 1 << pin)
+}
+
+/// Check if a pin is currently claimed.
+pub fn is_claimed(port: Port, pin: u8) -> bool {
+    assert!(pin <= 15, "Pin must be 0 - 15.");
+    free(|cs| CLAIMED.borrow(cs).get()[port.cr_val() as usize] & (1 << pin) != 0)
+}
+
+impl Pin {
+    /// Claim a pin in the registry, then create it as with `Pin::new()`. Returns an error, and
+    /// doesn't change the pin's configuration, if it's already claimed. Use `.expect()` on the
+    /// result to panic with the pin's port and number instead.
+    pub fn claim(port: Port, pin: u8, mode: PinMode) -> Result<Self, ClaimError> {
+        claim(port, pin)?;
+        Ok(Self::new(port, pin, mode))
+    }
+
+    /// Release this pin in the registry, so it can be claimed elsewhere. Its configuration is
+    /// left unchanged.
+    ///
+    /// # Safety
+    /// A `Pin` doesn't record whether it was claimed, and any code can create one. Only release
+    /// a pin created with `Pin::claim()`, or otherwise claimed by the caller; releasing one
+    /// claimed elsewhere lets it be claimed twice.
+    pub unsafe fn release(self) {
+        release(self.port, self.pin);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic]
+    fn release_out_of_range() {
+        release(Port::A, 16);
+    }
+
+    #[test]
+    #[should_panic]
+    fn is_claimed_out_of_range() {
+        is_claimed(Port::A, 16);
+    }
+}
@@ -0,0 +1,90 @@
+//! Save and restore port configuration around low-power modes. Setting unused pins to analog mode
+//! before `low_power::stop()` or `standby()` minimizes leakage current. Take a snapshot, apply
+//! the low-power profile, enter the low-power mode, then restore on wakeup:
+//!
+//! ```
+//! let saved = [
+//!     PortSnapshot::enter_low_power(Port::A, 1 << 0), // Keep PA0 as a wakeup pin.
+//!     PortSnapshot::enter_low_power(Port::B, 0),
+//! ];
+//! low_power::stop(StopMode::Two);
+//! for snapshot in &saved {
+//!     snapshot.restore();
+//! }
+//! ```
+//!
+//! The port's RCC clock must be enabled, eg by having created a `Pin` on it.
+
+use super::{regs, Port};
+
+/// A copy of a port's configuration registers.
+#[derive(Clone, Copy)]
+pub struct PortSnapshot {
+    pub port: Port,
+    pub moder: u32,
+    pub otyper: u32,
+    pub ospeedr: u32,
+    pub pupdr: u32,
+    pub afrl: u32,
+    pub afrh: u32,
+}
+
+impl PortSnapshot {
+    /// Save a port's mode, output type, speed, pull, and alternate function settings.
+    pub fn take(port: Port) -> Self {
+        let regs = unsafe { &(*regs(port)) };
+
+        Self {
+            port,
+            moder: regs.moder.read().bits(),
+            otyper: regs.otyper.read().bits(),
+            ospeedr: regs.ospeedr.read().bits(),
+            pupdr: regs.pupdr.read().bits(),
+            afrl: regs.afrl.read().bits(),
+            afrh: regs.afrh.read().bits(),
+        }
+    }
+
+    /// Save a port's configuration, then apply the low-power profile with `set_analog_except()`.
+    pub fn enter_low_power(port: Port, keep_mask: u16) -> Self {
+        let result = Self::take(port);
+        set_analog_except(port, keep_mask);
+
+        result
+    }
+
+    /// Write the saved configuration back to the port. Mode is written last, so pins don't
+    /// briefly output with their low-power settings.
+    pub fn restore(&self) {
+        let regs = unsafe { &(*regs(self.port)) };
+
+        unsafe {
+            regs.afrl.write(|w| w.bits(self.afrl));
+            regs.afrh.write(|w| w.bits(self.afrh));
+            regs.otyper.write(|w| w.bits(self.otyper));
+            regs.ospeedr.write(|w| w.bits(self.ospeedr));
+            regs.pupdr.write(|w| w.bits(self.pupdr));
+            regs.moder.write(|w| w.bits(self.moder));
+        }
+    }
+}
+
+/// Set all pins on a port to analog mode with no pull resistor, except for those in `keep_mask`,
+/// eg wakeup pins, or outputs that must hold their level. Bit 0 is pin 0, etc.
+pub fn set_analog_except(port: Port, keep_mask: u16) {
+    let regs = unsafe { &(*regs(port)) };
+
+    // Each pin has a 2-bit field in MODER and PUPDR.
+    let mut change_mask = 0;
+    for pin in 0..16 {
+        if keep_mask & (1 << pin) == 0 {
+            change_mask |= 0b11 << (pin * 2);
+        }
+    }
+
+    unsafe {
+        // Analog mode is 0b11, and no pull is 0b00.
+        regs.pupdr.modify(|r, w| w.bits(r.bits() & !change_mask));
+        regs.moder.modify(|r, w| w.bits(r.bits() | change_mask));
+    }
+}
@@ -0,0 +1,491 @@
+//! Opt-in type-state GPIO pins. A pin's port, number, and mode are part of its type, eg
+//! `PA9<Alternate<7>>`, or `PB3<Output<PushPull>>`. Peripheral constructors that accept these,
+//! like `Usart::new_with_pins()`, check at compile time that their pins are set to the right
+//! alternate function. Use `erase()` to convert to a dynamic `Pin` for code that needs to
+//! choose pins or modes at runtime.
+//!
+//! Example:
+//! ```
+//! let tx: PA9<Alternate<7>> = PA9::claim().unwrap();
+//! let rx = PA10::<Analog>::claim().unwrap().into_mode::<Alternate<7>>();
+//! let uart = Usart::new_with_pins(dp.USART1, (tx, rx), 9_600, Default::default(), &clock_cfg);
+//! ```
+//!
+//! A `TypedPin` stands for exclusive use of its pin, so they're created with `claim()`, which
+//! checks the pin registry (`gpio::registry`).
+//!
+//! Only common peripheral pin mappings are included. Their AF numbers come from the tables in
+//! `gpio::af`. For others, set up pins with the dynamic `Pin`, and use the peripheral's `new()`
+//! function.
+
+use core::marker::PhantomData;
+
+#[cfg(feature = "embedded-hal")]
+use core::convert::Infallible;
+
+#[cfg(feature = "embedded-hal")]
+use embedded_hal::digital::v2::{InputPin, OutputPin, ToggleableOutputPin};
+
+use super::{
+    af::{af_for, Signal},
+    registry::{self, ClaimError},
+    OutputType, Pin, PinMode, PinState, Port, Pull,
+};
+
+use crate::{pac, timer::TimChannel};
+
+use paste::paste;
+
+/// Input mode, with pull resistor setting `PULL`.
+pub struct Input<PULL> {
+    _pull: PhantomData<PULL>,
+}
+
+/// Input with no pull resistor.
+pub struct Floating;
+/// Input with the internal pull-up resistor enabled.
+pub struct PullUp;
+/// Input with the internal pull-down resistor enabled.
+pub struct PullDown;
+
+/// Output mode, with output type `OTYPE`.
+pub struct Output<OTYPE> {
+    _otype: PhantomData<OTYPE>,
+}
+
+pub struct PushPull;
+pub struct OpenDrain;
+
+/// Alternate function `AF`, with push-pull output.
+pub struct Alternate<const AF: u8>;
+
+/// Alternate function `AF`, with open-drain output. Eg for I2C.
+pub struct AlternateOD<const AF: u8>;
+
+/// Analog mode, eg for ADC or DAC. This is the reset state of most pins, on families other than
+/// F3 and F4.
+pub struct Analog;
+
+/// Implemented by pin mode markers; sets a pin's registers for the mode.
+pub trait Mode {
+    /// `MODER` setting, and the alternate function if applicable.
+    const PIN_MODE: PinMode;
+
+    /// Set the output type and pull resistor.
+    fn configure(pin: &mut Pin);
+}
+
+macro_rules! input_mode {
+    ($Pull:ident, $pull:ident) => {
+        impl Mode for Input<$Pull> {
+            const PIN_MODE: PinMode = PinMode::Input;
+
+            fn configure(pin: &mut Pin) {
+                pin.pull(Pull::$pull);
+            }
+        }
+    };
+}
+
+input_mode!(Floating, Floating);
+input_mode!(PullUp, Up);
+input_mode!(PullDown, Dn);
+
+impl Mode for Output<PushPull> {
+    const PIN_MODE: PinMode = PinMode::Output;
+
+    fn configure(pin: &mut Pin) {
+        pin.output_type(OutputType::PushPull);
+    }
+}
+
+impl Mode for Output<OpenDrain> {
+    const PIN_MODE: PinMode = PinMode::Output;
+
+    fn configure(pin: &mut Pin) {
+        pin.output_type(OutputType::OpenDrain);
+    }
+}
+
+impl<const AF: u8> Mode for Alternate<AF> {
+    const PIN_MODE: PinMode = PinMode::Alt(AF);
+
+    fn configure(pin: &mut Pin) {
+        pin.output_type(OutputType::PushPull);
+    }
+}
+
+impl<const AF: u8> Mode for AlternateOD<AF> {
+    const PIN_MODE: PinMode = PinMode::Alt(AF);
+
+    fn configure(pin: &mut Pin) {
+        pin.output_type(OutputType::OpenDrain);
+    }
+}
+
+impl Mode for Analog {
+    const PIN_MODE: PinMode = PinMode::Analog;
+
+    fn configure(pin: &mut Pin) {
+        pin.pull(Pull::Floating);
+    }
+}
+
+/// Find the `Port` from its letter. Only letters with aliases below (eg `PA0`) are valid.
+const fn port(letter: char) -> Port {
+    match letter {
+        'A' => Port::A,
+        'B' => Port::B,
+        'C' => Port::C,
+        #[cfg(not(any(feature = "f410", feature = "wl")))]
+        'D' => Port::D,
+        #[cfg(not(any(
+            feature = "f301",
+            feature = "f3x4",
+            feature = "f410",
+            feature = "g0",
+            feature = "wb",
+            feature = "wl"
+        )))]
+        'E' => Port::E,
+        #[cfg(not(any(
+            feature = "f401",
+            feature = "f410",
+            feature = "f411",
+            feature = "l4x1",
+            feature = "l4x2",
+            feature = "l412",
+            feature = "l4x3",
+            feature = "wb",
+            feature = "wl"
+        )))]
+        'F' => Port::F,
+        #[cfg(not(any(
+            feature = "f373",
+            feature = "f301",
+            feature = "f3x4",
+            feature = "f401",
+            feature = "f410",
+            feature = "f411",
+            feature = "l4",
+            feature = "g0",
+            feature = "g4",
+            feature = "wb",
+            feature = "wl"
+        )))]
+        'G' => Port::G,
+        #[cfg(not(any(
+            feature = "f373",
+            feature = "f301",
+            feature = "f3x4",
+            feature = "f410",
+            feature = "l4",
+            feature = "g0",
+            feature = "g4",
+            feature = "wb"
+        )))]
+        'H' => Port::H,
+        _ => panic!("This GPIO port isn't available on this MCU."),
+    }
+}
+
+/// Find the alternate function that connects `signal` to a pin, from `af_for()`. Used in the
+/// peripheral pin trait impls below, so a mapping missing from the AF tables fails to compile.
+const fn af(letter: char, pin: u8, signal: Signal) -> u8 {
+    match af_for(port(letter), pin, signal) {
+        Some(af) => af,
+        None => panic!("This pin can't carry this signal, or it isn't in the AF tables."),
+    }
+}
+
+/// A GPIO pin, with its port letter, pin number, and mode as part of its type. Use the aliases
+/// instead of this type directly; eg `PA9<MODE>`.
+pub struct TypedPin<const P: char, const N: u8, MODE> {
+    _mode: PhantomData<MODE>,
+}
+
+impl<const P: char, const N: u8, MODE: Mode> TypedPin<P, N, MODE> {
+    /// Claim the pin in the registry, and set it to mode `MODE`. Returns an error, and doesn't
+    /// change the pin's configuration, if it's already claimed. Enables the RCC peripheral clock
+    /// to the port, if not already enabled.
+    pub fn claim() -> Result<Self, ClaimError> {
+        registry::claim(port(P), N)?;
+        Ok(unsafe { Self::new() })
+    }
+
+    /// Create a pin, and set it to mode `MODE`, without claiming it in the registry. Enables the
+    /// RCC peripheral clock to the port, if not already enabled.
+    ///
+    /// # Safety
+    /// Peripherals that accept a `TypedPin` assume they have exclusive use of its pin. Don't
+    /// create one for a pin that's in use elsewhere, including by another `TypedPin`.
+    pub unsafe fn new() -> Self {
+        let mut pin = Pin::new(port(P), N, MODE::PIN_MODE);
+        MODE::configure(&mut pin);
+
+        Self { _mode: PhantomData }
+    }
+
+    /// Release the pin in the registry, so it can be claimed elsewhere. Its configuration is
+    /// left unchanged.
+    pub fn release(self) {
+        registry::release(port(P), N);
+    }
+
+    /// Change the pin's mode.
+    pub fn into_mode<NEW: Mode>(self) -> TypedPin<P, N, NEW> {
+        let mut pin = self.erase();
+        NEW::configure(&mut pin);
+        pin.mode(NEW::PIN_MODE);
+
+        TypedPin { _mode: PhantomData }
+    }
+}
+
+impl<const P: char, const N: u8, MODE> TypedPin<P, N, MODE> {
+    /// Convert to a dynamic `Pin`, without changing its configuration.
+    pub fn erase(self) -> Pin {
+        Pin {
+            port: port(P),
+            pin: N,
+        }
+    }
+}
+
+impl<const P: char, const N: u8, PULL> TypedPin<P, N, Input<PULL>> {
+    /// Check if the pin's input voltage is high. Reads from the `IDR` register.
+    pub fn is_high(&self) -> bool {
+        super::is_high(port(P), N)
+    }
+
+    /// Check if the pin's input voltage is low. Reads from the `IDR` register.
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+}
+
+impl<const P: char, const N: u8, OTYPE> TypedPin<P, N, Output<OTYPE>> {
+    /// Set the pin's output voltage to high. Sets the `BSRR` register. Atomic.
+    pub fn set_high(&mut self) {
+        super::set_high(port(P), N);
+    }
+
+    /// Set the pin's output voltage to low. Sets the `BSRR` register. Atomic.
+    pub fn set_low(&mut self) {
+        super::set_low(port(P), N);
+    }
+
+    /// Set the pin's output voltage level. Sets the `BSRR` register. Atomic.
+    pub fn set_state(&mut self, value: PinState) {
+        super::set_state(port(P), N, value);
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<const P: char, const N: u8, PULL> InputPin for TypedPin<P, N, Input<PULL>> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(TypedPin::is_high(self))
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(TypedPin::is_low(self))
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<const P: char, const N: u8, OTYPE> OutputPin for TypedPin<P, N, Output<OTYPE>> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        TypedPin::set_low(self);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        TypedPin::set_high(self);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<const P: char, const N: u8, OTYPE> ToggleableOutputPin for TypedPin<P, N, Output<OTYPE>> {
+    type Error = Infallible;
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        if super::is_high(port(P), N) {
+            TypedPin::set_low(self);
+        } else {
+            TypedPin::set_high(self);
+        }
+        Ok(())
+    }
+}
+
+// Create aliases for each pin on a port, eg `PA0` through `PA15`.
+macro_rules! pin_aliases {
+    ($letter:literal, $Port:ident) => {
+        pin_aliases!(@pins $letter, $Port, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+    };
+    (@pins $letter:literal, $Port:ident, [$($num:literal),+]) => {
+        paste! {
+            $(
+                pub type [<P $Port $num>]<MODE> = TypedPin<$letter, $num, MODE>;
+            )+
+        }
+    };
+}
+
+pin_aliases!('A', A);
+pin_aliases!('B', B);
+pin_aliases!('C', C);
+#[cfg(not(any(feature = "f410", feature = "wl")))]
+pin_aliases!('D', D);
+#[cfg(not(any(
+    feature = "f301",
+    feature = "f3x4",
+    feature = "f410",
+    feature = "g0",
+    feature = "wb",
+    feature = "wl"
+)))]
+pin_aliases!('E', E);
+#[cfg(not(any(
+    feature = "f401",
+    feature = "f410",
+    feature = "f411",
+    feature = "l4x1",
+    feature = "l4x2",
+    feature = "l412",
+    feature = "l4x3",
+    feature = "wb",
+    feature = "wl"
+)))]
+pin_aliases!('F', F);
+#[cfg(not(any(
+    feature = "f373",
+    feature = "f301",
+    feature = "f3x4",
+    feature = "f401",
+    feature = "f410",
+    feature = "f411",
+    feature = "l4",
+    feature = "g0",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl"
+)))]
+pin_aliases!('G', G);
+#[cfg(not(any(
+    feature = "f373",
+    feature = "f301",
+    feature = "f3x4",
+    feature = "f410",
+    feature = "l4",
+    feature = "g0",
+    feature = "g4",
+    feature = "wb"
+)))]
+pin_aliases!('H', H);
+
+/// A pin set up as a U[S]ART transmit pin for `USART`.
+pub trait TxPin<USART> {}
+
+/// A pin set up as a U[S]ART receive pin for `USART`.
+pub trait RxPin<USART> {}
+
+/// A pin set up as an SPI clock pin for `SPI`.
+pub trait SckPin<SPI> {}
+
+/// A pin set up as an SPI MISO pin for `SPI`.
+pub trait MisoPin<SPI> {}
+
+/// A pin set up as an SPI MOSI pin for `SPI`.
+pub trait MosiPin<SPI> {}
+
+/// A pin set up as an I2C clock pin for `I2C`. These must be open-drain.
+pub trait SclPin<I2C> {}
+
+/// A pin set up as an I2C data pin for `I2C`. These must be open-drain.
+pub trait SdaPin<I2C> {}
+
+/// A pin set up as a PWM output for `TIM`, on timer channel `CHANNEL`.
+pub trait PwmPin<TIM> {
+    const CHANNEL: TimChannel;
+}
+
+// Implement a peripheral pin trait for pins, in the mode listed. The alternate function for
+// each pin is taken from the AF tables, for the signal listed.
+macro_rules! af_pins {
+    ($($Trait:ident<$Periph:ident>, $Mode:ident, $Signal:ident: [$(($P:literal, $N:literal)),+ $(,)?];)+) => {
+        $(
+            $(
+                impl $Trait<pac::$Periph>
+                    for TypedPin<$P, $N, $Mode<{ af($P, $N, Signal::$Signal) }>> {}
+            )+
+        )+
+    };
+}
+
+// Implement `PwmPin` for pins, with the timer channel listed. The alternate function is taken
+// from the AF tables.
+macro_rules! pwm_pins {
+    ($Tim:ident: [$(($P:literal, $N:literal, $Signal:ident, $ch:ident)),+ $(,)?]) => {
+        $(
+            impl PwmPin<pac::$Tim> for TypedPin<$P, $N, Alternate<{ af($P, $N, Signal::$Signal) }>> {
+                const CHANNEL: TimChannel = TimChannel::$ch;
+            }
+        )+
+    };
+}
+
+// See the Alternate Function tables in your MCU's datasheet, and `gpio::af`.
+af_pins! {
+    TxPin<USART1>, Alternate, Usart1Tx: [('A', 9), ('B', 6)];
+    RxPin<USART1>, Alternate, Usart1Rx: [('A', 10), ('B', 7)];
+    SckPin<SPI1>, Alternate, Spi1Sck: [('A', 5), ('B', 3)];
+    MisoPin<SPI1>, Alternate, Spi1Miso: [('A', 6), ('B', 4)];
+    MosiPin<SPI1>, Alternate, Spi1Mosi: [('A', 7), ('B', 5)];
+    SclPin<I2C1>, AlternateOD, I2c1Scl: [('B', 8)];
+    SdaPin<I2C1>, AlternateOD, I2c1Sda: [('B', 7)];
+}
+
+// WB doesn't have USART2.
+#[cfg(not(feature = "wb"))]
+af_pins! {
+    TxPin<USART2>, Alternate, Usart2Tx: [('A', 2)];
+    RxPin<USART2>, Alternate, Usart2Rx: [('A', 3)];
+}
+
+#[cfg(not(feature = "g4"))]
+af_pins! {
+    SclPin<I2C1>, AlternateOD, I2c1Scl: [('B', 6)];
+}
+
+#[cfg(not(feature = "wl"))]
+af_pins! {
+    SdaPin<I2C1>, AlternateOD, I2c1Sda: [('B', 9)];
+}
+
+#[cfg(not(any(
+    feature = "f410",
+    feature = "g030",
+    feature = "g050",
+    feature = "g070",
+    feature = "g0b0"
+)))]
+pwm_pins!(TIM2: [
+    ('A', 0, Tim2Ch1, C1), ('A', 1, Tim2Ch2, C2), ('A', 2, Tim2Ch3, C3), ('A', 5, Tim2Ch1, C1),
+    ('A', 15, Tim2Ch1, C1), ('B', 3, Tim2Ch2, C2),
+]);
+
+#[cfg(not(any(
+    feature = "f410",
+    feature = "wl",
+    feature = "g030",
+    feature = "g050",
+    feature = "g070",
+    feature = "g0b0"
+)))]
+pwm_pins!(TIM2: [('A', 3, Tim2Ch4, C4)]);
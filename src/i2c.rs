@@ -12,8 +12,9 @@ use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
 
 use crate::{
     clocks::Clocks,
+    gpio::typestate::{SclPin, SdaPin},
     pac::{self, RCC},
-    util::RccPeriph,
+    util::{ClockPeriph, RccPeriph},
 };
 
 use cfg_if::cfg_if;
@@ -26,8 +27,7 @@ cfg_if! {
     }
 }
 
-#[cfg(not(feature = "l552"))]
-use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
+use crate::dma::{self, ChannelCfg, Dma, DmaChannel, ReadBuffer, Transfer, WriteBuffer};
 
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::dma::DmaInput;
@@ -165,14 +165,15 @@ impl Default for I2cConfig {
 }
 
 /// Represents an Inter-Integrated Circuit (I2C) peripheral.
-pub struct I2c<R> {
+pub struct I2c<R, PINS = ()> {
     pub regs: R,
     pub cfg: I2cConfig,
+    pins: PINS,
 }
 
 impl<R> I2c<R>
 where
-    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph + ClockPeriph,
 {
     /// Initialize a I2C peripheral, including configuration register writes, and enabling and resetting
     /// its RCC peripheral clock. `freq` is in Hz.
@@ -188,6 +189,102 @@ where
         // todo: Slave currently nonfunctional!
         // todo: Check out the RM recipes for slave transmitter and receiver.
 
+        Self::set_timing(&regs, cfg.speed, clocks);
+
+        // Before enabling the I2C peripheral by setting the PE bit in I2C_CR1 register, the user must
+        // configure the noise filters, if needed. By default, an analog noise filter is present on the SDA
+        // and SCL inputs. This analog filter is compliant with the I2C specification which requires the
+        // suppression of spikes with a pulse width up to 50 ns in Fast-mode and Fast-mode Plus. The
+        // user can disable this analog filter by setting the ANFOFF bit, and/or select a digital filter by
+        // configuring the DNF[3:0] bit in the I2C_CR1 register.
+        // When the digital filter is enabled, the level of the SCL or the SDA line is internally changed
+        // only if it remains stable for more than DNF x I2CCLK periods. This allows spikes with a
+        // programmable length of 1 to 15 I2CCLK periods to be suppressed.
+        let (anf_bit, dnf_bits) = match cfg.noise_filter {
+            NoiseFilter::Analog => (false, 0),
+            NoiseFilter::Digital(filtering_len) => {
+                assert!(filtering_len <= 0b1111);
+                (true, filtering_len)
+            }
+            NoiseFilter::Disabled => (true, 0),
+        };
+
+        regs.cr1.modify(|_, w| unsafe {
+            w.anfoff().bit(anf_bit);
+            w.dnf().bits(dnf_bits)
+        });
+
+        if let I2cMode::Slave = cfg.mode {
+            regs.cr1.modify(|_, w| w.nostretch().bit(cfg.nostretch));
+        }
+
+        let mut result = Self {
+            regs,
+            cfg,
+            pins: (),
+        };
+
+        if result.cfg.smbus {
+            result.enable_smbus();
+        }
+
+        // Enable the peripheral
+        result.regs.cr1.write(|w| w.pe().set_bit());
+
+        result
+    }
+}
+
+impl<R, SCL, SDA> I2c<R, (SCL, SDA)>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph + ClockPeriph,
+    SCL: SclPin<R>,
+    SDA: SdaPin<R>,
+{
+    /// Initialize an I2C peripheral, as in `new()`, with type-state pins. This checks at compile
+    /// time that the pins are set to this peripheral's SCL and SDA alternate functions, with
+    /// open-drain outputs. The pins are held until `free()`.
+    pub fn new_with_pins(regs: R, pins: (SCL, SDA), cfg: I2cConfig, clocks: &Clocks) -> Self {
+        let i2c = I2c::new(regs, cfg, clocks);
+
+        Self {
+            regs: i2c.regs,
+            cfg: i2c.cfg,
+            pins,
+        }
+    }
+}
+
+impl<R, PINS> I2c<R, PINS>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph + ClockPeriph,
+{
+    /// Release the peripheral's registers, and its pins if it was created with `new_with_pins()`.
+    pub fn free(self) -> (R, PINS) {
+        (self.regs, self.pins)
+    }
+
+    /// Re-derive I2C timing from the current kernel clock, eg after changing clock speeds
+    /// with `Clocks::switch_to`. Disables the peripheral while updating TIMINGR, so don't run
+    /// this during a transfer.
+    pub fn retime(&mut self, clocks: &Clocks) {
+        let originally_enabled = self.regs.cr1.read().pe().bit_is_set();
+
+        // TIMINGR can only be written when the peripheral is disabled.
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.pe().clear_bit());
+        }
+
+        Self::set_timing(&self.regs, self.cfg.speed, clocks);
+
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.pe().set_bit());
+        }
+    }
+
+    /// Set TIMINGR for a given speed, based on the I2C kernel clock. The peripheral must be
+    /// disabled.
+    fn set_timing(regs: &R, speed: I2cSpeed, clocks: &Clocks) {
         // RM: I2C timings:
         // The timings must be configured in order to guarantee a correct data hold and setup time,
         // used in master and slave modes. This is done by programming the PRESC[3:0],
@@ -195,8 +292,9 @@ where
         // ... Additionally, in master mode, the SCL clock high and low levels must be configured by
         // programming the PRESC[3:0], SCLH[7:0] and SCLL[7:0] bits in the I2C_TIMINGR register
 
-        // For these speed and frequency variables, we use the RM's conventions.
-        let t_i2cclk = clocks.apb1();
+        // For these speed and frequency variables, we use the RM's conventions. This is the I2C
+        // kernel clock, as selected in `Clocks::kernel_clocks`.
+        let t_i2cclk = R::kernel_speed(clocks);
 
         // assert!(t_i2cclk < (t_low - f_f) / 4);
         // assert!(t_i2cclk < t_high);
@@ -208,7 +306,7 @@ where
         // values.
 
         // We use this constant in several calculations.
-        let presc_const = match cfg.speed {
+        let presc_const = match speed {
             I2cSpeed::Standard10K => 4_000_000,
             I2cSpeed::Standard100K => 4_000_000,
             I2cSpeed::Fast400K => 8_000_000,
@@ -230,7 +328,7 @@ where
         // Hit the target freq by setting up t_scll (Period of SCL low)
         // to be half the whole period. These constants
         // are from the tables.
-        let freq = match cfg.speed {
+        let freq = match speed {
             I2cSpeed::Standard10K => 10_000,
             I2cSpeed::Standard100K => 100_000,
             I2cSpeed::Fast400K => 400_000,
@@ -253,7 +351,7 @@ where
         // 10K. THis may be due to delays
         // involved. The ratio is different for Fast-mode and Fast-mode+.
         // todo: Come back to this. How should we set this?
-        let sclh_val = match cfg.speed {
+        let sclh_val = match speed {
             I2cSpeed::Standard10K => scll_val - 4,
             I2cSpeed::Standard100K => scll_val - 4,
             I2cSpeed::Fast400K => scll_val * 4 / 10,
@@ -289,7 +387,7 @@ where
         // tSDADEL= SDADEL x tPRESC
         // Note: SDADEL is used to generate tHD:DAT timing
         // Sets TIMINGR reg, SDADEL field.
-        let sdadel = match cfg.speed {
+        let sdadel = match speed {
             I2cSpeed::Standard10K => 0x2,
             I2cSpeed::Standard100K => 0x2,
             I2cSpeed::Fast400K => 0x3,
@@ -303,7 +401,7 @@ where
         // tSCLDEL = (SCLDEL+1) x tPRESC
         // Note: tSCLDEL is used to generate tSU:DAT timing
         // Sets TIMINGR reg, SCLDEL field.
-        let scldel = match cfg.speed {
+        let scldel = match speed {
             I2cSpeed::Standard10K => 0x4,
             I2cSpeed::Standard100K => 0x4,
             I2cSpeed::Fast400K => 0x3,
@@ -326,44 +424,6 @@ where
             w.sclh().bits(sclh as u8);
             w.scll().bits(scll as u8)
         });
-
-        // Before enabling the I2C peripheral by setting the PE bit in I2C_CR1 register, the user must
-        // configure the noise filters, if needed. By default, an analog noise filter is present on the SDA
-        // and SCL inputs. This analog filter is compliant with the I2C specification which requires the
-        // suppression of spikes with a pulse width up to 50 ns in Fast-mode and Fast-mode Plus. The
-        // user can disable this analog filter by setting the ANFOFF bit, and/or select a digital filter by
-        // configuring the DNF[3:0] bit in the I2C_CR1 register.
-        // When the digital filter is enabled, the level of the SCL or the SDA line is internally changed
-        // only if it remains stable for more than DNF x I2CCLK periods. This allows spikes with a
-        // programmable length of 1 to 15 I2CCLK periods to be suppressed.
-        let (anf_bit, dnf_bits) = match cfg.noise_filter {
-            NoiseFilter::Analog => (false, 0),
-            NoiseFilter::Digital(filtering_len) => {
-                assert!(filtering_len <= 0b1111);
-                (true, filtering_len)
-            }
-            NoiseFilter::Disabled => (true, 0),
-        };
-
-        regs.cr1.modify(|_, w| unsafe {
-            w.anfoff().bit(anf_bit);
-            w.dnf().bits(dnf_bits)
-        });
-
-        if let I2cMode::Slave = cfg.mode {
-            regs.cr1.modify(|_, w| w.nostretch().bit(cfg.nostretch));
-        }
-
-        let mut result = Self { regs, cfg };
-
-        if result.cfg.smbus {
-            result.enable_smbus();
-        }
-
-        // Enable the peripheral
-        result.regs.cr1.write(|w| w.pe().set_bit());
-
-        result
     }
 
     /// Enable SMBus support. See L44 RM, section 37.4.11: SMBus initialization
@@ -539,29 +599,30 @@ where
 
     #[cfg(not(feature = "g0"))]
     /// Read data, using DMA. See L44 RM, 37.4.16: "Transmission using DMA"
-    /// Note that the `channel` argument is only used on F3 and L4.
+    /// On F3, the `channel` argument has no effect with DMA1, and on L4, it has no effect.
     /// For a single write, set `autoend` to `true`. For a write_read and other use cases,
-    /// set it to `false`.
-    #[cfg(not(feature = "l552"))]
-    pub unsafe fn write_dma<D>(
+    /// set it to `false`. Returns a `Transfer` that holds `buf` until the transfer is complete.
+    pub fn write_dma<B, D>(
         &mut self,
         addr: u8,
-        buf: &[u8],
+        buf: B,
         autoend: bool,
         channel: DmaChannel,
         channel_cfg: ChannelCfg,
         dma: &mut Dma<D>,
-    ) where
+    ) -> Transfer<B>
+    where
+        B: ReadBuffer<Word = u8>,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
         while self.regs.cr2.read().start().bit_is_set() {}
 
-        let (ptr, len) = (buf.as_ptr(), buf.len());
+        let (ptr, len) = unsafe { buf.read_buffer() };
 
-        #[cfg(any(feature = "f3", feature = "l4"))]
-        let channel = R::write_chan();
+        #[cfg(feature = "f3")]
+        let channel = dma.select_channel(R::write_chan(), channel);
         #[cfg(feature = "l4")]
-        R::write_sel(dma);
+        let channel = R::write_sel(dma);
 
         // DMA (Direct Memory Access) can be enabled for transmission by setting the TXDMAEN bit
         // in the I2C_CR1 register. Data is loaded from an SRAM area configured using the DMA
@@ -606,27 +667,31 @@ where
             dma::DataSize::S8,
             channel_cfg,
         );
+
+        Transfer::new(buf, channel, dma.periph)
     }
 
     /// Read data, using DMA. See L44 RM, 37.4.16: "Reception using DMA"
-    /// Note that the `channel` argument is only used on F3 and L4.
-    #[cfg(not(feature = "l552"))]
-    pub unsafe fn read_dma<D>(
+    /// On F3, the `channel` argument has no effect with DMA1, and on L4, it has no effect.
+    /// Returns a `Transfer` that holds `buf` until the transfer is complete.
+    pub fn read_dma<B, D>(
         &mut self,
         addr: u8,
-        buf: &mut [u8],
+        mut buf: B,
         channel: DmaChannel,
         channel_cfg: ChannelCfg,
         dma: &mut Dma<D>,
-    ) where
+    ) -> Transfer<B>
+    where
+        B: WriteBuffer<Word = u8>,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
-        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+        let (ptr, len) = unsafe { buf.write_buffer() };
 
-        #[cfg(any(feature = "f3", feature = "l4"))]
-        let channel = R::read_chan();
+        #[cfg(feature = "f3")]
+        let channel = dma.select_channel(R::read_chan(), channel);
         #[cfg(feature = "l4")]
-        R::read_sel(dma);
+        let channel = R::read_sel(dma);
 
         // DMA (Direct Memory Access) can be enabled for reception by setting the RXDMAEN bit in
         // the I2C_CR1 register. Data is loaded from the I2C_RXDR register to an SRAM area
@@ -665,6 +730,8 @@ where
             dma::DataSize::S8,
             channel_cfg,
         );
+
+        Transfer::new(buf, channel, dma.periph)
     }
 
     // /// Write, and read data, using DMA. This is the primary read api.
@@ -750,9 +817,9 @@ where
 
 #[cfg(feature = "embedded-hal")]
 // #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
-impl<R> Write for I2c<R>
+impl<R, PINS> Write for I2c<R, PINS>
 where
-    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph + ClockPeriph,
 {
     type Error = Error;
 
@@ -763,9 +830,9 @@ where
 
 #[cfg(feature = "embedded-hal")]
 // #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
-impl<R> Read for I2c<R>
+impl<R, PINS> Read for I2c<R, PINS>
 where
-    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph + ClockPeriph,
 {
     type Error = Error;
 
@@ -776,9 +843,9 @@ where
 
 #[cfg(feature = "embedded-hal")]
 // #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
-impl<R> WriteRead for I2c<R>
+impl<R, PINS> WriteRead for I2c<R, PINS>
 where
-    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph + ClockPeriph,
 {
     type Error = Error;
 
@@ -14,7 +14,9 @@ use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
 
 use crate::{
     clocks::Clocks,
-    pac::{self, i2c1, RCC},
+    dma::{self, ChannelCfg, Dma, DmaChannel, DmaInput, ReadBuffer, Transfer, WriteBuffer},
+    gpio::typestate::{SclPin, SdaPin},
+    pac::{self, dma2 as dma_p, i2c1, RCC},
     util::rcc_en_reset,
 };
 
@@ -41,8 +43,10 @@ pub enum Error {
 }
 
 /// Represents an Inter-Integrated Circuit (I2C) peripheral.
-pub struct I2c<R> {
+pub struct I2c<R, PINS = ()> {
     pub regs: R,
+    pub device: I2cDevice,
+    pins: PINS,
 }
 
 impl<R> I2c<R>
@@ -67,10 +71,50 @@ where
             }
         });
 
-        let result = Self { regs };
+        let result = Self {
+            regs,
+            device,
+            pins: (),
+        };
         result.i2c_init(speed, clocks.apb1());
         result
     }
+}
+
+impl<R, SCL, SDA> I2c<R, (SCL, SDA)>
+where
+    R: Deref<Target = i2c1::RegisterBlock>,
+    SCL: SclPin<R>,
+    SDA: SdaPin<R>,
+{
+    /// Initialize an I2C peripheral, as in `new()`, with type-state pins. This checks at compile
+    /// time that the pins are set to this peripheral's SCL and SDA alternate functions, with
+    /// open-drain outputs. The pins are held until `free()`.
+    pub fn new_with_pins(
+        regs: R,
+        pins: (SCL, SDA),
+        device: I2cDevice,
+        speed: u32,
+        clocks: &Clocks,
+    ) -> Self {
+        let i2c = I2c::new(regs, device, speed, clocks);
+
+        Self {
+            regs: i2c.regs,
+            device: i2c.device,
+            pins,
+        }
+    }
+}
+
+impl<R, PINS> I2c<R, PINS>
+where
+    R: Deref<Target = i2c1::RegisterBlock>,
+{
+    /// Release the peripheral's registers, and its pins if it was created with `new_with_pins()`.
+    pub fn free(self) -> (R, PINS) {
+        (self.regs, self.pins)
+    }
 
     fn i2c_init(&self, speed: u32, pclk: u32) {
         // Make sure the I2C unit is disabled so we can configure it
@@ -250,11 +294,146 @@ where
         let value = self.regs.dr.read().bits() as u8;
         Ok(value)
     }
+
+    /// Send a START condition, and the slave address, then clear the ADDR flag.
+    fn start_dma(&mut self, addr: u8, read: bool) -> Result<(), Error> {
+        self.regs.cr1.modify(|_, w| w.start().set_bit());
+        while self.check_and_clear_error_flags()?.sb().bit_is_clear() {}
+
+        self.regs
+            .dr
+            .write(|w| unsafe { w.bits((u32::from(addr) << 1) + read as u32) });
+
+        // If a NACK occurs, the ADDR bit will never be set.
+        while self.check_and_clear_error_flags()?.addr().bit_is_clear() {}
+
+        // Clear ADDR by reading SR2. The DMA starts serving requests after this.
+        self.regs.sr2.read();
+
+        Ok(())
+    }
+
+    /// Write data to a slave, using DMA. See F4 RM0090, section 27.3.7: "DMA requests". `channel`
+    /// is the DMA1 stream to use, eg stream 6 or 7 for I2C1. The start condition and address are
+    /// sent before returning; once the transfer is complete, call `stop_dma` to send the STOP.
+    /// Returns a `Transfer` that holds `buf` until the transfer is complete.
+    pub fn write_dma<B, D>(
+        &mut self,
+        addr: u8,
+        buf: B,
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma: &mut Dma<D>,
+    ) -> Result<Transfer<B>, Error>
+    where
+        B: ReadBuffer<Word = u8>,
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        let (ptr, len) = unsafe { buf.read_buffer() };
+
+        let input = match self.device {
+            I2cDevice::One => DmaInput::I2c1Tx,
+            I2cDevice::Two => DmaInput::I2c2Tx,
+            #[cfg(not(feature = "f410"))]
+            I2cDevice::Three => DmaInput::I2c3Tx,
+        };
+        dma.channel_select(channel, input);
+
+        dma.cfg_channel(
+            channel,
+            &self.regs.dr as *const _ as u32,
+            ptr as u32,
+            len as u16,
+            dma::Direction::ReadFromMem,
+            dma::DataSize::S8,
+            dma::DataSize::S8,
+            channel_cfg,
+        );
+
+        // DMA requests are generated when the data register becomes empty, once DMAEN is set.
+        self.regs.cr2.modify(|_, w| w.dmaen().set_bit());
+
+        self.start_dma(addr, false)?;
+
+        Ok(Transfer::new(buf, channel, dma.periph))
+    }
+
+    /// Read data from a slave, using DMA. See F4 RM0090, section 27.3.7: "DMA requests". `buf`
+    /// must be at least 2 bytes long. `channel` is the DMA1 stream to use, eg stream 0 or 5 for
+    /// I2C1. The last byte is NACKed automatically; once the transfer is complete, call
+    /// `stop_dma` to send the STOP. Returns a `Transfer` that holds `buf` until the transfer is
+    /// complete.
+    pub fn read_dma<B, D>(
+        &mut self,
+        addr: u8,
+        mut buf: B,
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma: &mut Dma<D>,
+    ) -> Result<Transfer<B>, Error>
+    where
+        B: WriteBuffer<Word = u8>,
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        let (ptr, len) = unsafe { buf.write_buffer() };
+
+        assert!(len >= 2, "DMA reads must be at least 2 bytes long.");
+
+        let input = match self.device {
+            I2cDevice::One => DmaInput::I2c1Rx,
+            I2cDevice::Two => DmaInput::I2c2Rx,
+            #[cfg(not(feature = "f410"))]
+            I2cDevice::Three => DmaInput::I2c3Rx,
+        };
+        dma.channel_select(channel, input);
+
+        dma.cfg_channel(
+            channel,
+            &self.regs.dr as *const _ as u32,
+            ptr as u32,
+            len as u16,
+            dma::Direction::ReadFromPeriph,
+            dma::DataSize::S8,
+            dma::DataSize::S8,
+            channel_cfg,
+        );
+
+        // Setting LAST makes the peripheral NACK the byte that ends the DMA transfer.
+        self.regs.cr1.modify(|_, w| w.ack().set_bit());
+        self.regs
+            .cr2
+            .modify(|_, w| w.dmaen().set_bit().last().set_bit());
+
+        self.start_dma(addr, true)?;
+
+        Ok(Transfer::new(buf, channel, dma.periph))
+    }
+
+    /// Finish a DMA transfer started with `write_dma` or `read_dma`: Stop the stream, wait for
+    /// the last byte of a write to finish sending, then send a STOP condition. Run this after
+    /// each transfer completes, eg in the DMA transfer complete interrupt.
+    pub fn stop_dma<D>(&mut self, channel: DmaChannel, dma: &mut Dma<D>)
+    where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        dma.stop(channel);
+
+        if self.regs.sr2.read().tra().bit_is_set() {
+            while self.regs.sr1.read().btf().bit_is_clear() {}
+        }
+
+        self.regs
+            .cr2
+            .modify(|_, w| w.dmaen().clear_bit().last().clear_bit());
+
+        self.regs.cr1.modify(|_, w| w.stop().set_bit());
+        while self.regs.cr1.read().stop().bit_is_set() {}
+    }
 }
 
 #[cfg(feature = "embedded-hal")]
 #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
-impl<R> WriteRead for I2c<R>
+impl<R, PINS> WriteRead for I2c<R, PINS>
 where
     R: Deref<Target = i2c1::RegisterBlock>,
 {
@@ -270,7 +449,7 @@ where
 
 #[cfg(feature = "embedded-hal")]
 #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
-impl<R> Write for I2c<R>
+impl<R, PINS> Write for I2c<R, PINS>
 where
     R: Deref<Target = i2c1::RegisterBlock>,
 {
@@ -292,7 +471,7 @@ where
 
 #[cfg(feature = "embedded-hal")]
 #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
-impl<R> Read for I2c<R>
+impl<R, PINS> Read for I2c<R, PINS>
 where
     R: Deref<Target = i2c1::RegisterBlock>,
 {
@@ -118,7 +118,8 @@
 // Using `unsafe` for all is cleaner than feature-gating, due to how many fields this affects. We've allowed
 // these warnings; ie hidden during build.
 
-#![no_std]
+// Unit tests of pure logic, eg clock validation, run on the host with `std`.
+#![cfg_attr(not(test), no_std)]
 // Some reg modifications are marked `unsafe` in some PAC crates, but not others.
 // Disable these warnings.
 #![allow(unused_unsafe)]
@@ -391,6 +392,14 @@ pub mod clocks;
     feature = "wl"
 )))]
 pub mod crc;
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "h7"
+))]
+pub mod crs;
 #[cfg(not(any(
     feature = "f401",
     feature = "f411",
@@ -417,7 +426,6 @@ pub mod dac;
 )))]
 pub mod dfsdm;
 
-#[cfg(not(any(feature = "f4", feature = "l552")))]
 pub mod dma;
 
 #[cfg(all(feature = "h7", feature = "net"))]
@@ -10,6 +10,15 @@ use crate::pac;
 #[cfg(any(feature = "l4", feature = "l5"))]
 use crate::clocks::{Clocks, MsiRange};
 
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl"
+))]
+use crate::gpio::{Port, Pull};
+
 use cortex_m::{asm::wfi, Peripherals};
 
 use cfg_if::cfg_if;
@@ -28,7 +37,7 @@ pub enum StopMode {
 /// L4 RM, table 24
 /// This assumes you're using MSI as the clock source, and changes speed by lowering the MSI speed.
 /// You must select an MSI speed of 2Mhz or lower. Note that you may need to adjust peripheral
-/// implementations that rely on system clock or APB speed.
+/// implementations that rely on system clock or APB speed, eg with their `retime` methods.
 #[cfg(any(feature = "l4", feature = "l5"))]
 pub fn low_power_run(clocks: &mut Clocks, speed: MsiRange) {
     let rcc = unsafe { &(*pac::RCC::ptr()) };
@@ -45,7 +54,7 @@ pub fn low_power_run(clocks: &mut Clocks, speed: MsiRange) {
 
 /// L4 RM, table 24
 /// Return to normal run mode from low-power run. Requires you to increase the clock speed
-/// manually after running this.
+/// manually after running this. `Clocks::switch_to` runs this automatically.
 #[cfg(any(feature = "l4", feature = "l5"))]
 pub fn return_from_low_power_run() {
     let pwr = unsafe { &(*PWR::ptr()) };
@@ -338,6 +347,78 @@ cfg_if! {
     }
 }
 
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl"
+))]
+/// Set or clear one pin's bit in a pair of PWR pull-up and pull-down control registers.
+macro_rules! set_pwr_pull {
+    ($pwr:expr, $pucr:ident, $pdcr:ident, $pin:expr, $pull:expr) => {{
+        let up = matches!($pull, Pull::Up);
+        let dn = matches!($pull, Pull::Dn);
+
+        $pwr.$pucr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(1 << $pin)) | ((up as u32) << $pin)) });
+        $pwr.$pdcr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(1 << $pin)) | ((dn as u32) << $pin)) });
+    }};
+}
+
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl"
+))]
+/// Configure the pull resistor PWR applies to a pin in Standby and Shutdown modes, when
+/// GPIO configuration is lost. Sets the `PWR_PUCRx` and `PWR_PDCRx` registers. These only take
+/// effect once enabled with `enable_standby_pulls()`. L4 RM, section 5.4.12 - 5.4.13.
+pub fn set_standby_pull(port: Port, pin: u8, pull: Pull) {
+    assert!(pin <= 15, "Pin must be 0 - 15.");
+    let pwr = unsafe { &(*PWR::ptr()) };
+
+    match port {
+        Port::A => set_pwr_pull!(pwr, pucra, pdcra, pin, pull),
+        Port::B => set_pwr_pull!(pwr, pucrb, pdcrb, pin, pull),
+        Port::C => set_pwr_pull!(pwr, pucrc, pdcrc, pin, pull),
+        #[cfg(not(feature = "wl"))]
+        Port::D => set_pwr_pull!(pwr, pucrd, pdcrd, pin, pull),
+        #[cfg(not(any(feature = "wb", feature = "wl")))]
+        Port::E => set_pwr_pull!(pwr, pucre, pdcre, pin, pull),
+        #[cfg(not(any(
+            feature = "l4x1",
+            feature = "l4x2",
+            feature = "l412",
+            feature = "l4x3",
+            feature = "wb",
+            feature = "wl"
+        )))]
+        Port::F => set_pwr_pull!(pwr, pucrf, pdcrf, pin, pull),
+        #[cfg(feature = "l5")]
+        Port::G => set_pwr_pull!(pwr, pucrg, pdcrg, pin, pull),
+        #[cfg(any(feature = "l5", feature = "wl"))]
+        Port::H => set_pwr_pull!(pwr, pucrh, pdcrh, pin, pull),
+    }
+}
+
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl"
+))]
+/// Apply (or stop applying) the pulls set with `set_standby_pull()` in Standby and Shutdown
+/// modes. Sets the `PWR_CR3` register, `APC` field.
+pub fn enable_standby_pulls(enabled: bool) {
+    let pwr = unsafe { &(*PWR::ptr()) };
+    pwr.cr3.modify(|_, w| w.apc().bit(enabled));
+}
+
 /// This function is used by both `sleep_now` (non-H7), and `csleep` (H7), so that the names
 /// can correctly reflect functionality.
 fn sleep() {
@@ -219,8 +219,19 @@ impl Qspi {
         // 2: FCLK = F/3
         // ...
         // 255: FCLK = F/256
-        // todo: What bus is QSPI on? is it selectable? SAI etc?? APB2 as placeholder.
-        let prescaler = match (clocks.apb2() + cfg.frequency - 1) / cfg.frequency {
+        // The QSPI kernel clock is HCLK on L4. It's selectable on H7, and G4, where we leave
+        // it at its reset value of SYSCLK.
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                let ker_ck = clocks.qspi_speed();
+            } else if #[cfg(feature = "g4")] {
+                let ker_ck = clocks.sysclk();
+            } else {
+                let ker_ck = clocks.hclk();
+            }
+        }
+
+        let prescaler = match (ker_ck + cfg.frequency - 1) / cfg.frequency {
             divisor @ 1..=256 => divisor - 1,
             _ => panic!("Invalid QSPI frequency requested"),
         };
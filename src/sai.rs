@@ -24,8 +24,10 @@ use crate::pac::dma as dma_p;
 ))]
 use crate::pac::dma1 as dma_p;
 
-#[cfg(not(any(feature = "f4", feature = "l552")))]
-use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
+use crate::dma::{self, ChannelCfg, Dma, DmaChannel, ReadBuffer, Transfer, WriteBuffer};
+
+#[cfg(feature = "h7")]
+use crate::dma::{Bdma, DmaPeriph};
 
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::dma::DmaInput;
@@ -976,30 +978,33 @@ It can generate an interrupt if WCKCFGIE bit is set in SAI_xIM register");
     /// There is one DMA channel per audio subblock supporting basic DMA request/acknowledge
     /// protocol.
     /// Before configuring the SAI block, the SAI DMA channel must be disabled.
-    #[cfg(not(any(feature = "f4", feature = "l552")))]
-    pub unsafe fn write_dma<D>(
+    /// Returns a `Transfer` that holds `buf` until the transfer is complete.
+    pub fn write_dma<B, D>(
         &mut self,
-        buf: &[i32], // todo size?
+        buf: B, // todo size?
         sai_channel: SaiChannel,
         dma_channel: DmaChannel,
         channel_cfg: ChannelCfg,
         dma: &mut Dma<D>,
-    ) where
+    ) -> Transfer<B>
+    where
+        B: ReadBuffer<Word = i32>,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
-        let (ptr, len) = (buf.as_ptr(), buf.len());
-
-        // todo: DMA2 support.
+        let (ptr, len) = unsafe { buf.read_buffer() };
 
         // L44 RM, Table 41. "DMA1 requests for each channel"
-        #[cfg(any(feature = "f3", feature = "l4"))]
-        let dma_channel = match sai_channel {
-            SaiChannel::A => DmaInput::Sai1A.dma1_channel(),
-            SaiChannel::B => DmaInput::Sai1B.dma1_channel(),
-        };
+        #[cfg(feature = "f3")]
+        let dma_channel = dma.select_channel(
+            match sai_channel {
+                SaiChannel::A => DmaInput::Sai1A.dma1_channel(),
+                SaiChannel::B => DmaInput::Sai1B.dma1_channel(),
+            },
+            dma_channel,
+        );
 
         #[cfg(feature = "l4")]
-        match sai_channel {
+        let dma_channel = match sai_channel {
             SaiChannel::A => dma.channel_select(DmaInput::Sai1A),
             SaiChannel::B => dma.channel_select(DmaInput::Sai1B),
         };
@@ -1012,10 +1017,7 @@ It can generate an interrupt if WCKCFGIE bit is set in SAI_xIM register");
         // DMA request to load the FIFO with data written in the SAI_xDR register.
         // • If the audio block is operates as a receiver, the DMA request is related to read
         // operations from the SAI_xDR register.
-        match sai_channel {
-            SaiChannel::A => self.regs.cha().cr1.modify(|_, w| w.dmaen().set_bit()),
-            SaiChannel::B => self.regs.chb().cr1.modify(|_, w| w.dmaen().set_bit()),
-        }
+        let (periph_addr, datasize) = self.enable_dma(sai_channel);
 
         // Follow the sequence below to configure the SAI interface in DMA mode:
         // 1. Configure SAI and FIFO threshold levels to specify when the DMA request will be
@@ -1024,28 +1026,11 @@ It can generate an interrupt if WCKCFGIE bit is set in SAI_xIM register");
         // 2. Configure SAI DMA channel. (handled by `dma.cfg_channel`)
         // 3. Enable the DMA. (handled by `dma.cfg_channel`)
 
-        let periph_addr = match sai_channel {
-            SaiChannel::A => &self.regs.cha().dr as *const _ as u32,
-            SaiChannel::B => &self.regs.chb().dr as *const _ as u32,
-        };
-
         #[cfg(feature = "h7")]
         let len = len as u32;
         #[cfg(not(feature = "h7"))]
         let len = len as u16;
 
-        let cfg_datasize = match sai_channel {
-            SaiChannel::A => self.config_a.datasize,
-            SaiChannel::B => self.config_b.datasize,
-        };
-
-        let datasize = match cfg_datasize {
-            DataSize::S8 => dma::DataSize::S8,
-            DataSize::S10 => dma::DataSize::S16,
-            DataSize::S16 => dma::DataSize::S16,
-            _ => dma::DataSize::S32,
-        };
-
         dma.cfg_channel(
             dma_channel,
             periph_addr,
@@ -1058,43 +1043,141 @@ It can generate an interrupt if WCKCFGIE bit is set in SAI_xIM register");
         );
 
         // 4. Enable the SAI interface. (handled by `Sai::enable() in user code`.)
+
+        Transfer::new(buf, dma_channel, dma.periph)
     }
 
     /// Read data from SAI with DMA. H743 RM, section 51.4.16: SAI DMA Interface.
     /// To free the CPU and to optimize bus bandwidth, each SAI audio block has an independent
     /// DMA interface to read/write from/to the SAI_xDR register (to access the internal FIFO).
     /// There is one DMA channel per audio subblock supporting basic DMA request/acknowledge
-    /// protocol.
-    #[cfg(not(any(feature = "f4", feature = "l552")))]
-    pub unsafe fn read_dma<D>(
+    /// protocol. Returns a `Transfer` that holds `buf` until the transfer is complete.
+    pub fn read_dma<B, D>(
         &mut self,
-        buf: &mut [i32], // todo size?
+        mut buf: B, // todo size?
         sai_channel: SaiChannel,
         dma_channel: DmaChannel,
         channel_cfg: ChannelCfg,
         dma: &mut Dma<D>,
-    ) where
+    ) -> Transfer<B>
+    where
+        B: WriteBuffer<Word = i32>,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
-        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+        let (ptr, len) = unsafe { buf.write_buffer() };
 
         // See commends on `write_dma`.
 
         // L44 RM, Table 41. "DMA1 requests for each channel
-        // todo: DMA2 support.
-
-        #[cfg(any(feature = "f3", feature = "l4"))]
-        let dma_channel = match sai_channel {
-            SaiChannel::A => DmaInput::Sai1A.dma1_channel(),
-            SaiChannel::B => DmaInput::Sai1B.dma1_channel(),
-        };
+        #[cfg(feature = "f3")]
+        let dma_channel = dma.select_channel(
+            match sai_channel {
+                SaiChannel::A => DmaInput::Sai1A.dma1_channel(),
+                SaiChannel::B => DmaInput::Sai1B.dma1_channel(),
+            },
+            dma_channel,
+        );
 
         #[cfg(feature = "l4")]
-        match sai_channel {
+        let dma_channel = match sai_channel {
             SaiChannel::A => dma.channel_select(DmaInput::Sai1A),
             SaiChannel::B => dma.channel_select(DmaInput::Sai1B),
         };
 
+        let (periph_addr, datasize) = self.enable_dma(sai_channel);
+
+        #[cfg(feature = "h7")]
+        let num_data = len as u32;
+        #[cfg(not(feature = "h7"))]
+        let num_data = len as u16;
+
+        dma.cfg_channel(
+            dma_channel,
+            periph_addr,
+            ptr as u32,
+            num_data,
+            dma::Direction::ReadFromPeriph,
+            datasize,
+            datasize,
+            channel_cfg,
+        );
+
+        // 4. Enable the SAI interface. (handled by `Sai::enable() in user code`.)
+
+        Transfer::new(buf, dma_channel, dma.periph)
+    }
+
+    #[cfg(feature = "h7")]
+    /// Send data over SAI4 with BDMA; SAI4 is in the D3 domain, so DMA1 and DMA2 can't reach it.
+    /// Route the request to `dma_channel` with `dma::mux2()` and `DmaInput2::Sai4A` or `Sai4B`
+    /// first. `buf` must be in SRAM4. Otherwise works like `write_dma`.
+    pub fn write_bdma<B>(
+        &mut self,
+        buf: B,
+        sai_channel: SaiChannel,
+        dma_channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma: &mut Bdma,
+    ) -> Transfer<B>
+    where
+        B: ReadBuffer<Word = i32>,
+    {
+        self.check_bdma();
+        let (ptr, len) = unsafe { buf.read_buffer() };
+
+        let (periph_addr, datasize) = self.enable_dma(sai_channel);
+
+        dma.cfg_channel(
+            dma_channel,
+            periph_addr,
+            ptr as u32,
+            dma::bdma_num_data(len),
+            dma::Direction::ReadFromMem,
+            datasize,
+            datasize,
+            channel_cfg,
+        );
+
+        Transfer::new(buf, dma_channel, DmaPeriph::Bdma)
+    }
+
+    #[cfg(feature = "h7")]
+    /// Read data from SAI4 with BDMA. Route the request to `dma_channel` with `dma::mux2()` and
+    /// `DmaInput2::Sai4A` or `Sai4B` first. `buf` must be in SRAM4. Otherwise works like
+    /// `read_dma`.
+    pub fn read_bdma<B>(
+        &mut self,
+        mut buf: B,
+        sai_channel: SaiChannel,
+        dma_channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma: &mut Bdma,
+    ) -> Transfer<B>
+    where
+        B: WriteBuffer<Word = i32>,
+    {
+        self.check_bdma();
+        let (ptr, len) = unsafe { buf.write_buffer() };
+
+        let (periph_addr, datasize) = self.enable_dma(sai_channel);
+
+        dma.cfg_channel(
+            dma_channel,
+            periph_addr,
+            ptr as u32,
+            dma::bdma_num_data(len),
+            dma::Direction::ReadFromPeriph,
+            datasize,
+            datasize,
+            channel_cfg,
+        );
+
+        Transfer::new(buf, dma_channel, DmaPeriph::Bdma)
+    }
+
+    /// Set a subblock's DMAEN bit, and find its data register address, and the DMA data size
+    /// matching its configured data size.
+    fn enable_dma(&mut self, sai_channel: SaiChannel) -> (u32, dma::DataSize) {
         match sai_channel {
             SaiChannel::A => self.regs.cha().cr1.modify(|_, w| w.dmaen().set_bit()),
             SaiChannel::B => self.regs.chb().cr1.modify(|_, w| w.dmaen().set_bit()),
@@ -1105,11 +1188,6 @@ It can generate an interrupt if WCKCFGIE bit is set in SAI_xIM register");
             SaiChannel::B => &self.regs.chb().dr as *const _ as u32,
         };
 
-        #[cfg(feature = "h7")]
-        let num_data = len as u32;
-        #[cfg(not(feature = "h7"))]
-        let num_data = len as u16;
-
         let cfg_datasize = match sai_channel {
             SaiChannel::A => self.config_a.datasize,
             SaiChannel::B => self.config_b.datasize,
@@ -1122,18 +1200,16 @@ It can generate an interrupt if WCKCFGIE bit is set in SAI_xIM register");
             _ => dma::DataSize::S32,
         };
 
-        dma.cfg_channel(
-            dma_channel,
-            periph_addr,
-            ptr as u32,
-            num_data,
-            dma::Direction::ReadFromPeriph,
-            datasize,
-            datasize,
-            channel_cfg,
-        );
+        (periph_addr, datasize)
+    }
 
-        // 4. Enable the SAI interface. (handled by `Sai::enable() in user code`.)
+    #[cfg(feature = "h7")]
+    /// BDMA only serves SAI4.
+    fn check_bdma(&self) {
+        assert!(
+            &*self.regs as *const _ == crate::pac::SAI4::ptr(),
+            "BDMA is only available for SAI4."
+        );
     }
 
     /// Enable a specific type of interrupt. See L4 RM, Table 220: "SAI interrupt sources".
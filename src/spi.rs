@@ -10,8 +10,10 @@ use cortex_m::interrupt::free;
 use embedded_hal::spi::FullDuplex;
 
 use crate::{
+    clocks::Clocks,
+    gpio::typestate::{MisoPin, MosiPin, SckPin},
     pac::{self, RCC},
-    util::RccPeriph,
+    util::{ClockPeriph, RccPeriph},
 };
 
 use cfg_if::cfg_if;
@@ -19,13 +21,17 @@ use cfg_if::cfg_if;
 cfg_if! {
     if #[cfg(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1"))))] {
         use crate::pac::dma as dma_p;
+    } else if #[cfg(feature = "f4")] {
+        use crate::pac::dma2 as dma_p;
     } else {
         use crate::pac::dma1 as dma_p;
     }
 }
 
-#[cfg(not(any(feature = "f4", feature = "l552")))]
-use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
+use crate::dma::{self, ChannelCfg, Dma, DmaChannel, ReadBuffer, Transfer, WriteBuffer};
+
+#[cfg(feature = "h7")]
+use crate::dma::{bdma_num_data, Bdma, DmaPeriph};
 
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::dma::DmaInput;
@@ -290,14 +296,15 @@ impl Default for SpiConfig {
 }
 
 /// Represents a Serial Peripheral Interface (SPI) peripheral.
-pub struct Spi<R> {
+pub struct Spi<R, PINS = ()> {
     pub regs: R,
     pub cfg: SpiConfig,
+    pins: PINS,
 }
 
 impl<R> Spi<R>
 where
-    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph + ClockPeriph,
 {
     /// Initialize an SPI peripheral, including configuration register writes, and enabling and resetting
     /// its RCC peripheral clock.
@@ -436,7 +443,47 @@ where
             // todo: This lets you use hardware CS management, and seems to be teh way the RM
             // todo steers you towards regardless.
         }
-        Spi { regs, cfg }
+        Spi {
+            regs,
+            cfg,
+            pins: (),
+        }
+    }
+}
+
+impl<R, SCK, MISO, MOSI> Spi<R, (SCK, MISO, MOSI)>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph + ClockPeriph,
+    SCK: SckPin<R>,
+    MISO: MisoPin<R>,
+    MOSI: MosiPin<R>,
+{
+    /// Initialize an SPI peripheral, as in `new()`, with type-state pins. This checks at compile
+    /// time that the pins are set to this peripheral's SCK, MISO, and MOSI alternate functions.
+    /// The pins are held until `free()`.
+    pub fn new_with_pins(
+        regs: R,
+        pins: (SCK, MISO, MOSI),
+        cfg: SpiConfig,
+        baud_rate: BaudRate,
+    ) -> Self {
+        let spi = Spi::new(regs, cfg, baud_rate);
+
+        Self {
+            regs: spi.regs,
+            cfg: spi.cfg,
+            pins,
+        }
+    }
+}
+
+impl<R, PINS> Spi<R, PINS>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph + ClockPeriph,
+{
+    /// Release the peripheral's registers, and its pins if it was created with `new_with_pins()`.
+    pub fn free(self) -> (R, PINS) {
+        (self.regs, self.pins)
     }
 
     /// Change the SPI baud rate.
@@ -458,6 +505,43 @@ where
         self.regs.cr1.modify(|_, w| w.spe().set_bit());
     }
 
+    /// Get the SCK frequency, in hz, from the SPI kernel clock and the baud rate currently set.
+    pub fn sck_speed(&self, clock_cfg: &Clocks) -> u32 {
+        #[cfg(feature = "h7")]
+        let br = self.regs.cfg1.read().mbr().bits();
+        #[cfg(not(feature = "h7"))]
+        let br = self.regs.cr1.read().br().bits();
+
+        // `BaudRate` divides by 2^(BR + 1).
+        R::kernel_speed(clock_cfg) >> (br + 1)
+    }
+
+    /// Select a new baud rate prescaler after a clock change, eg from `Clocks::switch_to`. This
+    /// picks the fastest SCK that isn't faster than the one under `prev_clocks`. Prescalers are
+    /// powers of 2, so SCK will be lower than before if the kernel clock didn't change by a
+    /// power of 2.
+    pub fn retime(&mut self, prev_clocks: &Clocks, clock_cfg: &Clocks) {
+        let sck_prev = self.sck_speed(prev_clocks);
+        let kernel_speed = R::kernel_speed(clock_cfg);
+
+        let baud_rate = [
+            BaudRate::Div2,
+            BaudRate::Div4,
+            BaudRate::Div8,
+            BaudRate::Div16,
+            BaudRate::Div32,
+            BaudRate::Div64,
+            BaudRate::Div128,
+            BaudRate::Div256,
+        ]
+        .iter()
+        .find(|br| kernel_speed >> (**br as u8 + 1) <= sck_prev)
+        .copied()
+        .unwrap_or(BaudRate::Div256);
+
+        self.reclock(baud_rate);
+    }
+
     /// L44 RM, section 40.4.9: "Procedure for disabling the SPI"
     /// When SPI is disabled, it is mandatory to follow the disable procedures described in this
     /// paragraph. It is important to do this before the system enters a low-power mode when the
@@ -592,19 +676,21 @@ where
     }
 
     /// Transmit data using DMA. See L44 RM, section 40.4.9: Communication using DMA.
-    /// Note that the `channel` argument has no effect on F3 and L4.
-    #[cfg(not(any(feature = "f4", feature = "l552")))]
-    pub unsafe fn write_dma<D>(
+    /// On F3, the `channel` argument has no effect with DMA1, and on L4, it has no effect. On F4,
+    /// it's the stream to use. Returns a `Transfer` that holds `buf` until the transfer is
+    /// complete; call `stop_dma` after.
+    pub fn write_dma<B, D>(
         &mut self,
-        buf: &[u8],
+        buf: B,
         channel: DmaChannel,
         channel_cfg: ChannelCfg,
         dma: &mut Dma<D>,
-    ) where
+    ) -> Transfer<B>
+    where
+        B: ReadBuffer<Word = u8>,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
-        // Static write and read buffers?
-        let (ptr, len) = (buf.as_ptr(), buf.len());
+        let (ptr, len) = unsafe { buf.read_buffer() };
 
         self.regs.cr1.modify(|_, w| w.spe().clear_bit());
 
@@ -624,10 +710,15 @@ where
         // (N/A)
 
         // 2. Enable DMA streams for Tx and Rx in DMA registers, if the streams are used.
-        #[cfg(any(feature = "f3", feature = "l4"))]
-        let channel = R::write_chan();
+        #[cfg(feature = "f3")]
+        let channel = dma.select_channel(R::write_chan(), channel);
         #[cfg(feature = "l4")]
-        R::write_sel(dma);
+        let channel = R::write_sel(dma);
+        #[cfg(feature = "f4")]
+        dma.channel_select(
+            channel,
+            R::write_input().expect("This peripheral has no DMA write request."),
+        );
 
         #[cfg(feature = "h7")]
         let periph_addr = &self.regs.txdr as *const _ as u32;
@@ -658,22 +749,27 @@ where
 
         // 4. Enable the SPI by setting the SPE bit.
         self.regs.cr1.modify(|_, w| w.spe().set_bit());
+
+        Transfer::new(buf, channel, dma.periph)
     }
 
     /// Receive data using DMA. See L44 RM, section 40.4.9: Communication using DMA.
-    /// Note thay the `channel` argument has no effect on F3 and L4.
-    #[cfg(not(any(feature = "f4", feature = "l552")))]
-    pub unsafe fn read_dma<D>(
+    /// On F3, the `channel` argument has no effect with DMA1, and on L4, it has no effect. On F4,
+    /// it's the stream to use. Returns a `Transfer` that holds `buf` until the transfer is
+    /// complete; call `stop_dma` after.
+    pub fn read_dma<B, D>(
         &mut self,
-        buf: &mut [u8],
+        mut buf: B,
         channel: DmaChannel,
         channel_cfg: ChannelCfg,
         dma: &mut Dma<D>,
-    ) where
+    ) -> Transfer<B>
+    where
+        B: WriteBuffer<Word = u8>,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
         // todo: Accept u16 words too.
-        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+        let (ptr, len) = unsafe { buf.write_buffer() };
 
         self.regs.cr1.modify(|_, w| w.spe().clear_bit());
 
@@ -682,10 +778,15 @@ where
         #[cfg(feature = "h7")]
         self.regs.cfg1.modify(|_, w| w.rxdmaen().set_bit());
 
-        #[cfg(any(feature = "f3", feature = "l4"))]
-        let channel = R::read_chan();
+        #[cfg(feature = "f3")]
+        let channel = dma.select_channel(R::read_chan(), channel);
         #[cfg(feature = "l4")]
-        R::read_sel(dma);
+        let channel = R::read_sel(dma);
+        #[cfg(feature = "f4")]
+        dma.channel_select(
+            channel,
+            R::read_input().expect("This peripheral has no DMA read request."),
+        );
 
         #[cfg(feature = "h7")]
         let periph_addr = &self.regs.rxdr as *const _ as u32;
@@ -709,26 +810,111 @@ where
         );
 
         self.regs.cr1.modify(|_, w| w.spe().set_bit());
+
+        Transfer::new(buf, channel, dma.periph)
+    }
+
+    #[cfg(feature = "h7")]
+    /// Transmit data on SPI6 using BDMA; SPI6 is in the D3 domain, so DMA1 and DMA2 can't reach
+    /// it. Route the request to `channel` with `dma::mux2()` and `DmaInput2::Spi6Tx` first. `buf`
+    /// must be in SRAM4. Otherwise works like `write_dma`.
+    pub fn write_bdma<B>(
+        &mut self,
+        buf: B,
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma: &mut Bdma,
+    ) -> Transfer<B>
+    where
+        B: ReadBuffer<Word = u8>,
+    {
+        self.check_bdma();
+        let (ptr, len) = unsafe { buf.read_buffer() };
+
+        self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+
+        dma.cfg_channel(
+            channel,
+            &self.regs.txdr as *const _ as u32,
+            ptr as u32,
+            bdma_num_data(len),
+            dma::Direction::ReadFromMem,
+            dma::DataSize::S8,
+            dma::DataSize::S8,
+            channel_cfg,
+        );
+
+        self.regs.cfg1.modify(|_, w| w.txdmaen().set_bit());
+        self.regs.cr1.modify(|_, w| w.spe().set_bit());
+
+        Transfer::new(buf, channel, DmaPeriph::Bdma)
+    }
+
+    #[cfg(feature = "h7")]
+    /// Receive data on SPI6 using BDMA. Route the request to `channel` with `dma::mux2()` and
+    /// `DmaInput2::Spi6Rx` first. `buf` must be in SRAM4. Otherwise works like `read_dma`.
+    pub fn read_bdma<B>(
+        &mut self,
+        mut buf: B,
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma: &mut Bdma,
+    ) -> Transfer<B>
+    where
+        B: WriteBuffer<Word = u8>,
+    {
+        self.check_bdma();
+        let (ptr, len) = unsafe { buf.write_buffer() };
+
+        self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+        self.regs.cfg1.modify(|_, w| w.rxdmaen().set_bit());
+
+        dma.cfg_channel(
+            channel,
+            &self.regs.rxdr as *const _ as u32,
+            ptr as u32,
+            bdma_num_data(len),
+            dma::Direction::ReadFromPeriph,
+            dma::DataSize::S8,
+            dma::DataSize::S8,
+            channel_cfg,
+        );
+
+        self.regs.cr1.modify(|_, w| w.spe().set_bit());
+
+        Transfer::new(buf, channel, DmaPeriph::Bdma)
+    }
+
+    #[cfg(feature = "h7")]
+    /// BDMA only serves SPI6.
+    fn check_bdma(&self) {
+        assert!(
+            &*self.regs as *const _ == pac::SPI6::ptr() as *const _,
+            "BDMA is only available for SPI6."
+        );
     }
 
     /// Transfer data from DMA; this is the basic reading API, using both write and read transfers:
-    /// It performs a write with register data, and reads to a buffer.
-    #[cfg(not(any(feature = "f4", feature = "l552")))]
-    pub unsafe fn transfer_dma<D>(
+    /// It performs a write with register data, and reads to a buffer. Returns the write and read
+    /// `Transfer`s, in that order. The read transfer completes last.
+    pub fn transfer_dma<BW, BR, D>(
         &mut self,
-        buf_write: &[u8],
-        buf_read: &mut [u8],
+        buf_write: BW,
+        mut buf_read: BR,
         channel_write: DmaChannel,
         channel_read: DmaChannel,
         channel_cfg_write: ChannelCfg,
         channel_cfg_read: ChannelCfg,
         dma: &mut Dma<D>,
-    ) where
+    ) -> (Transfer<BW>, Transfer<BR>)
+    where
+        BW: ReadBuffer<Word = u8>,
+        BR: WriteBuffer<Word = u8>,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
         // todo: Accept u16 words too.
-        let (ptr_write, len_write) = (buf_write.as_ptr(), buf_write.len());
-        let (ptr_read, len_read) = (buf_read.as_mut_ptr(), buf_read.len());
+        let (ptr_write, len_write) = unsafe { buf_write.read_buffer() };
+        let (ptr_read, len_read) = unsafe { buf_read.write_buffer() };
 
         self.regs.cr1.modify(|_, w| w.spe().clear_bit());
 
@@ -761,10 +947,15 @@ where
         #[cfg(feature = "h7")]
         self.regs.cfg1.modify(|_, w| w.rxdmaen().set_bit());
 
-        #[cfg(any(feature = "f3", feature = "l4"))]
-        let channel_write = R::write_chan();
+        #[cfg(feature = "f3")]
+        let channel_write = dma.select_channel(R::write_chan(), channel_write);
         #[cfg(feature = "l4")]
-        R::write_sel(dma);
+        let channel_write = R::write_sel(dma);
+        #[cfg(feature = "f4")]
+        dma.channel_select(
+            channel_write,
+            R::write_input().expect("This peripheral has no DMA write request."),
+        );
 
         dma.cfg_channel(
             channel_write,
@@ -777,10 +968,15 @@ where
             channel_cfg_write,
         );
 
-        #[cfg(any(feature = "f3", feature = "l4"))]
-        let channel_read = R::read_chan();
+        #[cfg(feature = "f3")]
+        let channel_read = dma.select_channel(R::read_chan(), channel_read);
         #[cfg(feature = "l4")]
-        R::read_sel(dma);
+        let channel_read = R::read_sel(dma);
+        #[cfg(feature = "f4")]
+        dma.channel_select(
+            channel_read,
+            R::read_input().expect("This peripheral has no DMA read request."),
+        );
 
         dma.cfg_channel(
             channel_read,
@@ -799,13 +995,17 @@ where
         self.regs.cfg1.modify(|_, w| w.txdmaen().set_bit());
 
         self.regs.cr1.modify(|_, w| w.spe().set_bit());
+
+        (
+            Transfer::new(buf_write, channel_write, dma.periph),
+            Transfer::new(buf_read, channel_read, dma.periph),
+        )
     }
 
     /// Stop a DMA transfer. Stops the channel, and disables the `txdmaen` and `rxdmaen` bits.
     /// Run this after each transfer completes - you may wish to do this in an interrupt
     /// (eg DMA transfer complete) instead of blocking. `channel` is an optional second channel 
     /// to stop; eg if you have both a tx and rx channel.
-    #[cfg(not(any(feature = "f4", feature = "l552")))]
     pub fn stop_dma<D>(&mut self, channel: DmaChannel, channel2: Option<DmaChannel>, dma: &mut Dma<D>)
     where
         D: Deref<Target = dma_p::RegisterBlock>,
@@ -887,9 +1087,9 @@ where
 }
 
 #[cfg(feature = "embedded-hal")]
-impl<R> FullDuplex<u8> for Spi<R>
+impl<R, PINS> FullDuplex<u8> for Spi<R, PINS>
 where
-    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph + ClockPeriph,
 {
     type Error = Error;
 
@@ -909,13 +1109,13 @@ where
 }
 
 #[cfg(feature = "embedded-hal")]
-impl<R> embedded_hal::blocking::spi::transfer::Default<u8> for Spi<R> where
-    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph
+impl<R, PINS> embedded_hal::blocking::spi::transfer::Default<u8> for Spi<R, PINS> where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph + ClockPeriph
 {
 }
 
 #[cfg(feature = "embedded-hal")]
-impl<R> embedded_hal::blocking::spi::write::Default<u8> for Spi<R> where
-    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph
+impl<R, PINS> embedded_hal::blocking::spi::write::Default<u8> for Spi<R, PINS> where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph + ClockPeriph
 {
 }
@@ -16,6 +16,7 @@ use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 
 use crate::{
     clocks::Clocks,
+    gpio::typestate::PwmPin,
     pac::{self, RCC},
     util::{rcc_en_reset, RccPeriph},
 };
@@ -28,9 +29,15 @@ cfg_if! {
     }
 }
 
-#[cfg(not(any(feature = "f4", feature = "l552")))]
+#[cfg(not(feature = "f4"))]
 use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
 
+#[cfg(not(any(feature = "f4", feature = "f3", feature = "l4")))]
+use crate::dma::{Transfer, WriteBuffer};
+
+#[cfg(not(any(feature = "g0", feature = "f4", feature = "f3", feature = "l4")))]
+use crate::dma::ReadBuffer;
+
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::dma::DmaInput;
 
@@ -511,6 +518,37 @@ macro_rules! make_timer {
                 Ok(())
             }
 
+            /// Update timing after a change to the timer's APB clock, eg from `Clocks::switch_to`, so
+            /// the timer keeps its frequency. This scales PSC, and keeps ARR, so duty cycles set on
+            /// the capture-compare channels are unchanged. If PSC can't be scaled to stay in range,
+            /// this sets the frequency using `set_freq()`, which changes ARR.
+            pub fn retime(&mut self, clocks: &Clocks) -> Result<(), ValueError> {
+                let clock_speed = match $apb {
+                    1 => clocks.apb1_timer(),
+                    _ => clocks.apb2_timer(),
+                };
+
+                let psc = self.regs.psc.read().bits();
+
+                if let Some(psc_new) = scale_psc(psc, self.clock_speed, clock_speed) {
+                    self.regs.psc.write(|w| unsafe { w.bits(psc_new.into()) });
+                    self.clock_speed = clock_speed;
+                    return Ok(());
+                }
+
+                let arr = self.regs.arr.read().bits();
+                let mut freq = self.clock_speed as f32 / ((psc + 1) as f32 * (arr + 1) as f32);
+
+                // Undo the doubling `set_freq` applies for center-aligned modes.
+                match self.cfg.alignment {
+                    Alignment::Edge => (),
+                    _ => freq /= 2.,
+                }
+
+                self.clock_speed = clock_speed;
+                self.set_freq(freq)
+            }
+
             /// Set the timer period, in seconds. Overrides the period or frequency set
             /// in the constructor.
             pub fn set_period(&mut self, period: f32) -> Result<(), ValueError> {
@@ -574,6 +612,17 @@ macro_rules! make_timer {
                 self.enable_capture_compare(channel);
             }
 
+            /// Enables PWM output on a type-state pin, as in `enable_pwm_output()`. The channel is
+            /// determined by the pin, and checked at compile time.
+            pub fn enable_pwm_pin<P: PwmPin<pac::$TIMX>>(
+                &mut self,
+                _pin: &P,
+                compare: OutputCompare,
+                duty: f32,
+            ) {
+                self.enable_pwm_output(P::CHANNEL, compare, duty);
+            }
+
             /// Return the integer associated with the maximum duty period.
             pub fn get_max_duty(&self) -> $res {
                 #[cfg(feature = "g0")]
@@ -588,17 +637,20 @@ macro_rules! make_timer {
              /// software overhead, but it can also be used to read several registers in a row, at regular
              /// intervals." This may be used to create arbitrary waveforms by modifying the CCR register
              /// (base address = 13-16, for CCR1-4), or for implementing duty-cycle based digital protocols.
-            #[cfg(not(any(feature = "g0", feature = "f4", feature = "l552", feature = "f3", feature = "l4")))]
-            pub unsafe fn write_dma_burst<D>(
+             /// Returns a `Transfer` that holds `buf` until the transfer is complete.
+            #[cfg(not(any(feature = "g0", feature = "f4", feature = "f3", feature = "l4")))]
+            pub fn write_dma_burst<B, D>(
                 &mut self,
-                buf: &[u16],
+                buf: B,
                 base_address: u8,
                 burst_len: u8,
                 dma_channel: DmaChannel,
                 channel_cfg: ChannelCfg,
                 dma: &mut Dma<D>,
                 ds_32_bits: bool,
-            ) where
+            ) -> Transfer<B>
+            where
+                B: ReadBuffer<Word = u16>,
                 D: Deref<Target = dma_p::RegisterBlock>,
             {
                 // Note: F3 and L4 are unsupported here, since I'm not sure how to select teh
@@ -606,7 +658,7 @@ macro_rules! make_timer {
 
                 // todo: Should we disable the timer here?
 
-                let (ptr, len) = (buf.as_ptr(), buf.len());
+                let (ptr, len) = unsafe { buf.read_buffer() };
 
                 // todo: For F3 and L4, manually set channel using PAC for now. Currently
                 // todo we don't have a way here to pick the timer. Could do it with a new macro arg.
@@ -658,7 +710,7 @@ macro_rules! make_timer {
                 // 00000: TIMx_CR1
                 // 00001: TIMx_CR2
                 // 00010: TIMx_SMCR
-                self.regs.dcr.modify(|_, w| {
+                self.regs.dcr.modify(|_, w| unsafe {
                     w.dba().bits(base_address);
                     w.dbl().bits(burst_len as u8 - 1)
                 });
@@ -683,23 +735,27 @@ macro_rules! make_timer {
                     dma::DataSize::S16,
                     channel_cfg,
                 );
+
+                Transfer::new(buf, dma_channel, dma.periph)
             }
 
-            #[cfg(not(any(feature = "g0", feature = "f4", feature = "l552", feature = "f3", feature = "l4")))]
-            pub unsafe fn read_dma_burst<D>(
+            #[cfg(not(any(feature = "g0", feature = "f4", feature = "f3", feature = "l4")))]
+            pub fn read_dma_burst<B, D>(
                 // todo: Experimenting with input capture.
                 &mut self,
-                buf: &[u16],
+                mut buf: B,
                 base_address: u8,
                 burst_len: u8,
                 dma_channel: DmaChannel,
                 channel_cfg: ChannelCfg,
                 dma: &mut Dma<D>,
                 ds_32_bits: bool,
-            ) where
+            ) -> Transfer<B>
+            where
+                B: WriteBuffer<Word = u16>,
                 D: Deref<Target = dma_p::RegisterBlock>,
             {
-                let (ptr, len) = (buf.as_ptr(), buf.len());
+                let (ptr, len) = unsafe { buf.write_buffer() };
 
                 let periph_addr = &self.regs.dmar as *const _ as u32;
 
@@ -708,7 +764,7 @@ macro_rules! make_timer {
                 #[cfg(not(feature = "h7"))]
                 let num_data = len as u16;
 
-                self.regs.dcr.modify(|_, w| {
+                self.regs.dcr.modify(|_, w| unsafe {
                     w.dba().bits(base_address);
                     w.dbl().bits(burst_len as u8 - 1)
                 });
@@ -727,6 +783,36 @@ macro_rules! make_timer {
                     dma::DataSize::S16,
                     channel_cfg,
                 );
+
+                Transfer::new(buf, dma_channel, dma.periph)
+            }
+
+            #[cfg(not(any(feature = "f4", feature = "l5", feature = "f3", feature = "l4")))]
+            /// Sample a GPIO port's input levels into `buf` once per timer update event, using DMA.
+            /// The sample rate is the timer's frequency; set it with `set_freq()` first. The DMA
+            /// channel must be routed to this timer's update request, eg
+            /// `dma::mux(DmaPeriph::Dma1, DmaChannel::C1, DmaInput::Tim2Up)`. Starts the timer.
+            /// Decode the result with `gpio::capture::Capture`. Returns a `Transfer` that holds `buf`
+            /// until the capture is complete.
+            pub fn capture_port_dma<B, D>(
+                &mut self,
+                buf: B,
+                port: crate::gpio::Port,
+                dma_channel: DmaChannel,
+                channel_cfg: ChannelCfg,
+                dma: &mut Dma<D>,
+            ) -> Transfer<B>
+            where
+                B: WriteBuffer<Word = u16>,
+                D: Deref<Target = dma_p::RegisterBlock>,
+            {
+                self.regs.dier.modify(|_, w| w.ude().set_bit());
+
+                let transfer = crate::gpio::read_dma(buf, port, dma_channel, channel_cfg, dma);
+
+                self.enable();
+
+                transfer
             }
         }
 
@@ -1537,6 +1623,21 @@ fn calc_freq_vals(freq: f32, clock_speed: u32) -> Result<(u16, u16), ValueError>
     Ok((psc as u16, arr as u16))
 }
 
+/// Scale a PSC value for a new timer clock speed, keeping the update frequency. Returns `None`
+/// if the result doesn't fit in PSC.
+fn scale_psc(psc: u32, clock_speed: u32, clock_speed_new: u32) -> Option<u16> {
+    // (PSC+1) is proportional to the timer clock, for a given ARR and update frequency. Round
+    // to the nearest integer.
+    let psc_plus_1 =
+        ((psc as u64 + 1) * clock_speed_new as u64 + clock_speed as u64 / 2) / clock_speed as u64;
+
+    if psc_plus_1 == 0 || psc_plus_1 > 65_536 {
+        return None;
+    }
+
+    Some((psc_plus_1 - 1) as u16)
+}
+
 cfg_if! {
     if #[cfg(not(any(
         feature = "f401",
@@ -1618,6 +1719,26 @@ cfg_if! {
                 Ok(())
             }
 
+            /// Update timing after a change to the APB1 timer clock, eg from `Clocks::switch_to`,
+            /// so the timer keeps its frequency. Scales PSC, or if this isn't possible, runs
+            /// `set_freq()`.
+            pub fn retime(&mut self, clock_cfg: &Clocks) -> Result<(), ValueError> {
+                let clock_speed = clock_cfg.apb1_timer();
+                let psc = self.regs.psc.read().bits();
+
+                if let Some(psc_new) = scale_psc(psc, self.clock_speed, clock_speed) {
+                    self.regs.psc.write(|w| unsafe { w.bits(psc_new.into()) });
+                    self.clock_speed = clock_speed;
+                    return Ok(());
+                }
+
+                let arr = self.regs.arr.read().bits();
+                let freq = self.clock_speed as f32 / ((psc + 1) as f32 * (arr + 1) as f32);
+
+                self.clock_speed = clock_speed;
+                self.set_freq(freq)
+            }
+
             /// Return the integer associated with the maximum duty period.
             pub fn get_max_duty(&self) -> u16 {
                 #[cfg(feature = "l5")]
@@ -1787,6 +1908,64 @@ make_timer!(TIM16, tim16, 2, u16);
 #[cfg(not(feature = "f4"))]
 cc_1_channel!(TIM16, u16);
 
+#[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
+impl Timer<pac::TIM16> {
+    /// Measure this timer's clock speed against the LSE, using input capture on channel 1, which
+    /// is internally connected to the LSE. Returns the measured speed, in Hz. Compare this to
+    /// `Clocks::apb2_timer()` to find the error of the oscillator the system clock is derived
+    /// from, eg to correct it with `clocks::set_hsi_trim()` or `clocks::set_msi_trim()`. The LSE
+    /// must be running. Each capture spans 8 LSE periods, (244us) so more captures give a more
+    /// precise result. This stops the timer, and overwrites its prescaler and channel 1 settings.
+    pub fn measure_lse(&mut self, num_captures: u16) -> u32 {
+        assert!(num_captures > 0);
+
+        self.disable();
+        self.regs.psc.write(|w| unsafe { w.bits(0) });
+        self.regs.arr.write(|w| unsafe { w.bits(0xffff) });
+
+        // Connect TI1 to the LSE.
+        cfg_if! {
+            if #[cfg(any(feature = "l4", feature = "wb"))] {
+                self.regs.or1.modify(|_, w| unsafe { w.ti1_rmp().bits(0b10) });
+            } else {
+                self.regs.tisel.modify(|_, w| unsafe { w.ti1sel().bits(0b0010) });
+            }
+        }
+
+        self.set_input_capture(
+            TimChannel::C1,
+            CaptureCompare::InputTi1,
+            Polarity::ActiveHigh,
+            Polarity::ActiveHigh,
+        );
+        // Capture once every 8 rising edges.
+        self.regs
+            .ccmr1_input()
+            .modify(|_, w| unsafe { w.ic1psc().bits(0b11) });
+
+        self.regs.egr.write(|w| w.ug().set_bit());
+        self.enable();
+
+        // Reading CCR1 clears the capture flag. The first capture is our reference point.
+        while self.regs.sr.read().cc1if().bit_is_clear() {}
+        let mut prev = self.get_duty(TimChannel::C1);
+
+        let mut total: u32 = 0;
+        for _ in 0..num_captures {
+            while self.regs.sr.read().cc1if().bit_is_clear() {}
+            let capture = self.get_duty(TimChannel::C1);
+            // The counter wraps at 16 bits; this holds as long as the timer clock is below
+            // 268Mhz.
+            total += capture.wrapping_sub(prev) as u32;
+            prev = capture;
+        }
+
+        self.disable();
+
+        (total as u64 * 32_768 / (num_captures as u64 * 8)) as u32
+    }
+}
+
 cfg_if! {
     if #[cfg(not(any(
         feature = "l4x1",
@@ -9,8 +9,9 @@
 
 use crate::{
     clocks::Clocks,
+    gpio::typestate::{RxPin, TxPin},
     pac::{self, RCC},
-    util::{BaudPeriph, RccPeriph},
+    util::{ClockPeriph, RccPeriph},
 };
 
 use core::ops::Deref;
@@ -20,13 +21,14 @@ use cortex_m::interrupt::free;
 cfg_if! {
     if #[cfg(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1"))))] {
         use crate::pac::dma as dma_p;
-    } else if #[cfg(feature = "f4")] {} else {
+    } else if #[cfg(feature = "f4")] {
+        use crate::pac::dma2 as dma_p;
+    } else {
         use crate::pac::dma1 as dma_p;
     }
 }
 
-#[cfg(not(any(feature = "f4", feature = "l552")))]
-use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
+use crate::dma::{self, ChannelCfg, Dma, DmaChannel, ReadBuffer, Transfer, WriteBuffer};
 
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::dma::DmaInput;
@@ -146,15 +148,16 @@ impl Default for UsartConfig {
 }
 
 /// Represents the USART peripheral, for serial communications.
-pub struct Usart<R> {
+pub struct Usart<R, PINS = ()> {
     pub regs: R,
     baud: u32,
     config: UsartConfig,
+    pins: PINS,
 }
 
 impl<R> Usart<R>
 where
-    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + ClockPeriph,
 {
     /// Initialize a U[s]ART peripheral, including configuration register writes, and enabling and
     /// resetting its RCC peripheral clock. `baud` is the baud rate, in bytes-per-second.
@@ -164,7 +167,12 @@ where
             R::en_reset(rcc);
         });
 
-        let mut result = Self { regs, baud, config };
+        let mut result = Self {
+            regs,
+            baud,
+            config,
+            pins: (),
+        };
 
         // This should already be disabled on power up, but disable here just in case;
         // some bits can't be set with USART enabled.
@@ -245,6 +253,43 @@ where
 
         result
     }
+}
+
+impl<R, TX, RX> Usart<R, (TX, RX)>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + ClockPeriph,
+    TX: TxPin<R>,
+    RX: RxPin<R>,
+{
+    /// Initialize a U[S]ART peripheral, as in `new()`, with type-state pins. This checks at compile
+    /// time that the pins are set to this peripheral's TX and RX alternate functions. The pins are
+    /// held until `free()`.
+    pub fn new_with_pins(
+        regs: R,
+        pins: (TX, RX),
+        baud: u32,
+        config: UsartConfig,
+        clock_cfg: &Clocks,
+    ) -> Self {
+        let usart = Usart::new(regs, baud, config, clock_cfg);
+
+        Self {
+            regs: usart.regs,
+            baud: usart.baud,
+            config: usart.config,
+            pins,
+        }
+    }
+}
+
+impl<R, PINS> Usart<R, PINS>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + ClockPeriph,
+{
+    /// Release the peripheral's registers, and its pins if it was created with `new_with_pins()`.
+    pub fn free(self) -> (R, PINS) {
+        (self.regs, self.pins)
+    }
 
     /// Set the BAUD rate. Called during init, and can be called later to change BAUD
     /// during program execution.
@@ -257,10 +302,8 @@ where
         }
 
         // To set BAUD rate, see L4 RM section 38.5.4: "USART baud rate generation".
-        // todo: This assumes the USART clock is APB1 or 2 depending on which USART.
-        // todo: Take into account the selectable USART clock in both
-        // todo util::baud implementation, and `clocks` module.
-        let fclk = R::baud(clock_cfg);
+        // This is the USART kernel clock, as selected in `Clocks::kernel_clocks`.
+        let fclk = R::kernel_speed(clock_cfg);
 
         let usart_div = match self.config.oversampling {
             OverSampling::O16 => fclk / baud,
@@ -284,6 +327,12 @@ where
         }
     }
 
+    /// Re-calculate the baud rate divider from the USART's kernel clock, eg after changing clock
+    /// speeds with `Clocks::switch_to`. Keeps the baud rate set previously.
+    pub fn retime(&mut self, clock_cfg: &Clocks) {
+        self.set_baud(self.baud, clock_cfg);
+    }
+
     /// Transmit data, as a sequence of u8. See L44 RM, section 38.5.2: "Character transmission procedure"
     pub fn write(&mut self, data: &[u8]) {
         // 7. Write the data to send in the USART_TDR register (this clears the TXE bit). Repeat this
@@ -363,27 +412,34 @@ where
         }
     }
 
-    #[cfg(not(any(feature = "f4", feature = "l552")))]
     /// Transmit data using DMA. (L44 RM, section 38.5.15)
-    /// Note that the `channel` argument is only used on F3 and L4.
-    pub unsafe fn write_dma<D>(
+    /// On F3, the `channel` argument is ignored with DMA1, which uses a fixed channel. On L4, it's
+    /// ignored with either controller. On F4, it's the stream to use. Returns a `Transfer` that holds `buf` until the transfer is complete.
+    pub fn write_dma<B, D>(
         &mut self,
-        buf: &[u8],
+        buf: B,
         channel: DmaChannel,
         channel_cfg: ChannelCfg,
         dma: &mut Dma<D>,
-    ) where
+    ) -> Transfer<B>
+    where
+        B: ReadBuffer<Word = u8>,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
-        let (ptr, len) = (buf.as_ptr(), buf.len());
+        let (ptr, len) = unsafe { buf.read_buffer() };
 
         // To map a DMA channel for USART transmission, use
         // the following procedure (x denotes the channel number):
 
-        #[cfg(any(feature = "f3", feature = "l4"))]
-        let channel = R::write_chan();
+        #[cfg(feature = "f3")]
+        let channel = dma.select_channel(R::write_chan(), channel);
         #[cfg(feature = "l4")]
-        R::write_sel(dma);
+        let channel = R::write_sel(dma);
+        #[cfg(feature = "f4")]
+        dma.channel_select(
+            channel,
+            R::write_input().expect("This peripheral has no DMA write request."),
+        );
 
         #[cfg(feature = "h7")]
         let num_data = len as u32;
@@ -396,12 +452,18 @@ where
         // register whenever the TXE bit is set."
         self.regs.cr3.modify(|_, w| w.dmat().set_bit());
 
+        // F4 uses a single data register for transmit and receive.
+        #[cfg(not(feature = "f4"))]
+        let periph_addr = &self.regs.tdr as *const _ as u32;
+        #[cfg(feature = "f4")]
+        let periph_addr = &self.regs.dr as *const _ as u32;
+
         dma.cfg_channel(
             channel,
             // 1. Write the USART_TDR register address in the DMA control register to configure it as
             // the destination of the transfer. The data is moved to this address from memory after
             // each TXE event.
-            &self.regs.tdr as *const _ as u32,
+            periph_addr,
             // 2. Write the memory address in the DMA control register to configure it as the source of
             // the transfer. The data is loaded into the USART_TDR register from this memory area
             // after each TXE event.
@@ -421,8 +483,11 @@ where
         // (Handled in `cfg_channel`)
 
         // 6. Clear the TC flag in the USART_ISR register by setting the TCCF bit in the
-        // USART_ICR register.
+        // USART_ICR register. (On F4, clear it by writing 0 to TC in USART_SR)
+        #[cfg(not(feature = "f4"))]
         self.regs.icr.write(|w| w.tccf().set_bit());
+        #[cfg(feature = "f4")]
+        self.regs.sr.modify(|_, w| w.tc().clear_bit());
 
         // 7. Activate the channel in the DMA register.
         // When the number of data transfers programmed in the DMA Controller is reached, the DMA
@@ -435,26 +500,35 @@ where
         // disabling the USART or entering Stop mode. Software must wait until TC=1. The TC flag
         // remains cleared during all data transfers and it is set by hardware at the end of transmission
         // of the last frame.
+
+        Transfer::new(buf, channel, dma.periph)
     }
 
-    #[cfg(not(any(feature = "f4", feature = "l552")))]
     /// Receive data using DMA. (L44 RM, section 38.5.15; G4 RM section 37.5.19.
-    /// Note that the `channel` argument is only used on F3 and L4.
-    pub unsafe fn read_dma<D>(
+    /// On F3, the `channel` argument is ignored with DMA1, which uses a fixed channel. On L4, it's
+    /// ignored with either controller. On F4, it's the stream to use. Returns a `Transfer` that holds `buf` until the transfer is complete.
+    pub fn read_dma<B, D>(
         &mut self,
-        buf: &mut [u8],
+        mut buf: B,
         channel: DmaChannel,
         channel_cfg: ChannelCfg,
         dma: &mut Dma<D>,
-    ) where
+    ) -> Transfer<B>
+    where
+        B: WriteBuffer<Word = u8>,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
-        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+        let (ptr, len) = unsafe { buf.write_buffer() };
 
-        #[cfg(any(feature = "f3", feature = "l4"))]
-        let channel = R::read_chan();
+        #[cfg(feature = "f3")]
+        let channel = dma.select_channel(R::read_chan(), channel);
         #[cfg(feature = "l4")]
-        R::read_sel(dma);
+        let channel = R::read_sel(dma);
+        #[cfg(feature = "f4")]
+        dma.channel_select(
+            channel,
+            R::read_input().expect("This peripheral has no DMA read request."),
+        );
 
         #[cfg(feature = "h7")]
         let num_data = len as u32;
@@ -464,12 +538,17 @@ where
         // DMA mode can be enabled for reception by setting the DMAR bit in USART_CR3 register.
         self.regs.cr3.modify(|_, w| w.dmar().set_bit());
 
+        #[cfg(not(feature = "f4"))]
+        let periph_addr = &self.regs.rdr as *const _ as u32;
+        #[cfg(feature = "f4")]
+        let periph_addr = &self.regs.dr as *const _ as u32;
+
         dma.cfg_channel(
             channel,
             // 1. Write the USART_RDR register address in the DMA control register to configure it as
             // the source of the transfer. The data is moved from this address to the memory after
             // each RXNE event.
-            &self.regs.rdr as *const _ as u32,
+            periph_addr,
             // 2. Write the memory address in the DMA control register to configure it as the destination
             // of the transfer. The data is loaded from USART_RDR to this memory area after each
             // RXNE event.
@@ -495,6 +574,8 @@ where
 
         // When the number of data transfers programmed in the DMA Controller is reached, the DMA
         // controller generates an interrupt on the DMA channel interrupt vector.
+
+        Transfer::new(buf, channel, dma.periph)
     }
 
     /// Flush the transmit buffer.
@@ -619,9 +700,9 @@ pub enum Error {
 // todo: Use those errors above.
 
 #[cfg(feature = "embedded-hal")]
-impl<R> Read<u8> for Usart<R>
+impl<R, PINS> Read<u8> for Usart<R, PINS>
 where
-    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + ClockPeriph,
 {
     type Error = Error;
 
@@ -639,9 +720,9 @@ where
 }
 
 #[cfg(feature = "embedded-hal")]
-impl<R> Write<u8> for Usart<R>
+impl<R, PINS> Write<u8> for Usart<R, PINS>
 where
-    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + ClockPeriph,
 {
     type Error = Error;
 
@@ -679,9 +760,9 @@ where
 
 #[cfg(feature = "embedded-hal")]
 // #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
-impl<R> blocking::serial::Write<u8> for Usart<R>
+impl<R, PINS> blocking::serial::Write<u8> for Usart<R, PINS>
 where
-    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + ClockPeriph,
 {
     type Error = Error;
 
@@ -4,7 +4,7 @@
 use core::ops::Deref;
 
 use crate::{
-    clocks::Clocks,
+    clocks::{Clocks, KernelClockPeriph},
     pac::{self, rcc::RegisterBlock},
 };
 
@@ -15,6 +15,9 @@ use crate::pac::DMA1;
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::dma::{self, Dma, DmaChannel, DmaInput};
 
+#[cfg(feature = "f4")]
+use crate::dma::DmaInput;
+
 use cfg_if::cfg_if;
 
 #[cfg(not(any(
@@ -132,21 +135,23 @@ macro_rules! rcc_en_reset {
 
 pub(crate) use rcc_en_reset;
 
-// todo: This trait is currently a one-off for usart
-pub trait BaudPeriph {
-    fn baud(clock_cfg: &Clocks) -> u32;
+/// Used to get the frequency of the clock a peripheral derives its timing from, eg for baud
+/// rates or I2C timings. This is its kernel clock, on peripherals where one can be selected
+/// (See `Clocks::kernel_clocks`), or its APB clock otherwise.
+pub trait ClockPeriph {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32;
 }
 
-impl BaudPeriph for pac::USART1 {
-    fn baud(clock_cfg: &Clocks) -> u32 {
-        clock_cfg.apb2()
+impl ClockPeriph for pac::USART1 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_speed(KernelClockPeriph::Usart1)
     }
 }
 
 #[cfg(not(any(feature = "wb", feature = "wl")))]
-impl BaudPeriph for pac::USART2 {
-    fn baud(clock_cfg: &Clocks) -> u32 {
-        clock_cfg.apb1()
+impl ClockPeriph for pac::USART2 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_speed(KernelClockPeriph::Usart2)
     }
 }
 
@@ -161,61 +166,213 @@ impl BaudPeriph for pac::USART2 {
     feature = "wb",
     feature = "wl",
 )))]
-impl BaudPeriph for pac::USART3 {
-    fn baud(clock_cfg: &Clocks) -> u32 {
-        clock_cfg.apb1()
+impl ClockPeriph for pac::USART3 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_speed(KernelClockPeriph::Usart3)
     }
 }
 
 cfg_if! {
-    if #[cfg(any(feature = "l4x6", feature = "h7"))] {
-        impl BaudPeriph for pac::UART4 {
-            fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb1()
+    if #[cfg(feature = "h7")] {
+        // H7 UARTs share a kernel clock selection with either USART1 or USART2.
+        impl ClockPeriph for pac::UART4 {
+            fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+                clock_cfg.kernel_speed(KernelClockPeriph::Usart2)
             }
         }
 
-        impl BaudPeriph for pac::UART5 {
-            fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb1()
+        impl ClockPeriph for pac::UART5 {
+            fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+                clock_cfg.kernel_speed(KernelClockPeriph::Usart2)
             }
         }
 
-        #[cfg(feature = "h7")]
-        impl BaudPeriph for pac::USART6 {
-            fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb2()
+        impl ClockPeriph for pac::USART6 {
+            fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+                clock_cfg.kernel_speed(KernelClockPeriph::Usart1)
             }
         }
 
-        #[cfg(feature = "h7")]
-        impl BaudPeriph for pac::UART7 {
-            fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb1()
+        impl ClockPeriph for pac::UART7 {
+            fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+                clock_cfg.kernel_speed(KernelClockPeriph::Usart2)
             }
         }
 
-        #[cfg(feature = "h7")]
-        impl BaudPeriph for pac::UART8 {
-            fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb1()
+        impl ClockPeriph for pac::UART8 {
+            fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+                clock_cfg.kernel_speed(KernelClockPeriph::Usart2)
             }
         }
 
         #[cfg(feature = "h735")]
-        impl BaudPeriph for pac::UART9 {
-            fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb2()
+        impl ClockPeriph for pac::UART9 {
+            fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+                clock_cfg.kernel_speed(KernelClockPeriph::Usart1)
             }
         }
 
         #[cfg(feature = "h735")]
-        impl BaudPeriph for pac::USART10 {
-            fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb2()
+        impl ClockPeriph for pac::USART10 {
+            fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+                clock_cfg.kernel_speed(KernelClockPeriph::Usart1)
+            }
+        }
+    } else if #[cfg(feature = "l4x6")] {
+        // We don't currently configure UART4 and 5's kernel clocks on L4; they use APB1.
+        impl ClockPeriph for pac::UART4 {
+            fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+                clock_cfg.apb1()
+            }
+        }
+
+        impl ClockPeriph for pac::UART5 {
+            fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+                clock_cfg.apb1()
             }
         }
+    }
+}
+
+impl ClockPeriph for pac::I2C1 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_speed(KernelClockPeriph::I2c1)
+    }
+}
 
+#[cfg(not(any(feature = "wb", feature = "f3x4")))]
+impl ClockPeriph for pac::I2C2 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_speed(KernelClockPeriph::I2c2)
+    }
+}
+
+#[cfg(any(feature = "h7", feature = "wb"))]
+impl ClockPeriph for pac::I2C3 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_speed(KernelClockPeriph::I2c3)
+    }
+}
+
+#[cfg(not(feature = "f301"))]
+impl ClockPeriph for pac::SPI1 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        #[cfg(feature = "h7")]
+        return clock_cfg.spi123_speed();
+        #[cfg(not(feature = "h7"))]
+        clock_cfg.apb2()
+    }
+}
+
+#[cfg(not(any(feature = "f3x4", feature = "wb", feature = "wl")))]
+impl ClockPeriph for pac::SPI2 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        #[cfg(feature = "h7")]
+        return clock_cfg.spi123_speed();
+        #[cfg(not(feature = "h7"))]
+        clock_cfg.apb1()
+    }
+}
+
+#[cfg(not(any(
+    feature = "f3x4",
+    feature = "f410",
+    feature = "g0",
+    feature = "wb",
+    feature = "wl"
+)))]
+impl ClockPeriph for pac::SPI3 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        #[cfg(feature = "h7")]
+        return clock_cfg.spi123_speed();
+        #[cfg(not(feature = "h7"))]
+        clock_cfg.apb1()
+    }
+}
+
+#[cfg(feature = "h7")]
+impl ClockPeriph for pac::SPI4 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.spi45_speed()
+    }
+}
+
+#[cfg(feature = "h7")]
+impl ClockPeriph for pac::SPI6 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.spi6_speed()
+    }
+}
+
+#[cfg(not(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "g0",
+    feature = "g4",
+    feature = "h7b3",
+    feature = "wl"
+)))]
+impl ClockPeriph for pac::SAI1 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.sai1_speed()
+    }
+}
+
+#[cfg(all(feature = "h7", not(feature = "h735")))]
+impl ClockPeriph for pac::SAI2 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.sai23_speed()
+    }
+}
+
+#[cfg(all(feature = "h7", not(feature = "h735")))]
+impl ClockPeriph for pac::SAI3 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.sai23_speed()
+    }
+}
+
+#[cfg(feature = "h7")]
+impl ClockPeriph for pac::SAI4 {
+    /// SAI4's A and B blocks have separate kernel clocks; this is block A's.
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.sai4a_speed()
+    }
+}
+
+#[cfg(not(any(feature = "f3", feature = "f4")))]
+impl ClockPeriph for pac::LPTIM1 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_speed(KernelClockPeriph::Lptim1)
+    }
+}
+
+#[cfg(not(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "g4",
+    feature = "g030",
+    feature = "g050",
+    feature = "g070",
+    feature = "g0b0"
+)))]
+impl ClockPeriph for pac::LPTIM2 {
+    fn kernel_speed(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_speed(KernelClockPeriph::Lptim2)
+    }
+}
+
+/// The previous name of `ClockPeriph`, used for USART baud rates. Implemented for every
+/// `ClockPeriph`.
+#[deprecated(note = "Use `ClockPeriph::kernel_speed` instead.")]
+pub trait BaudPeriph {
+    fn baud(clock_cfg: &Clocks) -> u32;
+}
+
+#[allow(deprecated)]
+impl<T: ClockPeriph> BaudPeriph for T {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        T::kernel_speed(clock_cfg)
     }
 }
 
@@ -264,7 +421,7 @@ impl VrefPeriph for pac::ADC5 {
     }
 }
 
-/// Used to provide peripheral-specific implementation for RCC enable/reset, and for F3 and L4,
+/// Used to provide peripheral-specific implementation for RCC enable/reset, and for F3, F4, and L4,
 /// DMA channel assignment.
 pub trait RccPeriph {
     fn en_reset(rcc: &RegisterBlock);
@@ -273,10 +430,24 @@ pub trait RccPeriph {
     fn read_chan() -> DmaChannel;
     #[cfg(any(feature = "f3", feature = "l4"))]
     fn write_chan() -> DmaChannel;
+    /// Route the peripheral's DMA read request on L4, and return the channel it's wired to.
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>);
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel;
+    /// Route the peripheral's DMA write request on L4, and return the channel it's wired to.
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>);
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel;
+    /// The DMA request input for reading, used to set a stream's channel select on F4. `None` if
+    /// the peripheral has no read request.
+    #[cfg(feature = "f4")]
+    fn read_input() -> Option<DmaInput> {
+        None
+    }
+    /// The DMA request input for writing, used to set a stream's channel select on F4. `None` if
+    /// the peripheral has no write request.
+    #[cfg(feature = "f4")]
+    fn write_input() -> Option<DmaInput> {
+        None
+    }
 }
 
 #[cfg(not(any(
@@ -306,14 +477,19 @@ impl RccPeriph for pac::TIM6 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {
         unimplemented!()
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {
         unimplemented!()
     }
+
+    #[cfg(feature = "f4")]
+    fn write_input() -> Option<DmaInput> {
+        Some(DmaInput::Tim6Up)
+    }
 }
 
 #[cfg(not(any(
@@ -345,14 +521,19 @@ impl RccPeriph for pac::TIM7 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {
         unimplemented!()
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {
         unimplemented!()
     }
+
+    #[cfg(feature = "f4")]
+    fn write_input() -> Option<DmaInput> {
+        Some(DmaInput::Tim7Up)
+    }
 }
 
 impl RccPeriph for pac::I2C1 {
@@ -371,13 +552,23 @@ impl RccPeriph for pac::I2C1 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::I2c1Rx);
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::I2c1Rx)
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::I2c1Tx);
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::I2c1Tx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn read_input() -> Option<DmaInput> {
+        Some(DmaInput::I2c1Rx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn write_input() -> Option<DmaInput> {
+        Some(DmaInput::I2c1Tx)
     }
 }
 
@@ -398,13 +589,23 @@ impl RccPeriph for pac::I2C2 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::I2c2Rx);
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::I2c2Rx)
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::I2c2Tx);
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::I2c2Tx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn read_input() -> Option<DmaInput> {
+        Some(DmaInput::I2c2Rx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn write_input() -> Option<DmaInput> {
+        Some(DmaInput::I2c2Tx)
     }
 }
 
@@ -432,13 +633,23 @@ impl RccPeriph for pac::SPI1 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::Spi1Rx);
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::Spi1Rx)
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::Spi1Tx);
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::Spi1Tx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn read_input() -> Option<DmaInput> {
+        Some(DmaInput::Spi1Rx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn write_input() -> Option<DmaInput> {
+        Some(DmaInput::Spi1Tx)
     }
 }
 
@@ -459,13 +670,23 @@ impl RccPeriph for pac::SPI2 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::Spi2Rx);
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::Spi2Rx)
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::Spi2Tx);
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::Spi2Tx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn read_input() -> Option<DmaInput> {
+        Some(DmaInput::Spi2Rx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn write_input() -> Option<DmaInput> {
+        Some(DmaInput::Spi2Tx)
     }
 }
 
@@ -501,13 +722,23 @@ impl RccPeriph for pac::SPI3 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::Spi3Rx);
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::Spi3Rx)
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::Spi3Tx);
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::Spi3Tx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn read_input() -> Option<DmaInput> {
+        Some(DmaInput::Spi3Rx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn write_input() -> Option<DmaInput> {
+        Some(DmaInput::Spi3Tx)
     }
 }
 
@@ -527,6 +758,13 @@ impl RccPeriph for pac::SPI4 {
     }
 }
 
+#[cfg(feature = "h7")]
+impl RccPeriph for pac::SPI6 {
+    fn en_reset(rcc: &RegisterBlock) {
+        rcc_en_reset!(apb4, spi6, rcc);
+    }
+}
+
 #[cfg(not(any(
     feature = "f3",
     feature = "f4",
@@ -551,12 +789,12 @@ impl RccPeriph for pac::SAI1 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {
         unimplemented!()
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {
         unimplemented!()
     }
 }
@@ -578,12 +816,12 @@ impl RccPeriph for pac::SAI2 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {
         unimplemented!()
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {
         unimplemented!()
     }
 }
@@ -605,12 +843,12 @@ impl RccPeriph for pac::SAI3 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {
         unimplemented!()
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {
         unimplemented!()
     }
 }
@@ -632,12 +870,12 @@ impl RccPeriph for pac::SAI4 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {
         unimplemented!()
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {
         unimplemented!()
     }
 }
@@ -658,13 +896,23 @@ impl RccPeriph for pac::USART1 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::Usart1Rx);
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::Usart1Rx)
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::Usart1Tx);
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::Usart1Tx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn read_input() -> Option<DmaInput> {
+        Some(DmaInput::Usart1Rx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn write_input() -> Option<DmaInput> {
+        Some(DmaInput::Usart1Tx)
     }
 }
 
@@ -694,13 +942,23 @@ impl RccPeriph for pac::USART2 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::Usart2Rx);
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::Usart2Rx)
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::Usart2Tx);
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::Usart2Tx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn read_input() -> Option<DmaInput> {
+        Some(DmaInput::Usart2Rx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn write_input() -> Option<DmaInput> {
+        Some(DmaInput::Usart2Tx)
     }
 }
 
@@ -739,13 +997,23 @@ impl RccPeriph for pac::USART3 {
     }
 
     #[cfg(feature = "l4")]
-    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::Usart3Rx);
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::Usart3Rx)
     }
 
     #[cfg(feature = "l4")]
-    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-        dma.channel_select(DmaInput::Usart3Tx);
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+        dma.channel_select(DmaInput::Usart3Tx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn read_input() -> Option<DmaInput> {
+        Some(DmaInput::Usart3Rx)
+    }
+
+    #[cfg(feature = "f4")]
+    fn write_input() -> Option<DmaInput> {
+        Some(DmaInput::Usart3Tx)
     }
 }
 
@@ -767,13 +1035,13 @@ cfg_if! {
             }
 
             #[cfg(feature = "l4")]
-            fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-                dma.channel_select(DmaInput::Uart4Rx);
+            fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+                dma.channel_select(DmaInput::Uart4Rx)
             }
 
             #[cfg(feature = "l4")]
-            fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-                dma.channel_select(DmaInput::Uart4Tx);
+            fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+                dma.channel_select(DmaInput::Uart4Tx)
             }
         }
 
@@ -793,13 +1061,13 @@ cfg_if! {
             }
 
             #[cfg(feature = "l4")]
-            fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-                dma.channel_select(DmaInput::Uart5Rx);
+            fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+                dma.channel_select(DmaInput::Uart5Rx)
             }
 
             #[cfg(feature = "l4")]
-            fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
-                dma.channel_select(DmaInput::Uart5Tx);
+            fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
+                dma.channel_select(DmaInput::Uart5Tx)
             }
         }
 
@@ -921,10 +1189,10 @@ cfg_if! {
             fn write_chan() -> DmaChannel {unimplemented!()}
 
             #[cfg(feature = "l4")]
-            fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {unimplemented!()}
+            fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {unimplemented!()}
 
             #[cfg(feature = "l4")]
-            fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {unimplemented!()}
+            fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) -> DmaChannel {unimplemented!()}
         }
     }
 }
@@ -951,12 +1219,12 @@ cfg_if! {
 //     }
 //
 //     #[cfg(feature = "l4")]
-//     fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
+//     fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
 //         dma.channel_select(DmaInput::Adc1);
 //     }
 //
 //     #[cfg(feature = "l4")]
-//     fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
+//     fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
 //         unimplemented!()
 //     }
 // }
@@ -980,12 +1248,12 @@ cfg_if! {
 //     }
 //
 //     #[cfg(feature = "l4")]
-//     fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
+//     fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
 //         dma.channel_select(DmaInput::Adc2);
 //     }
 //
 //     #[cfg(feature = "l4")]
-//     fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
+//     fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) -> DmaChannel {
 //         unimplemented!()
 //     }
 // }